@@ -1,70 +1,411 @@
-use crate::types::{Class, Point};
+use crate::brute_query::{
+    BitBruteForceQueryEngine, BruteForceQueryEngine, SparseBruteForceQueryEngine,
+};
+use crate::engine::EngineKind;
+use crate::types::{
+    aniso_dist_sq, check_aniso_eps, format_point, validate_point, weight_map, BitPoint, Class,
+    CloudView, Euclidean, Float, LabeledPoint, Metric, MetricKind, Point, PointN, SparsePoint,
+    ValidationError, WeightedPoint,
+};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
 
-pub trait RegionQuery<'a> {
-    fn init(&mut self, points: &'a HashSet<&'a Point>);
-    fn run(&self, point: &'a Point, eps: f64) -> HashSet<&'a Point>;
-    fn k_dist(&self, point: &'a Point, k: usize) -> f64;
+/// Why an engine could not answer a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// The engine was queried before `init`.
+    NotInitialized,
+    /// The query point does not have the dimensionality of the indexed points.
+    DimensionMismatch {
+        /// The query point, rendered by `types::format_point`.
+        point: String,
+        expected: usize,
+        got: usize,
+    },
 }
 
-pub struct Algo<'a, T: RegionQuery<'a>> {
-    region_query: &'a mut T,
-    points: &'a HashSet<&'a Point>,
+impl QueryError {
+    pub fn dimension_mismatch<F: Float>(point: &Point<F>, expected: usize) -> Self {
+        QueryError::DimensionMismatch {
+            point: format_point(point),
+            expected,
+            got: point.len(),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::NotInitialized => write!(f, "query engine is not initialized"),
+            QueryError::DimensionMismatch {
+                point,
+                expected,
+                got,
+            } => write!(
+                f,
+                "query point {} has dimension {}, but the indexed points have dimension {}",
+                point, got, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Neighborhood queries over the point set passed to `init`.
+///
+/// Query points do not have to belong to the indexed set, but must have its
+/// dimensionality. A query point is excluded from its own k-nearest neighbors
+/// only if it is the indexed entry itself (the same reference); an indexed point
+/// that merely has the same coordinates is a neighbor at distance 0.
+pub trait RegionQuery<'a, F: Float = f64> {
+    fn init(&mut self, points: &'a HashSet<&'a Point<F>>);
+    /// Indexed points `p` with `dist(point, p) <= eps`. The bound is inclusive,
+    /// and the query point itself is part of the result when it is indexed.
+    fn run(&self, point: &Point<F>, eps: f64) -> HashSet<&'a Point<F>>;
+
+    /// Like `run`, but reports an uninitialized engine or a query point of the
+    /// wrong dimensionality as an error instead of panicking. Engines that
+    /// cannot detect these cases keep this default.
+    fn try_run(&self, point: &Point<F>, eps: f64) -> Result<HashSet<&'a Point<F>>, QueryError> {
+        Ok(self.run(point, eps))
+    }
+    /// Distance from `point` to its k-th nearest neighbor (excluding the point
+    /// itself), so at least `k` neighbors lie within it. When several neighbors
+    /// tie at that distance the value does not depend on iteration order.
+    fn k_dist(&self, point: &Point<F>, k: usize) -> f64;
+
+    /// `k_dist` together with the number of neighbors (excluding the point
+    /// itself) at most that far away. The count exceeds `k` when other
+    /// neighbors tie with the k-th one.
+    fn k_dist_with_count(&self, point: &Point<F>, k: usize) -> (f64, usize) {
+        let d = self.k_dist(point, k);
+        let mut m = k;
+        loop {
+            let nn = self.knn(point, m);
+            let count = nn.iter().take_while(|&&(_, x)| x <= d).count();
+            if count < nn.len() || nn.len() < m {
+                return (d, count);
+            }
+            m *= 2;
+        }
+    }
+
+    /// Like `run`, but only keeps neighbors for which `pred` holds.
+    fn run_filtered(
+        &self,
+        point: &Point<F>,
+        eps: f64,
+        pred: &dyn Fn(&Point<F>) -> bool,
+    ) -> HashSet<&'a Point<F>> {
+        self.run(point, eps)
+            .into_iter()
+            .filter(|p| pred(p))
+            .collect()
+    }
+
+    /// Neighbors inside the axis-aligned ellipsoid around `point` whose semi-axes
+    /// are `eps_per_dim`, i.e. sum(((x_i - q_i) / eps_i)^2) <= 1. This is the
+    /// Euclidean distance after scaling every axis, whatever metric the engine
+    /// uses otherwise.
+    fn run_aniso(&self, point: &Point<F>, eps_per_dim: &[f64]) -> HashSet<&'a Point<F>> {
+        check_aniso_eps(point, eps_per_dim);
+
+        // The ball with the largest semi-axis as radius contains the ellipsoid,
+        // if `run` is Euclidean; engines with other metrics override this.
+        let radius = eps_per_dim.iter().cloned().fold(0.0, f64::max);
+        self.run(point, radius)
+            .into_iter()
+            .filter(|p| aniso_dist_sq(point, p, eps_per_dim) <= 1.0)
+            .collect()
+    }
+
+    /// Total weight of the neighbors within `eps`. Points weigh 1 unless the
+    /// engine was constructed with weights.
+    fn weight_within(&self, point: &Point<F>, eps: f64) -> f64 {
+        self.run(point, eps).len() as f64
+    }
+
+    /// Whether the neighbors within `eps` weigh at least `threshold`. Engines
+    /// may stop walking the neighborhood as soon as the answer is known.
+    fn weight_within_at_least(&self, point: &Point<F>, eps: f64, threshold: f64) -> bool {
+        self.weight_within(point, eps) >= threshold
+    }
+
+    /// The `k` nearest neighbors of `point`, excluding the point itself, closest
+    /// first. Returns fewer than `k` entries if the dataset is too small.
+    fn knn(&self, point: &Point<F>, k: usize) -> Vec<(&'a Point<F>, f64)>;
+
+    /// Runs `run` for every query; the output is aligned with `queries`.
+    fn run_many(&self, queries: &[&Point<F>], eps: f64) -> Vec<HashSet<&'a Point<F>>> {
+        queries.iter().map(|&q| self.run(q, eps)).collect()
+    }
+
+    /// Runs `knn` for every query; the output is aligned with `queries`.
+    fn knn_many(&self, queries: &[&Point<F>], k: usize) -> Vec<Vec<(&'a Point<F>, f64)>> {
+        queries.iter().map(|&q| self.knn(q, k)).collect()
+    }
+
+    /// Rough estimate of the heap memory held by the index, in bytes.
+    fn estimated_memory_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// Marks an engine that measures distances with the metric type `M`, so that
+/// `Algo` and `k_distances` carry the metric in their types.
+///
+/// Every engine implements it for exactly one metric type, which lets the
+/// compiler infer `M`. The fake and brute-force engines take any `Metric`; the
+/// R-tree engines only `MetricKind`, as their pruning needs an Lp norm, so
+/// they cannot be given a custom metric. Boxed engines only know their metric
+/// at run time and count as `MetricKind` too.
+pub trait UsesMetric<M: Metric> {}
+
+impl<'a, F: Float> UsesMetric<MetricKind> for dyn RegionQuery<'a, F> + 'a {}
+
+/// Neighborhood filter called as `filter(center, candidate)`.
+pub type NeighborFilter<'a, F = f64> = Box<dyn Fn(&Point<F>, &Point<F>) -> bool + 'a>;
+
+/// How far a `dbscan` run has got, as passed to the `with_progress` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Points classified so far, as cluster members or (for now) noise.
+    pub processed: usize,
+    pub total: usize,
+    /// Clusters found so far, including the one being expanded.
+    pub clusters: usize,
+}
+
+/// Progress callback, see `Algo::with_progress`.
+pub type ProgressCallback<'a> = Box<dyn Fn(Progress) + 'a>;
+
+// Callbacks are throttled to about this many reports per run.
+const PROGRESS_REPORTS: usize = 1000;
+
+// Counts the points leaving `Class::Unclassified` and decides when to report.
+struct Tracker {
+    processed: usize,
+    next_report: usize,
+    step: usize,
+}
+
+// The engine is borrowed for its own lifetime `'e` so that boxed engines
+// (`Box<dyn RegionQuery<'a> + 'a>`) can be dropped after the algorithm. `F` is
+// the coordinate scalar of the points and `M` the engine's metric.
+pub struct Algo<'e, 'a, T, F: Float = f64, M: Metric = Euclidean>
+where
+    T: RegionQuery<'a, F> + UsesMetric<M> + ?Sized,
+{
+    region_query: &'e mut T,
+    points: &'a HashSet<&'a Point<F>>,
     eps: f64,
     min_pts: usize,
+    filter: Option<NeighborFilter<'a, F>>,
+    eps_per_dim: Option<Vec<f64>>,
+    min_weight: Option<f64>,
+    progress: Option<ProgressCallback<'a>>,
+    metric: PhantomData<M>,
 }
 
-impl<'a, T: RegionQuery<'a>> Algo<'a, T> {
+impl<'e, 'a, T, F: Float, M: Metric> Algo<'e, 'a, T, F, M>
+where
+    T: RegionQuery<'a, F> + UsesMetric<M> + ?Sized,
+{
     pub fn new(
-        region_query: &'a mut T,
-        points: &'a HashSet<&'a Point>,
+        region_query: &'e mut T,
+        points: &'a HashSet<&'a Point<F>>,
         eps: f64,
         min_pts: usize,
     ) -> Self {
-        region_query.init(&points);
+        region_query.init(points);
 
         Self {
             region_query,
             points,
             eps,
             min_pts,
+            filter: None,
+            eps_per_dim: None,
+            min_weight: None,
+            progress: None,
+            metric: PhantomData,
         }
     }
 
-    pub fn dbscan(&self) -> HashMap<&'a Point, Class> {
+    /// Like `new`, but first checks that every point has finite coordinates,
+    /// since NaN or infinite values break the engines' distance computations
+    /// and sorting.
+    pub fn try_new(
+        region_query: &'e mut T,
+        points: &'a HashSet<&'a Point<F>>,
+        eps: f64,
+        min_pts: usize,
+    ) -> Result<Self, ValidationError> {
+        for p in points.iter() {
+            validate_point(p)?;
+        }
+        Ok(Self::new(region_query, points, eps, min_pts))
+    }
+
+    /// Restricts neighborhoods to candidates accepted by `filter(center, candidate)`,
+    /// e.g. "same category as the center" for GDBSCAN-like clustering. The filter
+    /// should accept `filter(p, p)` so that a point counts towards its own density.
+    pub fn with_filter(mut self, filter: impl Fn(&Point<F>, &Point<F>) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Uses ellipsoidal neighborhoods with one eps per dimension instead of the
+    /// scalar `eps`. Panics if the length does not match the point dimension.
+    pub fn with_aniso_eps(mut self, eps_per_dim: Vec<f64>) -> Self {
+        if let Some(&p) = self.points.iter().next() {
+            check_aniso_eps(p, &eps_per_dim);
+        }
+        self.eps_per_dim = Some(eps_per_dim);
+        self
+    }
+
+    /// Weighted DBSCAN: a point is a core point if the neighbors within `eps`
+    /// weigh at least `min_weight` (according to the engine's weights) instead of
+    /// numbering at least `min_pts`. Cannot be combined with `with_filter` or
+    /// `with_aniso_eps`.
+    pub fn with_min_weight(mut self, min_weight: f64) -> Self {
+        self.min_weight = Some(min_weight);
+        self
+    }
+
+    /// Replaces `eps`, e.g. with one estimated by `knee_eps` from the
+    /// k-distances of the engine `new` initialized.
+    pub fn with_eps(mut self, eps: f64) -> Self {
+        self.eps = eps;
+        self
+    }
+
+    /// Calls `callback` while `dbscan` runs, about a thousand times for a large
+    /// input and once more when it is done, so that it may be moderately costly.
+    pub fn with_progress(mut self, callback: impl Fn(Progress) + 'a) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn region_query(&self) -> &T {
+        self.region_query
+    }
+
+    pub fn dbscan(&self) -> HashMap<&'a Point<F>, Class> {
+        self.dbscan_with_cores().0
+    }
+
+    /// Same as `dbscan`, additionally returning the set of core points. Every
+    /// classified point that is not a core point is a border point.
+    ///
+    /// Points are visited in coordinate order, so the result does not depend
+    /// on the hash order of the point set: clusters are numbered by their
+    /// smallest point, and a border point within reach of several clusters
+    /// joins the one numbered first. `types::number_clusters_in_order`
+    /// renumbers them by input order instead.
+    pub fn dbscan_with_cores(&self) -> (HashMap<&'a Point<F>, Class>, HashSet<&'a Point<F>>) {
+        assert!(
+            self.min_weight.is_none() || (self.filter.is_none() && self.eps_per_dim.is_none()),
+            "weighted mode cannot be combined with a neighbor filter or per-dimension eps"
+        );
+
         let mut classes = self
             .points
             .iter()
             .map(|&p| (p, Class::Unclassified))
             .collect::<HashMap<_, _>>();
 
+        let mut cores = HashSet::new();
         let mut cluster_id = 0;
+        let mut tracker = Tracker {
+            processed: 0,
+            next_report: 0,
+            step: (self.points.len() / PROGRESS_REPORTS).max(1),
+        };
 
-        for &p in self.points.iter() {
+        let mut order = self.points.iter().copied().collect::<Vec<_>>();
+        order.sort_unstable();
+        for p in order {
             match classes[p] {
                 Class::Classified(_) | Class::Noise => continue,
                 Class::Unclassified => {
-                    if self.expand_cluster(p, cluster_id, &mut classes) {
+                    if self.expand_cluster(p, cluster_id, &mut classes, &mut cores, &mut tracker) {
                         cluster_id += 1;
                     }
                 }
             }
         }
+        if let Some(callback) = &self.progress {
+            callback(Progress {
+                processed: tracker.processed,
+                total: self.points.len(),
+                clusters: cluster_id,
+            });
+        }
 
-        classes
+        (classes, cores)
+    }
+
+    fn neighbors(&self, point: &'a Point<F>) -> HashSet<&'a Point<F>> {
+        match (&self.eps_per_dim, &self.filter) {
+            (Some(eps_per_dim), Some(filter)) => self
+                .region_query
+                .run_aniso(point, eps_per_dim)
+                .into_iter()
+                .filter(|q| filter(point, q))
+                .collect(),
+            (Some(eps_per_dim), None) => self.region_query.run_aniso(point, eps_per_dim),
+            (None, Some(filter)) => self
+                .region_query
+                .run_filtered(point, self.eps, &|q| filter(point, q)),
+            (None, None) => self.region_query.run(point, self.eps),
+        }
+    }
+
+    // Records that one more point left `Class::Unclassified`.
+    fn advance(&self, tracker: &mut Tracker, clusters: usize) {
+        tracker.processed += 1;
+        let Some(callback) = &self.progress else {
+            return;
+        };
+        if tracker.processed >= tracker.next_report {
+            tracker.next_report = tracker.processed + tracker.step;
+            callback(Progress {
+                processed: tracker.processed,
+                total: self.points.len(),
+                clusters,
+            });
+        }
+    }
+
+    // The neighbors of `point` if it is a core point. In weighted mode the
+    // engine decides with its early-exit weight query, so the neighborhood is
+    // only collected for core points.
+    fn core_neighbors(&self, point: &'a Point<F>) -> Option<HashSet<&'a Point<F>>> {
+        match self.min_weight {
+            Some(min_weight) => self
+                .region_query
+                .weight_within_at_least(point, self.eps, min_weight)
+                .then(|| self.neighbors(point)),
+            None => Some(self.neighbors(point)).filter(|n| n.len() >= self.min_pts),
+        }
     }
 
     // Main DFS entrypoint.
     fn expand_cluster(
         &self,
-        point: &'a Point,
+        point: &'a Point<F>,
         cluster_id: usize,
-        classes: &mut HashMap<&'a Point, Class>,
+        classes: &mut HashMap<&'a Point<F>, Class>,
+        cores: &mut HashSet<&'a Point<F>>,
+        tracker: &mut Tracker,
     ) -> bool {
-        let neighbors = self.region_query.run(point, self.eps);
-
         // This point can't be a core point.
-        if neighbors.len() < self.min_pts {
+        let Some(neighbors) = self.core_neighbors(point) else {
             // It is marked as Noise for now, but it can be a border point later.
             if let Some(old) = classes.insert(point, Class::Noise) {
                 assert_eq!(
@@ -73,15 +414,21 @@ impl<'a, T: RegionQuery<'a>> Algo<'a, T> {
                     "The entry should be unclassified here."
                 );
             }
+            self.advance(tracker, cluster_id);
             return false;
-        }
+        };
 
         // This point is a core point of a cluster {cluster_id}.
+        cores.insert(point);
 
         // Mark neighbors that are currently unassigned/noise as classified.
         for &p in neighbors.iter() {
             match classes[p] {
-                Class::Unclassified | Class::Noise => {
+                Class::Unclassified => {
+                    classes.insert(p, Class::Classified(cluster_id));
+                    self.advance(tracker, cluster_id + 1);
+                }
+                Class::Noise => {
                     classes.insert(p, Class::Classified(cluster_id));
                 }
                 Class::Classified(_) => {
@@ -95,10 +442,10 @@ impl<'a, T: RegionQuery<'a>> Algo<'a, T> {
         // Sub loop to expand the cluster.
         while !set.is_empty() {
             let current_point = *set.iter().next().unwrap();
-            let neighbors = self.region_query.run(current_point, self.eps);
 
             // If current_point is a core point.
-            if neighbors.len() >= self.min_pts {
+            if let Some(neighbors) = self.core_neighbors(current_point) {
+                cores.insert(current_point);
                 for &p in neighbors.iter() {
                     match classes[p] {
                         Class::Classified(_cid) => {
@@ -109,6 +456,7 @@ impl<'a, T: RegionQuery<'a>> Algo<'a, T> {
                             // Check neighbors of this point recursively.
                             set.insert(p);
                             classes.insert(p, Class::Classified(cluster_id));
+                            self.advance(tracker, cluster_id + 1);
                         }
                         Class::Noise => {
                             // Include as border point.
@@ -123,3 +471,1153 @@ impl<'a, T: RegionQuery<'a>> Algo<'a, T> {
         true
     }
 }
+
+/// DBSCAN over points that may share coordinates, returning one class per
+/// point keyed by `LabeledPoint::id`.
+///
+/// Each distinct coordinate is indexed once, weighted by the number of points
+/// at it, so every duplicate still counts towards the density of its
+/// neighbors. Points with equal coordinates have the same neighborhood and
+/// therefore always end up with the same class.
+pub fn dbscan_labeled(
+    points: &[LabeledPoint],
+    eps: f64,
+    min_pts: usize,
+    engine: EngineKind,
+) -> HashMap<usize, Class> {
+    let unique = points.iter().map(|p| &p.coords).collect::<HashSet<_>>();
+    let mut weights = HashMap::new();
+    for p in points.iter() {
+        *weights.entry(&p.coords).or_insert(0.0) += 1.0;
+    }
+
+    let mut region_query = engine.build_weighted(weights);
+    let classes = Algo::new(region_query.as_mut(), &unique, eps, min_pts)
+        .with_min_weight(min_pts as f64)
+        .dbscan();
+
+    points.iter().map(|p| (p.id, classes[&p.coords])).collect()
+}
+
+/// Weighted DBSCAN: a point is a core point if the points within `eps` weigh
+/// at least `min_weight` in total. A point of weight `w` counts like `w` unit
+/// points at its coordinates. Returns one class per point, in input order;
+/// points with equal coordinates share their weight and their class.
+pub fn dbscan_weighted(
+    points: &[WeightedPoint],
+    eps: f64,
+    min_weight: f64,
+    engine: EngineKind,
+) -> Vec<Class> {
+    let unique = points.iter().map(|p| &p.coords).collect::<HashSet<_>>();
+
+    let mut region_query = engine.build_weighted(weight_map(points));
+    let classes = Algo::new(region_query.as_mut(), &unique, eps, 0)
+        .with_min_weight(min_weight)
+        .dbscan();
+
+    points.iter().map(|p| classes[&p.coords]).collect()
+}
+
+/// Distance from each of `points` to its `k`-th nearest neighbor, excluding
+/// itself, as found by the initialized `engine`; aligned with `points`. A point
+/// with fewer than `k` other points takes the distance to the farthest one.
+/// The distances are measured with the engine's metric `M`. Panics if `k` is 0.
+pub fn k_distances<'a, E, F: Float, M: Metric>(
+    engine: &E,
+    points: &[&Point<F>],
+    k: usize,
+) -> Vec<f64>
+where
+    E: RegionQuery<'a, F> + UsesMetric<M> + ?Sized,
+{
+    assert!(k > 0, "k must be >= 1");
+    engine
+        .knn_many(points, k)
+        .into_iter()
+        .map(|neighbors| neighbors.last().map_or(0.0, |&(_, d)| d))
+        .collect()
+}
+
+/// Estimates `eps` as the knee of the sorted k-distance curve (see
+/// `k_distances`; `k = min_pts` is the usual choice). Points in clusters have
+/// small k-distances that grow slowly; the curve bends up sharply at noise.
+///
+/// Uses the Kneedle method: with both axes scaled to [0, 1], the knee is the
+/// point that falls furthest below the straight line from the smallest to the
+/// largest k-distance. Non-finite values are ignored; `None` if nothing is
+/// left.
+pub fn knee_eps(k_distances: &[f64]) -> Option<f64> {
+    let mut sorted = k_distances
+        .iter()
+        .copied()
+        .filter(|d| d.is_finite())
+        .collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+    let (&lo, &hi) = (sorted.first()?, sorted.last()?);
+    if hi <= lo {
+        return Some(hi);
+    }
+    let n = (sorted.len() - 1) as f64;
+    let knee = (0..sorted.len())
+        .max_by(|&a, &b| {
+            let below = |i: usize| i as f64 / n - (sorted[i] - lo) / (hi - lo);
+            below(a).total_cmp(&below(b))
+        })
+        .unwrap();
+    Some(sorted[knee])
+}
+
+/// DBSCAN over points of compile-time dimension `D`, using
+/// `BruteForceQueryEngine::from_static`. Returns the class of every point, aligned
+/// with `points`. Points are identified by index, so duplicates count towards
+/// density separately, as with `dbscan_labeled`.
+pub fn dbscan_static<const D: usize>(points: &[PointN<D>], eps: f64, min_pts: usize) -> Vec<Class> {
+    let engine = BruteForceQueryEngine::from_static(points);
+    dbscan_indexed(points.len(), min_pts, |i| {
+        engine.run_rows(&points[i].0, eps)
+    })
+}
+
+/// DBSCAN over sparse points, using `SparseBruteForceQueryEngine`. Like
+/// `dbscan_static`, returns classes aligned with `points`.
+pub fn dbscan_sparse(points: &[SparsePoint], eps: f64, min_pts: usize) -> Vec<Class> {
+    let engine = SparseBruteForceQueryEngine::new(points);
+    dbscan_indexed(points.len(), min_pts, |i| engine.run(&points[i], eps))
+}
+
+/// DBSCAN over the rows of a `CloudView` (e.g. `PointCloud::view`), using
+/// `BruteForceQueryEngine::from_view`, without copying them. Like
+/// `dbscan_static`, returns classes aligned with the rows.
+pub fn dbscan_cloud(cloud: CloudView<'_>, eps: f64, min_pts: usize) -> Vec<Class> {
+    let engine = BruteForceQueryEngine::from_view(cloud);
+    dbscan_indexed(cloud.len(), min_pts, |i| engine.run_rows(cloud.row(i), eps))
+}
+
+/// DBSCAN over binary vectors with Hamming distance, using
+/// `BitBruteForceQueryEngine`: points at most `eps` differing bits apart are
+/// neighbors. Returns classes aligned with `points`.
+pub fn dbscan_bits(points: &[BitPoint], eps: u32, min_pts: usize) -> Vec<Class> {
+    let engine = BitBruteForceQueryEngine::new(points);
+    dbscan_indexed(points.len(), min_pts, |i| engine.run(&points[i], eps))
+}
+
+// DBSCAN over `n` points identified by index. `neighbors(i)` lists the points
+// within eps of point `i`, including `i` itself.
+fn dbscan_indexed(n: usize, min_pts: usize, neighbors: impl Fn(usize) -> Vec<usize>) -> Vec<Class> {
+    let mut classes = vec![Class::Unclassified; n];
+    let mut cluster_id = 0;
+
+    for i in 0..n {
+        if classes[i] != Class::Unclassified {
+            continue;
+        }
+        let neighbors_i = neighbors(i);
+        if neighbors_i.len() < min_pts {
+            // It can still become a border point of a later cluster.
+            classes[i] = Class::Noise;
+            continue;
+        }
+
+        let mut stack = Vec::new();
+        for j in neighbors_i {
+            match classes[j] {
+                Class::Unclassified => {
+                    classes[j] = Class::Classified(cluster_id);
+                    if j != i {
+                        stack.push(j);
+                    }
+                }
+                Class::Noise => classes[j] = Class::Classified(cluster_id),
+                Class::Classified(_) => {}
+            }
+        }
+        while let Some(j) = stack.pop() {
+            let neighbors_j = neighbors(j);
+            if neighbors_j.len() < min_pts {
+                continue;
+            }
+            for k in neighbors_j {
+                match classes[k] {
+                    Class::Unclassified => {
+                        classes[k] = Class::Classified(cluster_id);
+                        stack.push(k);
+                    }
+                    Class::Noise => classes[k] = Class::Classified(cluster_id),
+                    Class::Classified(_) => {}
+                }
+            }
+        }
+        cluster_id += 1;
+    }
+
+    classes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brute_query::BruteForceQueryEngine;
+    use crate::fake_query::FakeQueryEngine;
+    use crate::query::RTreeQueryEngine;
+    use ordered_float::OrderedFloat;
+    use std::cell::Cell;
+
+    fn pt(x: f64) -> Point {
+        vec![OrderedFloat(x), OrderedFloat(0.0)]
+    }
+
+    // Counts the full neighborhood queries made through `run`.
+    struct CountingRuns<'a, E> {
+        inner: E,
+        runs: Cell<usize>,
+        _points: PhantomData<&'a ()>,
+    }
+
+    impl<M: Metric, E: UsesMetric<M>> UsesMetric<M> for CountingRuns<'_, E> {}
+
+    impl<'a, E: RegionQuery<'a>> RegionQuery<'a> for CountingRuns<'a, E> {
+        fn init(&mut self, points: &'a HashSet<&'a Point>) {
+            self.inner.init(points);
+        }
+
+        fn run(&self, point: &Point, eps: f64) -> HashSet<&'a Point> {
+            self.runs.set(self.runs.get() + 1);
+            self.inner.run(point, eps)
+        }
+
+        fn k_dist(&self, point: &Point, k: usize) -> f64 {
+            self.inner.k_dist(point, k)
+        }
+
+        fn weight_within_at_least(&self, point: &Point, eps: f64, threshold: f64) -> bool {
+            self.inner.weight_within_at_least(point, eps, threshold)
+        }
+
+        fn knn(&self, point: &Point, k: usize) -> Vec<(&'a Point, f64)> {
+            self.inner.knn(point, k)
+        }
+    }
+
+    // `a` and `b` are neighbors at eps 1.5, `c` is on its own.
+    fn line() -> Vec<Point> {
+        vec![pt(0.0), pt(1.0), pt(10.0)]
+    }
+
+    #[test]
+    fn clusters_are_numbered_by_their_smallest_point() {
+        let points = [pt(10.0), pt(11.0), pt(5.0), pt(0.0), pt(1.0)];
+        // Every set hashes with new keys, so the iteration orders differ.
+        for _ in 0..8 {
+            let refs = points.iter().collect::<HashSet<_>>();
+            let mut engine = BruteForceQueryEngine::new();
+            let classes = Algo::new(&mut engine, &refs, 1.5, 2).dbscan();
+            assert_eq!(classes[&pt(0.0)], Class::Classified(0));
+            assert_eq!(classes[&pt(1.0)], Class::Classified(0));
+            assert_eq!(classes[&pt(10.0)], Class::Classified(1));
+            assert_eq!(classes[&pt(11.0)], Class::Classified(1));
+            assert_eq!(classes[&pt(5.0)], Class::Noise);
+        }
+    }
+
+    #[test]
+    fn weight_makes_a_point_core() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+
+        // Counted, the pair has 2 < 3 points: everything is noise.
+        let mut engine = RTreeQueryEngine::new();
+        let classes = Algo::new(&mut engine, &refs, 1.5, 3).dbscan();
+        assert!(classes.values().all(|&c| c == Class::Noise));
+
+        // `b` weighs 2, so both neighborhoods reach weight 3.
+        for kind in [EngineKind::Fake, EngineKind::RTree, EngineKind::Brute] {
+            let weights = HashMap::from([(&points[1], 2.0)]);
+            let mut engine = kind.build_weighted(weights);
+            let classes = Algo::new(engine.as_mut(), &refs, 1.5, 3)
+                .with_min_weight(3.0)
+                .dbscan();
+            assert_eq!(classes[&points[0]], Class::Classified(0), "{}", kind);
+            assert_eq!(classes[&points[1]], Class::Classified(0), "{}", kind);
+            assert_eq!(classes[&points[2]], Class::Noise, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn weight_below_count_makes_a_point_noise() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+
+        let mut engine = FakeQueryEngine::new();
+        let classes = Algo::new(&mut engine, &refs, 1.5, 2).dbscan();
+        assert_eq!(classes[&points[0]], Class::Classified(0));
+
+        // Two points, but only weight 1.5 together.
+        let weights = HashMap::from([(&points[0], 0.5), (&points[1], 1.0)]);
+        let mut engine = BruteForceQueryEngine::with_weights(weights);
+        let classes = Algo::new(&mut engine, &refs, 1.5, 2)
+            .with_min_weight(2.0)
+            .dbscan();
+        assert!(classes.values().all(|&c| c == Class::Noise));
+    }
+
+    #[test]
+    fn weighted_mode_collects_neighborhoods_of_core_points_only() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let weights = HashMap::from([(&points[1], 2.0)]);
+        let mut engine = CountingRuns {
+            inner: RTreeQueryEngine::with_weights(weights),
+            runs: Cell::new(0),
+            _points: PhantomData,
+        };
+        let (_, cores) = Algo::new(&mut engine, &refs, 1.5, 3)
+            .with_min_weight(3.0)
+            .dbscan_with_cores();
+        assert_eq!(cores.len(), 2);
+        assert_eq!(engine.runs.get(), cores.len());
+    }
+
+    // Labels renamed in order of first appearance.
+    fn canonical(labels: impl IntoIterator<Item = Class>) -> Vec<Class> {
+        let mut ids = HashMap::new();
+        labels
+            .into_iter()
+            .map(|c| match c {
+                Class::Classified(id) => {
+                    let next = ids.len();
+                    Class::Classified(*ids.entry(id).or_insert(next))
+                }
+                other => other,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn static_and_dynamic_paths_give_the_same_labels() {
+        let mut rng = crate::sampling::SplitMix64::new(347);
+        // Tight blobs far apart, so no point borders two clusters, rows
+        // repeated to test that duplicates count separately on both paths,
+        // and scattered points that end up as noise.
+        let mut rows = Vec::new();
+        for center in [0.0, 10.0, 20.0] {
+            for _ in 0..6 {
+                let row: [f64; 3] = std::array::from_fn(|_| center + rng.below(10) as f64 / 20.0);
+                let copies = 1 + rng.below(2) as usize;
+                rows.extend(std::iter::repeat_n(row, copies));
+            }
+        }
+        rows.extend((0..5).map(|i| [40.0 + 5.0 * i as f64, 0.0, -3.0]));
+        rows.push([0.0, 20.0, 10.0]);
+        rows.push([0.0, 20.0, 10.0]);
+
+        let static_points = rows.iter().copied().map(PointN::from).collect::<Vec<_>>();
+        let labeled =
+            crate::types::label_points(rows.iter().map(|&r| PointN::from(r).into()).collect());
+        for min_pts in [2, 3, 6] {
+            let expected = dbscan_static(&static_points, 1.0, min_pts);
+            assert!(expected.contains(&Class::Noise));
+            for engine in [EngineKind::Fake, EngineKind::RTree, EngineKind::Brute] {
+                let classes = dbscan_labeled(&labeled, 1.0, min_pts, engine);
+                let dynamic = labeled.iter().map(|p| classes[&p.id]);
+                assert_eq!(
+                    canonical(dynamic),
+                    canonical(expected.iter().copied()),
+                    "{} at min_pts {}",
+                    engine,
+                    min_pts
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_points_act_like_duplicated_rows() {
+        use crate::types::WeightedPoint;
+
+        let mut rng = crate::sampling::SplitMix64::new(359);
+        let mut weighted = Vec::new();
+        let mut rows = Vec::new();
+        for _ in 0..60 {
+            let row = [0, 1].map(|_| rng.below(24) as f64 / 2.0);
+            let weight = 1 + rng.below(3) as usize;
+            let coords = row.iter().copied().map(OrderedFloat).collect();
+            weighted.push(WeightedPoint::new(coords, weight as f64).unwrap());
+            rows.extend(std::iter::repeat_n(row, weight));
+        }
+        let static_points = rows.iter().copied().map(PointN::from).collect::<Vec<_>>();
+        for min_pts in [2, 4, 7] {
+            // Row `k` of `rows` is the first copy of the `k`-th weighted point.
+            let duplicated = dbscan_static(&static_points, 1.0, min_pts);
+            let mut first = Vec::new();
+            let mut row = 0;
+            for p in &weighted {
+                first.push(duplicated[row]);
+                row += p.weight as usize;
+            }
+            assert!(first.contains(&Class::Noise));
+            assert!(first.contains(&Class::Classified(1)));
+            for engine in [EngineKind::Fake, EngineKind::RTree, EngineKind::Brute] {
+                let classes = dbscan_weighted(&weighted, 1.0, min_pts as f64, engine);
+                assert_eq!(
+                    canonical(classes),
+                    canonical(first.iter().copied()),
+                    "{} at min_pts {}",
+                    engine,
+                    min_pts
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lp_metrics_cluster_alike_on_every_engine() {
+        let mut rng = crate::sampling::SplitMix64::new(339);
+        let points = (0..120)
+            .map(|_| {
+                let mut c = || OrderedFloat(rng.below(40) as f64 / 4.0);
+                vec![c(), c()]
+            })
+            .collect::<HashSet<Point>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let labels = |classes: HashMap<&Point, Class>| canonical(points.iter().map(|p| classes[p]));
+        // From the smallest neighborhoods to the largest.
+        let metrics = [
+            MetricKind::Manhattan,
+            MetricKind::Euclidean,
+            MetricKind::Minkowski(3.0),
+            MetricKind::Chebyshev,
+        ];
+        let mut noise = Vec::new();
+        for metric in metrics {
+            let mut fake = FakeQueryEngine::with_metric(metric);
+            let expected = labels(Algo::new(&mut fake, &refs, 0.8, 4).dbscan());
+            let mut brute = BruteForceQueryEngine::with_metric(metric);
+            let brute = labels(Algo::new(&mut brute, &refs, 0.8, 4).dbscan());
+            assert_eq!(brute, expected, "{:?}", metric);
+            let mut rtree = RTreeQueryEngine::with_metric(metric);
+            let rtree = labels(Algo::new(&mut rtree, &refs, 0.8, 4).dbscan());
+            assert_eq!(rtree, expected, "{:?}", metric);
+            noise.push(expected.iter().filter(|&&c| c == Class::Noise).count());
+        }
+        // Larger neighborhoods leave fewer points as noise.
+        assert!(noise.windows(2).all(|w| w[0] >= w[1]), "{:?}", noise);
+        assert!(noise[0] > noise[3], "{:?}", noise);
+    }
+
+    #[test]
+    fn cosine_clusters_like_euclidean_on_normalized_points() {
+        let mut rng = crate::sampling::SplitMix64::new(340);
+        // Directions around three axes, no two of them parallel.
+        let mut directions = HashSet::new();
+        let mut points = Vec::new();
+        while points.len() < 60 {
+            let axis = rng.below(3) as usize;
+            let mut coords = [0, 1, 2].map(|_| rng.below(9) as f64 - 4.0);
+            coords[axis] = 40.0 + rng.below(30) as f64;
+            let scale = 1.0 + rng.below(4) as f64;
+            let mut unit = vec![coords.map(OrderedFloat).to_vec()];
+            crate::types::normalize_l2(&mut unit);
+            if directions.insert(unit.pop().unwrap()) {
+                points.push(coords.map(|x| OrderedFloat(x * scale)).to_vec());
+            }
+        }
+        let mut normalized = points.clone();
+        crate::types::normalize_l2(&mut normalized);
+
+        let refs = points.iter().collect::<HashSet<_>>();
+        let unit_refs = normalized.iter().collect::<HashSet<_>>();
+        // From mostly noise to three clusters and no noise.
+        for eps in [0.0002, 0.0005, 0.001, 0.01] {
+            let mut cosine = BruteForceQueryEngine::with_metric(MetricKind::Cosine);
+            let classes = Algo::new(&mut cosine, &refs, eps, 3).dbscan();
+            let mut euclid = BruteForceQueryEngine::new();
+            let unit = Algo::new(&mut euclid, &unit_refs, (2.0 * eps).sqrt(), 3).dbscan();
+            assert_eq!(
+                canonical(points.iter().map(|p| classes[p])),
+                canonical(normalized.iter().map(|p| unit[p])),
+                "eps {}",
+                eps
+            );
+        }
+    }
+
+    #[test]
+    fn angular_eps_in_radians_clusters_like_chords_on_the_sphere() {
+        use crate::types::{angular_to_chord, project_to_unit_sphere};
+
+        let mut rng = crate::sampling::SplitMix64::new(361);
+        // Directions around three axes, no two of them parallel, at various
+        // lengths, and a zero row.
+        let mut directions = HashSet::new();
+        let mut points = Vec::new();
+        while points.len() < 60 {
+            let axis = rng.below(3) as usize;
+            let mut coords = [0, 1, 2].map(|_| rng.below(9) as f64 - 4.0);
+            coords[axis] = 40.0 + rng.below(30) as f64;
+            let scale = 1.0 + rng.below(4) as f64;
+            let mut unit = vec![coords.map(OrderedFloat).to_vec()];
+            crate::types::normalize_l2(&mut unit);
+            if directions.insert(unit.pop().unwrap()) {
+                points.push(coords.map(|x| OrderedFloat(x * scale)).to_vec());
+            }
+        }
+        points.insert(7, vec![OrderedFloat(0.0); 3]);
+        let mut unit = points.clone();
+        assert_eq!(project_to_unit_sphere(&mut unit), [7]);
+        let unit_refs = unit.iter().collect::<HashSet<_>>();
+
+        let refs = points.iter().collect::<HashSet<_>>();
+        // From mostly noise to three clusters and no noise.
+        for theta in [0.01, 0.02, 0.05, 0.2] {
+            let mut angular = FakeQueryEngine::with_metric(MetricKind::Angular);
+            let classes = Algo::new(&mut angular, &refs, theta, 3).dbscan();
+            assert_eq!(classes[&points[7]], Class::Noise);
+            let mut euclid = BruteForceQueryEngine::new();
+            let chords = Algo::new(&mut euclid, &unit_refs, angular_to_chord(theta), 3).dbscan();
+            let mut projected = points.iter().map(|p| classes[p]).collect::<Vec<_>>();
+            projected.remove(7);
+            assert_eq!(
+                canonical(projected),
+                canonical(unit.iter().map(|p| chords[p])),
+                "theta {}",
+                theta
+            );
+        }
+    }
+
+    #[test]
+    fn haversine_clusters_places_across_the_antimeridian() {
+        let place = |lat: f64, lon: f64| vec![OrderedFloat(lat), OrderedFloat(lon)];
+        let points = [
+            // Around Paris, 6 to 14 km apart.
+            place(48.8566, 2.3522),
+            place(48.90, 2.40),
+            place(48.80, 2.30),
+            // Three places 6 to 11 km apart, across the antimeridian.
+            place(-17.0, 179.95),
+            place(-17.0, -179.95),
+            place(-17.05, 179.98),
+            // London, 340 km from Paris, alone.
+            place(51.5074, -0.1278),
+        ];
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut fake = FakeQueryEngine::with_metric(MetricKind::haversine_km());
+        let classes = Algo::new(&mut fake, &refs, 20.0, 3).dbscan();
+        let labels = canonical(points.iter().map(|p| classes[p]));
+        let (a, b) = (Class::Classified(0), Class::Classified(1));
+        assert_eq!(labels, [a, a, a, b, b, b, Class::Noise]);
+        let mut brute = BruteForceQueryEngine::with_metric(MetricKind::haversine_km());
+        let classes = Algo::new(&mut brute, &refs, 20.0, 3).dbscan();
+        assert_eq!(canonical(points.iter().map(|p| classes[p])), labels);
+
+        // The same places in miles need a radius in miles and eps in miles.
+        let miles = MetricKind::Haversine(3958.7613);
+        let mut brute = BruteForceQueryEngine::with_metric(miles);
+        let classes = Algo::new(&mut brute, &refs, 20.0 / 1.609344, 3).dbscan();
+        assert_eq!(canonical(points.iter().map(|p| classes[p])), labels);
+    }
+
+    #[test]
+    fn mahalanobis_merges_rows_across_a_wide_axis() {
+        use crate::types::MahalanobisMetric;
+
+        // Two rows of points 1 apart, 1.5 from each other.
+        let points = (0..10)
+            .flat_map(|i| {
+                [
+                    pt(i as f64),
+                    vec![OrderedFloat(i as f64), OrderedFloat(1.5)],
+                ]
+            })
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let clusters = |classes: HashMap<&Point, Class>| {
+            canonical(points.iter().map(|p| classes[p]))
+                .into_iter()
+                .collect::<HashSet<_>>()
+                .len()
+        };
+        let mut euclid = BruteForceQueryEngine::new();
+        assert_eq!(clusters(Algo::new(&mut euclid, &refs, 1.2, 3).dbscan()), 2);
+
+        // A variance of 4 along y halves the distance between the rows.
+        let metric = || {
+            MahalanobisMetric::from_inverse_covariance(vec![vec![1.0, 0.0], vec![0.0, 0.25]])
+                .unwrap()
+        };
+        let mut brute = BruteForceQueryEngine::with_metric(metric());
+        assert_eq!(clusters(Algo::new(&mut brute, &refs, 1.2, 3).dbscan()), 1);
+        let mut fake = FakeQueryEngine::with_metric(metric());
+        assert_eq!(clusters(Algo::new(&mut fake, &refs, 1.2, 3).dbscan()), 1);
+    }
+
+    #[test]
+    fn gower_groups_rows_by_their_category() {
+        use crate::types::GowerMetric;
+
+        // Points 1 apart along x whose category alternates.
+        let points = (0..20)
+            .map(|i| vec![OrderedFloat(i as f64), OrderedFloat((i % 2) as f64)])
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut euclid = BruteForceQueryEngine::new();
+        let classes = Algo::new(&mut euclid, &refs, 1.5, 3).dbscan();
+        assert!(points.iter().all(|p| classes[p] == Class::Classified(0)));
+
+        // Same-category neighbors are (2 / 19) / 2 apart, the others over 1/2.
+        let metric = || GowerMetric::fit(&points, &[1]).unwrap();
+        let expected = canonical((0..20).map(|i| Class::Classified(i % 2)));
+        let mut brute = BruteForceQueryEngine::with_metric(metric());
+        let classes = Algo::new(&mut brute, &refs, 0.06, 3).dbscan();
+        assert_eq!(canonical(points.iter().map(|p| classes[p])), expected);
+        let mut fake = FakeQueryEngine::with_metric(metric());
+        let classes = Algo::new(&mut fake, &refs, 0.06, 3).dbscan();
+        assert_eq!(canonical(points.iter().map(|p| classes[p])), expected);
+    }
+
+    // Points on a grid of quarters in the plane, each in category 0 or 1 given
+    // by its last coordinate, one unit away from the other category.
+    fn categorized(seed: u64, n: usize) -> Vec<Point> {
+        let mut rng = crate::sampling::SplitMix64::new(seed);
+        (0..n)
+            .map(|_| {
+                let mut c = || OrderedFloat(rng.below(40) as f64 / 4.0);
+                vec![c(), c(), OrderedFloat(rng.below(2) as f64)]
+            })
+            .collect::<HashSet<Point>>()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn filtered_queries_match_filtering_afterwards() {
+        let points = categorized(322, 300);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let last_is_one = |p: &Point| p[2].0 == 1.0;
+        let mut fake = FakeQueryEngine::new();
+        fake.init(&refs);
+        let mut brute = BruteForceQueryEngine::new();
+        brute.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+
+        let engines: [(&str, &dyn RegionQuery); 3] =
+            [("fake", &fake), ("brute", &brute), ("rtree", &rtree)];
+        for (name, engine) in engines {
+            for p in points.iter() {
+                for eps in [0.5, 1.25, 2.0] {
+                    let expected = engine
+                        .run(p, eps)
+                        .into_iter()
+                        .filter(|q| last_is_one(q))
+                        .collect::<HashSet<_>>();
+                    assert_eq!(
+                        engine.run_filtered(p, eps, &last_is_one),
+                        expected,
+                        "{} {:?} at eps {}",
+                        name,
+                        p,
+                        eps
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_dbscan_clusters_each_category_on_its_own() {
+        let points = categorized(323, 300);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let same_category = |p: &Point, q: &Point| p[2] == q[2];
+
+        // The reference clusters every category separately.
+        let mut expected = HashMap::new();
+        for category in [0.0, 1.0] {
+            let subset = refs
+                .iter()
+                .copied()
+                .filter(|p| p[2].0 == category)
+                .collect::<HashSet<_>>();
+            let mut engine = BruteForceQueryEngine::new();
+            for (p, class) in Algo::new(&mut engine, &subset, 1.25, 4).dbscan() {
+                let class = match class {
+                    Class::Classified(id) => Class::Classified(2 * id + category as usize),
+                    other => other,
+                };
+                expected.insert(p.clone(), class);
+            }
+        }
+        let expected = canonical(points.iter().map(|p| expected[p]));
+
+        let labels = |classes: HashMap<&Point, Class>| canonical(points.iter().map(|p| classes[p]));
+        let mut fake = FakeQueryEngine::new();
+        let filtered = Algo::new(&mut fake, &refs, 1.25, 4).with_filter(same_category);
+        assert_eq!(labels(filtered.dbscan()), expected, "fake");
+        let mut rtree = RTreeQueryEngine::new();
+        let filtered = Algo::new(&mut rtree, &refs, 1.25, 4).with_filter(same_category);
+        assert_eq!(labels(filtered.dbscan()), expected, "rtree");
+
+        // Without the filter, neighborhoods reach into the other category.
+        let mut brute = BruteForceQueryEngine::new();
+        assert_ne!(
+            labels(Algo::new(&mut brute, &refs, 1.25, 4).dbscan()),
+            expected
+        );
+    }
+
+    #[test]
+    fn engines_answer_queries_for_points_they_did_not_index() {
+        let mut rng = crate::sampling::SplitMix64::new(325);
+        let mut grid = || {
+            let mut c = || OrderedFloat(rng.below(40) as f64 / 4.0);
+            vec![c(), c()]
+        };
+        let points = (0..150).map(|_| grid()).collect::<HashSet<Point>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let fresh = (0..50)
+            .map(|_| grid())
+            .filter(|p| !points.contains(p))
+            .collect::<Vec<_>>();
+        // Same coordinates as indexed points, but other references.
+        let copies = points.iter().take(10).cloned().collect::<Vec<_>>();
+
+        let mut fake = FakeQueryEngine::new();
+        fake.init(&refs);
+        let mut lazy = FakeQueryEngine::lazy(4);
+        lazy.init(&refs);
+        let mut brute = BruteForceQueryEngine::new();
+        brute.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+        let engines: [(&str, &dyn RegionQuery); 4] = [
+            ("fake", &fake),
+            ("lazy", &lazy),
+            ("brute", &brute),
+            ("rtree", &rtree),
+        ];
+
+        for q in fresh.iter().chain(&copies) {
+            let mut dists = points
+                .iter()
+                .map(|p| crate::types::dist(q, p))
+                .collect::<Vec<_>>();
+            dists.sort_by(f64::total_cmp);
+            for (name, engine) in engines {
+                for eps in [0.25, 1.0, 2.5] {
+                    let expected = points
+                        .iter()
+                        .filter(|p| crate::types::dist(q, p) <= eps)
+                        .collect::<HashSet<_>>();
+                    assert_eq!(engine.run(q, eps), expected, "{} {:?}", name, q);
+                }
+                // Nothing is left out as the query itself, not even the
+                // indexed copy at distance 0.
+                for k in [1, 3, 8] {
+                    assert_eq!(engine.k_dist(q, k), dists[k - 1], "{} {:?}", name, q);
+                    let knn = engine.knn(q, k).iter().map(|&(_, d)| d).collect::<Vec<_>>();
+                    assert_eq!(knn, dists[..k], "{} {:?}", name, q);
+                }
+            }
+        }
+        assert!(copies.iter().all(|q| rtree.k_dist(q, 1) == 0.0));
+    }
+
+    #[test]
+    fn ellipsoidal_queries_match_brute_force() {
+        let points = categorized(326, 300);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut fake = FakeQueryEngine::new();
+        fake.init(&refs);
+        let mut brute = BruteForceQueryEngine::new();
+        brute.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+        let engines: [(&str, &dyn RegionQuery); 3] =
+            [("fake", &fake), ("brute", &brute), ("rtree", &rtree)];
+
+        for axes in [[0.5, 2.0, 1.0], [1.25, 0.25, 0.5], [1.0, 1.0, 1.0]] {
+            for q in points.iter() {
+                let expected = points
+                    .iter()
+                    .filter(|p| {
+                        let sum = (0..3)
+                            .map(|i| ((p[i].0 - q[i].0) / axes[i]).powi(2))
+                            .sum::<f64>();
+                        sum <= 1.0
+                    })
+                    .collect::<HashSet<_>>();
+                for (name, engine) in engines {
+                    assert_eq!(
+                        engine.run_aniso(q, &axes),
+                        expected,
+                        "{} {:?} {:?}",
+                        name,
+                        q,
+                        axes
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ellipsoidal_queries_ignore_the_metric() {
+        let points = categorized(339, 300);
+        let refs = points.iter().collect::<HashSet<_>>();
+        // (1, 1, 0) away is inside, but outside the Manhattan ball of 1.5.
+        let axes = [1.5, 1.5, 0.5];
+        let mut euclidean = BruteForceQueryEngine::new();
+        euclidean.init(&refs);
+        for metric in [
+            MetricKind::Manhattan,
+            MetricKind::Chebyshev,
+            MetricKind::Minkowski(3.0),
+            MetricKind::Cosine,
+        ] {
+            let mut fake = FakeQueryEngine::with_metric(metric);
+            fake.init(&refs);
+            let mut brute = BruteForceQueryEngine::with_metric(metric);
+            brute.init(&refs);
+            let mut engines: Vec<(&str, &dyn RegionQuery)> =
+                vec![("fake", &fake), ("brute", &brute)];
+            // The R-tree only takes Lp metrics.
+            let mut rtree;
+            if metric != MetricKind::Cosine {
+                rtree = RTreeQueryEngine::with_metric(metric);
+                rtree.init(&refs);
+                engines.push(("rtree", &rtree));
+            }
+            for q in points.iter() {
+                let expected = euclidean.run_aniso(q, &axes);
+                for &(name, engine) in &engines {
+                    assert_eq!(
+                        engine.run_aniso(q, &axes),
+                        expected,
+                        "{} {:?} {:?}",
+                        name,
+                        metric,
+                        q
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ellipsoidal_dbscan_clusters_like_scaled_points() {
+        let points = categorized(327, 300);
+        let refs = points.iter().collect::<HashSet<_>>();
+        // Powers of two, so that scaling the points is exact.
+        let axes = vec![0.5, 2.0, 4.0];
+        let scaled = points
+            .iter()
+            .map(|p| (0..3).map(|i| OrderedFloat(p[i].0 / axes[i])).collect())
+            .collect::<Vec<Point>>();
+        let scaled_refs = scaled.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        let classes = Algo::new(&mut engine, &scaled_refs, 1.0, 4).dbscan();
+        let expected = canonical(scaled.iter().map(|p| classes[p]));
+        assert!(expected.contains(&Class::Noise));
+
+        let labels = |classes: HashMap<&Point, Class>| canonical(points.iter().map(|p| classes[p]));
+        let mut fake = FakeQueryEngine::new();
+        let algo = Algo::new(&mut fake, &refs, 1.0, 4).with_aniso_eps(axes.clone());
+        assert_eq!(labels(algo.dbscan()), expected, "fake");
+        let mut rtree = RTreeQueryEngine::new();
+        let algo = Algo::new(&mut rtree, &refs, 1.0, 4).with_aniso_eps(axes.clone());
+        assert_eq!(labels(algo.dbscan()), expected, "rtree");
+    }
+
+    #[test]
+    fn ellipsoidal_eps_needs_one_positive_axis_per_dimension() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let message = |f: Box<dyn FnOnce() + '_>| {
+            let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_err();
+            match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_default(),
+            }
+        };
+
+        let mut engine = RTreeQueryEngine::new();
+        engine.init(&refs);
+        assert!(message(Box::new(|| {
+            engine.run_aniso(&points[0], &[1.0, 1.0, 1.0]);
+        }))
+        .contains("eps vector length 3 does not match point dimension 2"));
+        assert!(message(Box::new(|| {
+            engine.run_aniso(&points[0], &[1.0, 0.0]);
+        }))
+        .contains("every per-dimension eps must be positive"));
+
+        let mut fake = FakeQueryEngine::new();
+        assert!(message(Box::new(|| {
+            let _ = Algo::new(&mut fake, &refs, 1.0, 2).with_aniso_eps(vec![1.0]);
+        }))
+        .contains("eps vector length 1 does not match point dimension 2"));
+    }
+
+    #[test]
+    fn k_dist_counts_every_tie_on_a_lattice() {
+        let points = (0..5)
+            .flat_map(|x| (0..5).map(move |y| vec![OrderedFloat(x as f64), OrderedFloat(y as f64)]))
+            .collect::<Vec<Point>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut fake = FakeQueryEngine::new();
+        fake.init(&refs);
+        let mut brute = BruteForceQueryEngine::new();
+        brute.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+        let engines: [(&str, &dyn RegionQuery); 3] =
+            [("fake", &fake), ("brute", &brute), ("rtree", &rtree)];
+
+        // Around the center: 4 neighbors at 1, 4 at √2, 4 at 2 and 8 at √5.
+        let center = &points[12];
+        let rings = [(1.0, 4), (2f64.sqrt(), 8), (2.0, 12), (5f64.sqrt(), 20)];
+        // Around a corner: 2 at 1, 1 at √2, 2 at 2 and 2 at √5.
+        let corner = &points[0];
+        let corner_rings = [(1.0, 2), (2f64.sqrt(), 3), (2.0, 5), (5f64.sqrt(), 7)];
+        for (name, engine) in engines {
+            for (p, rings) in [(center, rings), (corner, corner_rings)] {
+                let mut k = 1;
+                for (d, count) in rings {
+                    while k <= count {
+                        assert_eq!(engine.k_dist(p, k), d, "{} k {}", name, k);
+                        assert_eq!(
+                            engine.k_dist_with_count(p, k),
+                            (d, count),
+                            "{} k {}",
+                            name,
+                            k
+                        );
+                        k += 1;
+                    }
+                }
+            }
+            // The count always matches a range query at the k-distance.
+            for p in points.iter() {
+                for k in 1..=10 {
+                    let (d, count) = engine.k_dist_with_count(p, k);
+                    assert!(count >= k);
+                    assert_eq!(
+                        engine.run(p, d).len() - 1,
+                        count,
+                        "{} {:?} k {}",
+                        name,
+                        p,
+                        k
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_points_cluster_like_their_dense_copies() {
+        let mut rng = crate::sampling::SplitMix64::new(356);
+        let mut sign = || if rng.below(2) == 0 { -1.0 } else { 1.0 };
+        // Two tight groups around different sparse centers, each point moved
+        // by 0.5 along some axis, and points moved by 3 along two axes.
+        let mut dense = Vec::new();
+        for (center, moves, step) in [(10, 1, 0.5), (500, 1, 0.5), (0, 2, 3.0)] {
+            for k in 0..15 {
+                let mut coords = vec![0.0; 2000];
+                coords[center] = 5.0;
+                coords[center + 1] = 5.0;
+                for m in 0..moves {
+                    coords[(37 * k + 1000 * m + center) % 2000] += step * sign();
+                }
+                dense.push(coords.into_iter().map(OrderedFloat).collect::<Point>());
+            }
+        }
+        // Copies count once in a point set, but every time in a slice.
+        dense.sort();
+        dense.dedup();
+        let sparse = dense
+            .iter()
+            .map(SparsePoint::from_dense)
+            .collect::<Vec<_>>();
+        let refs = dense.iter().collect::<HashSet<_>>();
+        for (eps, min_pts) in [(0.75, 3), (1.0, 5), (8.0, 4)] {
+            let mut engine = BruteForceQueryEngine::new();
+            let classes = Algo::new(&mut engine, &refs, eps, min_pts).dbscan();
+            let expected = canonical(dense.iter().map(|p| classes[p]));
+            let labels = canonical(dbscan_sparse(&sparse, eps, min_pts));
+            assert_eq!(labels, expected, "eps {} min_pts {}", eps, min_pts);
+        }
+        let labels = dbscan_sparse(&sparse, 0.75, 3);
+        assert!(labels.contains(&Class::Classified(1)));
+        assert!(labels.contains(&Class::Noise));
+    }
+
+    #[test]
+    fn fingerprints_cluster_around_their_prototypes() {
+        use crate::types::BitPoint;
+
+        // Two 256-bit prototypes 128 bits apart, copies with up to 2 flipped
+        // bits (so at most 4 apart), and one row far from both.
+        let mut rng = crate::sampling::SplitMix64::new(360);
+        let prototypes = [0, 1].map(|c| (0..256).map(|i| (i / 64) % 2 == c).collect::<Vec<_>>());
+        let mut points = Vec::new();
+        for k in 0..20 {
+            let mut bits = prototypes[k % 2].clone();
+            for _ in 0..rng.below(3) {
+                let i = rng.below(256) as usize;
+                bits[i] = !bits[i];
+            }
+            points.push(BitPoint::from_bits(&bits));
+        }
+        let far = (0..256).map(|i| i < 128).collect::<Vec<_>>();
+        points.push(BitPoint::from_bits(&far));
+
+        let labels = dbscan_bits(&points, 4, 3);
+        for (k, &label) in labels.iter().take(20).enumerate() {
+            assert_eq!(label, Class::Classified(k % 2), "row {}", k);
+        }
+        assert_eq!(labels[20], Class::Noise);
+        // Zero differing bits joins exact copies only.
+        let exact = dbscan_bits(&points, 0, 2);
+        assert!(exact.contains(&Class::Noise));
+        let wide = dbscan_bits(&points, 255, 3);
+        assert!(wide.iter().all(|&c| c == Class::Classified(0)));
+    }
+
+    #[test]
+    fn f32_points_cluster_like_f64_points() {
+        let mut rng = crate::sampling::SplitMix64::new(357);
+        // Blobs of spread 1 around centers 20 apart, and a few loose points.
+        let mut dense = Vec::new();
+        for center in [0.0, 20.0, 40.0] {
+            for _ in 0..30 {
+                let coords = (0..3).map(|_| center + rng.below(1000) as f64 / 1000.0);
+                dense.push(coords.map(OrderedFloat).collect::<Point>());
+            }
+        }
+        for i in 0..5 {
+            let x = 100.0 + 7.0 * i as f64;
+            dense.push(vec![OrderedFloat(x), OrderedFloat(-x), OrderedFloat(0.5)]);
+        }
+        dense.sort();
+        dense.dedup();
+        let single = dense
+            .iter()
+            .map(|p| {
+                p.iter()
+                    .map(|x| OrderedFloat(x.0 as f32))
+                    .collect::<Point<f32>>()
+            })
+            .collect::<Vec<_>>();
+        let refs = dense.iter().collect::<HashSet<_>>();
+        let single_refs = single.iter().collect::<HashSet<_>>();
+        for (eps, min_pts) in [(0.3, 3), (0.5, 8), (2.0, 4)] {
+            // No pair is within 1e-4 of eps, far beyond the f32 rounding.
+            for a in &dense {
+                for b in &dense {
+                    assert!((crate::types::dist(a, b) - eps).abs() > 1e-4);
+                }
+            }
+            let mut engine = BruteForceQueryEngine::new();
+            let classes = Algo::new(&mut engine, &refs, eps, min_pts).dbscan();
+            let mut engine = BruteForceQueryEngine::<Euclidean, f32>::new();
+            let single_classes = Algo::new(&mut engine, &single_refs, eps, min_pts).dbscan();
+            let expected = canonical(dense.iter().map(|p| classes[p]));
+            let labels = canonical(single.iter().map(|p| single_classes[p]));
+            assert_eq!(labels, expected, "eps {} min_pts {}", eps, min_pts);
+            assert!(expected.contains(&Class::Noise));
+            assert!(expected.contains(&Class::Classified(2)));
+        }
+    }
+
+    #[test]
+    fn progress_is_throttled_and_ends_at_the_total() {
+        // 5,000 points on a 100 by 50 grid, one cluster at eps 1.
+        let points = (0..5000)
+            .map(|i| {
+                vec![
+                    OrderedFloat((i % 100) as f64),
+                    OrderedFloat((i / 100) as f64),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let set = points.iter().collect::<HashSet<_>>();
+        let reports = std::cell::RefCell::new(Vec::new());
+        let mut engine = BruteForceQueryEngine::new();
+        Algo::new(&mut engine, &set, 1.0, 3)
+            .with_progress(|progress| reports.borrow_mut().push(progress))
+            .dbscan();
+
+        let reports = reports.into_inner();
+        let count = reports.len();
+        assert!(count > 100 && count <= PROGRESS_REPORTS + 2, "{}", count);
+        assert!(reports.windows(2).all(|w| w[0].processed <= w[1].processed));
+        assert!(reports.windows(2).all(|w| w[0].clusters <= w[1].clusters));
+        assert!(reports.iter().all(|r| r.total == 5000));
+        assert_eq!(
+            reports.last(),
+            Some(&Progress {
+                processed: 5000,
+                total: 5000,
+                clusters: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn knee_eps_falls_between_the_blob_and_gap_scales() {
+        // Three blobs of 100 points, at most 0.6 across, 20 apart, and 10
+        // noise points scattered far from them and from each other.
+        let mut rng = crate::sampling::SplitMix64::new(17);
+        let mut coord = |center: f64| OrderedFloat(center + rng.below(600) as f64 / 1000.0);
+        let mut points = Vec::new();
+        for center in [0.0, 20.0, 40.0] {
+            for _ in 0..100 {
+                points.push(vec![coord(center), coord(0.0)]);
+            }
+        }
+        for i in 0..10 {
+            let x = 10.0 + i as f64 * 7.0;
+            points.push(vec![OrderedFloat(x), OrderedFloat(-15.0)]);
+        }
+        points.sort();
+        points.dedup();
+        let set = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        engine.init(&set);
+        let refs = points.iter().collect::<Vec<_>>();
+
+        for k in [4, 8] {
+            let eps = knee_eps(&k_distances(&engine, &refs, k)).unwrap();
+            // Within a blob, the k-th neighbor is far nearer than 0.6; the
+            // noise is 7 from anything else.
+            assert!(eps > 0.01 && eps < 0.6, "k {}: {}", k, eps);
+            let mut fresh = BruteForceQueryEngine::new();
+            let classes = Algo::new(&mut fresh, &set, eps, k).dbscan();
+            let clusters = classes
+                .values()
+                .filter_map(|c| match c {
+                    Class::Classified(id) => Some(*id),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            assert_eq!(clusters.len(), 3, "k {}: {}", k, eps);
+        }
+    }
+
+    #[test]
+    fn knee_eps_ignores_non_finite_distances() {
+        assert_eq!(knee_eps(&[]), None);
+        assert_eq!(knee_eps(&[f64::NAN, f64::INFINITY]), None);
+        assert_eq!(knee_eps(&[2.0, 2.0, f64::NAN]), Some(2.0));
+        // The curve 1, 1, 1, 1, 10 bends at the last 1.
+        let curve = [1.0, 10.0, 1.0, f64::INFINITY, 1.0, 1.0];
+        assert_eq!(knee_eps(&curve), Some(1.0));
+    }
+}
@@ -1,11 +1,73 @@
-use anyhow::Result;
-use clap::Parser;
-use std::collections::HashSet;
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use rust::algo::Algo;
-use rust::io::{read_points_csv, write_clustered_csv};
-use rust::query::RTreeQueryEngine;
-use rust::types::Point;
+use rust::algo::{k_distances, knee_eps, Algo};
+use rust::cli::CsvArgs;
+use rust::engine::{choose_engine_for_metric, eps_share, EngineKind};
+use rust::io::{
+    open_input, read_csv, read_csv_many, read_label_lines, read_points_bin, shuffle_points,
+    unshuffle_points, write_clustered_csv_labeled, write_clustered_csv_passthrough,
+    write_clustered_csv_verbatim, write_clustered_csv_with_format,
+    write_clusters_split_with_format, write_points_bin, ColumnRef, Columns, CsvData, CsvFormat,
+    CsvOptions, FileFormat, FloatFormat, KeepRows, Labels, MissingWeight, OnError, RawLines,
+    RawRows, Weights,
+};
+use rust::plot::{write_png, PlotOptions};
+use rust::predict::Model;
+use rust::query::RTREE_MAX_DIM;
+use rust::sampling::{permute, unpermute};
+use rust::scaling::{fit_minmax, fit_zscore};
+use rust::types::{
+    drop_small_clusters, labels_in_order, number_clusters_in_order, select_rows,
+    sort_rows_by_cluster, Class, MetricKind, Point, RunSummary, Schema,
+};
+use rust::validation::{compare, NoiseHandling};
+
+// The CSV options that --cache-bin cannot honor: the binary copy holds the
+// coordinates of every row of INPUT and nothing else.
+const NOT_CACHED: [&str; 12] = [
+    "header",
+    "auto_header",
+    "columns",
+    "exclude_columns",
+    "max_rows",
+    "inputs",
+    "passthrough",
+    "preserve_input",
+    "label_column",
+    "weights_column",
+    "skip_bad_lines",
+    "sample",
+];
+
+// The options that may read fewer points than the input has rows, which a
+// --ground-truth file could then not be matched with.
+const DROPS_ROWS: [&str; 3] = ["sample", "skip_bad_lines", "max_rows"];
+
+// What --predict takes from the model, or does not do at all.
+const MODEL_PARAMETERS: [&str; 13] = [
+    "min_points",
+    "eps",
+    "min_pts",
+    "eps_flag",
+    "auto_eps",
+    "eps_list",
+    "metric",
+    "minkowski_p",
+    "normalize",
+    "engine",
+    "min_cluster_size",
+    "weights_column",
+    "save_model",
+];
 
 #[derive(Debug, Parser)]
 #[command(
@@ -15,33 +77,1887 @@ use rust::types::Point;
     about = "Density-based clustering (DBSCAN)"
 )]
 struct Args {
-    /// Input CSV file with points: x11,x12,...,x1D per line
+    /// Input CSV file with points: x11,x12,...,x1D per line (`-` for stdin)
     input: String,
-    /// Output CSV file: cid,x1,x2,...,xD per line
+    /// Output CSV file: cid,x1,x2,...,xD per line (`-` for stdout), with the
+    /// delimiter and header of CSV input
     output: String,
-    /// Minimum number of points to form a dense region
+    /// Minimum number of points to form a dense region; or use --min-pts
+    #[arg(
+        required_unless_present_any = ["min_pts", "predict"],
+        allow_negative_numbers = true
+    )]
+    min_points: Option<MinPoints>,
+    /// Neighborhood radius (epsilon); or use --eps, --auto-eps or --eps-list
+    #[arg(
+        required_unless_present_any = ["eps_flag", "auto_eps", "eps_list", "predict", "min_pts"],
+        allow_negative_numbers = true
+    )]
+    eps: Option<f64>,
+    /// MIN_POINTS as a named flag, instead of the positional argument; EPS
+    /// then has to be named too (--eps, --auto-eps or --eps-list)
+    #[arg(long, value_name = "N", conflicts_with = "min_points")]
+    min_pts: Option<MinPoints>,
+    /// EPS as a named flag, instead of the positional argument
+    #[arg(
+        id = "eps_flag",
+        long = "eps",
+        value_name = "EPS",
+        conflicts_with = "eps",
+        allow_negative_numbers = true
+    )]
+    eps_flag: Option<f64>,
+
+    /// More CSV input files, read after INPUT as part of the same data, e.g.
+    /// `--inputs day2.csv day3.csv`; all must have the same columns
+    #[arg(long, num_args = 1.., value_delimiter = ',', conflicts_with = "max_rows")]
+    inputs: Vec<String>,
+    /// With --inputs, write the input file of each point after its cluster id
+    /// in CSV output, in a column named `source`
+    #[arg(long, requires = "inputs", conflicts_with = "label_column")]
+    source_column: bool,
+
+    /// Query engine; `auto` picks one from the input size and dimensionality.
+    /// `rtree` supports up to 16 dimensions and falls back to `brute` with a
+    /// warning beyond that; `fake` needs O(N²) memory
+    #[arg(long, value_enum, default_value_t = Engine::Auto)]
+    engine: Engine,
+    /// Threads for indexing and clustering, on a pool of their own (default:
+    /// the number of physical cores). Needs the `parallel` feature; runs
+    /// without parallel work accept it with a warning
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+    /// Estimate EPS as the knee of the sorted k-distances of the points,
+    /// print it to stderr and cluster with it
+    #[arg(long, conflicts_with_all = ["eps", "eps_flag"])]
+    auto_eps: bool,
+    /// k of --auto-eps (default: MIN_POINTS)
+    #[arg(long, value_name = "K", requires = "auto_eps")]
+    auto_eps_k: Option<usize>,
+    /// Cluster once per EPS in this list, e.g. `--eps-list 0.1,0.2,0.5`,
+    /// reading the input and building the index only once. Each run is
+    /// written next to OUTPUT with the EPS in its name (`out.eps0.1.csv`),
+    /// and a table of clusters and noise per EPS goes to stderr
+    #[arg(
+        long,
+        value_name = "EPS",
+        num_args = 1..,
+        value_delimiter = ',',
+        conflicts_with_all = ["eps", "eps_flag", "auto_eps", "split_output", "sqlite"]
+    )]
+    eps_list: Vec<f64>,
+    /// With --eps-list, also write the table as CSV to this file (`-` for
+    /// stdout): eps,clusters,noise,noise_pct
+    #[arg(long, value_name = "PATH", requires = "eps_list")]
+    sweep_summary: Option<String>,
+    /// Distance between points, in which EPS is measured; `haversine` takes
+    /// (latitude, longitude) columns in degrees and EPS in kilometers.
+    /// `rtree` only supports euclidean, manhattan, chebyshev and minkowski
+    #[arg(long, value_enum, default_value_t = Metric::Euclidean)]
+    metric: Metric,
+    /// Scale every coordinate column before clustering: `minmax` maps it to
+    /// [0, 1], `zscore` to mean 0 and standard deviation 1; EPS is then a
+    /// distance between scaled points. Constant columns map to 0. The output
+    /// keeps the original coordinates
+    #[arg(long, value_enum, default_value_t = Normalize::None)]
+    normalize: Normalize,
+    /// Exponent of --metric minkowski, at least 1
+    #[arg(long, value_name = "P", required_if_eq("metric", "minkowski"))]
+    minkowski_p: Option<f64>,
+    /// Relabel clusters of fewer than M points as noise and renumber the
+    /// others 0, 1, ... before writing; with --eps-list, for every eps
+    #[arg(long, value_name = "M")]
+    min_cluster_size: Option<usize>,
+    /// Write only the noise points; the run report and --summary still cover
+    /// all of them
+    #[arg(long, conflicts_with = "only_cluster")]
+    only_noise: bool,
+    /// Write only the points of these clusters, e.g. `3,7,12`, by their ids
+    /// in the output (after --min-cluster-size); the run report and --summary
+    /// still cover all points
+    #[arg(long, value_name = "IDS", value_delimiter = ',')]
+    only_cluster: Vec<usize>,
+    /// Order of the written rows: as read (shuffled with --shuffle-seed
+    /// unless --restore-order is given), or by cluster id with noise last
+    /// and by coordinates within a cluster, equal points as read
+    #[arg(long, value_enum, default_value_t = SortOutput::Input)]
+    sort_output: SortOutput,
+
+    #[command(flatten)]
+    csv: CsvArgs,
+
+    /// File format of input and output; `auto` picks JSON Lines for `.jsonl`
+    /// and `.ndjson` paths, GeoJSON for `.geojson` paths, JSON (output only)
+    /// for `.json` paths, Parquet (input only) for `.parquet` paths, Arrow IPC
+    /// for `.arrow`, `.feather` and `.ipc` paths and CSV otherwise,
+    /// separately for each
+    #[arg(long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+    /// Layout of JSON output: `grouped` nests the points under their cluster
+    /// ({"clusters": [{"id": 0, "points": [...]}], "noise": [...]}), `flat`
+    /// lists {"cluster": 0, "coords": [...]} objects in input order
+    #[arg(long, value_enum, default_value_t = JsonLayout::Grouped)]
+    json_style: JsonLayout,
+    /// Indent JSON output; flat JSON without it is written one object per
+    /// line
+    #[arg(long)]
+    pretty: bool,
+    /// Copy the extra fields of JSON Lines (or the properties of GeoJSON) input
+    /// records to JSON Lines output
+    #[arg(long)]
+    keep_fields: bool,
+    /// Columns to read as coordinates: CSV column indices (from 0), ranges
+    /// such as `2..10` or header names; Parquet or Arrow column names (default:
+    /// all numeric columns); or GeoJSON properties to add to [lon, lat]
+    #[arg(long, value_delimiter = ',')]
+    columns: Option<Vec<String>>,
+    /// CSV columns not to read as coordinates, given as for --columns; all
+    /// the others are read unless --columns lists them
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    exclude_columns: Vec<String>,
+    /// Write each CSV input row unchanged after its cluster id, keeping the
+    /// columns that are not coordinates (CSV input and output only)
+    #[arg(long, conflicts_with = "preserve_input")]
+    passthrough: bool,
+    /// CSV column (index from 0, or header name) holding ground-truth labels:
+    /// it is not a coordinate, is written after the cluster id of CSV output,
+    /// and an agreement summary is printed to stderr
+    #[arg(long)]
+    label_column: Option<ColumnRef>,
+    /// CSV column (index from 0, or header name) holding a positive weight
+    /// for every point: it is not a coordinate, a point counts like that many
+    /// points, and MIN_POINTS is the total weight a core point's neighborhood
+    /// needs
+    #[arg(long, value_name = "COLUMN")]
+    weights_column: Option<ColumnRef>,
+    /// File of ground-truth labels, one per line in input order: the run
+    /// report and --summary score the clustering against them (ARI and NMI).
+    /// --label-column scores a column of the input instead
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "label_column",
+        conflicts_with_all = DROPS_ROWS
+    )]
+    ground_truth: Option<String>,
+    /// What the ground-truth scores do with noise: count it as one more
+    /// cluster, or leave noise points out
+    #[arg(long, value_enum, default_value_t = TruthNoise::Cluster)]
+    truth_noise: TruthNoise,
+    /// Write each CSV input line exactly as read after its cluster id, in
+    /// input order, so that numbers keep their formatting and the output
+    /// diffs cleanly against the input (CSV input and output only)
+    #[arg(long, conflicts_with = "shuffle_seed")]
+    preserve_input: bool,
+    /// Drop malformed CSV rows (text where a number belongs, a wrong number
+    /// of columns, invalid UTF-8) instead of failing, and print how many were
+    /// dropped to stderr; with --verbose, also the first ten of them
+    #[arg(long)]
+    skip_bad_lines: bool,
+    /// How CSV output prints coordinates: `shortest` (exact, never in exponent
+    /// notation), `roundtrip` (exact, exponent notation for tiny or huge
+    /// values) or a number of decimal places
+    #[arg(long, default_value = "shortest")]
+    precision: FloatFormat,
+    /// Keep a binary copy of the CSV input next to it, as `<INPUT>.bin`, and
+    /// read that instead while it is newer than the input; much faster for
+    /// large files that are clustered again and again
+    #[arg(long, conflicts_with_all = NOT_CACHED)]
+    cache_bin: bool,
+    /// Cluster a uniform random sample of this many CSV rows, picked while
+    /// reading so that the rest is never held in memory
+    #[arg(long, value_name = "N", conflicts_with = "max_rows")]
+    sample: Option<usize>,
+    /// Seed of --sample; the same seed picks the same rows
+    #[arg(long, value_name = "S", default_value_t = 0, requires = "sample")]
+    seed: u64,
+    /// Shuffle the points with this seed after reading, so that order-sensitive
+    /// steps do not depend on the order of the file; the output follows the
+    /// shuffled order unless --restore-order is given
+    #[arg(long, value_name = "S")]
+    shuffle_seed: Option<u64>,
+    /// With --shuffle-seed, write the output in the order of the input
+    #[arg(long, requires = "shuffle_seed")]
+    restore_order: bool,
+    /// Also write every cluster to its own CSV file of coordinates in this
+    /// directory: cluster_000.csv, cluster_001.csv, ... and noise.csv
+    #[arg(long, value_name = "DIR")]
+    split_output: Option<String>,
+    /// Let --split-output replace the files of an earlier run in a non-empty
+    /// directory
+    #[arg(long, requires = "split_output")]
+    force: bool,
+    /// Also write the clustered points to a new table of this SQLite
+    /// database: a cluster_id column and one REAL column per coordinate
+    #[arg(long, value_name = "PATH")]
+    sqlite: Option<String>,
+    /// Name of the --sqlite table; it must not exist yet
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "clusters",
+        requires = "sqlite"
+    )]
+    sqlite_table: String,
+    /// Write a JSON summary of the run to this file: parameters, engine,
+    /// cluster sizes and centroids, noise count and wall-clock time; with
+    /// --eps-list, one per eps
+    #[arg(long, value_name = "PATH")]
+    summary: Option<String>,
+    /// Also draw the clusters as a PNG scatter plot to this file (`-` for
+    /// stdout), as the `plot` binary does; with --eps-list, one per eps
+    #[arg(long, value_name = "PATH")]
+    plot: Option<String>,
+    /// Coordinate on the x axis of --plot (0-based)
+    #[arg(long, value_name = "COL", default_value_t = 0, requires = "plot")]
+    plot_x_col: usize,
+    /// Coordinate on the y axis of --plot (0-based)
+    #[arg(long, value_name = "COL", default_value_t = 1, requires = "plot")]
+    plot_y_col: usize,
+    /// Width of --plot in pixels
+    #[arg(long, value_name = "PX", default_value_t = 1000, requires = "plot")]
+    plot_width: u32,
+    /// Height of --plot in pixels
+    #[arg(long, value_name = "PX", default_value_t = 800, requires = "plot")]
+    plot_height: u32,
+
+    /// Print diagnostics to stderr, and every cluster in the run report
+    #[arg(long, short)]
+    verbose: bool,
+    /// Print nothing to stderr but errors: no run report, warnings or
+    /// progress
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Exit with status 5 if no cluster is found, i.e. every point is noise;
+    /// with --eps-list, if no eps finds one. The output is written first
+    #[arg(long)]
+    fail_if_no_clusters: bool,
+    /// Also save the clustering as a model to this file: EPS, MIN_POINTS,
+    /// the metric and the core points of every cluster, for --predict. Needs
+    /// the `serde` feature and an Lp metric
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["eps_list", "normalize"]
+    )]
+    save_model: Option<String>,
+    /// Label the points of INPUT with a model saved by --save-model instead
+    /// of clustering them: each gets the cluster of the nearest core point
+    /// within the model's EPS, or is noise. MIN_POINTS, EPS and the metric
+    /// come from the model. Needs the `serde` feature
+    #[arg(
+        long,
+        value_name = "MODEL",
+        conflicts_with_all = MODEL_PARAMETERS
+    )]
+    predict: Option<String>,
+}
+
+// MIN_POINTS as given. A number with a decimal point (`5.0`, `0.5`) is taken
+// by its whole part and remembered, since it usually means that MIN_POINTS
+// and EPS were swapped.
+#[derive(Debug, Clone)]
+struct MinPoints {
+    value: usize,
+    text: Option<String>,
+}
+
+impl FromStr for MinPoints {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(value) = s.parse() {
+            return Ok(MinPoints { value, text: None });
+        }
+        match s.parse::<f64>() {
+            Ok(x) if s.contains('.') && x.is_finite() && x >= 0.0 => Ok(MinPoints {
+                value: x as usize,
+                text: Some(s.to_string()),
+            }),
+            _ => Err(format!("expected a whole number of points, got '{}'", s)),
+        }
+    }
+}
+
+// The rows written with --only-noise or --only-cluster.
+#[derive(Debug, Clone)]
+enum Only {
+    Noise,
+    Clusters(HashSet<usize>),
+}
+
+impl Only {
+    fn keeps(&self, class: Class) -> bool {
+        match (self, class) {
+            (Only::Noise, Class::Noise) => true,
+            (Only::Clusters(ids), Class::Classified(id)) => ids.contains(&id),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum TruthNoise {
+    Cluster,
+    Exclude,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum SortOutput {
+    Input,
+    Cluster,
+}
+
+// The rows to write and whatever was read alongside them, cut down to the
+// rows selected by `Only` and put in the order of --sort-output.
+struct Kept {
+    points: Vec<Point>,
+    raw: Option<RawRows>,
+    lines: Option<RawLines>,
+    labels: Option<Labels>,
+    fields: Option<Fields>,
+}
+
+fn pick<T: Clone>(items: &[T], rows: &[usize]) -> Vec<T> {
+    rows.iter().map(|&i| items[i].clone()).collect()
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Format {
+    Auto,
+    Csv,
+    Jsonl,
+    Json,
+    Parquet,
+    Arrow,
+    Geojson,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum JsonLayout {
+    Flat,
+    Grouped,
+}
+
+impl Format {
+    fn for_path(self, path: &str) -> FileFormat {
+        match self {
+            Format::Auto => FileFormat::from_path(path),
+            Format::Csv => FileFormat::Csv,
+            Format::Jsonl => FileFormat::JsonLines,
+            Format::Json => FileFormat::Json,
+            Format::Parquet => FileFormat::Parquet,
+            Format::Arrow => FileFormat::Arrow,
+            Format::Geojson => FileFormat::GeoJson,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+type Fields = Vec<rust::io::JsonFields>;
+#[cfg(not(feature = "serde"))]
+type Fields = Vec<()>;
+
+#[cfg(feature = "serde")]
+fn read_jsonl(path: &str) -> Result<(Vec<Point>, Fields)> {
+    rust::io::read_points_jsonl(path)
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_jsonl(_: &str) -> Result<(Vec<Point>, Fields)> {
+    anyhow::bail!("JSON Lines support needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn write_jsonl(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    fields: Option<&Fields>,
+) -> Result<()> {
+    rust::io::write_clustered_jsonl(path, points, classes, fields.map(|f| f.as_slice()))
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_jsonl(_: &str, _: &[Point], _: &HashMap<&Point, Class>, _: Option<&Fields>) -> Result<()> {
+    anyhow::bail!("JSON Lines support needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn write_json(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    layout: JsonLayout,
+    pretty: bool,
+) -> Result<()> {
+    let style = match layout {
+        JsonLayout::Flat => rust::io::JsonStyle::Flat,
+        JsonLayout::Grouped => rust::io::JsonStyle::Grouped,
+    };
+    rust::io::write_clustered_json(path, points, classes, style, pretty)
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_json(
+    _: &str,
+    _: &[Point],
+    _: &HashMap<&Point, Class>,
+    _: JsonLayout,
+    _: bool,
+) -> Result<()> {
+    anyhow::bail!("JSON support needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn read_geojson(path: &str, properties: &[String]) -> Result<(Vec<Point>, Fields)> {
+    rust::io::read_points_geojson(path, properties)
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_geojson(_: &str, _: &[String]) -> Result<(Vec<Point>, Fields)> {
+    anyhow::bail!("GeoJSON support needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn write_geojson(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    fields: Option<&Fields>,
+) -> Result<()> {
+    rust::io::write_clustered_geojson(path, points, classes, fields.map(|f| f.as_slice()))
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_geojson(
+    _: &str,
+    _: &[Point],
+    _: &HashMap<&Point, Class>,
+    _: Option<&Fields>,
+) -> Result<()> {
+    anyhow::bail!("GeoJSON support needs the `serde` feature")
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &str, columns: Option<&[String]>) -> Result<Vec<Point>> {
+    rust::io::read_points_parquet(path, columns)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_parquet(_: &str, _: Option<&[String]>) -> Result<Vec<Point>> {
+    anyhow::bail!("Parquet support needs the `parquet` feature")
+}
+
+#[cfg(feature = "arrow")]
+fn read_arrow(path: &str, columns: Option<&[String]>) -> Result<Vec<Point>> {
+    rust::io::read_points_arrow(path, columns)
+}
+
+#[cfg(not(feature = "arrow"))]
+fn read_arrow(_: &str, _: Option<&[String]>) -> Result<Vec<Point>> {
+    anyhow::bail!("Arrow support needs the `arrow` feature")
+}
+
+#[cfg(feature = "arrow")]
+fn write_arrow(
+    path: &str,
+    schema: Option<&Schema>,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    rust::io::write_clustered_arrow(path, schema, points, classes)
+}
+
+#[cfg(not(feature = "arrow"))]
+fn write_arrow(_: &str, _: Option<&Schema>, _: &[Point], _: &HashMap<&Point, Class>) -> Result<()> {
+    anyhow::bail!("Arrow support needs the `arrow` feature")
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(
+    path: &str,
+    table: &str,
+    schema: Option<&Schema>,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    rust::io::write_clustered_sqlite(path, table, points, classes, schema)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(
+    _: &str,
+    _: &str,
+    _: Option<&Schema>,
+    _: &[Point],
+    _: &HashMap<&Point, Class>,
+) -> Result<()> {
+    anyhow::bail!("--sqlite needs the `sqlite` feature")
+}
+
+#[cfg(feature = "serde")]
+fn write_summary(path: &str, summary: &RunSummary) -> Result<()> {
+    rust::io::write_summary_json(path, summary)
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_summary(_: &str, _: &RunSummary) -> Result<()> {
+    anyhow::bail!("--summary needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn write_model(path: &str, model: &Model) -> Result<()> {
+    rust::io::write_model(path, model)
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_model(_: &str, _: &Model) -> Result<()> {
+    anyhow::bail!("--save-model needs the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn read_model(path: &str) -> Result<Model> {
+    rust::io::read_model(path)
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_model(_: &str) -> Result<Model> {
+    anyhow::bail!("--predict needs the `serde` feature")
+}
+
+// Reads the points of the CSV file `path` from its binary copy `<path>.bin`
+// if that is newer, otherwise from the CSV, writing the copy for next time.
+fn read_cached(path: &str, csv: CsvFormat, verbose: bool) -> Result<(Vec<Point>, char)> {
+    let cache = format!("{}.bin", path);
+    let modified = |p: &str| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let fresh = matches!(
+        (modified(path), modified(&cache)),
+        (Some(csv), Some(bin)) if bin > csv
+    );
+    if fresh {
+        if verbose {
+            eprintln!("reading cached points from {}", cache);
+        }
+        return Ok((read_points_bin(&cache)?, csv.delimiter.resolve_in(path)?));
+    }
+    let data = read_csv(open_input(path)?, &CsvOptions::new(csv))?;
+    let (points, separator) = (data.points, data.delimiter);
+    write_points_bin(&cache, &points)?;
+    if verbose {
+        eprintln!("wrote cached points to {}", cache);
+    }
+    Ok((points, separator))
+}
+
+// Prints how well the clusters match ground-truth labels: the purity is the
+// share of clustered points carrying the most common label of their cluster.
+fn print_agreement(cids: &[isize], labels: &[String]) {
+    let mut counts: HashMap<isize, HashMap<&str, usize>> = HashMap::new();
+    for (&cid, label) in cids.iter().zip(labels.iter()) {
+        *counts.entry(cid).or_default().entry(label).or_default() += 1;
+    }
+    let noise = counts.remove(&-1).map_or(0, |c| c.values().sum());
+    let clustered = cids.len() - noise;
+    let majority = counts
+        .values()
+        .map(|c| c.values().copied().max().unwrap_or(0))
+        .sum::<usize>();
+    let distinct = labels.iter().collect::<HashSet<_>>().len();
+    eprint!(
+        "ground truth: {} labels, {} clusters, {} noise points",
+        distinct,
+        counts.len(),
+        noise
+    );
+    if clustered > 0 {
+        eprint!(", purity {:.3}", majority as f64 / clustered as f64);
+    }
+    eprintln!();
+}
+
+// Prints what the run found and where the time went: the cluster count, the
+// five largest clusters, the noise and the time of every phase; with
+// `verbose`, also the size and centroid of every cluster.
+fn print_report(run: &RunSummary, phases: &[(&str, Duration)], verbose: bool) {
+    eprintln!(
+        "{} points: {} clusters, {} noise points ({:.1}%)",
+        run.n_points,
+        run.n_clusters,
+        run.n_noise,
+        noise_share(run)
+    );
+    if let Some(scaling) = &run.normalization {
+        eprintln!(
+            "eps {} is a distance between {}-scaled points",
+            run.eps, scaling
+        );
+    }
+    if let Some(m) = run.min_cluster_size {
+        eprintln!(
+            "dropped {} clusters of fewer than {} points",
+            run.dropped_clusters, m
+        );
+    }
+    let mut largest = run.clusters.iter().collect::<Vec<_>>();
+    largest.sort_by_key(|c| (std::cmp::Reverse(c.size), c.id));
+    if !largest.is_empty() {
+        let sizes = largest
+            .iter()
+            .take(5)
+            .map(|c| match c.weight {
+                Some(weight) => format!("{} (#{}, weight {})", c.size, c.id, weight),
+                None => format!("{} (#{})", c.size, c.id),
+            })
+            .collect::<Vec<_>>();
+        let more = if largest.len() > 5 { ", ..." } else { "" };
+        eprintln!("largest clusters: {}{}", sizes.join(", "), more);
+    }
+    if verbose {
+        for c in run.clusters.iter() {
+            let centroid = c.centroid.iter().map(|x| format!("{:.4}", x));
+            let weight = c.weight.map(|w| format!(", weight {}", w));
+            eprintln!(
+                "  cluster {}: {} points{}, centroid ({})",
+                c.id,
+                c.size,
+                weight.unwrap_or_default(),
+                centroid.collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+    if let Some(scores) = &run.ground_truth {
+        eprintln!(
+            "ground truth: ARI {:.3}, NMI {:.3} over {} points",
+            scores.ari, scores.nmi, scores.points
+        );
+    }
+    let total = phases.iter().map(|&(_, d)| d).sum::<Duration>();
+    let times = phases
+        .iter()
+        .map(|(name, d)| format!("{} {:.3}s", name, d.as_secs_f64()))
+        .collect::<Vec<_>>();
+    eprintln!(
+        "time: {}, total {:.3}s",
+        times.join(", "),
+        total.as_secs_f64()
+    );
+}
+
+// Bar over the `total` points to classify, with the clusters found so far in
+// its message. indicatif limits the redraw rate itself.
+fn progress_bar(total: usize) -> ProgressBar {
+    let bar = ProgressBar::new(total as u64);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{bar:40} {pos}/{len} points, {msg} [{elapsed_precise}, eta {eta}]",
+        )
+        .expect("valid progress template"),
+    );
+    bar.set_message("0 clusters");
+    bar
+}
+
+// Runs `op` on a rayon pool of `threads` threads, or of one per physical core.
+#[cfg(feature = "parallel")]
+fn with_threads<R: Send>(
+    threads: Option<usize>,
+    verbose: bool,
+    op: impl FnOnce() -> R + Send,
+) -> Result<R> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or_else(num_cpus::get_physical))
+        .build()
+        .context("failed to start the thread pool")?;
+    if verbose {
+        eprintln!("threads: {}", pool.current_num_threads());
+    }
+    Ok(pool.install(op))
+}
+
+#[cfg(not(feature = "parallel"))]
+fn with_threads<R: Send>(_: Option<usize>, _: bool, op: impl FnOnce() -> R + Send) -> Result<R> {
+    Ok(op())
+}
+
+// Whether a run has work for the --threads pool: the fake engine sorts the
+// neighbors of every point in parallel while indexing, and the rtree engine
+// answers the k-NN queries of --auto-eps in parallel.
+fn parallel_work(kind: EngineKind, auto_eps: bool) -> bool {
+    match kind {
+        EngineKind::Fake => true,
+        EngineKind::RTree => auto_eps,
+        EngineKind::Brute => false,
+    }
+}
+
+// `out.csv` -> `out.eps0.1.csv`: the output path of one --eps-list run. A
+// `.gz` suffix stays last so that the file is still compressed.
+fn sweep_path(output: &str, eps: f64) -> String {
+    let (base, gz) = match output.strip_suffix(".gz") {
+        Some(base) => (base, ".gz"),
+        None => (output, ""),
+    };
+    let path = Path::new(base);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!(
+            "{}.eps{}.{}{}",
+            stem.to_string_lossy(),
+            eps,
+            ext.to_string_lossy(),
+            gz
+        ),
+        _ => format!(
+            "{}.eps{}{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            eps,
+            gz
+        ),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+fn noise_share(run: &RunSummary) -> f64 {
+    if run.n_points > 0 {
+        100.0 * run.n_noise as f64 / run.n_points as f64
+    } else {
+        0.0
+    }
+}
+
+// Prints the --eps-list table: clusters and noise of every run.
+fn print_sweep(runs: &[RunSummary]) {
+    // The dropped clusters only get a column if --min-cluster-size was given,
+    // the scores only if there was ground truth.
+    let filtered = runs.iter().any(|run| run.min_cluster_size.is_some());
+    let scored = runs.iter().any(|run| run.ground_truth.is_some());
+    eprint!(
+        "{:>12} {:>9} {:>9} {:>7}",
+        "eps", "clusters", "noise", "noise%"
+    );
+    if filtered {
+        eprint!(" {:>9}", "dropped");
+    }
+    if scored {
+        eprint!(" {:>6} {:>6}", "ARI", "NMI");
+    }
+    eprintln!();
+    for run in runs.iter() {
+        eprint!(
+            "{:>12} {:>9} {:>9} {:>7.1}",
+            run.eps,
+            run.n_clusters,
+            run.n_noise,
+            noise_share(run)
+        );
+        if filtered {
+            eprint!(" {:>9}", run.dropped_clusters);
+        }
+        match &run.ground_truth {
+            Some(scores) => eprint!(" {:>6.3} {:>6.3}", scores.ari, scores.nmi),
+            None if scored => eprint!(" {:>6} {:>6}", "-", "-"),
+            None => {}
+        }
+        eprintln!();
+    }
+}
+
+// Writes the --eps-list table as CSV to `path`, or to stdout for `-`; as on
+// stderr, a `dropped` column is added with --min-cluster-size.
+fn write_sweep(path: &str, runs: &[RunSummary]) -> Result<()> {
+    let out: Box<dyn std::io::Write> = if path == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("failed to create {}", path))?;
+        Box::new(file)
+    };
+    let filtered = runs.iter().any(|run| run.min_cluster_size.is_some());
+    let scored = runs.iter().any(|run| run.ground_truth.is_some());
+    let mut w = csv::Writer::from_writer(out);
+    let mut header = vec!["eps", "clusters", "noise", "noise_pct"];
+    if filtered {
+        header.push("dropped");
+    }
+    if scored {
+        header.extend(["ari", "nmi"]);
+    }
+    w.write_record(&header)?;
+    for run in runs.iter() {
+        let mut row = vec![
+            run.eps.to_string(),
+            run.n_clusters.to_string(),
+            run.n_noise.to_string(),
+            format!("{:.3}", noise_share(run)),
+        ];
+        if filtered {
+            row.push(run.dropped_clusters.to_string());
+        }
+        if scored {
+            let scores = run.ground_truth.as_ref();
+            row.push(scores.map_or(String::new(), |s| format!("{:.6}", s.ari)));
+            row.push(scores.map_or(String::new(), |s| format!("{:.6}", s.nmi)));
+        }
+        w.write_record(&row)?;
+    }
+    w.flush()
+        .with_context(|| format!("failed to write {}", path))?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Engine {
+    Auto,
+    Fake,
+    Rtree,
+    Brute,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Normalize {
+    None,
+    Minmax,
+    Zscore,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Metric {
+    Euclidean,
+    Manhattan,
+    Chebyshev,
+    Minkowski,
+    Cosine,
+    Angular,
+    Haversine,
+}
+
+// What a run was doing when it failed, which decides the exit code; see the
+// README. Clap exits with 2 on bad usage by itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stage {
+    Arguments = 2,
+    Io = 3,
+    Clustering = 4,
+    // --fail-if-no-clusters found nothing but noise.
+    NoClusters = 5,
+}
+
+fn main() -> ExitCode {
+    let mut stage = Stage::Arguments;
+    match run(&mut stage) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(stage as u8)
+        }
+    }
+}
+
+// A run goes through four stages: the arguments are checked (`Settings`),
+// the input is read (`read_input`), the points are clustered once per eps
+// (`cluster`) and every clustering is written and reported (`write_run`).
+fn run(stage: &mut Stage) -> Result<()> {
+    let start = Instant::now();
+    let args = Args::parse();
+    let settings = Settings::new(&args, stage)?;
+    *stage = Stage::Io;
+    let input = read_input(&args, &settings)?;
+    settings.check_points(&args, &input.points, stage)?;
+    *stage = Stage::Clustering;
+    // Indexing and clustering run on a pool of their own, so that --threads
+    // leaves rayon's global pool alone.
+    catch_panic(|| {
+        with_threads(args.threads, args.verbose, || {
+            cluster(&args, &settings, input, start, stage)
+        })?
+    })
+}
+
+// Runs `op`, turning a panic into an error so that it exits with the code of
+// the stage it happened in rather than 101. The panic message has already
+// gone to stderr by then.
+fn catch_panic<T>(op: impl FnOnce() -> Result<T>) -> Result<T> {
+    catch_unwind(AssertUnwindSafe(op)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message,
+            None => payload
+                .downcast_ref::<String>()
+                .map_or("no message", String::as_str),
+        };
+        Err(anyhow::anyhow!("panicked: {}", message))
+    })
+}
+
+// What the arguments come to once checked against each other: the
+// parameters to cluster with and the formats to read and write.
+struct Settings {
     min_points: usize,
-    /// Neighborhood radius (epsilon)
-    eps: f64,
+    eps: Option<f64>,
+    metric: MetricKind,
+    // The model of --predict, which MIN_POINTS, EPS and the metric come from.
+    model: Option<Model>,
+    input_format: FileFormat,
+    output_format: FileFormat,
+    // How CSV input is read, with every reader option.
+    csv: CsvOptions,
+    // Added to messages about MIN_POINTS and EPS when they were given by
+    // position, so may have been swapped.
+    swapped: &'static str,
+    eps_name: &'static str,
+}
+
+impl Settings {
+    // Fails on arguments that clap accepts but that do not go together; the
+    // model of --predict is read here too.
+    fn new(args: &Args, stage: &mut Stage) -> Result<Self> {
+        let output = &args.output;
+        // Stdout carries the clustered points and nothing else, and stdin
+        // can only be read once.
+        if output == "-" && args.summary.as_deref() == Some("-") {
+            anyhow::bail!("OUTPUT and --summary cannot both be stdout (`-`)");
+        }
+        if args.plot.as_deref() == Some("-") && (output == "-" || !args.eps_list.is_empty()) {
+            anyhow::bail!(
+                "--plot can only write to stdout (`-`) for a single run with an OUTPUT file"
+            );
+        }
+        if args.summary.as_deref() == Some("-") && !args.eps_list.is_empty() {
+            anyhow::bail!("--summary can only write to stdout (`-`) for a single run");
+        }
+        if std::iter::once(&args.input)
+            .chain(&args.inputs)
+            .chain(&args.ground_truth)
+            .filter(|p| *p == "-")
+            .count()
+            > 1
+        {
+            anyhow::bail!("stdin (`-`) can only be read once");
+        }
+        if !args.eps_list.is_empty() && output == "-" {
+            anyhow::bail!(
+                "--eps-list writes one output file per eps; give an OUTPUT path, not `-`"
+            );
+        }
+        if let Some(&bad) = args.eps_list.iter().find(|&&e| !(e.is_finite() && e > 0.0)) {
+            anyhow::bail!("--eps-list values must be positive; got {}", bad);
+        }
+        // Only positional MIN_POINTS and EPS can be swapped by accident.
+        let swapped = if args.min_pts.is_none() && args.eps_flag.is_none() {
+            "; the order is INPUT OUTPUT MIN_POINTS EPS, were they swapped?"
+        } else {
+            ""
+        };
+        let min_name = if args.min_pts.is_some() {
+            "--min-pts"
+        } else {
+            "MIN_POINTS"
+        };
+        let eps_name = if args.eps_flag.is_some() {
+            "--eps"
+        } else {
+            "EPS"
+        };
+        // With --predict, MIN_POINTS, EPS and the metric are the model's.
+        let model = match &args.predict {
+            Some(path) => {
+                *stage = Stage::Io;
+                let model = read_model(path)?;
+                *stage = Stage::Arguments;
+                Some(model)
+            }
+            None => None,
+        };
+        let min_points = match (&model, args.min_pts.as_ref().or(args.min_points.as_ref())) {
+            (Some(model), _) => MinPoints {
+                value: model.min_points,
+                text: None,
+            },
+            (None, min_points) => min_points.expect("clap requires MIN_POINTS").clone(),
+        };
+        let eps = args.eps.or(args.eps_flag).or(model.as_ref().map(|m| m.eps));
+        // Clap cannot require EPS after --min-pts, or the positional EPS would
+        // make MIN_POINTS required too.
+        if eps.is_none() && !args.auto_eps && args.eps_list.is_empty() {
+            anyhow::bail!("--min-pts needs EPS as a flag too: --eps, --auto-eps or --eps-list");
+        }
+        match &min_points.text {
+            Some(text) if min_points.value == 0 => {
+                anyhow::bail!("{} must be at least 1, got {}{}", min_name, text, swapped)
+            }
+            Some(text) if !args.quiet => eprintln!(
+                "warning: {} {} has a decimal point, using {}{}",
+                min_name, text, min_points.value, swapped
+            ),
+            None if min_points.value == 0 => {
+                anyhow::bail!("{} must be at least 1, got 0", min_name)
+            }
+            _ => {}
+        }
+        if let Some(eps) = eps.filter(|e| !(e.is_finite() && *e > 0.0)) {
+            anyhow::bail!("{} must be a positive number, got {}", eps_name, eps);
+        }
+        if args.threads == Some(0) {
+            anyhow::bail!("--threads must be at least 1");
+        }
+        if args.minkowski_p.is_some() && !matches!(args.metric, Metric::Minkowski) {
+            anyhow::bail!("--minkowski-p needs --metric minkowski");
+        }
+        let metric = match args.metric {
+            _ if model.is_some() => model.as_ref().unwrap().metric,
+            Metric::Euclidean => MetricKind::Euclidean,
+            Metric::Manhattan => MetricKind::Manhattan,
+            Metric::Chebyshev => MetricKind::Chebyshev,
+            Metric::Minkowski => MetricKind::Minkowski(args.minkowski_p.unwrap()),
+            Metric::Cosine => MetricKind::Cosine,
+            Metric::Angular => MetricKind::Angular,
+            Metric::Haversine => MetricKind::haversine_km(),
+        };
+        if !matches!(args.normalize, Normalize::None) && matches!(metric, MetricKind::Haversine(_))
+        {
+            anyhow::bail!("--normalize cannot be combined with --metric haversine");
+        }
+        if args.save_model.is_some() && !metric.is_lp() {
+            anyhow::bail!(
+                "--save-model only supports the euclidean, manhattan, chebyshev and minkowski metrics"
+            );
+        }
+        if matches!(args.engine, Engine::Rtree) && !metric.is_lp() {
+            anyhow::bail!(
+                "--engine rtree only supports the euclidean, manhattan, chebyshev and minkowski metrics; use --engine brute or fake, or leave out --engine"
+            );
+        }
+        let output_format = args.format.for_path(output);
+        if output_format == FileFormat::Parquet {
+            anyhow::bail!("writing Parquet is not supported; use a CSV or JSON Lines output");
+        }
+        let input_format = args.format.for_path(&args.input);
+        let csv_in = input_format == FileFormat::Csv;
+        if args.passthrough && (!csv_in || output_format != FileFormat::Csv) {
+            anyhow::bail!("--passthrough needs CSV input and output");
+        }
+        if args.preserve_input && (!csv_in || output_format != FileFormat::Csv) {
+            anyhow::bail!("--preserve-input needs CSV input and output");
+        }
+        if args.label_column.is_some() && !csv_in {
+            anyhow::bail!("--label-column needs CSV input");
+        }
+        if args.weights_column.is_some() && !csv_in {
+            anyhow::bail!("--weights-column needs CSV input");
+        }
+        if args.weights_column.is_some() && metric != MetricKind::Euclidean {
+            anyhow::bail!("--weights-column only works with --metric euclidean");
+        }
+        if args.cache_bin && (!csv_in || args.input == "-") {
+            anyhow::bail!("--cache-bin needs a CSV input file");
+        }
+        if args.skip_bad_lines && !csv_in {
+            anyhow::bail!("--skip-bad-lines needs CSV input");
+        }
+        if !args.inputs.is_empty()
+            && (!csv_in
+                || args
+                    .inputs
+                    .iter()
+                    .any(|p| args.format.for_path(p) != FileFormat::Csv))
+        {
+            anyhow::bail!("--inputs needs CSV input files");
+        }
+        if args.source_column && output_format != FileFormat::Csv {
+            anyhow::bail!("--source-column needs CSV output");
+        }
+        if args.sample.is_some() && !csv_in {
+            anyhow::bail!("--sample needs CSV input");
+        }
+        if args.csv.max_rows.is_some() && !csv_in {
+            anyhow::bail!("--max-rows needs CSV input");
+        }
+        if !args.exclude_columns.is_empty() && !csv_in {
+            anyhow::bail!("--exclude-columns needs CSV input");
+        }
+        let selected = match input_format {
+            FileFormat::Csv => Columns::from_flags(args.columns.as_deref(), &args.exclude_columns)?,
+            _ => None,
+        };
+        let on_error = match (args.skip_bad_lines, args.verbose) {
+            (false, _) => OnError::Strict,
+            (true, false) => OnError::Collect(0),
+            (true, true) => OnError::Collect(10),
+        };
+        let keep = if args.passthrough {
+            KeepRows::Fields
+        } else if args.preserve_input {
+            KeepRows::Lines
+        } else {
+            KeepRows::None
+        };
+        let csv = CsvOptions::new(args.csv.format().with_on_error(on_error))
+            .with_columns(selected)
+            .with_label(args.label_column.clone())
+            .with_weight(args.weights_column.clone(), MissingWeight::Reject)
+            .keeping(keep)
+            .with_sample(args.sample, args.seed);
+        Ok(Settings {
+            min_points: min_points.value,
+            eps,
+            metric,
+            model,
+            input_format,
+            output_format,
+            csv,
+            swapped,
+            eps_name,
+        })
+    }
+
+    // The checks that need the points read: fails on points the metric
+    // cannot measure or too few columns for --plot, and warns of an EPS that
+    // looks like MIN_POINTS.
+    fn check_points(&self, args: &Args, points: &[Point], stage: &mut Stage) -> Result<()> {
+        self.metric.check_points(points)?;
+        let whole = |e: &f64| {
+            !args.quiet && self.model.is_none() && e.fract() == 0.0 && *e >= points.len() as f64
+        };
+        if let Some(eps) = self.eps.filter(whole) {
+            eprintln!(
+                "warning: {} {} is a whole number no smaller than the number of points ({}){}",
+                self.eps_name,
+                eps,
+                points.len(),
+                self.swapped
+            );
+        }
+        if args.plot.is_some() && args.plot_x_col.max(args.plot_y_col) >= points[0].len() {
+            *stage = Stage::Arguments;
+            anyhow::bail!(
+                "--plot-x-col/--plot-y-col out of bounds for {} data columns",
+                points[0].len()
+            );
+        }
+        Ok(())
+    }
+}
+
+// The points read, and whatever was read alongside them, in the order they
+// are clustered in: shuffled with --shuffle-seed.
+struct Input {
+    points: Vec<Point>,
+    schema: Option<Schema>,
+    separator: char,
+    raw: Option<RawRows>,
+    lines: Option<RawLines>,
+    labels: Option<Labels>,
+    weights: Option<Weights>,
+    truth: Option<Labels>,
+    fields: Option<Fields>,
+    // The permutation of --shuffle-seed, to put the output back in input
+    // order.
+    perm: Option<Vec<usize>>,
+}
+
+impl Input {
+    // Takes what was read alongside the points of CSV input, reporting the
+    // rows dropped by --skip-bad-lines and --sample, and returns the points,
+    // schema and delimiter.
+    fn csv_data(&mut self, args: &Args, data: CsvData) -> (Vec<Point>, Option<Schema>, char) {
+        if args.skip_bad_lines {
+            if !args.quiet {
+                eprintln!("skipped {} malformed lines", data.bad_lines.skipped);
+            }
+            for bad in &data.bad_lines.lines {
+                eprintln!("  {}: {}", bad, bad.text);
+            }
+        }
+        if args.sample.is_some() && args.verbose {
+            eprintln!("sampled {} points", data.points.len());
+        }
+        self.raw = data.raw;
+        self.lines = data.lines;
+        self.labels = data.labels;
+        self.weights = data.weights;
+        (data.points, data.schema, data.delimiter)
+    }
+
+    // Shuffles the points and everything read alongside them alike.
+    fn shuffle(&mut self, seed: u64) {
+        let perm = shuffle_points(&mut self.points, seed);
+        if let Some(raw) = &mut self.raw {
+            permute(&mut raw.rows, &perm);
+        }
+        if let Some(labels) = &mut self.labels {
+            permute(&mut labels.values, &perm);
+        }
+        if let Some(weights) = &mut self.weights {
+            permute(&mut weights.values, &perm);
+        }
+        if let Some(truth) = &mut self.truth {
+            permute(&mut truth.values, &perm);
+        }
+        if let Some(fields) = &mut self.fields {
+            permute(fields, &perm);
+        }
+        self.perm = Some(perm);
+    }
+
+    // Puts what is written alongside the points back in input order, and
+    // returns the points in input order. The points themselves stay shuffled,
+    // as the classes borrow them; the weights are only used with them.
+    fn restore(&mut self, perm: &[usize]) -> Vec<Point> {
+        let mut points = self.points.clone();
+        unshuffle_points(&mut points, perm);
+        if let Some(raw) = &mut self.raw {
+            unpermute(&mut raw.rows, perm);
+        }
+        if let Some(labels) = &mut self.labels {
+            unpermute(&mut labels.values, perm);
+        }
+        if let Some(truth) = &mut self.truth {
+            unpermute(&mut truth.values, perm);
+        }
+        if let Some(fields) = &mut self.fields {
+            unpermute(fields, perm);
+        }
+        points
+    }
+}
+
+// Reads INPUT (and --inputs) with all the reader options, then the
+// --ground-truth labels, and shuffles them all with --shuffle-seed.
+fn read_input(args: &Args, settings: &Settings) -> Result<Input> {
+    let input = &args.input;
+    let mut read = Input {
+        points: Vec::new(),
+        schema: None,
+        separator: ',',
+        raw: None,
+        lines: None,
+        labels: None,
+        weights: None,
+        truth: None,
+        fields: None,
+        perm: None,
+    };
+    (read.points, read.schema, read.separator) = match settings.input_format {
+        FileFormat::Csv if args.cache_bin => {
+            let (points, separator) = read_cached(input, settings.csv.format, args.verbose)?;
+            (points, None, separator)
+        }
+        FileFormat::Csv if args.inputs.is_empty() => {
+            let data = read_csv(open_input(input)?, &settings.csv)?;
+            read.csv_data(args, data)
+        }
+        FileFormat::Csv => {
+            let paths = std::iter::once(input)
+                .chain(&args.inputs)
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            let (data, sources) = read_csv_many(&paths, &settings.csv)?;
+            if args.verbose {
+                for (i, path) in paths.iter().enumerate() {
+                    let n = sources.iter().filter(|&&s| s == i).count();
+                    eprintln!("read {} points from {}", n, path);
+                }
+            }
+            let read_points = read.csv_data(args, data);
+            if args.source_column {
+                let values = sources.iter().map(|&i| paths[i].to_string()).collect();
+                read.labels = Some(Labels {
+                    name: Some("source".to_string()),
+                    values,
+                });
+            }
+            read_points
+        }
+        FileFormat::JsonLines => {
+            let (points, fields) = read_jsonl(input)?;
+            read.fields = Some(fields);
+            (points, None, ',')
+        }
+        FileFormat::Json => {
+            anyhow::bail!("JSON input is not supported; use JSON Lines (.jsonl) or GeoJSON")
+        }
+        FileFormat::Parquet => (read_parquet(input, args.columns.as_deref())?, None, ','),
+        FileFormat::Arrow => (read_arrow(input, args.columns.as_deref())?, None, ','),
+        FileFormat::GeoJson => {
+            let (points, fields) = read_geojson(input, args.columns.as_deref().unwrap_or(&[]))?;
+            read.fields = Some(fields);
+            (points, None, ',')
+        }
+    };
+
+    if let Some(path) = &args.ground_truth {
+        let labels = read_label_lines(open_input(path)?)?;
+        if labels.values.len() != read.points.len() {
+            anyhow::bail!(
+                "{} labels in {} for {} points; it needs one label per point",
+                labels.values.len(),
+                path,
+                read.points.len()
+            );
+        }
+        read.truth = Some(labels);
+    }
+    if let Some(seed) = args.shuffle_seed {
+        read.shuffle(seed);
+    }
+    Ok(read)
 }
 
-fn main() -> Result<()> {
-    let Args {
-        input,
-        output,
+// Clusters the points once per eps, or labels them with the model of
+// --predict, handing every clustering to `write_run`.
+fn cluster(
+    args: &Args,
+    settings: &Settings,
+    mut input: Input,
+    start: Instant,
+    stage: &mut Stage,
+) -> Result<()> {
+    let Settings {
         min_points,
         eps,
-    } = Args::parse();
-
-    let points = read_points_csv(&input)?;
+        metric,
+        ref model,
+        ..
+    } = *settings;
+    let quiet = args.quiet;
+    let verbose = args.verbose;
+    // `classes` borrows the points, so the restored order is written from a
+    // copy; classes are looked up by coordinates.
+    let perm = input.perm.take().filter(|_| args.restore_order);
+    let restored = perm.map(|perm| input.restore(&perm));
+    let points = &input.points;
+    let written = restored.as_ref().unwrap_or(points);
+    // Points are clustered scaled, but written with their original
+    // coordinates: `classes` is mapped back below.
+    let scaled = match args.normalize {
+        Normalize::None => None,
+        Normalize::Minmax => {
+            let scaler = fit_minmax(points)?;
+            Some(
+                points
+                    .iter()
+                    .map(|p| scaler.transform(p))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Normalize::Zscore => {
+            let scaler = fit_zscore(points)?;
+            Some(
+                points
+                    .iter()
+                    .map(|p| scaler.transform(p))
+                    .collect::<Vec<_>>(),
+            )
+        }
+    };
+    let clustered = scaled.as_ref().unwrap_or(points);
+    let read = Instant::now();
 
     // Build a set of references into `points` so the algorithm can refer to them.
-    let point_refs: HashSet<&Point> = points.iter().collect();
+    let point_refs: HashSet<&Point> = clustered.iter().collect();
 
-    let mut engine = RTreeQueryEngine::new();
-    let algo = Algo::new(&mut engine, &point_refs, eps, min_points);
-    let classes = algo.dbscan();
+    let dim = points[0].len();
+    let kind = match args.engine {
+        // A model labels points by the R-tree over its core points.
+        _ if model.is_some() => EngineKind::RTree,
+        Engine::Auto => {
+            // A sweep shares the engine, so it is picked for the largest eps.
+            let hint = eps
+                .or_else(|| args.eps_list.iter().copied().reduce(f64::max))
+                .and_then(|eps| eps_share(clustered, eps, metric));
+            choose_engine_for_metric(points.len(), dim, hint, metric)
+        }
+        Engine::Fake => EngineKind::Fake,
+        Engine::Rtree if dim > RTREE_MAX_DIM => {
+            if !quiet {
+                eprintln!(
+                    "warning: the rtree engine supports up to {} dimensions, not {}; using brute",
+                    RTREE_MAX_DIM, dim
+                );
+            }
+            EngineKind::Brute
+        }
+        Engine::Rtree => EngineKind::RTree,
+        Engine::Brute => EngineKind::Brute,
+    };
+    let auto = matches!(args.engine, Engine::Auto);
+    if args.threads.is_some() && !quiet {
+        if !cfg!(feature = "parallel") {
+            eprintln!("warning: built without the `parallel` feature; --threads has no effect");
+        } else if !parallel_work(kind, args.auto_eps) {
+            eprintln!(
+                "warning: the {} engine does no parallel work in this run; --threads has no effect",
+                kind
+            );
+        }
+    }
+    // Nothing is clustered with --predict, so no engine is built.
+    let mut engine = match (model, metric, &input.weights) {
+        (Some(_), _, _) => None,
+        (None, _, Some(weights)) => {
+            let mut total = HashMap::new();
+            for (p, &w) in clustered.iter().zip(weights.values.iter()) {
+                *total.entry(p).or_insert(0.0) += w;
+            }
+            Some(kind.build_weighted(total))
+        }
+        (None, MetricKind::Euclidean, None) => Some(kind.build()),
+        (None, _, _) => Some(kind.build_with_metric(metric)?),
+    };
+    let algo = engine.as_mut().map(|engine| {
+        let algo = Algo::new(engine.as_mut(), &point_refs, eps.unwrap_or(0.0), min_points);
+        match input.weights {
+            Some(_) => algo.with_min_weight(min_points as f64),
+            None => algo,
+        }
+    });
+    let indexed = Instant::now();
+    if verbose {
+        if auto {
+            eprintln!(
+                "engine: {} (auto, for {} points in {} dimensions)",
+                kind,
+                points.len(),
+                dim
+            );
+        } else {
+            eprintln!("engine: {}", kind);
+        }
+        eprintln!("metric: {:?}", metric);
+        if let Some(algo) = &algo {
+            let bytes = algo.region_query().estimated_memory_bytes();
+            eprintln!(
+                "engine memory: ~{:.1} MiB ({} bytes)",
+                bytes as f64 / (1024.0 * 1024.0),
+                bytes
+            );
+        }
+    }
+    // The engine `Algo::new` initialized also answers the k-NN queries.
+    let eps_values = match eps {
+        Some(eps) => vec![eps],
+        None if !args.eps_list.is_empty() => args.eps_list.clone(),
+        None => {
+            let k = args.auto_eps_k.unwrap_or(min_points);
+            if point_refs.len() < 2 {
+                anyhow::bail!("--auto-eps needs at least 2 distinct points");
+            }
+            if k == 0 || k >= point_refs.len() {
+                anyhow::bail!(
+                    "--auto-eps needs k in 1..={} for {} distinct points; got k={}",
+                    point_refs.len().saturating_sub(1),
+                    point_refs.len(),
+                    k
+                );
+            }
+            let refs = point_refs.iter().copied().collect::<Vec<_>>();
+            let algo = algo.as_ref().expect("--auto-eps conflicts with --predict");
+            let distances = k_distances(algo.region_query(), &refs, k);
+            let eps = knee_eps(&distances).context("no finite k-distances to estimate eps from")?;
+            if !quiet {
+                eprintln!("auto eps: {} (knee of the {}-distances)", eps, k);
+            }
+            vec![eps]
+        }
+    };
+    let sweep = !args.eps_list.is_empty();
 
-    write_clustered_csv(&output, &points, &classes)?;
+    let mut algo = algo;
+    let mut lap = indexed;
+    let mut rows = Vec::new();
+    // Progress goes where the report goes, and only to a terminal. A bar
+    // would garble the data written to stdout when both are on screen or
+    // the output is piped into a pager.
+    let progress = !quiet && args.output != "-" && std::io::stderr().is_terminal();
+    let mut found = false;
+    for &eps in eps_values.iter() {
+        // The previous run of a sweep left the stage at writing.
+        *stage = Stage::Clustering;
+        // No input fails a later run of a sweep only, so the tests of a debug
+        // build can make one panic.
+        #[cfg(debug_assertions)]
+        if std::env::var("DBSCAN_TEST_PANIC_AT_EPS").is_ok_and(|v| v.parse() == Ok(eps)) {
+            panic!("test panic at eps {}", eps);
+        }
+        let bar = (progress && algo.is_some()).then(|| progress_bar(point_refs.len()));
+        algo = algo.map(|algo| {
+            let algo = algo.with_eps(eps);
+            match bar.clone() {
+                Some(bar) => algo.with_progress(move |progress| {
+                    bar.set_position(progress.processed as u64);
+                    bar.set_message(format!("{} clusters", progress.clusters));
+                }),
+                None => algo,
+            }
+        });
+        let (classes, cores) = match (&algo, model) {
+            (_, Some(model)) => {
+                let predicted = model.predict(clustered)?;
+                (clustered.iter().zip(predicted).collect(), HashSet::new())
+            }
+            (Some(algo), None) => algo.dbscan_with_cores(),
+            (None, None) => unreachable!("an engine is built without --predict"),
+        };
+        if let Some(bar) = bar {
+            bar.finish_and_clear();
+        }
+        let mut classes: HashMap<&Point, Class> = match &scaled {
+            Some(scaled) => points
+                .iter()
+                .zip(scaled.iter())
+                .map(|(p, s)| (p, classes[s]))
+                .collect(),
+            None => classes,
+        };
+        // Cluster ids follow the input order, so that the same input gives
+        // the same output; predicted ids are the model's.
+        if model.is_none() {
+            number_clusters_in_order(points, &mut classes);
+        }
+        let dropped = args
+            .min_cluster_size
+            .map_or(0, |m| drop_small_clusters(points, &mut classes, m));
+        // --save-model refuses --normalize, so the cores are in `points`.
+        let fitted = match &args.save_model {
+            Some(_) => Some(Model::fit(&cores, &classes, eps, min_points, metric)?),
+            None => None,
+        };
+        let done = Instant::now();
+        // Both `written` and the labels are in output order.
+        let noise = match args.truth_noise {
+            TruthNoise::Cluster => NoiseHandling::Cluster,
+            TruthNoise::Exclude => NoiseHandling::Exclude,
+        };
+        let scores = input
+            .truth
+            .as_ref()
+            .or(input
+                .labels
+                .as_ref()
+                .filter(|_| args.label_column.is_some()))
+            .and_then(|truth| compare(&labels_in_order(written, &classes), &truth.values, noise));
+        let counts = RunSummary::from_classes(points, &classes);
+        let counts = match &input.weights {
+            Some(weights) => counts.with_weights(points, &classes, &weights.values),
+            None => counts,
+        };
+        let run = RunSummary {
+            input: std::iter::once(args.input.as_str())
+                .chain(args.inputs.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(","),
+            eps,
+            min_points,
+            engine: kind.to_string(),
+            normalization: match args.normalize {
+                Normalize::None => None,
+                Normalize::Minmax => Some("minmax".to_string()),
+                Normalize::Zscore => Some("zscore".to_string()),
+            },
+            min_cluster_size: args.min_cluster_size,
+            dropped_clusters: dropped,
+            ground_truth: scores,
+            elapsed_secs: (done - start).as_secs_f64(),
+            ..counts
+        };
+        found |= run.n_clusters > 0;
+        *stage = Stage::Io;
+        write_run(
+            args,
+            settings,
+            &input,
+            written,
+            &classes,
+            &run,
+            fitted.as_ref(),
+        )?;
+        if sweep {
+            rows.push(run);
+        } else if !quiet {
+            let phases = [
+                ("read", read - start),
+                ("index", indexed - read),
+                ("cluster", done - lap),
+                ("write", done.elapsed()),
+            ];
+            print_report(&run, &phases, verbose);
+        }
+        lap = Instant::now();
+    }
+    if sweep {
+        if !quiet {
+            print_sweep(&rows);
+        }
+        if let Some(path) = &args.sweep_summary {
+            write_sweep(path, &rows)?;
+        }
+    }
+    if args.fail_if_no_clusters && !found {
+        *stage = Stage::NoClusters;
+        anyhow::bail!("no clusters found; every point is noise");
+    }
     Ok(())
 }
+
+// Writes the clustering `run` summarizes: OUTPUT (named after the eps with
+// --eps-list), the --summary, --save-model, --split-output, --sqlite and
+// --plot files, and the agreement with the --label-column labels. `written`
+// are the points in output order.
+fn write_run(
+    args: &Args,
+    settings: &Settings,
+    input: &Input,
+    written: &[Point],
+    classes: &HashMap<&Point, Class>,
+    run: &RunSummary,
+    model: Option<&Model>,
+) -> Result<()> {
+    let eps = run.eps;
+    let sweep = !args.eps_list.is_empty();
+    // Every run of --eps-list gets files of its own, named after its eps.
+    let path = |path: &String| {
+        if sweep {
+            sweep_path(path, eps)
+        } else {
+            path.clone()
+        }
+    };
+    if let Some(summary) = &args.summary {
+        write_summary(&path(summary), run)?;
+    }
+    if let (Some(path), Some(model)) = (&args.save_model, model) {
+        write_model(path, model)?;
+    }
+    if let Some(labels) = input
+        .labels
+        .as_ref()
+        .filter(|_| args.label_column.is_some() && !args.quiet)
+    {
+        if sweep {
+            eprint!("eps {}: ", eps);
+        }
+        print_agreement(&labels_in_order(written, classes), &labels.values);
+    }
+
+    let missing = args.only_cluster.iter().find(|&&id| id >= run.n_clusters);
+    if let Some(missing) = missing.filter(|_| !args.quiet) {
+        eprintln!(
+            "warning: --only-cluster {} names no cluster; there are {}",
+            missing, run.n_clusters
+        );
+    }
+    let only = if args.only_noise {
+        Some(Only::Noise)
+    } else if !args.only_cluster.is_empty() {
+        Some(Only::Clusters(args.only_cluster.iter().copied().collect()))
+    } else {
+        None
+    };
+    let mut order = only.as_ref().map(|only| {
+        let rows = select_rows(written, classes, |class| only.keeps(class));
+        if args.verbose {
+            eprintln!("writing {} of {} points", rows.len(), written.len());
+        }
+        rows
+    });
+    if args.sort_output == SortOutput::Cluster {
+        let rows = order.get_or_insert_with(|| (0..written.len()).collect());
+        sort_rows_by_cluster(written, classes, rows);
+    }
+    let kept;
+    let (written, raw, lines, labels, fields) = match order {
+        Some(rows) => {
+            kept = Kept {
+                points: pick(written, &rows),
+                raw: input.raw.as_ref().map(|raw| RawRows {
+                    header: raw.header.clone(),
+                    rows: pick(&raw.rows, &rows),
+                }),
+                lines: input.lines.as_ref().map(|lines| RawLines {
+                    header: lines.header.clone(),
+                    lines: pick(&lines.lines, &rows),
+                }),
+                labels: input.labels.as_ref().map(|labels| Labels {
+                    name: labels.name.clone(),
+                    values: pick(&labels.values, &rows),
+                }),
+                fields: input.fields.as_ref().map(|fields| pick(fields, &rows)),
+            };
+            (
+                kept.points.as_slice(),
+                kept.raw.as_ref(),
+                kept.lines.as_ref(),
+                kept.labels.as_ref(),
+                kept.fields.as_ref(),
+            )
+        }
+        None => (
+            written,
+            input.raw.as_ref(),
+            input.lines.as_ref(),
+            input.labels.as_ref(),
+            input.fields.as_ref(),
+        ),
+    };
+    let (schema, separator, precision) = (input.schema.as_ref(), input.separator, args.precision);
+    let output = path(&args.output);
+    if let Some(dir) = &args.split_output {
+        write_clusters_split_with_format(
+            dir, schema, separator, precision, written, classes, args.force,
+        )?;
+    }
+    if let Some(db) = &args.sqlite {
+        write_sqlite(db, &args.sqlite_table, schema, written, classes)?;
+    }
+    if let Some(lines) = lines {
+        write_clustered_csv_verbatim(&output, lines, separator, written, classes)?;
+    } else if let Some(raw) = raw {
+        write_clustered_csv_passthrough(&output, raw, schema, separator, written, classes, None)?;
+    } else {
+        match (settings.output_format, labels) {
+            (FileFormat::Csv, Some(labels)) => write_clustered_csv_labeled(
+                &output, schema, separator, precision, written, labels, classes,
+            )?,
+            (FileFormat::Csv, None) => write_clustered_csv_with_format(
+                &output, schema, separator, precision, written, classes,
+            )?,
+            (FileFormat::JsonLines, _) => {
+                let fields = fields.filter(|_| args.keep_fields);
+                write_jsonl(&output, written, classes, fields)?
+            }
+            (FileFormat::Json, _) => {
+                write_json(&output, written, classes, args.json_style, args.pretty)?
+            }
+            (FileFormat::Arrow, _) => write_arrow(&output, schema, written, classes)?,
+            // GeoJSON properties are kept whenever the input had them.
+            (FileFormat::GeoJson, _) => write_geojson(&output, written, classes, fields)?,
+            (FileFormat::Parquet, _) => unreachable!("rejected by Settings::new"),
+        }
+    }
+    if let Some(plot) = &args.plot {
+        let options = PlotOptions {
+            x_col: args.plot_x_col,
+            y_col: args.plot_y_col,
+            width: args.plot_width,
+            height: args.plot_height,
+            title: format!("DBSCAN, eps = {}, min_points = {}", eps, run.min_points),
+            ..PlotOptions::default()
+        };
+        write_png(&path(plot), written, classes, schema, &options)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+
+    fn parse(extra: &[&str]) -> std::result::Result<Args, clap::Error> {
+        let args = ["dbscan", "in.csv", "out.csv", "5", "0.3"];
+        Args::try_parse_from(args.iter().chain(extra))
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn csv_reader_options_combine() {
+        for flags in [
+            &["--skip-bad-lines", "--label-column", "2"][..],
+            &["--skip-bad-lines", "--weights-column", "2"],
+            &["--sample", "10", "--label-column", "2"],
+            &["--passthrough", "--label-column", "2"],
+            &[
+                "--preserve-input",
+                "--weights-column",
+                "2",
+                "--skip-bad-lines",
+            ],
+            &["--inputs", "more.csv", "--sample", "10", "--passthrough"],
+        ] {
+            assert!(parse(flags).is_ok(), "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn cache_bin_reads_only_coordinates() {
+        for flags in [
+            &["--label-column", "2"][..],
+            &["--sample", "10"],
+            &["--inputs", "more.csv"],
+            &["--passthrough"],
+        ] {
+            let err = parse(&[&["--cache-bin"][..], flags].concat()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn ground_truth_needs_every_row() {
+        let truth = ["--ground-truth", "truth.txt"];
+        for flags in [
+            &["--sample", "10"][..],
+            &["--skip-bad-lines"],
+            &["--max-rows", "10"],
+            &["--label-column", "2"],
+        ] {
+            let err = parse(&[&truth[..], flags].concat()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{:?}", flags);
+        }
+        assert!(parse(&[&truth[..], &["--inputs", "more.csv"]].concat()).is_ok());
+    }
+
+    #[test]
+    fn predict_takes_the_parameters_of_the_model() {
+        let predict = ["dbscan", "in.csv", "out.csv", "--predict", "model.bin"];
+        assert!(Args::try_parse_from(predict).is_ok());
+        for flags in [
+            &["5"][..],
+            &["--min-pts", "5"],
+            &["--eps", "0.3"],
+            &["--auto-eps"],
+            &["--metric", "manhattan"],
+            &["--engine", "brute"],
+            &["--save-model", "other.bin"],
+        ] {
+            let err = Args::try_parse_from(predict.iter().chain(flags)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict, "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn sweep_outputs_are_named_after_their_eps() {
+        assert_eq!(sweep_path("out.csv", 0.5), "out.eps0.5.csv");
+        assert_eq!(sweep_path("dir/out.csv.gz", 2.0), "dir/out.eps2.csv.gz");
+        assert_eq!(sweep_path("out", 0.25), "out.eps0.25");
+        assert_eq!(sweep_path("out.gz", 1e-3), "out.eps0.001.gz");
+        let err = parse(&["--eps-list", "0.1,0.2"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn panics_become_errors_of_their_stage() {
+        assert_eq!(catch_panic(|| Ok(7)).unwrap(), 7);
+        let err = catch_panic::<()>(|| anyhow::bail!("failed")).unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+
+        let err = catch_panic::<()>(|| panic!("static")).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: static");
+        let err = catch_panic::<()>(|| panic!("formatted {}", 3)).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: formatted 3");
+        // Also from the pool the clustering runs on.
+        let mut stage = Stage::Clustering;
+        let err = catch_panic::<()>(|| {
+            with_threads(Some(2), false, || {
+                stage = Stage::Io;
+                panic!("in the pool")
+            })?
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "panicked: in the pool");
+        assert_eq!(stage, Stage::Io);
+    }
+}
@@ -1,11 +1,12 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use plotters::prelude::*;
-use std::collections::HashSet;
 use rust::algo::RegionQuery;
+use rust::cli::CsvArgs;
+use rust::io::{open_input, read_csv, write_rgb_png, Columns, CsvOptions};
 use rust::query::RTreeQueryEngine;
-use rust::io::read_points_csv;
 use rust::types::Point;
+use std::collections::HashSet;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -15,15 +16,32 @@ use rust::types::Point;
     about = "k-distance plot using RTree (real) query engine"
 )]
 struct Args {
-    /// Input CSV of points: x1,x2,... per line (no header)
+    /// Input CSV of points: x1,x2,... per line (`-` for stdin)
     input: String,
-    /// Output PNG path for the k-distance plot
+    /// Output PNG path for the k-distance plot (`-` for stdout)
     output: String,
 
     /// k for k-distance (k-th nearest neighbor, excluding self)
     #[arg(long, short = 'k', default_value_t = 4)]
     k: usize,
 
+    #[command(flatten)]
+    csv: CsvArgs,
+    /// Columns to read as coordinates: indices (from 0), ranges such as
+    /// `2..10`, or header names (default: all columns)
+    #[arg(long, value_delimiter = ',')]
+    columns: Option<Vec<String>>,
+    /// Columns not to read as coordinates, given as for --columns; all the
+    /// others are read unless --columns lists them
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    exclude_columns: Vec<String>,
+    /// Plot a uniform random sample of this many rows, picked while reading
+    #[arg(long, value_name = "N", conflicts_with = "max_rows")]
+    sample: Option<usize>,
+    /// Seed of --sample; the same seed picks the same rows
+    #[arg(long, value_name = "S", default_value_t = 0, requires = "sample")]
+    seed: u64,
+
     /// Image width in pixels
     #[arg(long, default_value_t = 1200)]
     width: u32,
@@ -50,7 +68,16 @@ fn compute_k_distances(points: &[Point], k: usize) -> Result<Vec<f64>> {
 }
 
 fn draw_plot(values: &[f64], args: &Args) -> Result<()> {
-    let root = BitMapBackend::new(&args.output, (args.width, args.height)).into_drawing_area();
+    // `-` renders into memory and writes the PNG to stdout.
+    let size = (args.width, args.height);
+    let mut rgb = Vec::new();
+    let backend = if args.output == "-" {
+        rgb.resize(size.0 as usize * size.1 as usize * 3, 0);
+        BitMapBackend::with_buffer(&mut rgb, size)
+    } else {
+        BitMapBackend::new(&args.output, size)
+    };
+    let root = backend.into_drawing_area();
     root.fill(&WHITE)?;
 
     // Sort descending (common for k-dist plots to inspect the knee)
@@ -88,12 +115,21 @@ fn draw_plot(values: &[f64], args: &Args) -> Result<()> {
     ))?;
 
     root.present().context("failed to write image")?;
+    drop(chart);
+    drop(root);
+    if args.output == "-" {
+        write_rgb_png("-", &rgb, size.0, size.1)?;
+    }
     Ok(())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let points = read_points_csv(&args.input)?;
+    let columns = Columns::from_flags(args.columns.as_deref(), &args.exclude_columns)?;
+    let options = CsvOptions::new(args.csv.format())
+        .with_columns(columns)
+        .with_sample(args.sample, args.seed);
+    let points = read_csv(open_input(&args.input)?, &options)?.points;
     if points.len() < 2 {
         anyhow::bail!("at least 2 points are required");
     }
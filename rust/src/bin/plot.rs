@@ -1,7 +1,11 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use plotters::prelude::*;
-use rust::io::read_clustered_csv;
+use rust::cli::CsvArgs;
+use rust::io::read_clustered_points_with_format;
+use rust::pca;
+use rust::plot::{write_png, PlotOptions};
+use rust::types::Schema;
+use std::collections::HashMap;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -11,9 +15,9 @@ use rust::io::read_clustered_csv;
     about = "Plot clustered CSV (cid,x1,x2,...) using Plotters"
 )]
 struct Args {
-    /// Input CSV file: cid,x1,x2,... per line
+    /// Input CSV file: cid,x1,x2,... per line (`-` for stdin)
     input: String,
-    /// Output image path (PNG), e.g., out.png
+    /// Output image path (PNG), e.g., out.png (`-` for stdout)
     output: String,
 
     /// X coordinate column index in the point (0-based, excluding cid)
@@ -23,6 +27,17 @@ struct Args {
     #[arg(long, default_value_t = 1)]
     y_col: usize,
 
+    // A header (cid,name1,name2,...) labels the axes.
+    #[command(flatten)]
+    csv: CsvArgs,
+    /// X coordinate column by name instead of --x-col (see --header; with
+    /// --pca the components are named PC1, PC2, ...)
+    #[arg(long, conflicts_with = "x_col")]
+    x_name: Option<String>,
+    /// Y coordinate column by name instead of --y-col
+    #[arg(long, conflicts_with = "y_col")]
+    y_name: Option<String>,
+
     /// Image width in pixels
     #[arg(long, default_value_t = 1000)]
     width: u32,
@@ -34,94 +49,51 @@ struct Args {
     #[arg(long, default_value_t = 2)]
     point_size: i32,
 
+    /// Project the points onto their top K principal components first;
+    /// --x-col/--y-col then index the components
+    #[arg(long, value_name = "K")]
+    pca: Option<usize>,
+
+    /// Mark the centroid of every cluster with a cross
+    #[arg(long)]
+    centroids: bool,
+
     /// Optional plot title
     #[arg(long, default_value = "Clustering Plot")]
     title: String,
 }
 
-#[derive(Clone, Copy)]
-struct Sample {
-    cid: isize,
-    x: f64,
-    y: f64,
-}
-
-fn compute_ranges(samples: &[Sample]) -> ((f64, f64), (f64, f64)) {
-    let mut x_min = f64::INFINITY;
-    let mut x_max = f64::NEG_INFINITY;
-    let mut y_min = f64::INFINITY;
-    let mut y_max = f64::NEG_INFINITY;
-    for s in samples {
-        x_min = x_min.min(s.x);
-        x_max = x_max.max(s.x);
-        y_min = y_min.min(s.y);
-        y_max = y_max.max(s.y);
-    }
-    // Add a small margin
-    let x_span = (x_max - x_min).abs();
-    let y_span = (y_max - y_min).abs();
-    let mx = if x_span == 0.0 { 1.0 } else { x_span * 0.05 };
-    let my = if y_span == 0.0 { 1.0 } else { y_span * 0.05 };
-    ((x_min - mx, x_max + mx), (y_min - my, y_max + my))
-}
-
-fn color_for(cid: isize) -> ShapeStyle {
-    if cid < 0 {
-        return BLACK.mix(0.3).filled();
-    }
-    // Map cluster id to a palette color deterministically.
-    let idx = (cid as usize) % Palette99::COLORS.len();
-    let c = Palette99::pick(idx).mix(0.9);
-    c.filled()
-}
-
-fn draw(samples: &[Sample], args: &Args) -> Result<()> {
-    let root = BitMapBackend::new(&args.output, (args.width, args.height)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let ((x_min, x_max), (y_min, y_max)) = compute_ranges(samples);
-
-    let mut chart = ChartBuilder::on(&root)
-        .margin(15)
-        .caption(args.title.clone(), ("sans-serif", 20))
-        .set_label_area_size(LabelAreaPosition::Left, 40)
-        .set_label_area_size(LabelAreaPosition::Bottom, 40)
-        .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
-
-    chart.configure_mesh().x_desc("x").y_desc("y").draw()?;
-
-    chart.draw_series(samples.iter().map(|s| {
-        let style = color_for(s.cid);
-        Circle::new((s.x, s.y), args.point_size, style)
-    }))?;
-
-    root.present().context("failed to write image")?;
-    Ok(())
-}
-
 fn main() -> Result<()> {
     let args = Args::parse();
-    let rows = read_clustered_csv(&args.input)?;
-    if rows.is_empty() {
+    let (schema, (mut points, classes)) =
+        read_clustered_points_with_format(&args.input, args.csv.format())?;
+    let mut schema =
+        schema.unwrap_or_else(|| Schema::numbered(points.first().map_or(0, |p| p.len())));
+    if points.is_empty() {
         anyhow::bail!("no samples found in input");
     }
-    let samples = rows
-        .into_iter()
-        .map(|(cid, coords)| {
-            let px = args.x_col;
-            let py = args.y_col;
-            if px >= coords.len() || py >= coords.len() {
-                anyhow::bail!(
-                    "x_col/y_col out of bounds for {} data columns",
-                    coords.len()
-                );
-            }
-            Ok(Sample {
-                cid,
-                x: coords[px],
-                y: coords[py],
-            })
-        })
-        .collect::<Result<Vec<_>>>()?;
-    draw(&samples, &args)
+    if let Some(k) = args.pca {
+        let pca = pca::fit(&points, k)?;
+        for p in points.iter_mut() {
+            *p = pca.transform(p);
+        }
+        schema = Schema::new((1..=k).map(|i| format!("PC{}", i)).collect())?;
+    }
+    let column = |name: &Option<String>, col: usize| match name {
+        Some(name) => schema
+            .index_of(name)
+            .with_context(|| format!("no column named '{}'", name)),
+        None => Ok(col),
+    };
+    let options = PlotOptions {
+        x_col: column(&args.x_name, args.x_col)?,
+        y_col: column(&args.y_name, args.y_col)?,
+        width: args.width,
+        height: args.height,
+        point_size: args.point_size,
+        centroids: args.centroids,
+        title: args.title,
+    };
+    let classes = points.iter().zip(classes).collect::<HashMap<_, _>>();
+    write_png(&args.output, &points, &classes, Some(&schema), &options)
 }
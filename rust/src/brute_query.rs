@@ -0,0 +1,602 @@
+use crate::algo::{RegionQuery, UsesMetric};
+use crate::types::{
+    check_aniso_eps, eps_sq_threshold, BitPoint, CloudView, Euclidean, Float, Metric, Point,
+    PointCloud, PointN, SparsePoint,
+};
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+// Rows are scanned in blocks of this many, distances for a block being
+// computed into a small buffer before the matching rows are collected.
+const BLOCK_ROWS: usize = 64;
+
+/// Row-major coordinates scanned by `BruteForceQueryEngine`: the `PointCloud`
+/// it builds in `init`, a `CloudView` borrowed through `from_cloud` or
+/// `from_view`, or `PointN<D>`s borrowed through `from_static`.
+pub trait Rows<T: Float> {
+    fn len(&self) -> usize;
+
+    fn dim(&self) -> usize;
+
+    /// Coordinates of the `i`-th row.
+    fn row(&self, i: usize) -> &[T];
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Float> Rows<T> for PointCloud<T> {
+    fn len(&self) -> usize {
+        PointCloud::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        PointCloud::dim(self)
+    }
+
+    fn row(&self, i: usize) -> &[T] {
+        PointCloud::row(self, i)
+    }
+}
+
+impl<T: Float> Rows<T> for CloudView<'_, T> {
+    fn len(&self) -> usize {
+        CloudView::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        CloudView::dim(self)
+    }
+
+    fn row(&self, i: usize) -> &[T] {
+        CloudView::row(self, i)
+    }
+}
+
+/// Exact brute-force engine. `init` copies all coordinates into a `PointCloud`,
+/// one contiguous row-major buffer (N x D), so queries scan memory linearly instead of chasing
+/// a pointer per point. O(N * D) memory and O(N * D) per query.
+///
+/// Rows are kept in the points' scalar `T`, so `f32` points take half the
+/// memory; distances are computed in `f64`.
+///
+/// `S` is where the rows live. Engines built by `from_cloud` or `from_view`
+/// scan borrowed coordinates in place (e.g. memory-mapped by
+/// `io::mmap_points`) without building any `Point`s, and answer `run_rows` by
+/// row index instead of the `RegionQuery` methods.
+pub struct BruteForceQueryEngine<'a, M: Metric = Euclidean, T: Float = f64, S = PointCloud<T>> {
+    // `points[i]` is the point stored in row `i` of `rows`; empty unless the
+    // engine was initialized through `RegionQuery::init`.
+    rows: S,
+    points: Vec<&'a Point<T>>,
+    weights: Option<HashMap<&'a Point<T>, f64>>,
+    metric: M,
+}
+
+impl<const D: usize> Rows<f64> for &[PointN<D>] {
+    fn len(&self) -> usize {
+        <[PointN<D>]>::len(self)
+    }
+
+    fn dim(&self) -> usize {
+        D
+    }
+
+    fn row(&self, i: usize) -> &[f64] {
+        &self[i].0
+    }
+}
+
+impl<'a, T: Float> BruteForceQueryEngine<'a, Euclidean, T> {
+    pub fn new() -> Self {
+        BruteForceQueryEngine::with_metric(Euclidean)
+    }
+
+    /// Engine whose weight queries use `weights` (keyed by indexed point)
+    /// instead of counting points. Points without an entry weigh 1.
+    pub fn with_weights(weights: HashMap<&'a Point<T>, f64>) -> Self {
+        Self {
+            weights: Some(weights),
+            ..Self::new()
+        }
+    }
+}
+
+impl<'a, T: Float> BruteForceQueryEngine<'a, Euclidean, T, CloudView<'a, T>> {
+    /// Engine over the rows of `cloud`, without copying them or building a
+    /// `Point` per row. Query it with `run_rows`.
+    pub fn from_cloud(cloud: &'a PointCloud<T>) -> Self {
+        Self::from_view(cloud.view())
+    }
+
+    /// Like `from_cloud`, for coordinates stored outside a `PointCloud`.
+    pub fn from_view(view: CloudView<'a, T>) -> Self {
+        Self {
+            rows: view,
+            points: Vec::new(),
+            weights: None,
+            metric: Euclidean,
+        }
+    }
+}
+
+impl<'a, const D: usize> BruteForceQueryEngine<'a, Euclidean, f64, &'a [PointN<D>]> {
+    /// Engine over points whose dimension `D` is known at compile time, so
+    /// that every row is a fixed-size array. Like `from_cloud`, it is queried
+    /// with `run_rows`, and points with equal coordinates stay distinct rows.
+    pub fn from_static(points: &'a [PointN<D>]) -> Self {
+        Self {
+            rows: points,
+            points: Vec::new(),
+            weights: None,
+            metric: Euclidean,
+        }
+    }
+}
+
+impl<M: Metric, T: Float> BruteForceQueryEngine<'_, M, T> {
+    /// Engine that measures distances with `metric` instead of Euclidean.
+    /// Metrics that override `Metric::key_coords` work on the coordinate buffer
+    /// directly.
+    pub fn with_metric(metric: M) -> Self {
+        Self {
+            rows: PointCloud::default(),
+            points: Vec::new(),
+            weights: None,
+            metric,
+        }
+    }
+}
+
+impl<'a, M: Metric, T: Float, S: Rows<T>> BruteForceQueryEngine<'a, M, T, S> {
+    /// Indices of the rows within `eps` of `point`, in increasing order. Like
+    /// `RegionQuery::run`, the bound is inclusive. Panics if the dimensions
+    /// differ.
+    pub fn run_rows(&self, point: &[T], eps: f64) -> Vec<usize> {
+        let q = self.query_row(point.iter().copied());
+        let bound = self.metric.key_bound(eps);
+        let mut out = Vec::new();
+        self.for_each_key(&q, |i, d| {
+            if d <= bound {
+                out.push(i);
+            }
+        });
+        out
+    }
+
+    fn weight_of(&self, point: &Point<T>) -> f64 {
+        match &self.weights {
+            Some(weights) => weights.get(point).copied().unwrap_or(1.0),
+            None => 1.0,
+        }
+    }
+
+    fn query_row(&self, point: impl ExactSizeIterator<Item = T>) -> Vec<f64> {
+        assert_eq!(
+            point.len(),
+            self.rows.dim(),
+            "query point dimension {} does not match indexed dimension {}",
+            point.len(),
+            self.rows.dim()
+        );
+        point.map(T::into_f64).collect()
+    }
+
+    // Calls `f(row, key)` for every indexed row, with `Metric::key_coords` as
+    // the key.
+    fn for_each_key(&self, q: &[f64], mut f: impl FnMut(usize, f64)) {
+        let _ = self.try_for_each_key(q, |i, d| {
+            f(i, d);
+            ControlFlow::Continue(())
+        });
+    }
+
+    // Like `for_each_key`, stopping after the first row for which `f` breaks,
+    // so that no later block is measured.
+    fn try_for_each_key(
+        &self,
+        q: &[f64],
+        mut f: impl FnMut(usize, f64) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let n = self.rows.len();
+        let mut buf = [0.0; BLOCK_ROWS];
+        let mut row_buf = Vec::with_capacity(self.rows.dim());
+
+        for start in (0..n).step_by(BLOCK_ROWS) {
+            let block = start..(start + BLOCK_ROWS).min(n);
+            for (d, i) in buf.iter_mut().zip(block.clone()) {
+                *d = self
+                    .metric
+                    .key_coords(T::as_f64s(self.rows.row(i), &mut row_buf), q);
+            }
+            for (i, &d) in block.zip(buf.iter()) {
+                f(i, d)?;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Calls `f(row, key)` for every row, with `point` as the query. Does
+    // nothing before `init`, when the dimension is not known yet.
+    fn for_each_point_key(&self, point: &Point<T>, f: impl FnMut(usize, f64)) {
+        if self.rows.is_empty() {
+            return;
+        }
+        self.for_each_key(&self.query_row(point.iter().map(|x| x.0)), f);
+    }
+
+    // Like `for_each_point_key`, stopping where `f` breaks.
+    fn try_for_each_point_key(
+        &self,
+        point: &Point<T>,
+        f: impl FnMut(usize, f64) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        if self.rows.is_empty() {
+            return ControlFlow::Continue(());
+        }
+        self.try_for_each_key(&self.query_row(point.iter().map(|x| x.0)), f)
+    }
+
+    // Keys of every indexed point except the query itself.
+    fn neighbor_keys(&self, point: &Point<T>) -> Vec<(usize, f64)> {
+        let mut out = Vec::with_capacity(self.points.len());
+        self.for_each_point_key(point, |i, d| {
+            if !std::ptr::eq(self.points[i], point) {
+                out.push((i, d));
+            }
+        });
+        out
+    }
+
+    // Moves the k-th smallest key to index `k - 1`, with smaller ones before
+    // it, and returns it.
+    fn select_kth(&self, dists: &mut [(usize, f64)], k: usize) -> f64 {
+        assert!(
+            !self.rows.is_empty(),
+            "BruteForceQueryEngine is not initialized"
+        );
+        assert!(k > 0, "k must be >= 1");
+        if k > dists.len() {
+            panic!("k={} is out of range for dataset", k);
+        }
+        dists.select_nth_unstable_by(k - 1, |a, b| a.1.total_cmp(&b.1));
+        dists[k - 1].1
+    }
+}
+
+impl<T: Float> Default for BruteForceQueryEngine<'_, Euclidean, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Metric, T: Float, S> UsesMetric<M> for BruteForceQueryEngine<'_, M, T, S> {}
+
+impl<'a, M: Metric, T: Float> RegionQuery<'a, T> for BruteForceQueryEngine<'a, M, T> {
+    fn init(&mut self, points: &'a HashSet<&'a Point<T>>) {
+        self.points = points.iter().copied().collect();
+        self.rows = PointCloud::from_points(self.points.iter().copied());
+    }
+
+    fn run(&self, point: &Point<T>, eps: f64) -> HashSet<&'a Point<T>> {
+        self.run_filtered(point, eps, &|_| true)
+    }
+
+    fn run_filtered(
+        &self,
+        point: &Point<T>,
+        eps: f64,
+        pred: &dyn Fn(&Point<T>) -> bool,
+    ) -> HashSet<&'a Point<T>> {
+        let bound = self.metric.key_bound(eps);
+        let mut out = HashSet::new();
+        self.for_each_point_key(point, |i, d| {
+            if d <= bound && pred(self.points[i]) {
+                out.insert(self.points[i]);
+            }
+        });
+        out
+    }
+
+    fn k_dist(&self, point: &Point<T>, k: usize) -> f64 {
+        let mut keys = self.neighbor_keys(point);
+        let kth = self.select_kth(&mut keys, k);
+        self.metric.key_to_dist(kth)
+    }
+
+    fn k_dist_with_count(&self, point: &Point<T>, k: usize) -> (f64, usize) {
+        let mut keys = self.neighbor_keys(point);
+        let d = self.metric.key_to_dist(self.select_kth(&mut keys, k));
+        let bound = self.metric.key_bound(d);
+        (d, keys.iter().filter(|&&(_, x)| x <= bound).count())
+    }
+
+    fn run_aniso(&self, point: &Point<T>, eps_per_dim: &[f64]) -> HashSet<&'a Point<T>> {
+        check_aniso_eps(point, eps_per_dim);
+        if self.points.is_empty() {
+            return HashSet::new();
+        }
+
+        let q = self.query_row(point.iter().map(|x| x.0));
+        self.rows
+            .rows()
+            .zip(self.points.iter())
+            .filter(|(row, _)| {
+                row.iter()
+                    .zip(q.iter())
+                    .zip(eps_per_dim.iter())
+                    .map(|((x, y), e)| {
+                        let d = (x.into_f64() - y) / e;
+                        d * d
+                    })
+                    .sum::<f64>()
+                    <= 1.0
+            })
+            .map(|(_, &p)| p)
+            .collect()
+    }
+
+    fn weight_within(&self, point: &Point<T>, eps: f64) -> f64 {
+        let bound = self.metric.key_bound(eps);
+        let mut total = 0.0;
+        self.for_each_point_key(point, |i, d| {
+            if d <= bound {
+                total += self.weight_of(self.points[i]);
+            }
+        });
+        total
+    }
+
+    // Stops after the block holding the neighbor that brings the weight to
+    // `threshold`, like the other engines stop at that neighbor.
+    fn weight_within_at_least(&self, point: &Point<T>, eps: f64, threshold: f64) -> bool {
+        if threshold <= 0.0 {
+            return true;
+        }
+
+        let bound = self.metric.key_bound(eps);
+        let mut total = 0.0;
+        self.try_for_each_point_key(point, |i, d| {
+            if d <= bound {
+                total += self.weight_of(self.points[i]);
+                if total >= threshold {
+                    return ControlFlow::Break(());
+                }
+            }
+            ControlFlow::Continue(())
+        })
+        .is_break()
+    }
+
+    fn knn(&self, point: &Point<T>, k: usize) -> Vec<(&'a Point<T>, f64)> {
+        let mut keys = self.neighbor_keys(point);
+        let k = k.min(keys.len());
+        if k == 0 {
+            return Vec::new();
+        }
+        keys.select_nth_unstable_by(k - 1, |a, b| a.1.total_cmp(&b.1));
+        keys.truncate(k);
+        keys.sort_by(|a, b| a.1.total_cmp(&b.1));
+        keys.into_iter()
+            .map(|(i, key)| (self.points[i], self.metric.key_to_dist(key)))
+            .collect()
+    }
+
+    fn estimated_memory_bytes(&self) -> usize {
+        size_of_val(self.rows.as_slice()) + self.points.capacity() * size_of::<&Point<T>>()
+    }
+}
+
+/// Brute-force engine over `SparsePoint`s. Each distance is a merge over the
+/// nonzero entries of the two points, so a query costs O(total nonzeros)
+/// instead of O(N * D). Points are identified by their index in the slice.
+pub struct SparseBruteForceQueryEngine<'a> {
+    points: &'a [SparsePoint],
+}
+
+impl<'a> SparseBruteForceQueryEngine<'a> {
+    pub fn new(points: &'a [SparsePoint]) -> Self {
+        Self { points }
+    }
+
+    pub fn points(&self) -> &'a [SparsePoint] {
+        self.points
+    }
+
+    /// Indices of the points `p` with `point.dist(p) <= eps`, in increasing
+    /// order. Panics if the dimensions differ.
+    pub fn run(&self, point: &SparsePoint, eps: f64) -> Vec<usize> {
+        let bound = eps_sq_threshold(eps);
+        self.points
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.dist_sq(point) <= bound)
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Brute-force engine over `BitPoint`s with Hamming distance: each distance is
+/// one XOR and popcount per 64-bit word. Points are identified by their index
+/// in the slice.
+pub struct BitBruteForceQueryEngine<'a> {
+    points: &'a [BitPoint],
+}
+
+impl<'a> BitBruteForceQueryEngine<'a> {
+    pub fn new(points: &'a [BitPoint]) -> Self {
+        Self { points }
+    }
+
+    pub fn points(&self) -> &'a [BitPoint] {
+        self.points
+    }
+
+    /// Indices of the points `p` with `point.hamming(p) <= eps`, in increasing
+    /// order. Panics if the lengths differ.
+    pub fn run(&self, point: &BitPoint, eps: u32) -> Vec<usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.hamming(point) <= eps)
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_query::FakeQueryEngine;
+    use crate::query::RTreeQueryEngine;
+    use crate::sampling::SplitMix64;
+    use ordered_float::OrderedFloat;
+
+    fn random_points(n: usize, dim: usize, seed: u64) -> Vec<Point> {
+        let mut rng = SplitMix64::new(seed);
+        (0..n)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| OrderedFloat(rng.below(200) as f64 / 20.0))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn zero_dimensional_points_are_all_neighbors() {
+        // `init` takes a set, which keeps one of several equal points, so the
+        // rows are indexed as `init` would, from distinct references.
+        let points: [Point; 3] = [Point::new(), Point::new(), Point::new()];
+        let mut engine = BruteForceQueryEngine::new();
+        engine.points = points.iter().collect();
+        engine.rows = PointCloud::from_points(engine.points.iter().copied());
+        assert_eq!(engine.rows.len(), 3);
+
+        assert_eq!(engine.weight_within(&points[0], 0.5), 3.0);
+        assert!(engine.weight_within_at_least(&points[0], 0.5, 3.0));
+        assert_eq!(engine.k_dist(&points[0], 2), 0.0);
+        assert_eq!(engine.k_dist_with_count(&points[0], 2), (0.0, 2));
+        let knn = engine.knn(&points[0], 5);
+        assert_eq!(knn.len(), 2);
+        assert!(
+            knn.iter()
+                .all(|&(p, d)| d == 0.0 && !std::ptr::eq(p, &points[0]))
+        );
+
+        let mut cloud = PointCloud::<f64>::new(0);
+        (0..3).for_each(|_| cloud.push([]));
+        let engine = BruteForceQueryEngine::from_cloud(&cloud);
+        assert_eq!(engine.run_rows(&[], 0.5), [0, 1, 2]);
+    }
+
+    #[test]
+    fn weight_threshold_stops_after_the_block_that_reaches_it() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Ten blocks of points, all within eps of each other.
+        let points = (0..10 * BLOCK_ROWS)
+            .map(|x| vec![OrderedFloat(x as f64)])
+            .collect::<Vec<Point>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let calls = AtomicUsize::new(0);
+        let counting = |a: &Point, b: &Point| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            (a[0].0 - b[0].0).abs()
+        };
+        let mut engine = BruteForceQueryEngine::with_metric(&counting);
+        engine.init(&refs);
+
+        assert!(engine.weight_within_at_least(&points[0], 1e4, 3.0));
+        assert_eq!(calls.swap(0, Ordering::Relaxed), BLOCK_ROWS);
+        assert!(engine.weight_within_at_least(&points[0], 1e4, BLOCK_ROWS as f64 + 1.0));
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 2 * BLOCK_ROWS);
+        // A threshold above the whole neighborhood still walks all of it.
+        assert!(!engine.weight_within_at_least(&points[0], 1e4, 1e4));
+        assert_eq!(calls.swap(0, Ordering::Relaxed), points.len());
+        assert_eq!(engine.weight_within(&points[0], 1e4), points.len() as f64);
+    }
+
+    // Enough rows for several blocks, with a partial one at the end.
+    #[test]
+    fn matches_fake_and_rtree_engines() {
+        let points = random_points(3 * BLOCK_ROWS + 17, 3, 11);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut brute = BruteForceQueryEngine::new();
+        brute.init(&refs);
+        let mut fake = FakeQueryEngine::new();
+        fake.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+
+        for p in refs.iter().copied() {
+            for eps in [0.3, 1.0, 2.0] {
+                let expected = fake.run(p, eps);
+                assert_eq!(brute.run(p, eps), expected);
+                assert_eq!(rtree.run(p, eps), expected);
+            }
+            for k in [1, 5] {
+                assert_eq!(brute.k_dist(p, k), fake.k_dist(p, k));
+                assert_eq!(brute.k_dist_with_count(p, k), fake.k_dist_with_count(p, k));
+            }
+        }
+    }
+
+    #[test]
+    fn cloud_engine_matches_the_point_engine() {
+        let points = random_points(2 * BLOCK_ROWS + 5, 3, 5);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        engine.init(&refs);
+        let cloud = PointCloud::from(points.as_slice());
+        let cloud_engine = BruteForceQueryEngine::from_cloud(&cloud);
+
+        for (i, p) in points.iter().enumerate() {
+            for eps in [0.0, 0.5, 1.5] {
+                let rows = cloud_engine.run_rows(cloud.row(i), eps);
+                assert!(rows.is_sorted());
+                let found = rows.iter().map(|&j| &points[j]).collect::<HashSet<_>>();
+                assert_eq!(found, engine.run(p, eps));
+            }
+        }
+    }
+
+    #[test]
+    fn static_engine_matches_the_point_engine() {
+        let points = random_points(BLOCK_ROWS + 9, 3, 8);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        engine.init(&refs);
+        let static_points = points
+            .iter()
+            .map(|p| PointN::<3>::from_point(p).unwrap())
+            .collect::<Vec<_>>();
+        let static_engine = BruteForceQueryEngine::from_static(&static_points);
+
+        for (i, p) in points.iter().enumerate() {
+            for eps in [0.0, 0.5, 1.5] {
+                let found = static_engine
+                    .run_rows(&static_points[i].0, eps)
+                    .into_iter()
+                    .map(|j| &points[j])
+                    .collect::<HashSet<_>>();
+                assert_eq!(found, engine.run(p, eps));
+            }
+        }
+    }
+
+    // The fake engine would hold 40_000^2 references (about 12 GiB) here.
+    #[test]
+    fn large_input_stays_linear_in_memory() {
+        let points = random_points(40_000, 4, 3);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        engine.init(&refs);
+        let per_point = engine.estimated_memory_bytes() / refs.len();
+        assert!(per_point <= 4 * size_of::<f64>() + 2 * size_of::<&Point>());
+        for p in points.iter().take(10) {
+            assert!(engine.run(p, 0.5).contains(p));
+        }
+    }
+}
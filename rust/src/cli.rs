@@ -0,0 +1,42 @@
+use crate::io::{CsvFormat, Delimiter, Header, NumberFormat};
+
+/// CSV input flags shared by the binaries: `#[command(flatten)]` them into a
+/// parser and build the reader options with `format`.
+#[derive(Debug, Clone, clap::Args)]
+pub struct CsvArgs {
+    /// The first line is a header naming the columns
+    #[arg(long)]
+    pub header: bool,
+    /// Like --header, but only if the first line is not all numbers
+    #[arg(long, conflicts_with = "header")]
+    pub auto_header: bool,
+    /// Field separator: a single character such as ';', `tab`, or `auto` to
+    /// detect tab, ';' or ',' from the first line
+    #[arg(long, default_value = ",")]
+    pub delimiter: Delimiter,
+    /// Numbers use ',' as the decimal separator, e.g. `1,5`; needs another
+    /// --delimiter
+    #[arg(long)]
+    pub decimal_comma: bool,
+    /// Read only the first N rows (after the header), leaving the rest of
+    /// the file unread
+    #[arg(long, value_name = "N")]
+    pub max_rows: Option<usize>,
+}
+
+impl CsvArgs {
+    /// The reader options selected by the flags, read strictly.
+    pub fn format(&self) -> CsvFormat {
+        let numbers = if self.decimal_comma {
+            NumberFormat::DECIMAL_COMMA
+        } else {
+            NumberFormat::STANDARD
+        };
+        CsvFormat::new(
+            Header::from_flags(self.header, self.auto_header),
+            self.delimiter,
+        )
+        .with_numbers(numbers)
+        .with_max_rows(self.max_rows)
+    }
+}
@@ -0,0 +1,265 @@
+use crate::algo::RegionQuery;
+use crate::brute_query::BruteForceQueryEngine;
+use crate::fake_query::FakeQueryEngine;
+use crate::query::{RTreeQueryEngine, RTREE_MAX_DIM};
+use crate::types::{Metric, MetricKind, Point};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Below this many points building an index is not worth it.
+pub const SMALL_INPUT_POINTS: usize = 256;
+
+/// The query engines provided by this crate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum EngineKind {
+    Fake,
+    RTree,
+    Brute,
+}
+
+impl EngineKind {
+    pub fn build<'a>(self) -> Box<dyn RegionQuery<'a> + 'a> {
+        match self {
+            EngineKind::Fake => Box::new(FakeQueryEngine::new()),
+            EngineKind::RTree => Box::new(RTreeQueryEngine::new()),
+            EngineKind::Brute => Box::new(BruteForceQueryEngine::new()),
+        }
+    }
+
+    /// Whether the engine can measure distances with `metric`: the R-tree
+    /// engine needs an Lp metric, the others take any.
+    pub fn supports(self, metric: MetricKind) -> bool {
+        self != EngineKind::RTree || metric.is_lp()
+    }
+
+    /// Like `build`, with the engine's `with_metric` constructor. Fails if the
+    /// engine does not `support` the metric or the metric is not well defined.
+    pub fn build_with_metric<'a>(
+        self,
+        metric: MetricKind,
+    ) -> anyhow::Result<Box<dyn RegionQuery<'a> + 'a>> {
+        if !self.supports(metric) {
+            anyhow::bail!(
+                "the {} engine only supports Lp metrics (Euclidean, Manhattan, Chebyshev, Minkowski), got {:?}",
+                self,
+                metric
+            );
+        }
+        match metric {
+            MetricKind::Minkowski(p) if p.is_nan() || p < 1.0 => {
+                anyhow::bail!("Minkowski p must be >= 1, got {}", p)
+            }
+            MetricKind::Haversine(radius) if radius.is_nan() || radius <= 0.0 => {
+                anyhow::bail!("Haversine radius must be positive, got {}", radius)
+            }
+            _ => {}
+        }
+        Ok(match self {
+            EngineKind::Fake => Box::new(FakeQueryEngine::with_metric(metric)),
+            EngineKind::RTree => Box::new(RTreeQueryEngine::with_metric(metric)),
+            EngineKind::Brute => Box::new(BruteForceQueryEngine::with_metric(metric)),
+        })
+    }
+
+    /// Like `build`, with the engine's `with_weights` constructor.
+    pub fn build_weighted<'a>(
+        self,
+        weights: HashMap<&'a Point, f64>,
+    ) -> Box<dyn RegionQuery<'a> + 'a> {
+        match self {
+            EngineKind::Fake => Box::new(FakeQueryEngine::with_weights(weights)),
+            EngineKind::RTree => Box::new(RTreeQueryEngine::with_weights(weights)),
+            EngineKind::Brute => Box::new(BruteForceQueryEngine::with_weights(weights)),
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineKind::Fake => write!(f, "fake"),
+            EngineKind::RTree => write!(f, "rtree"),
+            EngineKind::Brute => write!(f, "brute"),
+        }
+    }
+}
+
+/// With an eps of at least this share of the diagonal of the points'
+/// bounding box, a neighborhood holds much of the input and an index prunes
+/// too little to pay for building it.
+pub const WIDE_EPS_SHARE: f64 = 0.5;
+
+/// Picks an engine for `n_points` points of dimension `dim`:
+///
+/// - `n_points <= SMALL_INPUT_POINTS`: `Brute`.
+/// - `eps_hint >= WIDE_EPS_SHARE`: `Brute`.
+/// - `dim <= RTREE_MAX_DIM`: `RTree`.
+/// - otherwise: `Brute`, which has no dimension limit and, unlike `Fake`, only
+///   needs O(N * D) memory.
+///
+/// `eps_hint` is eps as a share of the bounding-box diagonal (`eps_share`),
+/// or `None` if eps is not known yet.
+pub fn choose_engine(n_points: usize, dim: usize, eps_hint: Option<f64>) -> EngineKind {
+    let wide = eps_hint.is_some_and(|share| share >= WIDE_EPS_SHARE);
+    if n_points <= SMALL_INPUT_POINTS || wide {
+        EngineKind::Brute
+    } else if dim <= RTREE_MAX_DIM {
+        EngineKind::RTree
+    } else {
+        EngineKind::Brute
+    }
+}
+
+/// `eps` as a share of the diagonal of the bounding box of `points`, measured
+/// with `metric` like eps: the `eps_hint` of `choose_engine`. `None` if the
+/// points are all the same.
+pub fn eps_share(points: &[Point], eps: f64, metric: MetricKind) -> Option<f64> {
+    let first = points.first()?;
+    let mut low = first.clone();
+    let mut high = first.clone();
+    for point in points {
+        for ((low, high), &x) in low.iter_mut().zip(high.iter_mut()).zip(point) {
+            *low = (*low).min(x);
+            *high = (*high).max(x);
+        }
+    }
+    let diagonal = metric.dist(&low, &high);
+    (diagonal > 0.0).then(|| eps / diagonal)
+}
+
+/// Like `choose_engine`, for distances measured with `metric`: `Brute` instead
+/// of `RTree` for metrics the R-tree engine does not support.
+pub fn choose_engine_for_metric(
+    n_points: usize,
+    dim: usize,
+    eps_hint: Option<f64>,
+    metric: MetricKind,
+) -> EngineKind {
+    match choose_engine(n_points, dim, eps_hint) {
+        kind if kind.supports(metric) => kind,
+        _ => EngineKind::Brute,
+    }
+}
+
+/// Builds the engine selected by `choose_engine`, returned with its kind.
+pub fn auto_engine<'a>(
+    n_points: usize,
+    dim: usize,
+    eps_hint: Option<f64>,
+) -> (EngineKind, Box<dyn RegionQuery<'a> + 'a>) {
+    let kind = choose_engine(n_points, dim, eps_hint);
+    (kind, kind.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ordered_float::OrderedFloat;
+    use std::collections::HashSet;
+
+    #[test]
+    fn small_inputs_are_searched_by_brute_force() {
+        assert_eq!(
+            choose_engine(SMALL_INPUT_POINTS, 2, None),
+            EngineKind::Brute
+        );
+        assert_eq!(
+            choose_engine(SMALL_INPUT_POINTS + 1, 2, None),
+            EngineKind::RTree
+        );
+        assert_eq!(choose_engine(256, 16, Some(0.1)), EngineKind::Brute);
+        assert_eq!(choose_engine(257, 16, Some(0.1)), EngineKind::RTree);
+    }
+
+    #[test]
+    fn the_rtree_is_used_up_to_its_dimension_limit() {
+        assert_eq!(choose_engine(257, RTREE_MAX_DIM, None), EngineKind::RTree);
+        assert_eq!(
+            choose_engine(257, RTREE_MAX_DIM + 1, None),
+            EngineKind::Brute
+        );
+        assert_eq!(choose_engine(100_000, 16, None), EngineKind::RTree);
+        assert_eq!(choose_engine(100_000, 17, None), EngineKind::Brute);
+    }
+
+    #[test]
+    fn wide_eps_is_searched_by_brute_force() {
+        assert_eq!(choose_engine(1000, 2, Some(0.49)), EngineKind::RTree);
+        assert_eq!(
+            choose_engine(1000, 2, Some(WIDE_EPS_SHARE)),
+            EngineKind::Brute
+        );
+        assert_eq!(choose_engine(1000, 2, Some(3.0)), EngineKind::Brute);
+    }
+
+    #[test]
+    fn unsupported_metrics_fall_back_to_brute_force() {
+        let cosine = MetricKind::Cosine;
+        assert_eq!(
+            choose_engine_for_metric(1000, 2, None, cosine),
+            EngineKind::Brute
+        );
+        let manhattan = MetricKind::Manhattan;
+        assert_eq!(
+            choose_engine_for_metric(1000, 2, None, manhattan),
+            EngineKind::RTree
+        );
+    }
+
+    #[test]
+    fn eps_share_is_relative_to_the_bounding_box() {
+        let point = |x: f64, y: f64| vec![OrderedFloat(x), OrderedFloat(y)];
+        // A 3 x 4 box has a diagonal of 5.
+        let points = [point(1.0, 1.0), point(4.0, 2.0), point(2.0, 5.0)];
+        let euclidean = MetricKind::Euclidean;
+        assert_eq!(eps_share(&points, 2.5, euclidean), Some(0.5));
+        assert_eq!(eps_share(&points, 0.5, euclidean), Some(0.1));
+        let same = [point(1.0, 1.0), point(1.0, 1.0)];
+        assert_eq!(eps_share(&same, 0.5, euclidean), None);
+        assert_eq!(eps_share(&[], 0.5, euclidean), None);
+        // Other metrics measure the diagonal like eps: 3 + 4 and max(3, 4).
+        assert_eq!(eps_share(&points, 3.5, MetricKind::Manhattan), Some(0.5));
+        assert_eq!(eps_share(&points, 2.0, MetricKind::Chebyshev), Some(0.5));
+    }
+
+    #[test]
+    fn auto_engine_builds_the_chosen_engine() {
+        let mut rng = crate::sampling::SplitMix64::new(324);
+        let mut points = |n: usize, dim: usize| {
+            (0..n)
+                .map(|_| {
+                    (0..dim)
+                        .map(|_| OrderedFloat(rng.below(1000) as f64))
+                        .collect()
+                })
+                .collect::<HashSet<Point>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        let large = points(400, 2);
+        for (points, eps, expected) in [
+            (large.clone(), None, EngineKind::RTree),
+            (large.clone(), Some(1.0), EngineKind::RTree),
+            (large, Some(1000.0), EngineKind::Brute),
+            (points(100, 2), Some(1.0), EngineKind::Brute),
+            (points(400, RTREE_MAX_DIM), Some(1.0), EngineKind::RTree),
+            (points(400, RTREE_MAX_DIM + 1), Some(1.0), EngineKind::Brute),
+        ] {
+            let hint = eps.and_then(|eps| eps_share(&points, eps, MetricKind::Euclidean));
+            let (kind, _) = auto_engine(points.len(), points[0].len(), hint);
+            assert_eq!(kind, expected, "{} points, eps {:?}", points.len(), eps);
+        }
+        // Queries are answered like the chosen engine.
+        let points = points(400, 2);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let (kind, mut auto) = auto_engine(points.len(), 2, None);
+        assert_eq!(kind, EngineKind::RTree);
+        auto.init(&refs);
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+        for p in points.iter().take(20) {
+            assert_eq!(auto.run(p, 100.0), rtree.run(p, 100.0));
+            assert_eq!(auto.knn(p, 3), rtree.knn(p, 3));
+        }
+    }
+}
@@ -1,90 +1,1125 @@
-use crate::algo::RegionQuery;
-use crate::types::{dist, Point};
+use crate::algo::{QueryError, RegionQuery, UsesMetric};
+use crate::types::{Euclidean, Metric, Point, aniso_dist_sq, check_aniso_eps};
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
 
-pub struct FakeQueryEngine<'a> {
-    // Includes the point itself at distance 0.
+// Sorted neighbor lists built on first query in lazy mode. Once `cap` lists are
+// cached, the oldest one is dropped to make room.
+struct LazyLists<'a> {
+    cap: usize,
+    indexed: Option<&'a HashSet<&'a Point>>,
+    lists: HashMap<&'a Point, Vec<&'a Point>>,
+    order: VecDeque<&'a Point>,
+}
+
+pub struct FakeQueryEngine<'a, M: Metric = Euclidean> {
+    points: Vec<&'a Point>,
+    // Includes the point itself at distance 0. Empty in on-demand and lazy mode,
+    // and limited to the query subset after `init_for`.
     sorted_by_distance: HashMap<&'a Point, Vec<&'a Point>>,
+    weights: Option<HashMap<&'a Point, f64>>,
+    on_demand: bool,
+    lazy: Option<Mutex<LazyLists<'a>>>,
+    // Lists are sorted and compared by `Metric::key`.
+    metric: M,
+    initialized: bool,
 }
 
 impl<'a> FakeQueryEngine<'a> {
     pub fn new() -> Self {
+        FakeQueryEngine::with_metric(Euclidean)
+    }
+
+    /// Engine that only stores the point set and answers every query with a
+    /// linear scan (plus a partial sort for k-nearest queries). Results match
+    /// the eager engine, but memory is O(N) instead of O(N^2).
+    pub fn on_demand() -> Self {
+        Self {
+            on_demand: true,
+            ..Self::new()
+        }
+    }
+
+    /// Engine that builds the sorted neighbor list of an indexed point the first
+    /// time it is queried and keeps at most `max_cached` lists, dropping the
+    /// oldest first. Suits workloads that query a small part of the data set.
+    pub fn lazy(max_cached: usize) -> Self {
+        assert!(max_cached > 0, "max_cached must be >= 1");
+        Self {
+            lazy: Some(Mutex::new(LazyLists {
+                cap: max_cached,
+                indexed: None,
+                lists: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+            ..Self::new()
+        }
+    }
+
+    /// Engine whose weight queries use `weights` (keyed by indexed point)
+    /// instead of counting points. Points without an entry weigh 1.
+    pub fn with_weights(weights: HashMap<&'a Point, f64>) -> Self {
         Self {
+            weights: Some(weights),
+            ..Self::new()
+        }
+    }
+}
+
+impl<'a, M: Metric> FakeQueryEngine<'a, M> {
+    /// Engine that measures distances with `metric` (any `Metric`, including a
+    /// closure) instead of the Euclidean `dist`. Every query, including the
+    /// neighbor ordering built by `init`, uses it, so it can serve as the
+    /// reference for other metrics.
+    pub fn with_metric(metric: M) -> Self {
+        Self {
+            points: Vec::new(),
             sorted_by_distance: HashMap::new(),
+            weights: None,
+            on_demand: false,
+            lazy: None,
+            metric,
+            initialized: false,
+        }
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.metric.dist(a, b)
+    }
+
+    // Orders points like `dist`; compare it against `key_bound(eps)`, not `eps`.
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        self.metric.key(a, b)
+    }
+
+    fn key_bound(&self, eps: f64) -> f64 {
+        self.metric.key_bound(eps)
+    }
+
+    fn weight_of(&self, point: &Point) -> f64 {
+        match &self.weights {
+            Some(weights) => weights.get(point).copied().unwrap_or(1.0),
+            None => 1.0,
+        }
+    }
+
+    /// Indexes `points` like `init`, but only precomputes sorted neighbor lists
+    /// for `query_points`. Queries for any other point fall back to a linear
+    /// scan, so results are the same as after `init`. Costs O(M N log N) for M
+    /// query points.
+    pub fn init_for(&mut self, points: &'a HashSet<&'a Point>, query_points: &[&'a Point]) {
+        self.points = points.iter().copied().collect();
+        self.initialized = true;
+        if let Some(lazy) = &mut self.lazy {
+            let lazy = lazy.get_mut().unwrap();
+            lazy.indexed = Some(points);
+            lazy.lists.clear();
+            lazy.order.clear();
         }
+
+        // Every per-point sort is independent.
+        #[cfg(feature = "parallel")]
+        let sorted_by_distance = query_points
+            .par_iter()
+            .map(|&p| (p, self.sort_from(p)))
+            .collect();
+        #[cfg(not(feature = "parallel"))]
+        let sorted_by_distance = query_points
+            .iter()
+            .map(|&p| (p, self.sort_from(p)))
+            .collect();
+
+        self.sorted_by_distance = sorted_by_distance;
     }
 
     /// Returns the distance from `point` to its k-th nearest neighbor
     /// (excluding the point itself). Requires `k` in 1..=N-1.
-    pub fn k_distance(&self, point: &'a Point, k: usize) -> f64 {
-        let sorted = self
-            .sorted_by_distance
-            .get(point)
-            .expect("FakeQueryEngine not initialized for this point");
-        assert!(k > 0 && k < sorted.len(), "k must be in 1..=N-1");
-        crate::types::dist(point, sorted[k])
+    pub fn k_distance(&self, point: &Point, k: usize) -> f64 {
+        self.k_dist(point, k)
+    }
+
+    // Every indexed point, closest to `point` first.
+    fn sort_from(&self, point: &Point) -> Vec<&'a Point> {
+        self.points
+            .iter()
+            .sorted_by(|&&a, &&b| {
+                let a_dist = self.key(a, point);
+                let b_dist = self.key(b, point);
+                a_dist.partial_cmp(&b_dist).unwrap()
+            })
+            .copied()
+            .collect_vec()
+    }
+
+    // In lazy mode, runs `f` on the sorted neighbor list of `point`, building
+    // and caching it first if needed. `None` if not lazy or `point` is not
+    // indexed.
+    fn with_lazy_list<R>(&self, point: &Point, f: impl FnOnce(&[&'a Point]) -> R) -> Option<R> {
+        let mut lazy = self.lazy.as_ref()?.lock().unwrap();
+        let &key = lazy.indexed?.get(point)?;
+        if !lazy.lists.contains_key(key) {
+            if lazy.order.len() == lazy.cap {
+                let oldest = lazy.order.pop_front().unwrap();
+                lazy.lists.remove(oldest);
+            }
+            let sorted = self.sort_from(key);
+            lazy.lists.insert(key, sorted);
+            lazy.order.push_back(key);
+        }
+        Some(f(&lazy.lists[key]))
+    }
+
+    // The `m` indexed points nearest to `point` (all of them if there are
+    // fewer), closest first. Points with a precomputed or cached list use it;
+    // any other point falls back to a linear scan with a partial sort.
+    fn nearest_sorted(&self, point: &Point, m: usize) -> Cow<'_, [&'a Point]> {
+        if let Some(sorted) = self.sorted_by_distance.get(point) {
+            return Cow::Borrowed(&sorted[..m.min(sorted.len())]);
+        }
+        if let Some(prefix) = self.with_lazy_list(point, |s| s[..m.min(s.len())].to_vec()) {
+            return Cow::Owned(prefix);
+        }
+
+        let mut dists = self
+            .points
+            .iter()
+            .map(|&p| (p, self.key(p, point)))
+            .collect_vec();
+        let m = m.min(dists.len());
+        if m == 0 {
+            return Cow::Owned(Vec::new());
+        }
+        dists.select_nth_unstable_by(m - 1, |a, b| a.1.total_cmp(&b.1));
+        dists.truncate(m);
+        dists.sort_by(|a, b| a.1.total_cmp(&b.1));
+        Cow::Owned(dists.into_iter().map(|(p, _)| p).collect())
+    }
+
+    // The k nearest neighbors of `point` excluding the point itself.
+    fn k_nearest(&self, point: &Point, k: usize) -> Vec<&'a Point> {
+        let sorted = self.nearest_sorted(point, k.saturating_add(1));
+        Self::others(&sorted, point).take(k).collect()
+    }
+
+    fn check_query(&self, point: &Point) -> Result<(), QueryError> {
+        if !self.initialized {
+            return Err(QueryError::NotInitialized);
+        }
+        match self.points.first() {
+            Some(first) if first.len() != point.len() => {
+                Err(QueryError::dimension_mismatch(point, first.len()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Panics like `run` if `check_query` fails.
+    fn check(&self, point: &Point) {
+        if let Err(e) = self.check_query(point) {
+            panic!("{}", e);
+        }
+    }
+
+    // Same failure behavior as `RTreeQueryEngine::k_dist`.
+    fn check_k(neighbors: &[&'a Point], k: usize) {
+        assert!(k > 0, "k must be >= 1");
+        if k > neighbors.len() {
+            panic!("k={} is out of range for dataset", k);
+        }
+    }
+
+    // The entries of `sorted` other than the query point, so that it is not
+    // its own neighbor. Only the indexed entry itself is left out, not a
+    // different point with the same coordinates. With ties at distance 0 it
+    // need not come first.
+    fn others<'b>(
+        sorted: &'b [&'a Point],
+        point: &'b Point,
+    ) -> impl Iterator<Item = &'a Point> + 'b {
+        sorted
+            .iter()
+            .copied()
+            .filter(move |&p| !std::ptr::eq(p, point))
+    }
+
+    // Whether `neighbors` weigh at least `threshold`, taking no more of them
+    // than that needs.
+    fn weighs_at_least<'b>(
+        &self,
+        neighbors: impl Iterator<Item = &'b &'a Point>,
+        threshold: f64,
+    ) -> bool
+    where
+        'a: 'b,
+    {
+        let mut total = 0.0;
+        for p in neighbors {
+            total += self.weight_of(p);
+            if total >= threshold {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Indexed points within eps of `point`: a prefix of the precomputed or
+    // cached list if there is one, a linear scan otherwise.
+    fn within(&self, point: &Point, eps: f64) -> Cow<'_, [&'a Point]> {
+        // A sorted list is ordered by distance, so the neighbors within eps
+        // (inclusive) form a prefix. Unlike a bisection seeded at index 0, this
+        // does not assume the first entry is within eps, which fails for query
+        // points that are not indexed.
+        let bound = self.key_bound(eps);
+        let end = |sorted: &[&'a Point]| sorted.partition_point(|p| self.key(p, point) <= bound);
+
+        if let Some(sorted) = self.sorted_by_distance.get(point) {
+            return Cow::Borrowed(&sorted[..end(sorted)]);
+        }
+        if let Some(prefix) = self.with_lazy_list(point, |s| s[..end(s)].to_vec()) {
+            return Cow::Owned(prefix);
+        }
+        Cow::Owned(
+            self.points
+                .iter()
+                .copied()
+                .filter(|p| self.key(p, point) <= bound)
+                .collect(),
+        )
     }
 }
 
-impl<'a> RegionQuery<'a> for FakeQueryEngine<'a> {
-    // This will take O(N^2 logN) for initialization.
+impl Default for FakeQueryEngine<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Metric> UsesMetric<M> for FakeQueryEngine<'_, M> {}
+
+impl<'a, M: Metric> RegionQuery<'a> for FakeQueryEngine<'a, M> {
+    // This will take O(N^2 logN) for initialization, or O(N) on demand or lazily.
     fn init(&mut self, points: &'a HashSet<&'a Point>) {
-        let mut sorted_by_distance = HashMap::new();
+        let query_points = if self.on_demand || self.lazy.is_some() {
+            Vec::new()
+        } else {
+            points.iter().copied().collect_vec()
+        };
+        self.init_for(points, &query_points);
+    }
 
-        for &point in points.iter() {
-            let sorted = points
-                .iter()
-                .sorted_by(|&&a, &&b| {
-                    let a_dist = dist(a, point);
-                    let b_dist = dist(b, point);
-                    a_dist.partial_cmp(&b_dist).unwrap()
+    fn run(&self, point: &Point, eps: f64) -> HashSet<&'a Point> {
+        self.try_run(point, eps).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_run(&self, point: &Point, eps: f64) -> Result<HashSet<&'a Point>, QueryError> {
+        self.check_query(point)?;
+        Ok(self.within(point, eps).iter().cloned().collect())
+    }
+
+    fn run_filtered(
+        &self,
+        point: &Point,
+        eps: f64,
+        pred: &dyn Fn(&Point) -> bool,
+    ) -> HashSet<&'a Point> {
+        self.check(point);
+        self.within(point, eps)
+            .iter()
+            .filter(|p| pred(p))
+            .cloned()
+            .collect()
+    }
+
+    // A linear scan: the sorted lists follow the metric, which the ellipsoid
+    // does not.
+    fn run_aniso(&self, point: &Point, eps_per_dim: &[f64]) -> HashSet<&'a Point> {
+        self.check(point);
+        check_aniso_eps(point, eps_per_dim);
+        self.points
+            .iter()
+            .copied()
+            .filter(|p| aniso_dist_sq(point, p, eps_per_dim) <= 1.0)
+            .collect()
+    }
+
+    fn weight_within(&self, point: &Point, eps: f64) -> f64 {
+        self.check(point);
+        self.within(point, eps)
+            .iter()
+            .map(|p| self.weight_of(p))
+            .sum()
+    }
+
+    // Walks a sorted list closest first, or the indexed points in turn, and
+    // stops at the first neighbor that brings the weight to `threshold`,
+    // without measuring the distance to any point after it.
+    fn weight_within_at_least(&self, point: &Point, eps: f64, threshold: f64) -> bool {
+        self.check(point);
+        if threshold <= 0.0 {
+            return true;
+        }
+
+        let bound = self.key_bound(eps);
+        let within = |p: &&&'a Point| self.key(p, point) <= bound;
+        if let Some(sorted) = self.sorted_by_distance.get(point) {
+            return self.weighs_at_least(sorted.iter().take_while(within), threshold);
+        }
+        let cached = self.with_lazy_list(point, |sorted| {
+            self.weighs_at_least(sorted.iter().take_while(within), threshold)
+        });
+        cached.unwrap_or_else(|| self.weighs_at_least(self.points.iter().filter(within), threshold))
+    }
+
+    fn k_dist(&self, point: &Point, k: usize) -> f64 {
+        self.check(point);
+        let neighbors = self.k_nearest(point, k);
+
+        Self::check_k(&neighbors, k);
+
+        self.dist(neighbors[k - 1], point)
+    }
+
+    fn k_dist_with_count(&self, point: &Point, k: usize) -> (f64, usize) {
+        let d = self.k_dist(point, k);
+        let bound = self.key_bound(d);
+        let count_sorted = |sorted: &[&'a Point]| {
+            let end = sorted.partition_point(|p| self.key(p, point) <= bound);
+            Self::others(&sorted[..end], point).count()
+        };
+        let count = match self.sorted_by_distance.get(point) {
+            Some(sorted) => count_sorted(sorted),
+            None => self.with_lazy_list(point, count_sorted).unwrap_or_else(|| {
+                self.points
+                    .iter()
+                    .filter(|&&p| !std::ptr::eq(p, point) && self.key(p, point) <= bound)
+                    .count()
+            }),
+        };
+        (d, count)
+    }
+
+    fn knn(&self, point: &Point, k: usize) -> Vec<(&'a Point, f64)> {
+        self.check(point);
+        self.k_nearest(point, k)
+            .into_iter()
+            .map(|p| (p, self.dist(p, point)))
+            .collect()
+    }
+
+    // Counts the map entries plus every sorted neighbor list (precomputed or
+    // cached), so O(N^2) after a full eager `init`.
+    fn estimated_memory_bytes(&self) -> usize {
+        let entry = size_of::<&Point>() + size_of::<Vec<&Point>>();
+        let map_bytes = |map: &HashMap<&'a Point, Vec<&'a Point>>| {
+            let lists = map
+                .values()
+                .map(|v| v.capacity() * size_of::<&Point>())
+                .sum::<usize>();
+            map.capacity() * entry + lists
+        };
+        let cached = self.lazy.as_ref().map_or(0, |lazy| {
+            let lazy = lazy.lock().unwrap();
+            map_bytes(&lazy.lists) + lazy.order.capacity() * size_of::<&Point>()
+        });
+        self.points.capacity() * size_of::<&Point>() + map_bytes(&self.sorted_by_distance) + cached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{RTreeQueryEngine, RTreeQueryEngineN};
+    use crate::sampling::SplitMix64;
+    use ordered_float::OrderedFloat;
+
+    fn pt(x: f64) -> Point {
+        vec![OrderedFloat(x)]
+    }
+
+    // Every way the engine can answer: precomputed lists, linear scans, lazy
+    // lists, and a list for only some points.
+    fn engines<'a>(points: &'a HashSet<&'a Point>) -> Vec<(&'static str, FakeQueryEngine<'a>)> {
+        let mut eager = FakeQueryEngine::new();
+        eager.init(points);
+        let mut on_demand = FakeQueryEngine::on_demand();
+        on_demand.init(points);
+        let mut lazy = FakeQueryEngine::lazy(1);
+        lazy.init(points);
+        let mut partial = FakeQueryEngine::new();
+        let first = points.iter().copied().take(1).collect::<Vec<_>>();
+        partial.init_for(points, &first);
+        vec![
+            ("eager", eager),
+            ("on demand", on_demand),
+            ("lazy", lazy),
+            ("init_for", partial),
+        ]
+    }
+
+    // 0, 1, 3 and 6 on a line: gaps of 1, 2 and 3.
+    fn line() -> Vec<Point> {
+        [0.0, 1.0, 3.0, 6.0].map(pt).to_vec()
+    }
+
+    fn xs(set: &HashSet<&Point>) -> Vec<f64> {
+        let mut xs = set.iter().map(|p| p[0].0).collect::<Vec<_>>();
+        xs.sort_by(f64::total_cmp);
+        xs
+    }
+
+    #[test]
+    fn eps_below_every_gap_finds_only_the_point_itself() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        for (mode, engine) in engines(&refs) {
+            for p in points.iter() {
+                assert_eq!(xs(&engine.run(p, 0.5)), vec![p[0].0], "{}", mode);
+                assert_eq!(xs(&engine.run(p, 0.0)), vec![p[0].0], "{}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn eps_equal_to_a_distance_is_inclusive() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        for (mode, engine) in engines(&refs) {
+            assert_eq!(xs(&engine.run(&points[0], 1.0)), [0.0, 1.0], "{}", mode);
+            assert_eq!(
+                xs(&engine.run(&points[1], 2.0)),
+                [0.0, 1.0, 3.0],
+                "{}",
+                mode
+            );
+            assert_eq!(xs(&engine.run(&points[3], 3.0)), [3.0, 6.0], "{}", mode);
+            assert_eq!(engine.k_dist(&points[2], 1), 2.0, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn eps_above_the_largest_distance_finds_everything() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        for (mode, engine) in engines(&refs) {
+            for p in points.iter() {
+                assert_eq!(engine.run(p, 6.0), refs, "{}", mode);
+                assert_eq!(engine.run(p, 100.0), refs, "{}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn single_point_dataset() {
+        let points = [pt(2.0)];
+        let refs = points.iter().collect::<HashSet<_>>();
+        for (mode, engine) in engines(&refs) {
+            assert_eq!(engine.run(&points[0], 0.0), refs, "{}", mode);
+            assert_eq!(engine.run(&points[0], 1.0), refs, "{}", mode);
+            // Not indexed: only within eps of the indexed point.
+            assert!(engine.run(&pt(3.5), 1.0).is_empty(), "{}", mode);
+            assert_eq!(engine.run(&pt(3.5), 1.5), refs, "{}", mode);
+            assert!(engine.knn(&points[0], 1).is_empty(), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn matches_rtree_on_random_points() {
+        let mut rng = SplitMix64::new(332);
+        for round in 0..20 {
+            let n = 1 + rng.below(50) as usize;
+            let points = (0..n)
+                .map(|_| {
+                    let mut c = || OrderedFloat(rng.below(40) as f64 / 4.0);
+                    vec![c(), c()]
                 })
-                .map(|&p| p)
-                .collect_vec();
+                .collect::<HashSet<Point>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            let refs = points.iter().collect::<HashSet<_>>();
+            let mut rtree = RTreeQueryEngine::new();
+            rtree.init(&refs);
+            for (mode, engine) in engines(&refs) {
+                for p in points.iter() {
+                    // Exact distances to other points hit the boundary.
+                    let eps = points[rng.below(n as u64) as usize % points.len()]
+                        .iter()
+                        .zip(p.iter())
+                        .map(|(a, b)| (a.0 - b.0).powi(2))
+                        .sum::<f64>()
+                        .sqrt();
+                    for eps in [eps, rng.below(30) as f64 / 4.0] {
+                        assert_eq!(
+                            engine.run(p, eps),
+                            rtree.run(p, eps),
+                            "{} round {} eps {}",
+                            mode,
+                            round,
+                            eps
+                        );
+                    }
+                }
+            }
+        }
+    }
 
-            sorted_by_distance.insert(point, sorted);
+    #[test]
+    fn weight_threshold_stops_at_the_point_that_reaches_it() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Ten points, all within eps of each other.
+        let points = (0..10).map(|x| pt(x as f64)).collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let calls = AtomicUsize::new(0);
+        let counting = |a: &Point, b: &Point| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            (a[0].0 - b[0].0).abs()
+        };
+        let mut eager = FakeQueryEngine::with_metric(&counting);
+        eager.init(&refs);
+        let mut on_demand = FakeQueryEngine {
+            on_demand: true,
+            ..FakeQueryEngine::with_metric(&counting)
+        };
+        on_demand.init(&refs);
+        let mut lazy = FakeQueryEngine {
+            lazy: FakeQueryEngine::lazy(1).lazy,
+            ..FakeQueryEngine::with_metric(&counting)
+        };
+        lazy.init(&refs);
+        // Builds and caches the list of the point queried below.
+        assert!(lazy.weight_within_at_least(&points[0], 100.0, 1.0));
+
+        for (mode, engine) in [
+            ("eager", &eager),
+            ("on demand", &on_demand),
+            ("lazy", &lazy),
+        ] {
+            calls.store(0, Ordering::Relaxed);
+            assert!(
+                engine.weight_within_at_least(&points[0], 100.0, 3.0),
+                "{}",
+                mode
+            );
+            assert_eq!(calls.load(Ordering::Relaxed), 3, "{}", mode);
+            // A threshold above the whole neighborhood still walks all of it.
+            calls.store(0, Ordering::Relaxed);
+            assert!(
+                !engine.weight_within_at_least(&points[0], 100.0, 11.0),
+                "{}",
+                mode
+            );
+            assert_eq!(calls.load(Ordering::Relaxed), 10, "{}", mode);
         }
+    }
 
-        self.sorted_by_distance = sorted_by_distance;
+    #[test]
+    fn weight_threshold_matches_the_total_weight() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let weights = points
+            .iter()
+            .zip([0.5, 2.0, 1.0, 4.0])
+            .collect::<HashMap<_, _>>();
+        let mut weighted = FakeQueryEngine::with_weights(weights);
+        weighted.init(&refs);
+        let mut engines = engines(&refs);
+        engines.push(("weighted", weighted));
+        for (mode, engine) in engines.iter() {
+            for p in points.iter() {
+                for eps in [0.0, 1.0, 2.5, 6.0] {
+                    let total = engine.weight_within(p, eps);
+                    for threshold in [0.0, 0.5, 1.0, 2.5, 3.0, 7.5, 8.0] {
+                        assert_eq!(
+                            engine.weight_within_at_least(p, eps, threshold),
+                            total >= threshold,
+                            "{} {:?} eps {} threshold {}",
+                            mode,
+                            p,
+                            eps,
+                            threshold
+                        );
+                    }
+                }
+            }
+        }
     }
 
-    fn run(&self, point: &'a Point, eps: f64) -> HashSet<&'a Point> {
-        assert_ne!(
-            self.sorted_by_distance.get(point),
-            None,
-            "The query engine is not initialized for this point."
-        );
+    // The message of the panic `f` raises, if it does.
+    fn panic_message<R>(f: impl FnOnce() -> R) -> Option<String> {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).err()?;
+        match payload.downcast_ref::<&str>() {
+            Some(message) => Some(message.to_string()),
+            None => payload.downcast_ref::<String>().cloned(),
+        }
+    }
 
-        let sorted = self.sorted_by_distance.get(point).unwrap();
+    #[test]
+    fn k_dist_matches_rtree_with_ties_and_copies() {
+        let mut rng = SplitMix64::new(331);
+        for round in 0..10 {
+            // Few distinct coordinates, so many distances tie.
+            let points = (0..60)
+                .map(|_| {
+                    vec![
+                        OrderedFloat(rng.below(6) as f64),
+                        OrderedFloat(rng.below(6) as f64),
+                    ]
+                })
+                .collect::<HashSet<Point>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+            let refs = points.iter().collect::<HashSet<_>>();
+            let mut rtree = RTreeQueryEngine::new();
+            rtree.init(&refs);
+            for (mode, engine) in engines(&refs) {
+                for p in points.iter() {
+                    // A copy of an indexed point is not the point itself, so
+                    // the indexed one is its nearest neighbor at distance 0.
+                    let copy = p.clone();
+                    assert_eq!(engine.k_dist(&copy, 1), 0.0, "{} round {}", mode, round);
+                    for k in [1, 2, 5, points.len() - 1] {
+                        for q in [p, &copy] {
+                            assert_eq!(
+                                engine.k_dist(q, k),
+                                rtree.k_dist(q, k),
+                                "{} round {} k {}",
+                                mode,
+                                round,
+                                k
+                            );
+                            assert_eq!(
+                                engine.k_dist_with_count(q, k),
+                                rtree.k_dist_with_count(q, k)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ties_at_distance_zero_never_return_the_query_point() {
+        use crate::types::MetricKind;
+
+        // Parallel vectors are at cosine distance 0, so the query point may
+        // sort anywhere among the other two on its axis.
+        let points = [
+            [1.0, 0.0],
+            [2.0, 0.0],
+            [3.0, 0.0],
+            [0.0, 1.0],
+            [0.0, 2.0],
+            [0.0, 3.0],
+        ]
+        .map(|xy| xy.map(OrderedFloat).to_vec());
+        let refs = points.iter().collect::<HashSet<_>>();
+        let cosine = || FakeQueryEngine::with_metric(MetricKind::Cosine);
+        let mut eager = cosine();
+        eager.init(&refs);
+        let mut on_demand = FakeQueryEngine {
+            on_demand: true,
+            ..cosine()
+        };
+        on_demand.init(&refs);
+        let mut lazy = FakeQueryEngine {
+            lazy: FakeQueryEngine::lazy(1).lazy,
+            ..cosine()
+        };
+        lazy.init(&refs);
+        let mut partial = cosine();
+        partial.init_for(&refs, &[&points[0], &points[4]]);
+        for (mode, engine) in [
+            ("eager", eager),
+            ("on demand", on_demand),
+            ("lazy", lazy),
+            ("init_for", partial),
+        ] {
+            for p in points.iter() {
+                let knn = engine.knn(p, 3);
+                let others = knn.iter().filter(|(q, _)| !std::ptr::eq(*q, p)).count();
+                assert_eq!(others, 3, "{} {:?}", mode, p);
+                let dists = knn.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+                assert_eq!(dists, [0.0, 0.0, 1.0], "{} {:?}", mode, p);
+                assert_eq!(engine.k_dist(p, 2), 0.0, "{} {:?}", mode, p);
+                assert_eq!(engine.k_dist_with_count(p, 2), (0.0, 2), "{} {:?}", mode, p);
+                assert_eq!(engine.k_dist(p, 3), 1.0, "{} {:?}", mode, p);
+            }
+        }
+    }
 
-        let mut lt = 0;
-        let mut ge = sorted.len();
-        while ge - lt > 1 {
-            let mid = (lt + ge) / 2;
-            if dist(&sorted[mid], &point) <= eps {
-                lt = mid;
-            } else {
-                ge = mid;
+    #[test]
+    fn k_dist_fails_like_rtree() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut rtree = RTreeQueryEngine::new();
+        rtree.init(&refs);
+        for (mode, engine) in engines(&refs) {
+            for k in [0, points.len()] {
+                let expected = panic_message(|| rtree.k_dist(&points[0], k));
+                assert!(expected.is_some(), "k {}", k);
+                assert_eq!(
+                    panic_message(|| engine.k_dist(&points[0], k)),
+                    expected,
+                    "{} k {}",
+                    mode,
+                    k
+                );
             }
+            // Every other point is within reach of k = N - 1.
+            assert_eq!(engine.k_dist(&points[0], points.len() - 1), 6.0, "{}", mode);
         }
 
-        sorted.iter().take(ge).cloned().collect()
+        let fake = FakeQueryEngine::new();
+        let message = panic_message(|| fake.k_dist(&points[0], 1)).unwrap();
+        assert!(message.contains("not initialized"), "{}", message);
+        let rtree = RTreeQueryEngine::new();
+        let message = panic_message(|| rtree.k_dist(&points[0], 1)).unwrap();
+        assert!(message.contains("not initialized"), "{}", message);
+    }
+
+    // The fake engine and both R-tree engines, indexing `points` if given.
+    fn every_engine<'a>(
+        points: Option<&'a HashSet<&'a Point>>,
+    ) -> Vec<(&'static str, Box<dyn RegionQuery<'a> + 'a>)> {
+        let mut engines: Vec<(&'static str, Box<dyn RegionQuery<'a> + 'a>)> = vec![
+            ("fake", Box::new(FakeQueryEngine::new())),
+            ("rtree", Box::new(RTreeQueryEngine::new())),
+            ("static rtree", Box::new(RTreeQueryEngineN::<2>::new())),
+        ];
+        if let Some(points) = points {
+            for (_, engine) in &mut engines {
+                engine.init(points);
+            }
+        }
+        engines
     }
 
-    fn k_dist(&self, point: &'a Point, k: usize) -> f64 {
-        assert_ne!(
-            self.sorted_by_distance.get(point),
-            None,
-            "The query engine is not initialized for this point."
+    #[test]
+    fn every_query_checks_the_engine_like_try_run() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let plane = points.iter().map(|p| vec![p[0], p[0]]).collect::<Vec<_>>();
+        let plane_refs = plane.iter().collect::<HashSet<_>>();
+        // One coordinate too many for the line, and one too few for the plane,
+        // which the static engine would otherwise pad with a zero.
+        let long = vec![OrderedFloat(1.5), OrderedFloat(-2.0)];
+        let short = pt(1.5);
+        let cases = [
+            (None, &points[0]),
+            (Some(&refs), &long),
+            (Some(&plane_refs), &short),
+        ];
+        for (indexed, q) in cases {
+            for (name, engine) in every_engine(indexed) {
+                let expected = engine.try_run(q, 1.0).unwrap_err().to_string();
+                let eps = vec![1.0; q.len()];
+                let messages = [
+                    (
+                        "run_filtered",
+                        panic_message(|| engine.run_filtered(q, 1.0, &|_| true)),
+                    ),
+                    ("run_aniso", panic_message(|| engine.run_aniso(q, &eps))),
+                    (
+                        "weight_within",
+                        panic_message(|| engine.weight_within(q, 1.0)),
+                    ),
+                    (
+                        "weight_within_at_least",
+                        panic_message(|| engine.weight_within_at_least(q, 1.0, 1.0)),
+                    ),
+                    ("k_dist", panic_message(|| engine.k_dist(q, 1))),
+                    (
+                        "k_dist_with_count",
+                        panic_message(|| engine.k_dist_with_count(q, 1)),
+                    ),
+                    ("knn", panic_message(|| engine.knn(q, 1))),
+                    ("run_many", panic_message(|| engine.run_many(&[q], 1.0))),
+                    ("knn_many", panic_message(|| engine.knn_many(&[q], 1))),
+                ];
+                for (query, message) in messages {
+                    assert_eq!(
+                        message,
+                        Some(expected.clone()),
+                        "{} {} {:?}",
+                        name,
+                        query,
+                        q
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lazy_mode_keeps_at_most_cap_lists() {
+        let mut rng = SplitMix64::new(333);
+        let points = (0..100)
+            .map(|_| vec![OrderedFloat(rng.below(1000) as f64 / 10.0)])
+            .collect::<HashSet<Point>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut eager = FakeQueryEngine::new();
+        eager.init(&refs);
+        let mut lazy = FakeQueryEngine::lazy(3);
+        lazy.init(&refs);
+        let empty = lazy.estimated_memory_bytes();
+        let cached = || lazy.lazy.as_ref().unwrap().lock().unwrap().lists.len();
+
+        let mut largest = 0;
+        for (i, p) in points.iter().enumerate() {
+            assert_eq!(lazy.run(p, 5.0), eager.run(p, 5.0));
+            assert_eq!(lazy.knn(p, 4), eager.knn(p, 4));
+            assert_eq!(lazy.k_dist(p, 7), eager.k_dist(p, 7));
+            assert_eq!(cached(), (i + 1).min(3));
+            largest = largest.max(lazy.estimated_memory_bytes());
+        }
+        // Three lists of 100 points, against the 100 lists of the eager engine.
+        let list = points.len() * size_of::<&Point>();
+        assert!(
+            largest - empty <= 4 * (list + 64),
+            "{} bytes",
+            largest - empty
         );
+        assert!(eager.estimated_memory_bytes() >= points.len() * list);
 
-        let sorted = self.sorted_by_distance.get(point).unwrap();
+        // Asking again for a cached point does not evict anything.
+        let last = &points[points.len() - 1];
+        lazy.run(last, 5.0);
+        assert_eq!(cached(), 3);
+        // Query points that are not indexed are scanned, not cached.
+        let other = vec![OrderedFloat(0.05)];
+        assert_eq!(lazy.run(&other, 5.0), eager.run(&other, 5.0));
+        assert_eq!(lazy.knn(&other, 4), eager.knn(&other, 4));
+        assert_eq!(cached(), 3);
+    }
 
-        if k >= sorted.len() {
-            panic!("k is out of bounds");
+    #[test]
+    fn on_demand_mode_scales_to_200k_points() {
+        let mut rng = SplitMix64::new(200_000);
+        let points = (0..200_000)
+            .map(|i| vec![OrderedFloat(i as f64), OrderedFloat(rng.below(1000) as f64)])
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = FakeQueryEngine::on_demand();
+        engine.init(&refs);
+        // Only the point references: 1.6 MB rather than 320 GB of lists.
+        let bytes = engine.estimated_memory_bytes();
+        assert!(
+            bytes <= 2 * points.len() * size_of::<&Point>(),
+            "{} bytes",
+            bytes
+        );
+
+        let mut brute = crate::brute_query::BruteForceQueryEngine::new();
+        brute.init(&refs);
+        for p in [&points[0], &points[123_456], &points[199_999]] {
+            assert_eq!(engine.run(p, 3.0), brute.run(p, 3.0));
+            assert_eq!(engine.k_dist(p, 5), brute.k_dist(p, 5));
         }
+    }
 
-        dist(&sorted[k], &point)
+    #[test]
+    fn parallel_init_and_queries_match_serial_ones() {
+        let mut rng = SplitMix64::new(334);
+        let points = (0..300)
+            .map(|_| {
+                vec![
+                    OrderedFloat(rng.below(20) as f64),
+                    OrderedFloat(rng.below(20) as f64),
+                ]
+            })
+            .collect::<HashSet<Point>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        // `init` sorts on the rayon pool with the `parallel` feature.
+        let mut engine = FakeQueryEngine::new();
+        engine.init(&refs);
+        let serial = points
+            .iter()
+            .map(|p| (p, engine.sort_from(p)))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(engine.sorted_by_distance, serial);
+
+        let queries = points.iter().collect::<Vec<_>>();
+        let runs = queries
+            .iter()
+            .map(|q| engine.run(q, 2.0))
+            .collect::<Vec<_>>();
+        let knns = queries.iter().map(|q| engine.knn(q, 5)).collect::<Vec<_>>();
+        assert_eq!(engine.run_many(&queries, 2.0), runs);
+        assert_eq!(engine.knn_many(&queries, 5), knns);
+        #[cfg(feature = "parallel")]
+        {
+            let par_runs = queries
+                .par_iter()
+                .map(|q| engine.run(q, 2.0))
+                .collect::<Vec<_>>();
+            assert_eq!(par_runs, runs);
+            let par_knns = queries
+                .par_iter()
+                .map(|q| engine.knn(q, 5))
+                .collect::<Vec<_>>();
+            assert_eq!(par_knns, knns);
+        }
+    }
+
+    #[test]
+    fn manhattan_and_chebyshev_change_the_clusters() {
+        use crate::algo::Algo;
+        use crate::types::{Class, MetricKind};
+
+        // Three pairs, 1.41, 1.98 and 1.2 apart in Euclidean distance; 2,
+        // 2.8 and 1.2 in Manhattan; 1, 1.4 and 1.2 in Chebyshev.
+        let pairs = [
+            [[0.0, 0.0], [1.0, 1.0]],
+            [[10.0, 0.0], [11.4, 1.4]],
+            [[20.0, 0.0], [21.2, 0.0]],
+        ];
+        let points = pairs
+            .iter()
+            .flatten()
+            .map(|&[x, y]| vec![OrderedFloat(x), OrderedFloat(y)])
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        // The pairs that are clusters of their own, the others being noise.
+        let clustered = |metric: MetricKind| {
+            let mut engine = FakeQueryEngine::with_metric(metric);
+            let classes = Algo::new(&mut engine, &refs, 1.5, 2).dbscan();
+            (0..pairs.len())
+                .filter(|i| {
+                    let (a, b) = (&points[2 * i], &points[2 * i + 1]);
+                    match (classes[a], classes[b]) {
+                        (Class::Classified(a), Class::Classified(b)) => a == b,
+                        (Class::Noise, Class::Noise) => false,
+                        other => panic!("pair {} is {:?}", i, other),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clustered(MetricKind::Euclidean), [0, 2]);
+        assert_eq!(clustered(MetricKind::Manhattan), [2]);
+        assert_eq!(clustered(MetricKind::Chebyshev), [0, 1, 2]);
+
+        // The lists are sorted and cut by the metric's own distances.
+        let mut manhattan = FakeQueryEngine::with_metric(MetricKind::Manhattan);
+        manhattan.init(&refs);
+        assert_eq!(manhattan.k_dist(&points[0], 1), 2.0);
+        assert_eq!(manhattan.run(&points[0], 2.0).len(), 2);
+        assert_eq!(manhattan.run(&points[0], 1.99).len(), 1);
+        let mut chebyshev = FakeQueryEngine::with_metric(MetricKind::Chebyshev);
+        chebyshev.init(&refs);
+        assert_eq!(chebyshev.k_dist(&points[0], 1), 1.0);
+        assert_eq!(chebyshev.knn(&points[4], 1), [(&points[5], 21.2 - 20.0)]);
+    }
+
+    #[test]
+    fn try_run_reports_what_went_wrong() {
+        let points = line();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut rtree = RTreeQueryEngine::new();
+
+        let engine = FakeQueryEngine::new();
+        assert_eq!(
+            engine.try_run(&points[0], 1.0),
+            Err(QueryError::NotInitialized)
+        );
+        assert_eq!(
+            rtree.try_run(&points[0], 1.0),
+            Err(QueryError::NotInitialized)
+        );
+
+        rtree.init(&refs);
+        let wrong = vec![OrderedFloat(1.5), OrderedFloat(-2.0)];
+        let expected = QueryError::DimensionMismatch {
+            point: "(1.5, -2)".to_string(),
+            expected: 1,
+            got: 2,
+        };
+        assert_eq!(
+            expected.to_string(),
+            "query point (1.5, -2) has dimension 2, but the indexed points have dimension 1"
+        );
+        assert_eq!(rtree.try_run(&wrong, 1.0), Err(expected.clone()));
+        for (mode, engine) in engines(&refs) {
+            assert_eq!(
+                engine.try_run(&wrong, 1.0),
+                Err(expected.clone()),
+                "{}",
+                mode
+            );
+            // `run` panics with the same message.
+            let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                engine.run(&wrong, 1.0);
+            }))
+            .unwrap_err();
+            assert_eq!(
+                panic.downcast_ref::<String>(),
+                Some(&expected.to_string()),
+                "{}",
+                mode
+            );
+
+            // Indexed points and others of the right dimension are answered.
+            let found = engine.try_run(&points[1], 2.0).unwrap();
+            assert_eq!(xs(&found), [0.0, 1.0, 3.0], "{}", mode);
+            assert_eq!(found, engine.run(&points[1], 2.0), "{}", mode);
+            let found = engine.try_run(&pt(4.5), 1.5).unwrap();
+            assert_eq!(xs(&found), [3.0, 6.0], "{}", mode);
+        }
+    }
+
+    #[test]
+    fn init_for_lists_only_the_subset() {
+        let mut rng = SplitMix64::new(338);
+        let points = (0..120)
+            .map(|_| {
+                vec![
+                    OrderedFloat(rng.below(30) as f64),
+                    OrderedFloat(rng.below(30) as f64),
+                ]
+            })
+            .collect::<HashSet<Point>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut full = FakeQueryEngine::new();
+        full.init(&refs);
+        let subset = points.iter().step_by(10).collect::<Vec<_>>();
+        let mut partial = FakeQueryEngine::new();
+        partial.init_for(&refs, &subset);
+
+        // Only the subset has lists, and only its lists are counted.
+        assert_eq!(partial.sorted_by_distance.len(), subset.len());
+        assert!(
+            subset
+                .iter()
+                .all(|p| partial.sorted_by_distance.contains_key(p))
+        );
+        let list = points.len() * size_of::<&Point>();
+        assert!(partial.estimated_memory_bytes() >= subset.len() * list);
+        assert!(partial.estimated_memory_bytes() < full.estimated_memory_bytes() / 5);
+
+        // Subset points take the lists, the others a scan; both answer like
+        // the full index.
+        for (i, p) in points.iter().enumerate() {
+            assert_eq!(partial.sorted_by_distance.contains_key(p), i % 10 == 0);
+            for eps in [0.0, 2.0, 5.5] {
+                assert_eq!(partial.run(p, eps), full.run(p, eps), "{:?} eps {}", p, eps);
+            }
+            // Neighbors that tie may come in another order.
+            let dists = |knn: Vec<(&Point, f64)>| knn.into_iter().map(|(_, d)| d).collect_vec();
+            assert_eq!(dists(partial.knn(p, 6)), dists(full.knn(p, 6)));
+            assert_eq!(
+                partial.k_dist_with_count(p, 3),
+                full.k_dist_with_count(p, 3)
+            );
+        }
     }
 }
@@ -0,0 +1,209 @@
+use crate::types::Point;
+use ordered_float::OrderedFloat;
+
+/// How `Imputer` deals with missing cells.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Strategy {
+    /// Drop every row with a missing cell.
+    DropRow,
+    /// Fill with the mean of the column's present values.
+    Mean,
+    /// Fill with the median of the column's present values.
+    Median,
+    /// Fill with a fixed value.
+    Constant(f64),
+}
+
+/// Points produced by `Imputer::apply`, with a record of what was changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Imputed {
+    pub points: Vec<Point>,
+    /// `source_rows[i]` is the input row `points[i]` came from.
+    pub source_rows: Vec<usize>,
+    /// Input rows removed by `Strategy::DropRow`.
+    pub dropped_rows: Vec<usize>,
+    /// `(row, column)` of every filled cell, by input row.
+    pub filled_cells: Vec<(usize, usize)>,
+}
+
+impl Imputed {
+    /// Whether any cell of input row `row` was filled in.
+    pub fn is_filled(&self, row: usize) -> bool {
+        self.filled_cells.iter().any(|&(r, _)| r == row)
+    }
+}
+
+/// Turns rows with missing cells (as read by `io::read_points_csv_missing`)
+/// into points that can be handed to the algorithm.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Imputer {
+    strategy: Strategy,
+}
+
+impl Imputer {
+    pub fn new(strategy: Strategy) -> Self {
+        Self { strategy }
+    }
+
+    /// Fills or drops the missing cells of `rows`. Fails if the rows have
+    /// different lengths, a column is missing in every row, or no row is left.
+    pub fn apply(&self, rows: &[Vec<Option<f64>>]) -> anyhow::Result<Imputed> {
+        let dim = rows.first().map_or(0, |r| r.len());
+        if let Some(i) = rows.iter().position(|r| r.len() != dim) {
+            anyhow::bail!(
+                "dimension mismatch at row {}: expected {}, got {}",
+                i,
+                dim,
+                rows[i].len()
+            );
+        }
+
+        let mut columns = vec![Vec::new(); dim];
+        for row in rows.iter() {
+            for (col, x) in columns.iter_mut().zip(row.iter()) {
+                col.extend(*x);
+            }
+        }
+        if let Some(c) = columns.iter().position(|col| col.is_empty()) {
+            anyhow::bail!("column {} is missing in every row", c);
+        }
+
+        let fill = columns
+            .iter_mut()
+            .map(|col| match self.strategy {
+                Strategy::DropRow => f64::NAN,
+                Strategy::Mean => col.iter().sum::<f64>() / col.len() as f64,
+                Strategy::Median => median(col),
+                Strategy::Constant(v) => v,
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = Imputed {
+            points: Vec::with_capacity(rows.len()),
+            source_rows: Vec::with_capacity(rows.len()),
+            dropped_rows: Vec::new(),
+            filled_cells: Vec::new(),
+        };
+        for (r, row) in rows.iter().enumerate() {
+            if self.strategy == Strategy::DropRow && row.iter().any(Option::is_none) {
+                out.dropped_rows.push(r);
+                continue;
+            }
+            let point = row
+                .iter()
+                .enumerate()
+                .map(|(c, x)| {
+                    OrderedFloat(x.unwrap_or_else(|| {
+                        out.filled_cells.push((r, c));
+                        fill[c]
+                    }))
+                })
+                .collect();
+            out.points.push(point);
+            out.source_rows.push(r);
+        }
+
+        if out.points.is_empty() {
+            anyhow::bail!("no rows left after dropping rows with missing values");
+        }
+        Ok(out)
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let n = values.len();
+    if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    // Missing cells scattered over three columns; the present values of the
+    // columns have means 2, 20 and 7 and medians 1.5, 20 and 7.
+    fn rows() -> Vec<Vec<Option<f64>>> {
+        vec![
+            vec![Some(1.0), None, Some(7.0)],
+            vec![Some(4.0), Some(10.0), Some(7.0)],
+            vec![None, Some(30.0), None],
+            vec![Some(1.0), Some(20.0), Some(7.0)],
+            vec![Some(2.0), None, Some(7.0)],
+        ]
+    }
+
+    #[test]
+    fn drop_row_keeps_complete_rows() {
+        let imputed = Imputer::new(Strategy::DropRow).apply(&rows()).unwrap();
+        assert_eq!(
+            imputed.points,
+            [pt(&[4.0, 10.0, 7.0]), pt(&[1.0, 20.0, 7.0])]
+        );
+        assert_eq!(imputed.source_rows, [1, 3]);
+        assert_eq!(imputed.dropped_rows, [0, 2, 4]);
+        assert!(imputed.filled_cells.is_empty());
+    }
+
+    #[test]
+    fn mean_median_and_constant_fill_the_missing_cells() {
+        let cases = [
+            (Strategy::Mean, [2.0, 20.0, 7.0]),
+            (Strategy::Median, [1.5, 20.0, 7.0]),
+            (Strategy::Constant(-1.0), [-1.0, -1.0, -1.0]),
+        ];
+        for (strategy, fill) in cases {
+            let imputed = Imputer::new(strategy).apply(&rows()).unwrap();
+            assert_eq!(
+                imputed.points,
+                [
+                    pt(&[1.0, fill[1], 7.0]),
+                    pt(&[4.0, 10.0, 7.0]),
+                    pt(&[fill[0], 30.0, fill[2]]),
+                    pt(&[1.0, 20.0, 7.0]),
+                    pt(&[2.0, fill[1], 7.0]),
+                ],
+                "{:?}",
+                strategy
+            );
+            assert_eq!(imputed.source_rows, [0, 1, 2, 3, 4]);
+            assert!(imputed.dropped_rows.is_empty());
+            assert_eq!(imputed.filled_cells, [(0, 1), (2, 0), (2, 2), (4, 1)]);
+            assert!(imputed.is_filled(2));
+            assert!(!imputed.is_filled(3));
+        }
+    }
+
+    #[test]
+    fn columns_missing_everywhere_are_an_error() {
+        let rows = vec![vec![Some(1.0), None], vec![Some(2.0), None]];
+        for strategy in [Strategy::DropRow, Strategy::Mean, Strategy::Constant(0.0)] {
+            let err = Imputer::new(strategy).apply(&rows).unwrap_err();
+            assert_eq!(err.to_string(), "column 1 is missing in every row");
+        }
+    }
+
+    #[test]
+    fn imputing_needs_rows_of_one_length_and_something_left() {
+        let err = Imputer::new(Strategy::Mean)
+            .apply(&[vec![Some(1.0)], vec![Some(1.0), Some(2.0)]])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dimension mismatch at row 1: expected 1, got 2"
+        );
+        let rows = vec![vec![Some(1.0), None], vec![None, Some(2.0)]];
+        let err = Imputer::new(Strategy::DropRow).apply(&rows).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no rows left after dropping rows with missing values"
+        );
+    }
+}
@@ -1,133 +1,6345 @@
-use crate::types::{Class, Point};
+use crate::sampling::{permutation, permute, reservoir_sample, unpermute};
+use crate::types::{
+    format_point, labels_in_order, validate_point, BitPoint, Class, Dataset, GowerMetric,
+    LabeledPoint, Point, PointCloud, Schema, SparsePoint, WeightedPoint,
+};
 use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use itertools::Itertools;
 use ordered_float::OrderedFloat;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// What the point readers do with rows containing NaN or infinite values.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum InvalidRows {
+    /// Fail with the line and column of the first bad value.
+    #[default]
+    Reject,
+    /// Drop such rows.
+    Skip,
+    /// Keep them as they are.
+    Allow,
+}
+
+/// What the CSV point readers do with malformed rows: rows with a field that
+/// is not a number, of another dimension than the first row (or the header),
+/// too short for the selected columns, or not valid UTF-8. Rows that
+/// `InvalidRows::Reject` fails on count as malformed too.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum OnError {
+    /// Fail at the first malformed row.
+    #[default]
+    Strict,
+    /// Drop malformed rows, counting them.
+    Skip,
+    /// Drop malformed rows, keeping the first `n` of them in
+    /// `CsvData::bad_lines`.
+    Collect(usize),
+}
+
+/// A malformed row dropped under `OnError::Collect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadLine {
+    /// Line number (from 1) on which the row starts.
+    pub line: usize,
+    /// The fields of the row joined by the delimiter, without quotes.
+    pub text: String,
+    /// Why the row was dropped, as an error message naming the line.
+    pub error: String,
+}
+
+impl fmt::Display for BadLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.error)
+    }
+}
+
+impl std::error::Error for BadLine {}
+
+/// Malformed rows dropped by a reader under `OnError::Skip` or `Collect`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BadLines {
+    /// Number of rows dropped.
+    pub skipped: usize,
+    /// The first of them, as many as `OnError::Collect` asks for.
+    pub lines: Vec<BadLine>,
+}
+
+impl BadLines {
+    fn add(&mut self, bad: BadLine, on_error: OnError) {
+        self.skipped += 1;
+        if matches!(on_error, OnError::Collect(n) if self.lines.len() < n) {
+            self.lines.push(bad);
+        }
+    }
+}
+
+/// Whether the first non-empty line of a CSV is a header naming the columns.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum Header {
+    /// Every line is data.
+    #[default]
+    Absent,
+    /// The first line is a header.
+    Present,
+    /// The first line is a header if any of its fields does not parse as a
+    /// number, so an all-numeric first row is kept as data.
+    Auto,
+}
+
+impl Header {
+    /// The mode selected by a binary's `--header` and `--auto-header` flags.
+    pub fn from_flags(header: bool, auto_header: bool) -> Self {
+        if header {
+            Header::Present
+        } else if auto_header {
+            Header::Auto
+        } else {
+            Header::Absent
+        }
+    }
+}
+
+/// Field separator of a CSV file. Numbers use `.` as the decimal separator
+/// unless `NumberFormat::decimal_comma` is set.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Delimiter {
+    Char(char),
+    /// Detected from the first non-empty line: tab if it has one, otherwise
+    /// semicolon if it has one, otherwise comma.
+    Auto,
+}
+
+impl Delimiter {
+    pub const COMMA: Self = Delimiter::Char(',');
+    pub const TAB: Self = Delimiter::Char('\t');
+    pub const SEMICOLON: Self = Delimiter::Char(';');
+
+    /// The separator to use for a file whose first non-empty line is
+    /// `first_line`.
+    pub fn resolve(self, first_line: &str) -> char {
+        match self {
+            Delimiter::Char(c) => c,
+            Delimiter::Auto => ['\t', ';']
+                .into_iter()
+                .find(|&c| first_line.contains(c))
+                .unwrap_or(','),
+        }
+    }
+
+    /// `resolve` for the file at `path`, e.g. to write output with the
+    /// separator of the input.
+    pub fn resolve_in(self, path: &str) -> Result<char> {
+        if let Delimiter::Char(c) = self {
+            return Ok(c);
+        }
+        if path == "-" {
+            anyhow::bail!("cannot detect the delimiter of stdin before reading it");
+        }
+        let (_, delimiter, _) =
+            data_records(open_input(path)?, CsvFormat::new(Header::Absent, self))?;
+        Ok(delimiter)
+    }
+}
+
+impl Default for Delimiter {
+    fn default() -> Self {
+        Delimiter::COMMA
+    }
+}
+
+/// Error returned when parsing a `Delimiter` fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDelimiterError(String);
+
+impl fmt::Display for ParseDelimiterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid delimiter '{}': expected one character that cannot be part of a number, 'tab' or 'auto'",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseDelimiterError {}
+
+/// Parses `auto`, `tab` (or `\t`), `comma`, `semicolon`, or a single
+/// character such as `;` or `|`. Characters that can appear in numbers are
+/// rejected.
+impl FromStr for Delimiter {
+    type Err = ParseDelimiterError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => return Ok(Delimiter::Auto),
+            "tab" | "\\t" => return Ok(Delimiter::TAB),
+            "comma" => return Ok(Delimiter::COMMA),
+            "semicolon" => return Ok(Delimiter::SEMICOLON),
+            _ => {}
+        }
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !(c.is_alphanumeric() || "+-.".contains(c)) => {
+                Ok(Delimiter::Char(c))
+            }
+            _ => Err(ParseDelimiterError(s.to_string())),
+        }
+    }
+}
+
+/// How numbers are written in a CSV file. The default reads them as
+/// `str::parse` does: `1.5`, `1.5E+003`, and `nan`, `inf` or `infinity` in
+/// any case, leaving NaN and infinite values to `InvalidRows`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct NumberFormat {
+    /// Numbers use `,` as the decimal separator, e.g. `1,5`, as in files
+    /// from many European locales. A `.` is still read as one. Only valid
+    /// with a delimiter other than `,`.
+    pub decimal_comma: bool,
+    /// Fail on NaN and infinite values (`nan`, `inf`, or numbers too large
+    /// for an `f64`) with their line and column, whatever `InvalidRows` says.
+    pub reject_non_finite: bool,
+}
+
+impl NumberFormat {
+    /// `.` decimals, NaN and infinite values left to `InvalidRows`.
+    pub const STANDARD: Self = NumberFormat {
+        decimal_comma: false,
+        reject_non_finite: false,
+    };
+    /// `,` decimals, NaN and infinite values left to `InvalidRows`.
+    pub const DECIMAL_COMMA: Self = NumberFormat {
+        decimal_comma: true,
+        reject_non_finite: false,
+    };
+
+    /// The same format, failing on NaN and infinite values if `reject`.
+    pub fn reject_non_finite(self, reject: bool) -> Self {
+        Self {
+            reject_non_finite: reject,
+            ..self
+        }
+    }
+}
+
+/// Layout of a CSV file: its header mode, field separator and number format,
+/// what to do with malformed rows, and how many rows to read. The default is
+/// a comma-separated file without header and with standard numbers, read
+/// strictly to the end.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct CsvFormat {
+    pub header: Header,
+    pub delimiter: Delimiter,
+    pub numbers: NumberFormat,
+    pub on_error: OnError,
+    /// Stop after this many non-empty rows after the header, malformed ones
+    /// included, without reading the rest of the input.
+    pub max_rows: Option<usize>,
+}
+
+impl CsvFormat {
+    /// A format with standard numbers, read strictly.
+    pub fn new(header: Header, delimiter: Delimiter) -> Self {
+        Self {
+            header,
+            delimiter,
+            numbers: NumberFormat::STANDARD,
+            on_error: OnError::Strict,
+            max_rows: None,
+        }
+    }
+
+    /// The same format with `numbers`.
+    pub fn with_numbers(self, numbers: NumberFormat) -> Self {
+        Self { numbers, ..self }
+    }
+
+    /// The same format, handling malformed rows as `on_error` says.
+    pub fn with_on_error(self, on_error: OnError) -> Self {
+        Self { on_error, ..self }
+    }
+
+    /// The same format, reading at most `max_rows` rows if given. Readers
+    /// that sample rows sample from these.
+    pub fn with_max_rows(self, max_rows: Option<usize>) -> Self {
+        Self { max_rows, ..self }
+    }
+}
+
+/// A CSV column, by 0-based index or by header name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnRef {
+    Index(usize),
+    Name(String),
+}
+
+/// Parses a 0-based index such as `3`, or anything else as a header name.
+impl FromStr for ColumnRef {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.parse::<usize>() {
+            Ok(i) => ColumnRef::Index(i),
+            Err(_) => ColumnRef::Name(s.to_string()),
+        })
+    }
+}
+
+impl ColumnRef {
+    // Index of the column, looking a name up in the header `names`.
+    fn resolve(&self, names: Option<&[String]>) -> Result<usize> {
+        match self {
+            ColumnRef::Index(i) => Ok(*i),
+            ColumnRef::Name(name) => {
+                let Some(names) = names else {
+                    anyhow::bail!("selecting column '{}' by name needs a header", name);
+                };
+                names
+                    .iter()
+                    .position(|n| n == name)
+                    .with_context(|| format!("no column '{}' in header", name))
+            }
+        }
+    }
+}
+
+/// Columns to read as coordinates, in order; see `CsvOptions::columns`.
+/// Names are looked up once the header is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Columns {
+    /// Columns to read, in this order; all of them if `None`.
+    pub include: Option<Vec<ColumnRef>>,
+    /// Columns not to read. None of them may be in `include`.
+    pub exclude: Vec<ColumnRef>,
+}
+
+impl Columns {
+    /// Parses items such as `3`, `2..10` (half-open, like Rust ranges) or
+    /// header names like `temperature`.
+    pub fn parse<S: AsRef<str>>(items: &[S]) -> Result<Self> {
+        Ok(Self {
+            include: Some(parse_column_refs(items)?),
+            exclude: Vec::new(),
+        })
+    }
+
+    /// The columns selected by a `--columns` and an `--exclude-columns` flag,
+    /// either of which may be missing (or empty); `None` for all columns.
+    pub fn from_flags<S: AsRef<str>>(include: Option<&[S]>, exclude: &[S]) -> Result<Option<Self>> {
+        let exclude = match exclude {
+            [] => Vec::new(),
+            items => parse_column_refs(items)?,
+        };
+        let include = include.map(parse_column_refs).transpose()?;
+        Ok((include.is_some() || !exclude.is_empty()).then_some(Self { include, exclude }))
+    }
+
+    // Indices of the columns, looking names up in the header `names`. `width`
+    // is the number of columns in a row, or 0 if unknown (no rows).
+    fn resolve(&self, names: Option<&[String]>, width: usize) -> Result<Vec<usize>> {
+        let excluded = self
+            .exclude
+            .iter()
+            .map(|column| column.resolve(names))
+            .collect::<Result<HashSet<_>>>()?;
+        if let Some(&i) = excluded.iter().filter(|&&i| width > 0 && i >= width).min() {
+            anyhow::bail!(
+                "excluded column {} is out of range; rows have {} columns",
+                i,
+                width
+            );
+        }
+        let Some(include) = &self.include else {
+            let kept = (0..width)
+                .filter(|i| !excluded.contains(i))
+                .collect::<Vec<_>>();
+            if kept.is_empty() && width > 0 {
+                anyhow::bail!("every column is excluded");
+            }
+            return Ok(kept);
+        };
+        let included = include
+            .iter()
+            .map(|column| column.resolve(names))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(&i) = included.iter().find(|i| excluded.contains(i)) {
+            match names.and_then(|names| names.get(i)) {
+                Some(name) => anyhow::bail!("column '{}' is both selected and excluded", name),
+                None => anyhow::bail!("column {} is both selected and excluded", i),
+            }
+        }
+        Ok(included)
+    }
+}
+
+// The items of `Columns::parse`.
+fn parse_column_refs<S: AsRef<str>>(items: &[S]) -> Result<Vec<ColumnRef>> {
+    let mut columns = Vec::new();
+    for item in items {
+        let item = item.as_ref().trim();
+        if item.is_empty() {
+            anyhow::bail!("empty column name");
+        } else if let Some((a, b)) = item.split_once("..") {
+            let (Ok(a), Ok(b)) = (a.trim().parse::<usize>(), b.trim().parse::<usize>()) else {
+                anyhow::bail!("invalid column range '{}'", item);
+            };
+            if a >= b {
+                anyhow::bail!("empty column range '{}'", item);
+            }
+            columns.extend((a..b).map(ColumnRef::Index));
+        } else {
+            let Ok(column) = item.parse();
+            columns.push(column);
+        }
+    }
+    if columns.is_empty() {
+        anyhow::bail!("no columns selected");
+    }
+    Ok(columns)
+}
+
+// Number of columns in a row: of the header if one was read, otherwise of
+// the first record; 0 without either.
+fn row_width(
+    names: Option<&[String]>,
+    records: &mut Peekable<impl Iterator<Item = Record>>,
+) -> usize {
+    match (names, records.peek()) {
+        (Some(names), _) => names.len(),
+        (None, Some(Ok((_, record)))) => record.len(),
+        _ => 0,
+    }
+}
 
 /// Read a CSV of pure coordinates (no header), each line: x1,x2,...,xD
 /// Returns points as `Vec<Point>` where `Point = Vec<OrderedFloat<f64>>`.
+/// Rows with NaN or infinite values are rejected. `read_csv` reads other
+/// layouts, and more than the coordinates.
 pub fn read_points_csv(path: &str) -> Result<Vec<Point>> {
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+    Ok(read_csv(open_input(path)?, &CsvOptions::default())?.points)
+}
 
-    let mut points: Vec<Point> = Vec::new();
-    let mut expected_dim: Option<usize> = None;
+/// Streams the points of a coordinate CSV one row at a time, for input too
+/// large to hold at once. Rows are checked as in `read_points_csv`: the
+/// iterator yields an error at the first bad row and then ends, and yields a
+/// single error for a file without points.
+pub fn stream_points_csv(path: &str) -> Result<impl Iterator<Item = Result<Point>>> {
+    let (_, _, rows) = point_rows(
+        open_input(path)?,
+        CsvFormat::default(),
+        InvalidRows::Reject,
+        None,
+    )?;
+    Ok(rows.map(|row| row.map(|coords| coords.into_iter().map(OrderedFloat).collect())))
+}
 
-    for (lineno, raw_line) in content.lines().enumerate() {
-        let line = raw_line.trim();
-        if line.is_empty() {
-            continue;
+/// What `read_csv` keeps of every row besides its coordinates, for output
+/// that copies the input.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum KeepRows {
+    /// Nothing.
+    #[default]
+    None,
+    /// The fields of every row, unquoted and trimmed, in `CsvData::raw`.
+    Fields,
+    /// The text of every row exactly as in the input (spacing, quotes and
+    /// number formatting included), in `CsvData::lines`. A BOM and `\r` line
+    /// endings are dropped as by every reader. The whole input is held in
+    /// memory while reading.
+    Lines,
+}
+
+/// How `read_csv` reads a coordinate CSV: its layout, and what to read
+/// besides the coordinates. The options combine freely, e.g. a sample of the
+/// rows of a file with a label column, skipping malformed rows. The default
+/// reads every column of a `CsvFormat::default()` file as coordinates and
+/// rejects rows with NaN or infinite values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub format: CsvFormat,
+    /// What happens to rows with NaN or infinite values.
+    pub invalid: InvalidRows,
+    /// Columns to read as coordinates, in this order; all of them if `None`,
+    /// in both cases without the label and weight columns. Other columns are
+    /// not parsed, so they may hold text.
+    pub columns: Option<Columns>,
+    /// Column split off as ground-truth labels, e.g. the class column of a
+    /// benchmark dataset. It may hold text.
+    pub label: Option<ColumnRef>,
+    /// Column split off as the weight of every point, and what an empty or
+    /// missing weight means. Weights must be finite and positive; rows with
+    /// other weights are malformed.
+    pub weight: Option<(ColumnRef, MissingWeight)>,
+    pub keep: KeepRows,
+    /// Read a uniform random sample of this many rows (all of them if there
+    /// are fewer) in one pass, holding only the sample; the same seed picks
+    /// the same rows. Rows keep the order of the input.
+    pub sample: Option<(usize, u64)>,
+}
+
+impl CsvOptions {
+    /// Every column of a file in `format` as coordinates.
+    pub fn new(format: CsvFormat) -> Self {
+        Self {
+            format,
+            ..Self::default()
         }
+    }
 
-        let coords: Vec<OrderedFloat<f64>> = line
-            .split(',')
-            .map(|s| s.trim().parse::<f64>().map(OrderedFloat))
-            .collect::<std::result::Result<_, _>>()
-            .with_context(|| format!("parse error at line {}", lineno + 1))?;
+    /// The same options, with `invalid` deciding what happens to rows with
+    /// NaN or infinite values.
+    pub fn with_invalid(self, invalid: InvalidRows) -> Self {
+        Self { invalid, ..self }
+    }
 
-        if let Some(dim) = expected_dim {
-            if coords.len() != dim {
-                anyhow::bail!(
-                    "dimension mismatch at line {}: expected {}, got {}",
-                    lineno + 1,
+    /// The same options, reading only `columns` as coordinates if given.
+    pub fn with_columns(self, columns: Option<Columns>) -> Self {
+        Self { columns, ..self }
+    }
+
+    /// The same options, splitting off the `label` column if given.
+    pub fn with_label(self, label: Option<ColumnRef>) -> Self {
+        Self { label, ..self }
+    }
+
+    /// The same options, splitting off the `weight` column if given, with
+    /// `missing` saying what an empty or missing weight means.
+    pub fn with_weight(self, weight: Option<ColumnRef>, missing: MissingWeight) -> Self {
+        Self {
+            weight: weight.map(|weight| (weight, missing)),
+            ..self
+        }
+    }
+
+    /// The same options, keeping `keep` of every row.
+    pub fn keeping(self, keep: KeepRows) -> Self {
+        Self { keep, ..self }
+    }
+
+    /// The same options, reading a sample of `n` rows picked with `seed` if
+    /// `n` is given.
+    pub fn with_sample(self, n: Option<usize>, seed: u64) -> Self {
+        Self {
+            sample: n.map(|n| (n, seed)),
+            ..self
+        }
+    }
+}
+
+/// What `read_csv` read: the points, and one item per point of whatever
+/// `CsvOptions` asked for besides.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CsvData {
+    pub points: Vec<Point>,
+    /// Names of the coordinate columns, if a header was read.
+    pub schema: Option<Schema>,
+    /// The delimiter used, which tells what `Delimiter::Auto` resolved to.
+    pub delimiter: char,
+    /// The line number (from 1) on which the row of each point starts, so
+    /// that sampled points can be traced back.
+    pub line_numbers: Vec<usize>,
+    /// The fields of every row, with `KeepRows::Fields`.
+    pub raw: Option<RawRows>,
+    /// The text of every row, with `KeepRows::Lines`.
+    pub lines: Option<RawLines>,
+    /// The labels of `CsvOptions::label`.
+    pub labels: Option<Labels>,
+    /// The weights of `CsvOptions::weight`.
+    pub weights: Option<Weights>,
+    /// The malformed rows dropped as `format.on_error` says.
+    pub bad_lines: BadLines,
+}
+
+/// Reads the points of a coordinate CSV from any buffered reader (a file
+/// opened with `open_input`, a byte slice), and whatever else `options`
+/// asks for.
+pub fn read_csv(reader: impl BufRead, options: &CsvOptions) -> Result<CsvData> {
+    let source = csv_source(reader, options)?;
+    let rows = take_rows(source.rows, options.sample)?;
+    let bad_lines = source.report.take();
+    csv_data(source.head, rows, options, bad_lines)
+}
+
+/// Reads the coordinate CSVs at `paths` one after the other as one set of
+/// points, e.g. daily shards of the same data, as `read_csv` reads one of
+/// them; a sample is taken from all the rows. Also returns, for each point,
+/// the index in `paths` of the file it comes from. All files must have the
+/// same dimension, and files with a header must name the same columns; files
+/// without points are skipped, but not all of them may be. The schema,
+/// delimiter and header are those of the first file with points, and line
+/// numbers count within each file.
+pub fn read_csv_many(paths: &[&str], options: &CsvOptions) -> Result<(CsvData, Vec<usize>)> {
+    let mut sources = Vec::new();
+    // The first file with points: its index, column names and dimension.
+    let mut first: Option<(usize, Option<Vec<String>>, Option<usize>)> = None;
+    for (i, &path) in paths.iter().enumerate() {
+        let context = || format!("failed to read '{}'", path);
+        let mut source = csv_source(open_input(path)?, options).with_context(context)?;
+        if !source.has_rows {
+            continue;
+        }
+        // The first row of each file is checked against the first file.
+        let names = &source.head.names;
+        let dim = match source.rows.peek() {
+            Some(Ok(row)) => Some(row.coords.len()),
+            _ => None,
+        };
+        if let Some((j, first_names, first_dim)) = &first {
+            match (first_names, names) {
+                (Some(a), Some(b)) if a != b => anyhow::bail!(
+                    "'{}' has columns {}, but '{}' has {}",
+                    path,
+                    b.join(","),
+                    paths[*j],
+                    a.join(",")
+                ),
+                _ => {}
+            }
+            match (*first_dim, dim) {
+                (Some(first_dim), Some(dim)) if dim != first_dim => anyhow::bail!(
+                    "dimension mismatch: '{}' has {} coordinates per row, '{}' has {}",
+                    path,
                     dim,
-                    coords.len()
-                );
+                    paths[*j],
+                    first_dim
+                ),
+                _ => {}
             }
         } else {
-            expected_dim = Some(coords.len());
+            first = Some((i, names.clone(), dim));
         }
+        sources.push((i, source));
+    }
+    if sources.is_empty() {
+        anyhow::bail!("no points found in any of the {} input files", paths.len());
+    }
 
-        points.push(coords);
+    let mut head = None;
+    let mut reports = Vec::new();
+    let mut rows = Vec::new();
+    for (i, source) in sources {
+        if head.is_none() {
+            head = Some(source.head);
+        }
+        reports.push(source.report);
+        let path = paths[i];
+        rows.push(source.rows.map(move |row| {
+            row.map(|row| (i, row))
+                .with_context(|| format!("failed to read '{}'", path))
+        }));
+    }
+    let (sources, rows) = take_rows(rows.into_iter().flatten(), options.sample)?
+        .into_iter()
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+    let mut bad_lines = BadLines::default();
+    for report in reports {
+        let report = report.take();
+        bad_lines.skipped += report.skipped;
+        bad_lines.lines.extend(report.lines);
     }
+    if let OnError::Collect(n) = options.format.on_error {
+        bad_lines.lines.truncate(n);
+    }
+    let head = head.expect("there is a file with points");
+    Ok((csv_data(head, rows, options, bad_lines)?, sources))
+}
 
-    if points.is_empty() {
-        anyhow::bail!("no points found in input");
+// Points read from several files, and the index of the file of each.
+type Shards = (Vec<Point>, Vec<usize>);
+
+/// Reads the coordinate CSVs at `paths` one after the other as one set of
+/// points, as `read_points_csv` reads one; see `read_csv_many`. Also returns,
+/// for each point, the index in `paths` of the file it comes from.
+pub fn read_points_csv_many(paths: &[&str]) -> Result<Shards> {
+    let (data, sources) = read_csv_many(paths, &CsvOptions::default())?;
+    Ok((data.points, sources))
+}
+
+/// Reads a uniform random sample of `n` points of a coordinate CSV (all of
+/// them if there are fewer) in one pass, holding only the sample. The same
+/// `seed` gives the same sample. Points keep the order of the file and come
+/// with their line numbers (from 1), so they can be traced back.
+pub fn sample_points_csv(path: &str, n: usize, seed: u64) -> Result<Vec<(usize, Point)>> {
+    let options = CsvOptions::default().with_sample(Some(n), seed);
+    let data = read_csv(open_input(path)?, &options)?;
+    Ok(data.line_numbers.into_iter().zip(data.points).collect())
+}
+
+// A row kept by `read_csv`: its 0-based line number, its coordinates, and
+// whatever else `CsvOptions` asks for.
+struct CsvRow {
+    line: usize,
+    coords: Vec<f64>,
+    fields: Option<Vec<String>>,
+    text: Option<String>,
+    label: Option<String>,
+    weight: Option<f64>,
+}
+
+// What the header of an input of `read_csv` says.
+struct Head {
+    // Names of the coordinate columns.
+    names: Option<Vec<String>>,
+    // Names of all the columns.
+    header: Option<Vec<String>>,
+    // The header line as read, with `KeepRows::Lines`.
+    header_line: Option<String>,
+    // Names of the label and weight columns.
+    label: Option<String>,
+    weight: Option<String>,
+    delimiter: char,
+}
+
+// An input of `read_csv`, with its header read.
+struct Source<I: Iterator> {
+    head: Head,
+    // Whether there are rows after the header, valid or not.
+    has_rows: bool,
+    rows: Peekable<I>,
+    report: Report,
+}
+
+// The input text of `KeepRows::Lines`, shared by the CSV reader and the rows
+// cut out of it.
+#[derive(Clone)]
+struct SharedText(Rc<str>);
+
+impl AsRef<[u8]> for SharedText {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
     }
+}
 
-    Ok(points)
+// Reads the header of `reader` and streams its rows as `options` says.
+fn csv_source<'a, R: BufRead + 'a>(
+    reader: R,
+    options: &CsvOptions,
+) -> Result<Source<impl Iterator<Item = Result<CsvRow>> + use<'a, R>>> {
+    let (format, keep) = (options.format, options.keep);
+    // Rows are cut out of the text by line number; they are counted on the
+    // text as `TextInput` hands it to `data_records`.
+    let (reader, text): (Box<dyn BufRead + 'a>, _) = if keep == KeepRows::Lines {
+        let mut text = String::new();
+        TextInput::new(reader)
+            .read_to_string(&mut text)
+            .context("failed to read input")?;
+        let text = SharedText(Rc::from(text));
+        (Box::new(std::io::Cursor::new(text.clone())), Some(text))
+    } else {
+        (Box::new(reader), None)
+    };
+    let (names, delimiter, mut records) = data_records(reader, format)?;
+    let has_rows = records.peek().is_some();
+    let header_line = text.as_ref().zip(names.as_ref()).and_then(|(text, _)| {
+        let line = text.0.split('\n').find(|l| !l.trim().is_empty())?;
+        Some(line.to_string())
+    });
+    // Where each line starts in the text.
+    let starts = text.as_ref().map(|text| {
+        std::iter::once(0)
+            .chain(text.0.match_indices('\n').map(|(i, _)| i + 1))
+            .collect::<Vec<_>>()
+    });
+
+    let label = options.label.as_ref().map(|column| (column, "label"));
+    let weight = options
+        .weight
+        .as_ref()
+        .map(|(column, _)| (column, "weight"));
+    let extras = label.into_iter().chain(weight).collect::<Vec<_>>();
+    let (split, features) = match extras.as_slice() {
+        [] => (Vec::new(), None),
+        extras => {
+            let columns = options.columns.as_ref();
+            let (split, features) = split_columns(names.as_deref(), &mut records, extras, columns)?;
+            (split, Some(features))
+        }
+    };
+    let mut split = split.into_iter();
+    let label = options.label.as_ref().and_then(|_| split.next());
+    let weight = options
+        .weight
+        .as_ref()
+        .and_then(|&(_, missing)| split.next().map(|(i, name)| (i, name, missing)));
+
+    let header = names.clone();
+    let columns = features.as_ref().or(options.columns.as_ref());
+    let (label_index, weight_index) = (
+        label.as_ref().map(|&(i, _)| i),
+        weight.as_ref().map(|&(i, _, missing)| (i, missing)),
+    );
+    let (names, rows, report) = point_records(
+        names,
+        records,
+        format,
+        delimiter,
+        options.invalid,
+        columns,
+        move |lineno, record| {
+            let fields = (keep == KeepRows::Fields)
+                .then(|| record.iter().map(|s| s.trim().to_string()).collect());
+            let text = text.as_ref().zip(starts.as_ref()).map(|(text, starts)| {
+                let inner = record
+                    .iter()
+                    .map(|field| field.bytes().filter(|&b| b == b'\n').count())
+                    .sum::<usize>();
+                let end = starts
+                    .get(lineno + inner + 1)
+                    .map_or(text.0.len(), |&start| start - 1);
+                text.0[starts[lineno]..end].to_string()
+            });
+            let label = label_index
+                .map(|i| {
+                    let value = record.get(i).with_context(|| {
+                        format!(
+                            "line {}: label column {} is out of range; the row has {} columns",
+                            lineno + 1,
+                            i,
+                            record.len()
+                        )
+                    })?;
+                    anyhow::Ok(value.trim().to_string())
+                })
+                .transpose()?;
+            let weight = weight_index
+                .map(|(i, missing)| parse_weight(lineno, record, i, missing, format.numbers))
+                .transpose()?;
+            Ok(CsvRow {
+                line: lineno,
+                coords: Vec::new(),
+                fields,
+                text,
+                label,
+                weight,
+            })
+        },
+    )?;
+    let head = Head {
+        names,
+        header,
+        header_line,
+        label: label.and_then(|(_, name)| name),
+        weight: weight.and_then(|(_, name, _)| name),
+        delimiter,
+    };
+    let rows = rows
+        .map(|row| row.map(|(coords, row)| CsvRow { coords, ..row }))
+        .peekable();
+    Ok(Source {
+        head,
+        has_rows,
+        rows,
+        report,
+    })
 }
 
-/// Write clustered output: each line is `cid,x1,x2,...`.
-pub fn write_clustered_csv(
-    path: &str,
-    points: &[Point],
-    classes: &HashMap<&Point, Class>,
-) -> Result<()> {
-    let file = fs::File::create(path).with_context(|| {
-        format!(
-            "failed to create '{}': insufficient permissions or path invalid",
-            path
-        )
-    })?;
-    let mut w = BufWriter::new(file);
+// The weight in `column` of the record starting on line `lineno` (from 0).
+fn parse_weight(
+    lineno: usize,
+    record: &csv::StringRecord,
+    column: usize,
+    missing: MissingWeight,
+    numbers: NumberFormat,
+) -> Result<f64> {
+    match record.get(column).map(str::trim).filter(|s| !s.is_empty()) {
+        Some(s) => {
+            let value = parse_field(s, numbers)
+                .with_context(|| format!("line {}: invalid weight", lineno + 1))?;
+            if !(value.is_finite() && value > 0.0) {
+                anyhow::bail!(
+                    "line {}: weight must be finite and positive, got {}",
+                    lineno + 1,
+                    s
+                );
+            }
+            Ok(value)
+        }
+        None if missing == MissingWeight::Unit => Ok(1.0),
+        None => anyhow::bail!(
+            "line {}: weight column {} is empty or missing",
+            lineno + 1,
+            column
+        ),
+    }
+}
 
-    for p in points.iter() {
-        let cid = match classes.get(p).copied().unwrap_or(Class::Noise) {
-            Class::Classified(id) => id as isize,
-            Class::Noise | Class::Unclassified => -1,
-        };
+// All the `rows`, or a sample of them.
+fn take_rows<T>(
+    rows: impl Iterator<Item = Result<T>>,
+    sample: Option<(usize, u64)>,
+) -> Result<Vec<T>> {
+    match sample {
+        Some((n, seed)) => itertools::process_results(rows, |rows| reservoir_sample(rows, n, seed)),
+        None => rows.collect(),
+    }
+}
 
-        write!(w, "{}", cid)?;
-        for x in p.iter() {
-            write!(w, ",{}", x.0)?;
+// Gathers the `rows` kept by `read_csv` from inputs with the header `head`.
+fn csv_data(
+    head: Head,
+    rows: Vec<CsvRow>,
+    options: &CsvOptions,
+    bad_lines: BadLines,
+) -> Result<CsvData> {
+    let keep = options.keep;
+    let mut data = CsvData {
+        points: Vec::with_capacity(rows.len()),
+        schema: head.names.map(Schema::new).transpose()?,
+        delimiter: head.delimiter,
+        line_numbers: Vec::with_capacity(rows.len()),
+        raw: (keep == KeepRows::Fields).then(|| RawRows {
+            header: head.header,
+            rows: Vec::new(),
+        }),
+        lines: (keep == KeepRows::Lines).then(|| RawLines {
+            header: head.header_line,
+            lines: Vec::new(),
+        }),
+        labels: options.label.as_ref().map(|_| Labels {
+            name: head.label,
+            values: Vec::new(),
+        }),
+        weights: options.weight.as_ref().map(|_| Weights {
+            name: head.weight,
+            values: Vec::new(),
+        }),
+        bad_lines,
+    };
+    for row in rows {
+        data.points
+            .push(row.coords.into_iter().map(OrderedFloat).collect());
+        data.line_numbers.push(row.line + 1);
+        if let (Some(raw), Some(fields)) = (&mut data.raw, row.fields) {
+            raw.rows.push(fields);
+        }
+        if let (Some(lines), Some(text)) = (&mut data.lines, row.text) {
+            lines.lines.push(text);
+        }
+        if let (Some(labels), Some(label)) = (&mut data.labels, row.label) {
+            labels.values.push(label);
+        }
+        if let (Some(weights), Some(weight)) = (&mut data.weights, row.weight) {
+            weights.values.push(weight);
         }
-        writeln!(w)?;
     }
+    Ok(data)
+}
 
-    w.flush()?;
-    Ok(())
+/// Shuffles `points` (Fisher-Yates), the same way for the same `seed`, so
+/// that order-sensitive steps do not depend on the order of the file. Returns
+/// the permutation: `points[i]` is the point formerly at `perm[i]`.
+pub fn shuffle_points(points: &mut Vec<Point>, seed: u64) -> Vec<usize> {
+    let perm = permutation(points.len(), seed);
+    permute(points, &perm);
+    perm
 }
 
-/// Read clustered CSV: each line `cid,x1,x2,...` into `(cid, Vec<f64>)`.
-pub fn read_clustered_csv(path: &str) -> Result<Vec<(isize, Vec<f64>)>> {
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+/// Restores the order of `points` before `shuffle_points` returned `perm`.
+pub fn unshuffle_points(points: &mut Vec<Point>, perm: &[usize]) {
+    unpermute(points, perm);
+}
 
-    let mut out = Vec::new();
-    let mut expected_dim: Option<usize> = None; // number of coordinates per row
-    for (lineno, raw) in content.lines().enumerate() {
-        let line = raw.trim();
-        if line.is_empty() {
+/// Input rows kept as text by `read_csv` with `KeepRows::Fields`, for
+/// `write_clustered_csv_passthrough`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawRows {
+    /// Names of all the columns, if a header was read.
+    pub header: Option<Vec<String>>,
+    /// The fields of each point's row as read, unquoted and trimmed.
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Input lines kept verbatim by `read_csv` with `KeepRows::Lines`, for
+/// `write_clustered_csv_verbatim`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawLines {
+    /// The header line, if a header was read.
+    pub header: Option<String>,
+    /// The text of each point's row, without the line break; rows with quoted
+    /// line breaks span several lines.
+    pub lines: Vec<String>,
+}
+
+/// Ground-truth labels split off by `read_csv` (see `CsvOptions::label`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    /// Name of the label column, if a header was read.
+    pub name: Option<String>,
+    /// The label of each point as text; numeric labels are left for the caller
+    /// to parse.
+    pub values: Vec<String>,
+}
+
+/// Reads ground-truth labels kept apart from the points: one label per line,
+/// in the order of the points, e.g. for `validation::compare`. Labels are
+/// trimmed and may hold text; empty lines at the end are ignored, elsewhere
+/// they fail with their line number. A BOM and any line endings are handled
+/// as for the points.
+pub fn read_label_lines(reader: impl BufRead) -> Result<Labels> {
+    let mut values = Vec::new();
+    let mut blank = None;
+    for (i, line) in TextInput::new(reader).lines().enumerate() {
+        let line = line.with_context(|| format!("line {}: failed to read", i + 1))?;
+        let label = line.trim();
+        if label.is_empty() {
+            blank.get_or_insert(i + 1);
             continue;
         }
-        let cols: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
-        if cols.len() < 2 {
+        if let Some(line) = blank {
+            anyhow::bail!("line {}: empty label", line);
+        }
+        values.push(label.to_string());
+    }
+    Ok(Labels { name: None, values })
+}
+
+// A column split off the coordinates, and its name if a header was read.
+type SplitColumn = (usize, Option<String>);
+
+// Resolves the `extras` columns that `read_csv` splits off the coordinates
+// (the label and weight columns, each with what it is called in errors),
+// and the coordinate columns: `columns` without them if given, otherwise all
+// the others. Also returns the name of each extra column if a header was
+// read.
+fn split_columns(
+    names: Option<&[String]>,
+    records: &mut Peekable<impl Iterator<Item = Record>>,
+    extras: &[(&ColumnRef, &str)],
+    columns: Option<&Columns>,
+) -> Result<(Vec<SplitColumn>, Columns)> {
+    let mut split: Vec<SplitColumn> = Vec::new();
+    for &(column, what) in extras {
+        let extra = column.resolve(names)?;
+        if let Some(j) = split.iter().position(|&(other, _)| other == extra) {
             anyhow::bail!(
-                "line {}: expected at least 2 columns (cid,x1,...)",
-                lineno + 1
+                "column {} cannot be both the {} and the {} column",
+                extra,
+                extras[j].1,
+                what
             );
         }
+        let name = names
+            .map(|names| {
+                names.get(extra).cloned().with_context(|| {
+                    format!(
+                        "header: {} column {} is out of range; it has {} columns",
+                        what,
+                        extra,
+                        names.len()
+                    )
+                })
+            })
+            .transpose()?;
+        split.push((extra, name));
+    }
+    let width = row_width(names, records);
+    let features = match columns {
+        Some(columns) => columns.resolve(names, width)?,
+        None => (0..width).collect(),
+    };
+    let features = features
+        .into_iter()
+        .filter(|&i| split.iter().all(|&(extra, _)| extra != i))
+        .map(ColumnRef::Index)
+        .collect::<Vec<_>>();
+    if features.is_empty() && records.peek().is_some() {
+        let besides = split
+            .iter()
+            .zip(extras)
+            .map(|((extra, _), (_, what))| format!("the {} column {}", what, extra))
+            .join(" and ");
+        anyhow::bail!("no coordinate columns besides {}", besides);
+    }
+    let features = Columns {
+        include: Some(features),
+        exclude: Vec::new(),
+    };
+    Ok((split, features))
+}
 
-        let cid: isize = cols[0]
-            .parse()
-            .with_context(|| format!("line {}: invalid cid '{}'", lineno + 1, cols[0]))?;
+/// What `read_csv` does with a row whose weight field is empty or missing
+/// (the row is too short).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MissingWeight {
+    /// Weigh the point 1.
+    #[default]
+    Unit,
+    /// Treat the row as malformed, failing with the line number.
+    Reject,
+}
 
-        let coords: Vec<f64> = cols[1..]
-            .iter()
-            .map(|s| s.parse::<f64>())
-            .collect::<std::result::Result<_, _>>()
-            .with_context(|| format!("line {}: invalid coordinate value", lineno + 1))?;
+/// Point weights split off by `read_csv` (see `CsvOptions::weight`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Weights {
+    /// Name of the weight column, if a header was read.
+    pub name: Option<String>,
+    /// The weight of each point, finite and positive.
+    pub values: Vec<f64>,
+}
 
-        if let Some(dim) = expected_dim {
-            if coords.len() != dim {
-                anyhow::bail!(
-                    "dimension mismatch at line {}: expected {}, got {}",
-                    lineno + 1,
-                    dim,
-                    coords.len()
-                );
+impl Weights {
+    /// Pairs `points`, from the same `read_csv` call, with their weights,
+    /// e.g. for `algo::dbscan_weighted`.
+    pub fn attach(&self, points: Vec<Point>) -> Vec<WeightedPoint> {
+        points
+            .into_iter()
+            .zip(self.values.iter())
+            .map(|(coords, &weight)| WeightedPoint { coords, weight })
+            .collect()
+    }
+}
+
+/// Same format as `read_points_csv`, with coordinates rounded to `f32`. Values
+/// that are finite in `f64` but overflow `f32` are rejected too.
+pub fn read_points_csv_f32(path: &str) -> Result<Vec<Point<f32>>> {
+    let mut points: Vec<Point<f32>> = Vec::new();
+    let mut overflow: Option<usize> = None;
+    for_each_point_row(
+        open_input(path)?,
+        CsvFormat::default(),
+        InvalidRows::Reject,
+        |row| {
+            let point: Point<f32> = row.iter().map(|&x| OrderedFloat(x as f32)).collect();
+            if overflow.is_none() && point.iter().any(|x| !x.is_finite()) {
+                overflow = Some(points.len());
             }
-        } else {
-            expected_dim = Some(coords.len());
+            points.push(point);
+        },
+    )?;
+    if let Some(i) = overflow {
+        anyhow::bail!(
+            "point {} has a coordinate out of f32 range: {}",
+            i,
+            format_point(&points[i])
+        );
+    }
+    Ok(points)
+}
+
+/// Reads a coordinate CSV whose columns listed in `categorical` (0-based) hold
+/// integer category codes, and fits a `GowerMetric` to it: those columns are
+/// compared for equality, the others scaled by their range.
+pub fn read_points_csv_mixed(
+    path: &str,
+    categorical: &[usize],
+) -> Result<(Vec<Point>, GowerMetric)> {
+    let points = read_points_csv(path)?;
+    for (i, p) in points.iter().enumerate() {
+        if let Some(&c) = categorical
+            .iter()
+            .find(|&&c| p.get(c).is_some_and(|x| x.fract() != 0.0))
+        {
+            anyhow::bail!(
+                "point {}: categorical column {} holds {}, which is not an integer code",
+                i,
+                c,
+                p[c]
+            );
         }
+    }
+    let metric = GowerMetric::fit(&points, categorical)?;
+    Ok((points, metric))
+}
 
-        out.push((cid, coords));
+/// Reads a coordinate CSV in which column `weight_col` (0-based) holds the
+/// weight of the point made of the other columns. Weights must be finite and
+/// positive.
+pub fn read_weighted_points_csv(path: &str, weight_col: usize) -> Result<Vec<WeightedPoint>> {
+    let mut rows: Vec<Vec<f64>> = Vec::new();
+    for_each_point_row(
+        open_input(path)?,
+        CsvFormat::default(),
+        InvalidRows::Reject,
+        |row| rows.push(row.to_vec()),
+    )?;
+    if weight_col >= rows[0].len() {
+        anyhow::bail!(
+            "weight column {} is out of range for {} columns",
+            weight_col,
+            rows[0].len()
+        );
     }
 
-    if out.is_empty() {
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, mut row)| {
+            let weight = row.remove(weight_col);
+            let coords = row.into_iter().map(OrderedFloat).collect();
+            WeightedPoint::new(coords, weight).with_context(|| format!("point {}", i))
+        })
+        .collect()
+}
+
+/// Reads a CSV of 0/1 columns (e.g. fingerprints) into packed `BitPoint`s.
+/// Any other value is rejected.
+pub fn read_bit_points_csv(path: &str) -> Result<Vec<BitPoint>> {
+    let mut points: Vec<BitPoint> = Vec::new();
+    let mut invalid: Option<(usize, anyhow::Error)> = None;
+    for_each_point_row(
+        open_input(path)?,
+        CsvFormat::default(),
+        InvalidRows::Reject,
+        |row| {
+            if invalid.is_some() {
+                return;
+            }
+            let point = row.iter().copied().map(OrderedFloat).collect();
+            match BitPoint::from_dense(&point) {
+                Ok(p) => points.push(p),
+                Err(e) => invalid = Some((points.len(), e)),
+            }
+        },
+    )?;
+    if let Some((i, e)) = invalid {
+        return Err(e).with_context(|| format!("point {}", i));
+    }
+    Ok(points)
+}
+
+/// Same format as `read_points_csv`, read into a single contiguous
+/// `PointCloud` instead of one `Vec` per point.
+pub fn read_points_csv_cloud(path: &str) -> Result<PointCloud> {
+    let mut cloud: Option<PointCloud> = None;
+    for_each_point_row(
+        open_input(path)?,
+        CsvFormat::default(),
+        InvalidRows::Reject,
+        |row| {
+            cloud
+                .get_or_insert_with(|| PointCloud::new(row.len()))
+                .push(row.iter().copied())
+        },
+    )?;
+    Ok(cloud.unwrap_or_default())
+}
+
+/// Reads a coordinate CSV whose first line is a header naming the columns,
+/// e.g. `temperature,pressure,flow`. Rows with NaN or infinite values are
+/// rejected, naming the offending column.
+pub fn read_dataset_csv(path: &str) -> Result<Dataset> {
+    let mut points: Vec<Point> = Vec::new();
+    let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+    let (names, _) = for_each_point_row(open_input(path)?, format, InvalidRows::Allow, |row| {
+        points.push(row.iter().copied().map(OrderedFloat).collect())
+    })?;
+    let dataset = Dataset::new(Schema::new(names.unwrap_or_default())?, points)?;
+    dataset.validate()?;
+    Ok(dataset)
+}
+
+/// Reads a coordinate CSV in which cells may be missing: empty cells and cells
+/// equal to one of `sentinels` (e.g. `"NA"`) become `None`. Rows are returned
+/// in file order, for `impute::Imputer` to fill or drop. Non-finite values
+/// that are not sentinels are rejected.
+pub fn read_points_csv_missing(path: &str, sentinels: &[&str]) -> Result<Vec<Vec<Option<f64>>>> {
+    let (_, _, records) = data_records(open_input(path)?, CsvFormat::default())?;
+    let mut rows: Vec<Vec<Option<f64>>> = Vec::new();
+    for record in records {
+        let (lineno, record) = record?;
+        let mut row = Vec::new();
+        for s in record.iter().map(str::trim) {
+            if s.is_empty() || sentinels.contains(&s) {
+                row.push(None);
+                continue;
+            }
+            let x = s
+                .parse::<f64>()
+                .with_context(|| format!("parse error at line {}", lineno + 1))?;
+            if !x.is_finite() {
+                anyhow::bail!("invalid value at line {}: '{}'", lineno + 1, s);
+            }
+            row.push(Some(x));
+        }
+
+        if rows.first().is_some_and(|first| first.len() != row.len()) {
+            anyhow::bail!(
+                "dimension mismatch at line {}: expected {}, got {}",
+                lineno + 1,
+                rows[0].len(),
+                row.len()
+            );
+        }
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok(rows)
+}
+
+/// Reads sparse points, one per line as whitespace-separated `index:value`
+/// pairs with 0-based indices, e.g. `3:0.5 17:-1.25`. Pairs may come in any
+/// order. The dimension is `dim`, or one more than the largest index seen if
+/// `None`. Empty lines are skipped, so an all-zero point needs an explicit
+/// `index:0` entry.
+pub fn read_sparse_points(path: &str, dim: Option<u32>) -> Result<Vec<SparsePoint>> {
+    let lines = data_lines(open_input(path)?);
+    let mut rows: Vec<(usize, Vec<(u32, f64)>)> = Vec::new();
+    for line in lines {
+        let (lineno, line) = line?;
+        let mut entries = line
+            .split_whitespace()
+            .map(|pair| {
+                let (i, x) = pair.split_once(':')?;
+                let x = x.parse::<f64>().ok().filter(|x| x.is_finite())?;
+                Some((i.parse::<u32>().ok()?, x))
+            })
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| {
+                format!(
+                    "line {}: expected index:value pairs with finite values",
+                    lineno + 1
+                )
+            })?;
+        entries.sort_by_key(|&(i, _)| i);
+        rows.push((lineno, entries));
+    }
+
+    if rows.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    sparse_rows(rows, dim)
+}
+
+/// Reads a libsvm/svmlight file: one point per line as `label index:value
+/// ...` with 1-based indices, e.g. `+1 1:0.5 4:-2`. The label is optional,
+/// `#` starts a comment, and svmlight `qid:` entries are ignored. Returns the
+/// points as 0-based `SparsePoint`s (`to_dense` densifies them) of dimension
+/// equal to the largest index, and the label of each point.
+pub fn read_points_libsvm(path: &str) -> Result<(Vec<SparsePoint>, Vec<Option<f64>>)> {
+    let lines = data_lines(open_input(path)?);
+    let mut rows: Vec<(usize, Vec<(u32, f64)>)> = Vec::new();
+    let mut labels = Vec::new();
+    for line in lines {
+        let (lineno, line) = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut tokens = line.split_whitespace().peekable();
+        let label = match tokens.next_if(|t| !t.contains(':')) {
+            Some(t) => Some(
+                t.parse::<f64>()
+                    .with_context(|| format!("line {}: invalid label '{}'", lineno + 1, t))?,
+            ),
+            None => None,
+        };
+        let mut entries = tokens
+            .filter(|t| !t.starts_with("qid:"))
+            .map(|pair| {
+                let (i, x) = pair.split_once(':')?;
+                let x = x.parse::<f64>().ok().filter(|x| x.is_finite())?;
+                let i = i.parse::<u32>().ok().filter(|&i| i >= 1)?;
+                Some((i - 1, x))
+            })
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| {
+                format!(
+                    "line {}: expected index:value pairs with 1-based indices and finite values",
+                    lineno + 1
+                )
+            })?;
+        entries.sort_by_key(|&(i, _)| i);
+        if let Some(w) = entries.windows(2).find(|w| w[0].0 == w[1].0) {
+            anyhow::bail!("line {}: duplicate index {}", lineno + 1, w[0].0 + 1);
+        }
+        rows.push((lineno, entries));
+        labels.push(label);
+    }
+
+    if rows.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok((sparse_rows(rows, None)?, labels))
+}
+
+/// Reads one WKT point per line, e.g. `POINT (1 2)`, `POINT Z (1 2 3)` or
+/// `SRID=4326;point(1 2)`. Keywords are case-insensitive and whitespace is
+/// free. M values are dropped, so `POINT ZM (1 2 3 4)` is `[1, 2, 3]`.
+/// `POINT EMPTY` lines are skipped if `skip_empty`, rejected otherwise; other
+/// geometry types are rejected. All points must have the same dimension.
+pub fn read_points_wkt(path: &str, skip_empty: bool) -> Result<Vec<Point>> {
+    let lines = data_lines(open_input(path)?);
+    let mut points: Vec<Point> = Vec::new();
+    for line in lines {
+        let (lineno, line) = line?;
+        let point = parse_wkt_point(&line).with_context(|| format!("line {}", lineno + 1))?;
+        let Some(point) = point else {
+            if skip_empty {
+                continue;
+            }
+            anyhow::bail!("line {}: POINT EMPTY", lineno + 1);
+        };
+        if points
+            .first()
+            .is_some_and(|first| first.len() != point.len())
+        {
+            anyhow::bail!(
+                "dimension mismatch at line {}: expected {}, got {}",
+                lineno + 1,
+                points[0].len(),
+                point.len()
+            );
+        }
+        points.push(point);
+    }
+
+    if points.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok(points)
+}
+
+// Parses a WKT point; `None` for `POINT EMPTY`.
+fn parse_wkt_point(text: &str) -> Result<Option<Point>> {
+    // An EWKT `SRID=...;` prefix does not change the coordinates.
+    let text = match text.split_once(';') {
+        Some((srid, rest)) if srid.trim().to_ascii_uppercase().starts_with("SRID=") => rest,
+        _ => text,
+    };
+    let upper = text.trim().to_ascii_uppercase();
+    let Some(rest) = upper.strip_prefix("POINT") else {
+        let kind = upper
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .next()
+            .unwrap_or("");
+        anyhow::bail!("unsupported geometry '{}'; only POINT is supported", kind);
+    };
+    let rest = rest.trim_start();
+    let (has_z, has_m, rest) = if let Some(r) = rest.strip_prefix("ZM") {
+        (true, true, r)
+    } else if let Some(r) = rest.strip_prefix('Z') {
+        (true, false, r)
+    } else if let Some(r) = rest.strip_prefix('M') {
+        (false, true, r)
+    } else {
+        (false, false, rest)
+    };
+    let rest = rest.trim();
+    if rest == "EMPTY" {
+        return Ok(None);
+    }
+    let Some(inner) = rest.strip_prefix('(').and_then(|r| r.strip_suffix(')')) else {
+        anyhow::bail!("expected '(x y)' or EMPTY after POINT");
+    };
+    let mut coords = inner
+        .split_whitespace()
+        .map(|s| s.parse::<f64>().ok().filter(|x| x.is_finite()))
+        .collect::<Option<Vec<f64>>>()
+        .context("coordinates must be finite numbers")?;
+    let expected = match (has_z, has_m) {
+        (false, false) => coords.len().clamp(2, 4),
+        (true, true) => 4,
+        (false, true) | (true, false) => 3,
+    };
+    if coords.len() != expected {
+        anyhow::bail!("expected {} coordinates, got {}", expected, coords.len());
+    }
+    // Measures are not coordinates; an untagged fourth value is M as well.
+    if has_m || coords.len() == 4 {
+        coords.pop();
+    }
+    Ok(Some(coords.into_iter().map(OrderedFloat).collect()))
+}
+
+/// Writes clustered output as `cid<TAB>POINT (x y)` lines, or `POINT Z (x y
+/// z)` for 3-D points, with `cid` as in `write_clustered_csv`. Fails for other
+/// dimensions.
+pub fn write_clustered_wkt(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    if let Some(i) = points.iter().position(|p| p.len() != 2 && p.len() != 3) {
+        anyhow::bail!(
+            "point {} has dimension {}; WKT points need 2 or 3",
+            i,
+            points[i].len()
+        );
+    }
+
+    let mut w = create_output(path)?;
+    for (p, cid) in points.iter().zip(labels_in_order(points, classes)) {
+        let coords = p.iter().map(|x| x.0.to_string()).collect::<Vec<_>>();
+        let tag = if p.len() == 3 { "POINT Z" } else { "POINT" };
+        writeln!(w, "{}\t{} ({})", cid, tag, coords.join(" "))?;
+    }
+    w.finish()
+}
+
+// Builds sparse points from sorted `(index, value)` entries per line, of
+// dimension `dim` or one more than the largest index.
+fn sparse_rows(rows: Vec<(usize, Vec<(u32, f64)>)>, dim: Option<u32>) -> Result<Vec<SparsePoint>> {
+    let dim = dim.unwrap_or_else(|| {
+        rows.iter()
+            .filter_map(|(_, e)| e.last())
+            .map(|&(i, _)| i + 1)
+            .max()
+            .unwrap_or(0)
+    });
+
+    rows.into_iter()
+        .map(|(lineno, entries)| {
+            let (indices, values) = entries.into_iter().unzip();
+            SparsePoint::new(dim, indices, values).with_context(|| format!("line {}", lineno + 1))
+        })
+        .collect()
+}
+
+/// A binary point file (see `write_points_bin`) mapped into memory by
+/// `mmap_points`. The coordinates stay in the page cache, read-only, instead
+/// of being copied to the heap.
+#[cfg(feature = "mmap")]
+pub struct MappedPoints {
+    map: memmap2::Mmap,
+    dim: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedPoints {
+    /// The points, e.g. for `algo::dbscan_cloud`.
+    pub fn view(&self) -> crate::types::CloudView<'_> {
+        // Safety: `mmap_points` checked that the coordinates are aligned f64s
+        // in the byte order of this machine.
+        let (_, coords, _) = unsafe { self.map[BIN_HEADER_LEN..].align_to::<f64>() };
+        crate::types::CloudView::new(coords, self.dim)
+    }
+}
+
+/// Maps the binary point file at `path` into memory. Besides the checks of
+/// `read_points_bin`, fails if the file is compressed or the machine is not
+/// little-endian, as the coordinates are used as stored. Needs the `mmap`
+/// feature.
+#[cfg(feature = "mmap")]
+pub fn mmap_points(path: &str) -> Result<MappedPoints> {
+    if path == "-" || is_gzip(path) {
+        anyhow::bail!(
+            "cannot map '{}'; only uncompressed files can be mapped",
+            path
+        );
+    }
+    if cfg!(target_endian = "big") {
+        anyhow::bail!("binary point files can only be mapped on little-endian machines");
+    }
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+    // Safety: the map is read-only; like any reader, it assumes the file is
+    // not modified while in use.
+    let map = unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("failed to map '{}'", path))?;
+    let Some(header) = map.first_chunk::<BIN_HEADER_LEN>() else {
+        anyhow::bail!("'{}' is truncated: no complete header", path);
+    };
+    let (n, dim) = parse_bin_header(path, header)?;
+    let len = n.checked_mul(dim).and_then(|k| k.checked_mul(8));
+    let found = (map.len() - BIN_HEADER_LEN) as u64;
+    if len != Some(found) {
+        anyhow::bail!(
+            "'{}' has the wrong size: expected {} points of dimension {}, found {} coordinates",
+            path,
+            n,
+            dim,
+            found / 8
+        );
+    }
+    // Maps are page-aligned, so this only fails for unusual platforms.
+    let (head, _, tail) = unsafe { map[BIN_HEADER_LEN..].align_to::<f64>() };
+    if !head.is_empty() || !tail.is_empty() {
+        anyhow::bail!("'{}' is not mapped at an 8-byte aligned address", path);
+    }
+    Ok(MappedPoints {
+        map,
+        dim: dim as usize,
+    })
+}
+
+// Binary point files start with this magic and a format version, followed by
+// the number of points and their dimension as little-endian u64s and then the
+// coordinates as little-endian f64s, point after point.
+const BIN_MAGIC: &[u8; 4] = b"KPTS";
+const BIN_VERSION: u32 = 1;
+// The coordinates start right after the header, 8-byte aligned in the file.
+const BIN_HEADER_LEN: usize = 24;
+
+/// Writes `points` in the binary point format read by `read_points_bin`,
+/// which loads much faster than CSV. Fails on mixed dimensions.
+pub fn write_points_bin(path: &str, points: &[Point]) -> Result<()> {
+    let dim = points.first().map_or(0, |p| p.len());
+    if let Some(i) = points.iter().position(|p| p.len() != dim) {
+        anyhow::bail!(
+            "dimension mismatch at point {}: expected {}, got {}",
+            i,
+            dim,
+            points[i].len()
+        );
+    }
+
+    let mut w = create_output(path)?;
+    w.write_all(BIN_MAGIC)?;
+    w.write_all(&BIN_VERSION.to_le_bytes())?;
+    w.write_all(&(points.len() as u64).to_le_bytes())?;
+    w.write_all(&(dim as u64).to_le_bytes())?;
+    for x in points.iter().flatten() {
+        w.write_all(&x.0.to_le_bytes())?;
+    }
+    w.finish()
+}
+
+// Checks the header of a binary point file and returns its point count and
+// dimension, which are positive.
+fn parse_bin_header(path: &str, header: &[u8; BIN_HEADER_LEN]) -> Result<(u64, u64)> {
+    if &header[..4] != BIN_MAGIC {
+        anyhow::bail!("'{}' is not a binary point file", path);
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != BIN_VERSION {
+        anyhow::bail!(
+            "'{}' has binary point format version {}; only version {} is supported",
+            path,
+            version,
+            BIN_VERSION
+        );
+    }
+    let n = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let dim = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    if n == 0 || dim == 0 {
+        anyhow::bail!("no points found in input");
+    }
+    Ok((n, dim))
+}
+
+/// Reads a binary point file written by `write_points_bin`. Fails on files of
+/// another format or version, truncated files and files without points.
+pub fn read_points_bin(path: &str) -> Result<Vec<Point>> {
+    let mut r = open_input(path)?;
+    let mut header = [0u8; BIN_HEADER_LEN];
+    r.read_exact(&mut header)
+        .with_context(|| format!("'{}' is truncated: no complete header", path))?;
+    let (n, dim) = parse_bin_header(path, &header)?;
+
+    // Reading through `take` keeps a corrupt count from allocating up front.
+    let len = n.checked_mul(dim).and_then(|k| k.checked_mul(8));
+    let mut data = Vec::new();
+    r.take(len.unwrap_or(u64::MAX)).read_to_end(&mut data)?;
+    if len != Some(data.len() as u64) {
+        anyhow::bail!(
+            "'{}' is truncated: expected {} points of dimension {}, found {} coordinates",
+            path,
+            n,
+            dim,
+            data.len() / 8
+        );
+    }
+    Ok(data
+        .chunks_exact(dim as usize * 8)
+        .map(|p| {
+            p.chunks_exact(8)
+                .map(|x| OrderedFloat(f64::from_le_bytes(x.try_into().unwrap())))
+                .collect()
+        })
+        .collect())
+}
+
+/// Opens `path` for buffered reading: `-` is stdin, and files whose name ends
+/// in `.gz` are decompressed on the fly. All path-based readers use this.
+pub fn open_input(path: &str) -> Result<Box<dyn BufRead>> {
+    if path == "-" {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+    Ok(if is_gzip(path) {
+        Box::new(BufReader::new(GzInput {
+            inner: MultiGzDecoder::new(file),
+            path: path.to_string(),
+        }))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+// Gzip decoder whose errors name the file, since they surface while the
+// readers parse lines, where only the line number is known.
+struct GzInput {
+    inner: MultiGzDecoder<fs::File>,
+    path: String,
+}
+
+impl Read for GzInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("failed to decompress '{}': {}", self.path, e),
+            )
+        })
+    }
+}
+
+// Text input with a leading UTF-8 BOM removed and `\r\n` and lone `\r` line
+// endings turned into `\n`, so that files saved on Windows (or classic Mac OS)
+// read like any other. A missing `\n` at the end is added.
+struct TextInput<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    // The bytes of a leading BOM still to come; it may span reads.
+    bom: &'static [u8],
+    // The last byte read was `\r`, so a `\n` right after it is dropped.
+    after_cr: bool,
+    // The last byte passed on, if any, to tell whether a final `\n` is needed.
+    last: Option<u8>,
+}
+
+impl<R: BufRead> TextInput<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            bom: b"\xEF\xBB\xBF",
+            after_cr: false,
+            last: None,
+        }
+    }
+}
+
+impl<R: BufRead> Read for TextInput<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for TextInput<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() {
+            let chunk = self.inner.fill_buf()?;
+            if chunk.is_empty() {
+                if self.last.is_some_and(|b| b != b'\n') {
+                    self.buf.clear();
+                    self.buf.push(b'\n');
+                    self.pos = 0;
+                    self.last = Some(b'\n');
+                    continue;
+                }
+                break;
+            }
+            let len = chunk.len();
+            let mut bytes = chunk;
+            if !self.bom.is_empty() {
+                let n = bytes
+                    .iter()
+                    .zip(self.bom)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                self.bom = if n == bytes.len() {
+                    &self.bom[n..]
+                } else {
+                    &[]
+                };
+                bytes = &bytes[n..];
+            }
+            self.buf.clear();
+            self.pos = 0;
+            if !self.after_cr && !bytes.contains(&b'\r') {
+                self.buf.extend_from_slice(bytes);
+            } else {
+                for &b in bytes {
+                    match b {
+                        b'\n' if self.after_cr => {}
+                        b'\r' => self.buf.push(b'\n'),
+                        _ => self.buf.push(b),
+                    }
+                    self.after_cr = b == b'\r';
+                }
+            }
+            self.inner.consume(len);
+            if let Some(&b) = self.buf.last() {
+                self.last = Some(b);
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+fn is_gzip(path: &str) -> bool {
+    path.ends_with(".gz")
+}
+
+// Column names if a header was read, resolved delimiter, and rows.
+type Rows<I> = (Option<Vec<String>>, char, I);
+
+// A non-empty trimmed line and its 0-based line number.
+type Line = Result<(usize, String)>;
+
+// A non-empty CSV record and the 0-based number of the line it starts on.
+type Record = Result<(usize, csv::StringRecord)>;
+
+// The coordinates of a coordinate CSV row, and what else was taken from it.
+type TextRow<E> = Result<(Vec<f64>, E)>;
+
+// Streams the non-empty trimmed lines of `reader` with their 0-based line
+// numbers, for line-based formats. A BOM and any line endings are handled as
+// by `TextInput`. Read errors (including corrupt gzip data) come out of the
+// iterator.
+fn data_lines(reader: impl BufRead) -> impl Iterator<Item = Line> {
+    TextInput::new(reader)
+        .lines()
+        .enumerate()
+        .filter_map(|(lineno, line)| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(Ok((lineno, line.trim().to_string()))),
+            Err(e) => Some(Err(e).with_context(|| format!("failed to read line {}", lineno + 1))),
+        })
+}
+
+// Streams the non-empty records of a CSV `reader`, parsed by the `csv` crate
+// (so fields may be quoted), with their 0-based line numbers, and returns the
+// resolved delimiter. Fields are not trimmed, which `Trim::All` would do at a
+// large cost; users trim them. If the first record is a header according to
+// `format`, it is returned separately as trimmed column names. Read and parse
+// errors come out of the iterator. A BOM and any line endings are handled as
+// by `TextInput`.
+fn data_records(
+    reader: impl BufRead,
+    format: CsvFormat,
+) -> Result<Rows<Peekable<impl Iterator<Item = Record>>>> {
+    let mut reader = TextInput::new(reader);
+    // The first non-empty line resolves `Delimiter::Auto`; it is put back in
+    // front of the rest for the CSV reader.
+    let mut first = String::new();
+    let mut blank = 0;
+    loop {
+        first.clear();
+        let n = reader
+            .read_line(&mut first)
+            .with_context(|| format!("failed to read line {}", blank + 1))?;
+        if n == 0 || !first.trim().is_empty() {
+            break;
+        }
+        blank += 1;
+    }
+    let delimiter = format.delimiter.resolve(&first);
+    if !delimiter.is_ascii() {
+        anyhow::bail!("delimiter '{}' is not an ASCII character", delimiter);
+    }
+    if format.numbers.decimal_comma && delimiter == ',' {
+        anyhow::bail!("decimal commas need a delimiter other than ','");
+    }
+
+    let mut csv = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter as u8)
+        // Reading through `Chain` in the default 8 KiB pieces is slow.
+        .buffer_capacity(1 << 16)
+        .from_reader(std::io::Cursor::new(first).chain(reader))
+        .into_byte_records();
+    let mut records = std::iter::from_fn(move || {
+        loop {
+            let bytes = match csv.next()? {
+                Ok(bytes) => bytes,
+                Err(e) => return Some(Err(e).context("failed to read CSV input")),
+            };
+            // The position `csv` gives a record is taken before the empty
+            // lines it skips, so count back from the end of the record
+            // instead: `TextInput` ends every line with `\n`. A quote left
+            // open to the end of the input takes in the last `\n` as well,
+            // which would count back one line too far; the record cannot
+            // start before the position `csv` gives it.
+            let end = csv.reader().position().line() as usize - 1;
+            let inner = bytes
+                .iter()
+                .map(|field| field.iter().filter(|&&b| b == b'\n').count())
+                .sum::<usize>();
+            let start = bytes.position().map_or(0, |p| p.line() as usize - 1);
+            let lineno = (end - 1).saturating_sub(inner).max(start) + blank;
+            match csv::StringRecord::from_byte_record(bytes) {
+                // Whitespace-only lines are one blank field.
+                Ok(record) if record.len() == 1 && record[0].trim().is_empty() => {}
+                Ok(record) => return Some(Ok((lineno, record))),
+                Err(e) => {
+                    let error = format!("line {}: {}", lineno + 1, e.utf8_error());
+                    let text = e
+                        .into_byte_record()
+                        .iter()
+                        .map(String::from_utf8_lossy)
+                        .join(&delimiter.to_string());
+                    let bad = BadLine {
+                        line: lineno + 1,
+                        text,
+                        error,
+                    };
+                    return Some(Err(bad.into()));
+                }
+            }
+        }
+    })
+    .peekable();
+    let is_header = match (format.header, records.peek()) {
+        (Header::Absent, _) => false,
+        (Header::Present, _) => true,
+        (Header::Auto, Some(Ok((_, record)))) => {
+            // A `nan` field is a number here, rejected or not later.
+            let numbers = format.numbers.reject_non_finite(false);
+            record
+                .iter()
+                .any(|s| parse_field(s.trim(), numbers).is_err())
+        }
+        (Header::Auto, _) => false,
+    };
+    let names = if is_header {
+        match records.next() {
+            Some(record) => Some(record?.1.iter().map(|s| s.trim().to_string()).collect()),
+            None => None,
+        }
+    } else {
+        None
+    };
+    // `Take` stops pulling records at the limit, so the rest of the input is
+    // left unread beyond what the CSV reader has buffered.
+    let records = records
+        .take(format.max_rows.unwrap_or(usize::MAX))
+        .peekable();
+    Ok((names, delimiter, records))
+}
+
+// Parses one trimmed field as a number in the format `numbers`. Decimal
+// commas, common in semicolon- and tab-separated files (or quoted), get a
+// dedicated message when not expected.
+fn parse_field(s: &str, numbers: NumberFormat) -> Result<f64> {
+    let x = if numbers.decimal_comma {
+        s.replace(',', ".").parse::<f64>()?
+    } else {
+        s.parse::<f64>().or_else(|e| {
+            if s.contains(',') && s.replace(',', ".").parse::<f64>().is_ok() {
+                anyhow::bail!(
+                    "'{}' uses a decimal comma; use '.' as the decimal separator",
+                    s
+                );
+            }
+            Err(e.into())
+        })?
+    };
+    if numbers.reject_non_finite && !x.is_finite() {
+        anyhow::bail!("'{}' is not a finite number", s);
+    }
+    Ok(x)
+}
+
+// Streams the rows of a coordinate CSV, restricted to `columns` if given,
+// checking that they all have the same dimension and dropping or rejecting
+// rows as `invalid` says. Also returns the column names (of the selected
+// columns) if a header was read, and the delimiter. The iterator stops after
+// the first error, and yields an error instead if no row is kept.
+fn point_rows(
+    reader: impl BufRead,
+    format: CsvFormat,
+    invalid: InvalidRows,
+    columns: Option<&Columns>,
+) -> Result<Rows<impl Iterator<Item = Result<Vec<f64>>>>> {
+    let (names, delimiter, records) = data_records(reader, format)?;
+    let (names, rows, _) = point_records(
+        names,
+        records,
+        format,
+        delimiter,
+        invalid,
+        columns,
+        |_, _| Ok(()),
+    )?;
+    Ok((
+        names,
+        delimiter,
+        rows.map(|row| row.map(|(coords, ())| coords)),
+    ))
+}
+
+// Malformed rows dropped by the iterator of `point_records`, to be looked at
+// once it is done.
+type Report = Rc<RefCell<BadLines>>;
+
+// The part of `point_rows` after the header: parses `records`, yielding the
+// coordinates of each along with what `extra` takes from it, given its
+// 0-based line number. Rows that `extra` fails on are malformed too.
+// Malformed rows are dropped into the returned report or fail as
+// `format.on_error` says; `delimiter` joins their fields.
+fn point_records<E, I, F>(
+    mut names: Option<Vec<String>>,
+    records: I,
+    format: CsvFormat,
+    delimiter: char,
+    invalid: InvalidRows,
+    columns: Option<&Columns>,
+    mut extra: F,
+) -> Result<(
+    Option<Vec<String>>,
+    impl Iterator<Item = TextRow<E>> + use<E, I, F>,
+    Report,
+)>
+where
+    I: Iterator<Item = Record>,
+    F: FnMut(usize, &csv::StringRecord) -> Result<E>,
+{
+    let mut records = records.peekable();
+    let selected = match columns {
+        Some(columns) => {
+            let width = row_width(names.as_deref(), &mut records);
+            Some(columns.resolve(names.as_deref(), width)?)
+        }
+        None => None,
+    };
+    if let (Some(selected), Some(all)) = (&selected, &names) {
+        let picked = selected
+            .iter()
+            .map(|&i| {
+                all.get(i).cloned().with_context(|| {
+                    format!(
+                        "header: column {} is out of range; it has {} columns",
+                        i,
+                        all.len()
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        names = Some(picked);
+    }
+    let mut expected_dim: Option<usize> = names.as_ref().map(|n| n.len());
+    let mut rows = 0;
+    let mut done = false;
+    let report = Report::default();
+    let log = Rc::clone(&report);
+
+    let iter = std::iter::from_fn(move || {
+        while !done {
+            let Some(record) = records.next() else {
+                done = true;
+                return (rows == 0).then(|| Err(anyhow::anyhow!("no points found in input")));
+            };
+            let strict = format.on_error == OnError::Strict;
+            let bad = match record {
+                Ok((lineno, record)) => match point_row(
+                    lineno,
+                    &record,
+                    selected.as_deref(),
+                    &mut expected_dim,
+                    format.numbers,
+                    invalid,
+                )
+                .and_then(|coords| match coords {
+                    Some(coords) => Ok(Some((coords, extra(lineno, &record)?))),
+                    None => Ok(None),
+                }) {
+                    Ok(Some(row)) => {
+                        rows += 1;
+                        return Some(Ok(row));
+                    }
+                    Ok(None) => continue,
+                    Err(e) if !strict => BadLine {
+                        line: lineno + 1,
+                        text: record.iter().join(&delimiter.to_string()),
+                        error: format!("{:#}", e),
+                    },
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                },
+                // Rows that are not valid UTF-8 come as `BadLine`s; other
+                // errors are read errors.
+                Err(e) => match e.downcast::<BadLine>() {
+                    Ok(bad) if !strict => bad,
+                    Ok(bad) => {
+                        done = true;
+                        return Some(Err(bad.into()));
+                    }
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                },
+            };
+            log.borrow_mut().add(bad, format.on_error);
+        }
+        None
+    });
+    Ok((names, iter, report))
+}
+
+// Parses the record of a coordinate CSV starting on line `lineno` (from 0),
+// keeping the `selected` columns if given; `None` if `invalid` skips it.
+fn point_row(
+    lineno: usize,
+    record: &csv::StringRecord,
+    selected: Option<&[usize]>,
+    expected_dim: &mut Option<usize>,
+    numbers: NumberFormat,
+    invalid: InvalidRows,
+) -> Result<Option<Vec<f64>>> {
+    let parse = |i: usize, s: &str| {
+        parse_field(s.trim(), numbers)
+            .with_context(|| format!("parse error at line {}, column {}", lineno + 1, i))
+    };
+    let coords = match selected {
+        None => record
+            .iter()
+            .enumerate()
+            .map(|(i, s)| parse(i, s))
+            .collect::<Result<Vec<f64>>>()?,
+        Some(selected) => selected
+            .iter()
+            .map(|&i| match record.get(i) {
+                Some(s) => parse(i, s),
+                None => anyhow::bail!(
+                    "line {}: column {} is out of range; the row has {} columns",
+                    lineno + 1,
+                    i,
+                    record.len()
+                ),
+            })
+            .collect::<Result<Vec<f64>>>()?,
+    };
+
+    if let Some(dim) = *expected_dim {
+        if coords.len() != dim {
+            anyhow::bail!(
+                "dimension mismatch at line {}: expected {}, got {}",
+                lineno + 1,
+                dim,
+                coords.len()
+            );
+        }
+    } else {
+        *expected_dim = Some(coords.len());
+    }
+
+    if invalid != InvalidRows::Allow && coords.iter().any(|x| !x.is_finite()) {
+        if invalid == InvalidRows::Skip {
+            return Ok(None);
+        }
+        let point = coords.iter().copied().map(OrderedFloat).collect();
+        if let Err(e) = validate_point(&point) {
+            return Err(e).with_context(|| format!("invalid value at line {}", lineno + 1));
+        }
+    }
+    Ok(Some(coords))
+}
+
+// Calls `f` for each row of a coordinate CSV, as streamed by `point_rows`.
+// Returns the column names if a header was read, and the delimiter.
+fn for_each_point_row(
+    reader: impl BufRead,
+    format: CsvFormat,
+    invalid: InvalidRows,
+    mut f: impl FnMut(&[f64]),
+) -> Result<(Option<Vec<String>>, char)> {
+    let (names, delimiter, rows) = point_rows(reader, format, invalid, None)?;
+    for row in rows {
+        f(&row?);
+    }
+    Ok((names, delimiter))
+}
+
+/// How the CSV writers print coordinates. Both `Shortest` and `RoundTrip`
+/// read back as exactly the same `f64`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FloatFormat {
+    /// The shortest decimal that reads back as the same value, never in
+    /// exponent notation: `0.30000000000000004`, `0.0000001`, `2`.
+    #[default]
+    Shortest,
+    /// A fixed number of decimal places, e.g. `Fixed(3)` prints `0.300`. Does
+    /// not round-trip in general.
+    Fixed(usize),
+    /// The same digits as `Shortest`, switching to exponent notation for
+    /// magnitudes below 1e-4 or from 1e16 on: `1e-7`, `1.5e300`, `2.0`.
+    RoundTrip,
+}
+
+impl FloatFormat {
+    fn write(self, w: &mut impl Write, x: f64) -> std::io::Result<()> {
+        match self {
+            FloatFormat::Shortest => write!(w, "{}", x),
+            FloatFormat::Fixed(n) => write!(w, "{:.*}", n, x),
+            FloatFormat::RoundTrip => write!(w, "{:?}", x),
+        }
+    }
+}
+
+/// Error returned when parsing a `FloatFormat` fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFloatFormatError(String);
+
+impl fmt::Display for ParseFloatFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid float format '{}': expected 'shortest', 'roundtrip' or a number of decimal places",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseFloatFormatError {}
+
+/// Parses `shortest`, `roundtrip`, or a number of decimal places such as `6`.
+impl FromStr for FloatFormat {
+    type Err = ParseFloatFormatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "shortest" => Ok(FloatFormat::Shortest),
+            "roundtrip" => Ok(FloatFormat::RoundTrip),
+            _ => s
+                .parse::<usize>()
+                .map(FloatFormat::Fixed)
+                .map_err(|_| ParseFloatFormatError(s.to_string())),
+        }
+    }
+}
+
+/// Write clustered output: each line is `cid,x1,x2,...`, with `cid` as
+/// computed by `types::labels_in_order` (-1 for noise and for points missing
+/// from `classes`, -2 for unclassified).
+pub fn write_clustered_csv(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    write_clustered_csv_with_header(path, None, points, classes)
+}
+
+/// Like `write_clustered_csv` for labeled points: classes are looked up by id
+/// (-1 for ids missing from `classes`), and rows are written in increasing id
+/// order, i.e. in input order for points from `types::label_points`, so rows
+/// with equal coordinates each keep their own line and label.
+pub fn write_labeled_clustered_csv(
+    path: &str,
+    points: &[LabeledPoint],
+    classes: &HashMap<usize, Class>,
+) -> Result<()> {
+    let mut output = create_output(path)?;
+    let mut w = ClusteredCsvWriter::new(&mut output, None, ',', FloatFormat::Shortest)?;
+
+    let mut sorted = points.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|p| p.id);
+    for p in sorted {
+        let class = classes.get(&p.id).copied().unwrap_or(Class::Noise);
+        w.write_row(class.cid(), &p.coords)?;
+    }
+
+    w.flush()?;
+    drop(w);
+    output.finish()
+}
+
+/// Like `write_clustered_csv`, starting with a `cid,name1,name2,...` header
+/// line if `schema` is given, as read back by `read_clustered_csv_with`.
+pub fn write_clustered_csv_with_header(
+    path: &str,
+    schema: Option<&Schema>,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    write_clustered_csv_with(path, schema, ',', points, classes)
+}
+
+/// Like `write_clustered_csv_with`, printing coordinates as `floats` says.
+pub fn write_clustered_csv_with_format(
+    path: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    let mut w = create_output(path)?;
+    write_clustered_csv_to(&mut w, schema, delimiter, floats, points, classes)?;
+    w.finish()
+}
+
+/// Like `write_clustered_csv_with_header`, separating fields with `delimiter`,
+/// e.g. `'\t'` to write a TSV file.
+pub fn write_clustered_csv_with(
+    path: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    write_clustered_csv_with_format(
+        path,
+        schema,
+        delimiter,
+        FloatFormat::Shortest,
+        points,
+        classes,
+    )
+}
+
+/// Like `write_clustered_csv_with_format`, writing to any writer, e.g. a
+/// `Vec<u8>`. The writer is flushed but not closed.
+pub fn write_clustered_csv_to(
+    w: &mut impl Write,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    let mut w = ClusteredCsvWriter::new(w, schema, delimiter, floats)?;
+    for (p, cid) in points.iter().zip(labels_in_order(points, classes)) {
+        w.write_row(cid, p)?;
+    }
+    w.flush()
+}
+
+/// Writes a clustered CSV (`cid,x1,x2,...`) one row at a time, e.g. as an
+/// incremental clustering finalizes points, instead of all at once like
+/// `write_clustered_csv_to`. Every row must have the dimension of the first
+/// one (or of the header, or of the file appended to).
+pub struct ClusteredCsvWriter<W: Write> {
+    w: csv::Writer<W>,
+    floats: FloatFormat,
+    dim: Option<usize>,
+}
+
+impl<W: Write> ClusteredCsvWriter<W> {
+    /// Starts a clustered CSV on `w`, with a `cid,name1,name2,...` header line
+    /// if `schema` is given.
+    pub fn new(
+        w: W,
+        schema: Option<&Schema>,
+        delimiter: char,
+        floats: FloatFormat,
+    ) -> Result<Self> {
+        let mut w = csv_writer(w, delimiter)?;
+        if let Some(schema) = schema {
+            w.write_record(
+                std::iter::once("cid").chain(schema.names().iter().map(String::as_str)),
+            )?;
+        }
+        Ok(Self {
+            w,
+            floats,
+            dim: schema.map(Schema::len),
+        })
+    }
+
+    /// Writes the row `cid,x1,x2,...` of `point`. Fails if its dimension
+    /// differs from that of the earlier rows.
+    pub fn write_row(&mut self, cid: isize, point: &[OrderedFloat<f64>]) -> Result<()> {
+        match self.dim {
+            Some(dim) if dim != point.len() => anyhow::bail!(
+                "dimension mismatch: expected {} coordinates, got {}",
+                dim,
+                point.len()
+            ),
+            Some(_) => {}
+            None => self.dim = Some(point.len()),
+        }
+        write_clustered_row(&mut self.w, cid, &[] as &[&str], point, self.floats)
+    }
+
+    /// Pushes the rows written so far to the underlying writer and flushes it.
+    pub fn flush(&mut self) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        self.w
+            .into_inner()
+            .map_err(|e| anyhow::Error::new(e.into_error()))
+    }
+}
+
+impl ClusteredCsvWriter<OutputFile> {
+    /// Creates (or truncates) the clustered CSV file at `path`, gzip-compressed
+    /// if its name ends in `.gz`, or stdout for `-`. Call `finish` when done.
+    pub fn create(
+        path: &str,
+        schema: Option<&Schema>,
+        delimiter: char,
+        floats: FloatFormat,
+    ) -> Result<Self> {
+        Self::new(OutputFile(create_output(path)?), schema, delimiter, floats)
+    }
+
+    /// Opens the clustered CSV file at `path` to add rows at its end, creating
+    /// it if missing. The dimension of the rows to come is taken from the last
+    /// non-blank line of the file (a row or a header), split at `delimiter`.
+    /// A `.gz` file gets a new gzip member, which the readers read on from
+    /// the previous one. Call `finish` when done.
+    pub fn append(path: &str, delimiter: char, floats: FloatFormat) -> Result<Self> {
+        if path == "-" {
+            anyhow::bail!("cannot append to stdout");
+        }
+        if !delimiter.is_ascii() {
+            anyhow::bail!("delimiter '{}' is not an ASCII character", delimiter);
+        }
+        let existing = fs::metadata(path).is_ok_and(|m| m.len() > 0);
+        let (last, line_break) = if existing {
+            last_line(path)?
+        } else {
+            (None, true)
+        };
+        let dim = match last {
+            Some(line) => {
+                let fields = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .delimiter(delimiter as u8)
+                    .from_reader(line.as_bytes())
+                    .records()
+                    .next()
+                    .transpose()
+                    .with_context(|| format!("failed to parse the last line of '{}'", path))?
+                    .map_or(0, |record| record.len());
+                if fields < 2 {
+                    anyhow::bail!(
+                        "'{}': the last line has {} field(s); expected cid{}x1,...",
+                        path,
+                        fields,
+                        delimiter
+                    );
+                }
+                Some(fields - 1)
+            }
+            None => None,
+        };
+
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("failed to open '{}' for appending", path))?;
+        let w = BufWriter::new(file);
+        let mut output = OutputFile(if is_gzip(path) {
+            Output::Gzip(GzEncoder::new(w, flate2::Compression::default()))
+        } else {
+            Output::Plain(w)
+        });
+        if !line_break {
+            output.write_all(b"\n")?;
+        }
+        let mut writer = Self::new(output, None, delimiter, floats)?;
+        writer.dim = dim;
+        Ok(writer)
+    }
+
+    /// Flushes the file (and writes the gzip trailer), reporting errors that
+    /// dropping the writer would ignore.
+    pub fn finish(self) -> Result<()> {
+        self.into_inner()?.0.finish()
+    }
+}
+
+// The last non-blank line of the file at `path`, and whether the file ends
+// with a line break. Plain files are read from a block at their end, grown
+// until it holds a whole line, so that appending to a large file is cheap;
+// gzip files are read whole.
+fn last_line(path: &str) -> Result<(Option<String>, bool)> {
+    let context = || format!("failed to read '{}'", path);
+    let scan = |reader: &mut dyn BufRead, skip_partial: bool| -> Result<(Option<Vec<u8>>, bool)> {
+        let mut line = Vec::new();
+        let mut last = None;
+        let mut line_break = true;
+        let mut first = skip_partial;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).with_context(context)? == 0 {
+                break;
+            }
+            line_break = line.ends_with(b"\n");
+            if std::mem::take(&mut first) {
+                continue;
+            }
+            if !line.trim_ascii().is_empty() {
+                last = Some(line.clone());
+            }
+        }
+        Ok((last, line_break))
+    };
+
+    let (last, line_break) = if is_gzip(path) {
+        scan(&mut open_input(path)?, false)?
+    } else {
+        let mut file = fs::File::open(path).with_context(context)?;
+        let len = file.metadata().with_context(context)?.len();
+        let mut block = 1 << 16;
+        loop {
+            let start = len.saturating_sub(block);
+            file.seek(SeekFrom::Start(start)).with_context(context)?;
+            // The block may start inside a line, which is skipped.
+            let found = scan(&mut BufReader::new(&mut file), start > 0)?;
+            if found.0.is_some() || start == 0 {
+                break found;
+            }
+            block *= 2;
+        }
+    };
+    let last = last
+        .map(|line| String::from_utf8(line).with_context(context))
+        .transpose()?
+        .map(|line| line.trim_start_matches('\u{feff}').trim().to_string());
+    Ok((last, line_break))
+}
+
+/// Like `write_clustered_csv_with`, writing the ground-truth label of each
+/// point after its cluster id: `cid,label,x1,x2,...`. `labels` must come from
+/// the same `read_csv` call as `points`.
+pub fn write_clustered_csv_labeled(
+    path: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    labels: &Labels,
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    let name = labels.name.as_deref().unwrap_or("label");
+    write_clustered_csv_extra(
+        path,
+        schema,
+        delimiter,
+        floats,
+        points,
+        ("label", name, &labels.values),
+        classes,
+    )
+}
+
+/// Like `write_clustered_csv_labeled`, writing the weight of each point after
+/// its cluster id, printed as `floats` says: `cid,weight,x1,x2,...`.
+/// `weights` must come from the same `read_csv` call as `points`.
+pub fn write_clustered_csv_weighted(
+    path: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    weights: &Weights,
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    let name = weights.name.as_deref().unwrap_or("weight");
+    let values = weights
+        .values
+        .iter()
+        .map(|&w| {
+            let mut buf = Vec::new();
+            floats.write(&mut buf, w)?;
+            Ok(buf)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    write_clustered_csv_extra(
+        path,
+        schema,
+        delimiter,
+        floats,
+        points,
+        ("weight", name, &values),
+        classes,
+    )
+}
+
+// Writes `cid,value,x1,x2,...` lines from `extra`, which is what the values
+// are, the column name, and one value per point, and a `cid,name,...` header
+// if `schema` is given.
+fn write_clustered_csv_extra(
+    path: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    extra: (&str, &str, &[impl AsRef<[u8]>]),
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    let (what, name, values) = extra;
+    if values.len() != points.len() {
+        anyhow::bail!(
+            "{} {}s for {} points; they must come from the same input",
+            values.len(),
+            what,
+            points.len()
+        );
+    }
+    let mut output = create_output(path)?;
+    let mut w = csv_writer(&mut output, delimiter)?;
+    if let Some(schema) = schema {
+        let names = schema.names().iter().map(String::as_str);
+        w.write_record(["cid", name].into_iter().chain(names))?;
+    }
+
+    let cids = labels_in_order(points, classes);
+    for ((p, value), cid) in points.iter().zip(values.iter()).zip(cids) {
+        write_clustered_row(&mut w, cid, &[value], p, floats)?;
+    }
+
+    w.flush()?;
+    drop(w);
+    output.finish()
+}
+
+/// Writes `cid,<row>` lines, copying the fields of each input row of `raw` as
+/// read (no number reformatting, quoted again where needed), with a
+/// `cid,<header>` line first if the input had a header. `points` and
+/// `raw.rows` must come from the same `read_csv` call. The coordinates are
+/// already in the rows; `append_coords` also writes them again after each
+/// row, as parsed and printed in that format, named by `schema` in the
+/// header.
+pub fn write_clustered_csv_passthrough(
+    path: &str,
+    raw: &RawRows,
+    schema: Option<&Schema>,
+    delimiter: char,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    append_coords: Option<FloatFormat>,
+) -> Result<()> {
+    if raw.rows.len() != points.len() {
+        anyhow::bail!(
+            "{} rows for {} points; they must come from the same input",
+            raw.rows.len(),
+            points.len()
+        );
+    }
+    let mut output = create_output(path)?;
+    let mut w = csv_writer(&mut output, delimiter)?;
+    if let Some(header) = &raw.header {
+        let coords = schema.filter(|_| append_coords.is_some());
+        let record = std::iter::once("cid")
+            .chain(header.iter().map(String::as_str))
+            .chain(
+                coords
+                    .iter()
+                    .flat_map(|s| s.names().iter().map(String::as_str)),
+            );
+        w.write_record(record)?;
+    }
+
+    let cids = labels_in_order(points, classes);
+    for ((row, p), cid) in raw.rows.iter().zip(points.iter()).zip(cids) {
+        let coords = if append_coords.is_some() { &p[..] } else { &[] };
+        write_clustered_row(&mut w, cid, row, coords, append_coords.unwrap_or_default())?;
+    }
+
+    w.flush()?;
+    drop(w);
+    output.finish()
+}
+
+/// Writes each line of `raw` after its cluster id and `delimiter`, so that
+/// the output is the input byte for byte apart from the added `cid` column
+/// (and `cid` before a header). `points` and `raw.lines` must come from the
+/// same `read_csv` call.
+pub fn write_clustered_csv_verbatim(
+    path: &str,
+    raw: &RawLines,
+    delimiter: char,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    if raw.lines.len() != points.len() {
+        anyhow::bail!(
+            "{} lines for {} points; they must come from the same input",
+            raw.lines.len(),
+            points.len()
+        );
+    }
+    let mut w = create_output(path)?;
+    if let Some(header) = &raw.header {
+        writeln!(w, "cid{}{}", delimiter, header)?;
+    }
+    for (line, cid) in raw.lines.iter().zip(labels_in_order(points, classes)) {
+        writeln!(w, "{}{}{}", cid, delimiter, line)?;
+    }
+    w.finish()
+}
+
+/// Writes every cluster to its own CSV file of coordinates (no cid column) in
+/// `dir`: `cluster_000.csv`, `cluster_001.csv`, ... and `noise.csv`, which is
+/// written even if there is no noise. Ids get more digits from cluster 1000
+/// on. `dir` is created if needed. A non-empty `dir` is refused unless `force`
+/// is set, in which case the cluster and noise files already in it are
+/// removed first.
+pub fn write_clusters_split(
+    dir: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    force: bool,
+) -> Result<()> {
+    write_clusters_split_with_format(
+        dir,
+        None,
+        ',',
+        FloatFormat::Shortest,
+        points,
+        classes,
+        force,
+    )
+}
+
+/// Like `write_clusters_split`, with the options of
+/// `write_clustered_csv_with_format`; the header (if `schema` is given) names
+/// only the coordinates.
+pub fn write_clusters_split_with_format(
+    dir: &str,
+    schema: Option<&Schema>,
+    delimiter: char,
+    floats: FloatFormat,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    force: bool,
+) -> Result<()> {
+    let dir = std::path::Path::new(dir);
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create directory '{}'", dir.display()))?;
+    for entry in fs::read_dir(dir)? {
+        if !force {
+            anyhow::bail!(
+                "output directory '{}' is not empty; refusing to overwrite it",
+                dir.display()
+            );
+        }
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == "noise.csv" || (name.starts_with("cluster_") && name.ends_with(".csv")) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    let mut groups: BTreeMap<isize, Vec<&Point>> = BTreeMap::new();
+    groups.entry(-1).or_default();
+    for (p, cid) in points.iter().zip(labels_in_order(points, classes)) {
+        groups.entry(cid.max(-1)).or_default().push(p);
+    }
+    let last = groups.keys().next_back().copied().unwrap_or(0);
+    let width = last.to_string().len().max(3);
+    for (cid, group) in groups {
+        let name = if cid < 0 {
+            "noise.csv".to_string()
+        } else {
+            format!("cluster_{:0width$}.csv", cid, width = width)
+        };
+        let path = dir.join(name);
+        let mut output = create_output(&path.to_string_lossy())?;
+        let mut w = csv_writer(&mut output, delimiter)?;
+        if let Some(schema) = schema {
+            w.write_record(schema.names())?;
+        }
+        for p in group {
+            write_point_row(&mut w, p, floats)?;
+        }
+        w.flush()?;
+        drop(w);
+        output.finish()?;
+    }
+    Ok(())
+}
+
+// Output file, gzip-compressed if its name ends in `.gz`, or stdout for `-`.
+// `finish` must be called to flush it (and write the gzip trailer) with error
+// reporting.
+enum Output {
+    Plain(BufWriter<fs::File>),
+    Gzip(GzEncoder<BufWriter<fs::File>>),
+    Stdout(BufWriter<std::io::StdoutLock<'static>>),
+}
+
+impl Output {
+    fn finish(self) -> Result<()> {
+        match self {
+            Output::Plain(mut w) => w.flush()?,
+            Output::Gzip(w) => w.finish()?.flush()?,
+            Output::Stdout(mut w) => w.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Gzip(w) => w.write(buf),
+            Output::Stdout(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+            Output::Stdout(w) => w.flush(),
+        }
+    }
+}
+
+/// An output file opened by `ClusteredCsvWriter::create` or `append`.
+pub struct OutputFile(Output);
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Encodes an 8-bit RGB image (`width * height * 3` bytes, row by row) as PNG
+/// and writes it to `path`, or to stdout for `-`.
+pub fn write_rgb_png(path: &str, rgb: &[u8], width: u32, height: u32) -> Result<()> {
+    let mut w = create_output(path)?;
+    PngEncoder::new(&mut w)
+        .write_image(rgb, width, height, ColorType::Rgb8)
+        .context("failed to encode image")?;
+    w.finish()
+}
+
+fn create_output(path: &str) -> Result<Output> {
+    if path == "-" {
+        return Ok(Output::Stdout(BufWriter::new(std::io::stdout().lock())));
+    }
+    let file = fs::File::create(path).with_context(|| {
+        format!(
+            "failed to create '{}': insufficient permissions or path invalid",
+            path
+        )
+    })?;
+    let w = BufWriter::new(file);
+    Ok(if is_gzip(path) {
+        Output::Gzip(GzEncoder::new(w, flate2::Compression::default()))
+    } else {
+        Output::Plain(w)
+    })
+}
+
+// CSV writer over `w` that quotes fields only where needed, i.e. text
+// containing the delimiter, quotes or line breaks.
+fn csv_writer<W: Write>(w: W, delimiter: char) -> Result<csv::Writer<W>> {
+    if !delimiter.is_ascii() {
+        anyhow::bail!("delimiter '{}' is not an ASCII character", delimiter);
+    }
+    Ok(csv::WriterBuilder::new()
+        .delimiter(delimiter as u8)
+        .flexible(true)
+        .from_writer(w))
+}
+
+// Writes one record: `cid`, then the text `fields`, then `coords` printed as
+// `floats`.
+fn write_clustered_row<W: Write>(
+    w: &mut csv::Writer<W>,
+    cid: isize,
+    fields: &[impl AsRef<[u8]>],
+    coords: &[OrderedFloat<f64>],
+    floats: FloatFormat,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    write!(buf, "{}", cid)?;
+    w.write_field(&buf)?;
+    for field in fields {
+        w.write_field(field)?;
+    }
+    write_point_row(w, coords, floats)
+}
+
+// Writes `coords` as the rest of the current record and ends it.
+fn write_point_row<W: Write>(
+    w: &mut csv::Writer<W>,
+    coords: &[OrderedFloat<f64>],
+    floats: FloatFormat,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    for x in coords {
+        buf.clear();
+        floats.write(&mut buf, x.0)?;
+        w.write_field(&buf)?;
+    }
+    w.write_record(None::<&[u8]>)?;
+    Ok(())
+}
+
+/// Rows of a clustered CSV: the cid and the coordinates of each point.
+pub type ClusteredRows = Vec<(isize, Vec<f64>)>;
+
+/// Read clustered CSV: each line `cid,x1,x2,...` into `(isize, Vec<f64>)`.
+pub fn read_clustered_csv(path: &str) -> Result<ClusteredRows> {
+    Ok(read_clustered(open_input(path)?, CsvFormat::default())?.1)
+}
+
+/// Like `read_clustered_csv` for a file whose first line is a header, e.g.
+/// `cid,temperature,pressure`. The schema names the coordinate columns.
+pub fn read_clustered_csv_with_header(path: &str) -> Result<(Schema, ClusteredRows)> {
+    let (schema, rows) = read_clustered_csv_with(path, Header::Present)?;
+    Ok((schema.unwrap_or_default(), rows))
+}
+
+/// Like `read_clustered_csv`, with `header` deciding whether the first line is
+/// a header. Returns the schema of the coordinate columns if a header was read.
+pub fn read_clustered_csv_with(
+    path: &str,
+    header: Header,
+) -> Result<(Option<Schema>, ClusteredRows)> {
+    read_clustered_csv_with_format(path, CsvFormat::new(header, Delimiter::COMMA))
+}
+
+/// Like `read_clustered_csv_with`, for files in any `CsvFormat`.
+pub fn read_clustered_csv_with_format(
+    path: &str,
+    format: CsvFormat,
+) -> Result<(Option<Schema>, ClusteredRows)> {
+    read_clustered_csv_from(open_input(path)?, format)
+}
+
+/// Like `read_clustered_csv_with_format`, reading from any buffered reader.
+pub fn read_clustered_csv_from(
+    reader: impl BufRead,
+    format: CsvFormat,
+) -> Result<(Option<Schema>, ClusteredRows)> {
+    read_clustered(reader, format)
+}
+
+/// Points of a clustered CSV and the class of each, in file order. Unlike
+/// `ClusteredRows`, it uses the types the algorithms and writers take.
+pub type ClusteredPoints = (Vec<Point>, Vec<Class>);
+
+/// Like `read_clustered_csv`, returning `Point`s and `Class`es (cids as in
+/// `Class::from_cid`, so -2 is `Unclassified` and lower cids fail), which is
+/// what `write_clustered_csv_with_header` takes: writing and reading back gives
+/// the same points and classes.
+pub fn read_clustered_points(path: &str) -> Result<ClusteredPoints> {
+    Ok(read_clustered_points_with_format(path, CsvFormat::default())?.1)
+}
+
+/// Like `read_clustered_points`, for files in any `CsvFormat`. Returns the
+/// schema of the coordinate columns if a header was read.
+pub fn read_clustered_points_with_format(
+    path: &str,
+    format: CsvFormat,
+) -> Result<(Option<Schema>, ClusteredPoints)> {
+    let (schema, rows) = read_clustered::<Class>(open_input(path)?, format)?;
+    let (classes, points) = rows
+        .into_iter()
+        .map(|(class, coords)| (class, coords.into_iter().map(OrderedFloat).collect()))
+        .unzip();
+    Ok((schema, (points, classes)))
+}
+
+// Like `ClusteredRows`, with the cid parsed as a `C`.
+type RowsOf<C> = Vec<(C, Vec<f64>)>;
+
+// Reads `cid,x1,x2,...` rows, parsing the cid as a `C` (an `isize` or a
+// `Class`), and the schema of the coordinate columns if a header was read.
+fn read_clustered<C: FromStr>(
+    reader: impl BufRead,
+    format: CsvFormat,
+) -> Result<(Option<Schema>, RowsOf<C>)> {
+    let (names, _, records) = data_records(reader, format)?;
+    let mut out = Vec::new();
+    // number of coordinates per row
+    let mut expected_dim: Option<usize> = names.as_ref().map(|n| n.len().saturating_sub(1));
+    for record in records {
+        let (lineno, record) = record?;
+        let cols: Vec<&str> = record.iter().map(str::trim).collect();
+        if cols.len() < 2 {
+            anyhow::bail!(
+                "line {}: expected at least 2 columns (cid,x1,...)",
+                lineno + 1
+            );
+        }
+
+        let cid: C = cols[0]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("line {}: invalid cid '{}'", lineno + 1, cols[0]))?;
+
+        let coords: Vec<f64> = cols[1..]
+            .iter()
+            .map(|s| parse_field(s, format.numbers))
+            .collect::<Result<_>>()
+            .with_context(|| format!("line {}: invalid coordinate value", lineno + 1))?;
+
+        if let Some(dim) = expected_dim {
+            if coords.len() != dim {
+                anyhow::bail!(
+                    "dimension mismatch at line {}: expected {}, got {}",
+                    lineno + 1,
+                    dim,
+                    coords.len()
+                );
+            }
+        } else {
+            expected_dim = Some(coords.len());
+        }
+
+        out.push((cid, coords));
+    }
+
+    if out.is_empty() {
         anyhow::bail!("no samples found in input");
     }
-    Ok(out)
+    let Some(mut names) = names else {
+        return Ok((None, out));
+    };
+    if names.len() < 2 {
+        anyhow::bail!("header: expected at least 2 columns (cid,x1,...)");
+    }
+    names.remove(0);
+    Ok((Some(Schema::new(names)?), out))
+}
+
+/// Format of a point or clustered file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum FileFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line (NDJSON), see `read_points_jsonl`. Needs the
+    /// `serde` feature.
+    JsonLines,
+    /// Input only, see `read_points_parquet`. Needs the `parquet` feature.
+    Parquet,
+    /// Arrow IPC file (Feather v2), see `read_points_arrow`. Needs the `arrow`
+    /// feature.
+    Arrow,
+    /// GeoJSON `FeatureCollection` of points, see `read_points_geojson`.
+    /// Needs the `serde` feature.
+    GeoJson,
+    /// Output only, a JSON document, see `write_clustered_json`. Needs the
+    /// `serde` feature.
+    Json,
+}
+
+impl FileFormat {
+    /// `JsonLines` for `.jsonl` and `.ndjson` files and `GeoJson` for
+    /// `.geojson` files (also gzipped), `Parquet` for `.parquet` files, `Arrow`
+    /// for `.arrow`, `.feather` and `.ipc` files, `Csv` otherwise, including
+    /// for `-`.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".parquet") {
+            return FileFormat::Parquet;
+        }
+        if [".arrow", ".feather", ".ipc"]
+            .iter()
+            .any(|ext| path.ends_with(ext))
+        {
+            return FileFormat::Arrow;
+        }
+        let path = path.strip_suffix(".gz").unwrap_or(path);
+        if path.ends_with(".geojson") {
+            FileFormat::GeoJson
+        } else if path.ends_with(".jsonl") || path.ends_with(".ndjson") {
+            FileFormat::JsonLines
+        } else if path.ends_with(".json") {
+            FileFormat::Json
+        } else {
+            FileFormat::Csv
+        }
+    }
+}
+
+/// Fields of a JSON Lines record other than `coords`.
+#[cfg(feature = "serde")]
+pub type JsonFields = serde_json::Map<String, serde_json::Value>;
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct JsonPointRecord {
+    coords: Vec<f64>,
+    #[serde(flatten)]
+    extra: JsonFields,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct JsonClusteredRecord<'a> {
+    cluster: isize,
+    coords: Vec<f64>,
+    #[serde(flatten)]
+    extra: Option<&'a JsonFields>,
+}
+
+/// Reads points from JSON Lines, one object per line such as
+/// `{"coords": [1.0, 2.0]}`. Other fields are returned alongside each point,
+/// in input order, so they can be passed on to `write_clustered_jsonl`.
+/// Malformed lines are reported with their line number and a snippet.
+#[cfg(feature = "serde")]
+pub fn read_points_jsonl(path: &str) -> Result<(Vec<Point>, Vec<JsonFields>)> {
+    let lines = data_lines(open_input(path)?);
+    let mut points: Vec<Point> = Vec::new();
+    let mut fields = Vec::new();
+    for line in lines {
+        let (lineno, line) = line?;
+        let record: JsonPointRecord = serde_json::from_str(&line).with_context(|| {
+            let snippet = line.chars().take(40).collect::<String>();
+            let ellipsis = if snippet.len() < line.len() {
+                "..."
+            } else {
+                ""
+            };
+            format!(
+                "line {}: invalid record '{}{}'",
+                lineno + 1,
+                snippet,
+                ellipsis
+            )
+        })?;
+        if points
+            .first()
+            .is_some_and(|first| first.len() != record.coords.len())
+        {
+            anyhow::bail!(
+                "dimension mismatch at line {}: expected {}, got {}",
+                lineno + 1,
+                points[0].len(),
+                record.coords.len()
+            );
+        }
+        points.push(record.coords.into_iter().map(OrderedFloat).collect());
+        fields.push(record.extra);
+    }
+
+    if points.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok((points, fields))
+}
+
+/// Writes clustered output as JSON Lines: `{"cluster": cid, "coords": [...]}`
+/// per point, with `cid` as in `write_clustered_csv`. If `fields` is given
+/// (one entry per point, e.g. from `read_points_jsonl`), its entries are
+/// appended to each record; entries named `cluster` or `coords` are dropped.
+#[cfg(feature = "serde")]
+pub fn write_clustered_jsonl(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    fields: Option<&[JsonFields]>,
+) -> Result<()> {
+    if let Some(fields) = fields.filter(|f| f.len() != points.len()) {
+        anyhow::bail!(
+            "expected one set of fields per point: {} points, {} sets",
+            points.len(),
+            fields.len()
+        );
+    }
+    let cleaned = fields.map(|fields| {
+        fields
+            .iter()
+            .map(|f| {
+                let mut f = f.clone();
+                f.shift_remove("cluster");
+                f.shift_remove("coords");
+                f
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut w = create_output(path)?;
+    for (i, (p, cid)) in points
+        .iter()
+        .zip(labels_in_order(points, classes))
+        .enumerate()
+    {
+        let record = JsonClusteredRecord {
+            cluster: cid,
+            coords: p.iter().map(|x| x.0).collect(),
+            extra: cleaned.as_ref().map(|c| &c[i]),
+        };
+        serde_json::to_writer(&mut w, &record)?;
+        writeln!(w)?;
+    }
+    w.finish()
+}
+
+/// Layout of `write_clustered_json` output.
+#[cfg(feature = "serde")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum JsonStyle {
+    /// One `{"cluster": 0, "coords": [1.0, 2.0]}` object per point, in input
+    /// order: a JSON array, or JSON Lines when not pretty.
+    Flat,
+    /// `{"clusters": [{"id": 0, "points": [[1.0, 2.0], ...]}, ...], "noise":
+    /// [[...], ...]}`, with clusters by ascending id and the points of each in
+    /// input order.
+    #[default]
+    Grouped,
+}
+
+/// Writes clustered points as JSON in `style`, indented if `pretty`. Points
+/// are serialized one by one as the output is written, never as a whole
+/// `serde_json::Value`. Cluster ids are as in `write_clustered_csv`; points
+/// with negative ids (noise, unclassified) are the `noise` of `Grouped`.
+#[cfg(feature = "serde")]
+pub fn write_clustered_json(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    style: JsonStyle,
+    pretty: bool,
+) -> Result<()> {
+    let cids = labels_in_order(points, classes);
+    let mut w = create_output(path)?;
+    match (style, pretty) {
+        (JsonStyle::Flat, false) => {
+            for (p, &cid) in points.iter().zip(cids.iter()) {
+                serde_json::to_writer(&mut w, &JsonFlatRecord(cid, p))?;
+                writeln!(w)?;
+            }
+        }
+        (JsonStyle::Flat, true) => {
+            let records = JsonSeq(|| {
+                points
+                    .iter()
+                    .zip(cids.iter())
+                    .map(|(p, &c)| JsonFlatRecord(c, p))
+            });
+            serde_json::to_writer_pretty(&mut w, &records)?;
+            writeln!(w)?;
+        }
+        (JsonStyle::Grouped, _) => {
+            let mut clusters: BTreeMap<isize, Vec<&Point>> = BTreeMap::new();
+            let mut noise = Vec::new();
+            for (p, &cid) in points.iter().zip(cids.iter()) {
+                if cid < 0 {
+                    noise.push(p);
+                } else {
+                    clusters.entry(cid).or_default().push(p);
+                }
+            }
+            let grouped = JsonGrouped {
+                clusters: JsonSeq(|| {
+                    clusters.iter().map(|(&id, members)| JsonCluster {
+                        id,
+                        points: JsonSeq(|| members.iter().map(|p| JsonCoords(p))),
+                    })
+                }),
+                noise: JsonSeq(|| noise.iter().map(|p| JsonCoords(p))),
+            };
+            if pretty {
+                serde_json::to_writer_pretty(&mut w, &grouped)?;
+            } else {
+                serde_json::to_writer(&mut w, &grouped)?;
+            }
+            writeln!(w)?;
+        }
+    }
+    w.finish()
+}
+
+// A JSON array of the items of the iterators made by the closure, serialized
+// as they come.
+#[cfg(feature = "serde")]
+struct JsonSeq<F>(F);
+
+#[cfg(feature = "serde")]
+impl<F, I> serde::Serialize for JsonSeq<F>
+where
+    F: Fn() -> I,
+    I: Iterator,
+    I::Item: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq((self.0)())
+    }
+}
+
+// The coordinates of a point as a JSON array of numbers.
+#[cfg(feature = "serde")]
+struct JsonCoords<'a>(&'a [OrderedFloat<f64>]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for JsonCoords<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|x| x.0))
+    }
+}
+
+// A point of `JsonStyle::Flat` output: its cluster id and coordinates.
+#[cfg(feature = "serde")]
+struct JsonFlatRecord<'a>(isize, &'a [OrderedFloat<f64>]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for JsonFlatRecord<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut record = serializer.serialize_struct("JsonFlatRecord", 2)?;
+        record.serialize_field("cluster", &self.0)?;
+        record.serialize_field("coords", &JsonCoords(self.1))?;
+        record.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct JsonCluster<P> {
+    id: isize,
+    points: P,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct JsonGrouped<C, N> {
+    clusters: C,
+    noise: N,
+}
+
+/// Writes `summary` as pretty-printed JSON, e.g. as a sidecar of the
+/// clustered output. Needs the `serde` feature.
+#[cfg(feature = "serde")]
+pub fn write_summary_json(path: &str, summary: &crate::types::RunSummary) -> Result<()> {
+    let mut w = create_output(path)?;
+    serde_json::to_writer_pretty(&mut w, summary)?;
+    writeln!(w)?;
+    w.finish()
+}
+
+// Model files start with this magic and a format version as a little-endian
+// u32, followed by the bincode encoding of the `Model`.
+#[cfg(feature = "serde")]
+const MODEL_MAGIC: &[u8; 4] = b"KMDL";
+#[cfg(feature = "serde")]
+const MODEL_VERSION: u32 = 1;
+
+/// Writes `model` for `read_model`. Needs the `serde` feature.
+#[cfg(feature = "serde")]
+pub fn write_model(path: &str, model: &crate::predict::Model) -> Result<()> {
+    let mut w = create_output(path)?;
+    w.write_all(MODEL_MAGIC)?;
+    w.write_all(&MODEL_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut w, model)?;
+    w.finish()
+}
+
+/// Reads a model written by `write_model`. Fails on files of another format
+/// or version, and on models `predict::Model::predict` could not use, e.g.
+/// with core points of the wrong dimension. Needs the `serde` feature.
+#[cfg(feature = "serde")]
+pub fn read_model(path: &str) -> Result<crate::predict::Model> {
+    let mut r = open_input(path)?;
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)
+        .with_context(|| format!("'{}' is not a model file", path))?;
+    if &header[..4] != MODEL_MAGIC {
+        anyhow::bail!("'{}' is not a model file", path);
+    }
+    let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if version != MODEL_VERSION {
+        anyhow::bail!(
+            "'{}' has model format version {}; only version {} is supported",
+            path,
+            version,
+            MODEL_VERSION
+        );
+    }
+    let model: crate::predict::Model = bincode::deserialize_from(r)
+        .with_context(|| format!("'{}' is a corrupt model file", path))?;
+    model
+        .check()
+        .with_context(|| format!("'{}' is a corrupt model file", path))?;
+    Ok(model)
+}
+
+/// Reads a GeoJSON `FeatureCollection` of `Point` features into points
+/// `[lon, lat]`, followed by the numeric properties named in `properties` as
+/// extra dimensions. Also returns the properties of every feature, for
+/// `write_clustered_geojson`. Features with another geometry, a missing
+/// property or a non-numeric one are rejected with their index.
+#[cfg(feature = "serde")]
+pub fn read_points_geojson(
+    path: &str,
+    properties: &[String],
+) -> Result<(Vec<Point>, Vec<JsonFields>)> {
+    use serde_json::Value;
+
+    let root: Value = serde_json::from_reader(TextInput::new(open_input(path)?))
+        .with_context(|| format!("'{}' is not valid JSON", path))?;
+    if root["type"] != "FeatureCollection" {
+        anyhow::bail!("expected a GeoJSON FeatureCollection");
+    }
+    let Some(features) = root["features"].as_array() else {
+        anyhow::bail!("FeatureCollection has no 'features' array");
+    };
+
+    let mut points: Vec<Point> = Vec::with_capacity(features.len());
+    let mut fields = Vec::with_capacity(features.len());
+    for (i, feature) in features.iter().enumerate() {
+        let geometry = &feature["geometry"];
+        match geometry["type"].as_str() {
+            Some("Point") => {}
+            Some(other) => anyhow::bail!(
+                "feature {}: geometry type '{}' is not supported; only Point is",
+                i,
+                other
+            ),
+            None => anyhow::bail!("feature {}: missing geometry", i),
+        }
+        let lon_lat = geometry["coordinates"]
+            .as_array()
+            .filter(|c| c.len() >= 2)
+            .and_then(|c| Some([c[0].as_f64()?, c[1].as_f64()?]))
+            .with_context(|| format!("feature {}: expected [lon, lat] coordinates", i))?;
+
+        let props = match &feature["properties"] {
+            Value::Object(props) => props.clone(),
+            _ => JsonFields::new(),
+        };
+        let mut point: Point = lon_lat.into_iter().map(OrderedFloat).collect();
+        for name in properties {
+            let x = props.get(name).and_then(Value::as_f64).with_context(|| {
+                format!(
+                    "feature {}: property '{}' is missing or not a number",
+                    i, name
+                )
+            })?;
+            point.push(OrderedFloat(x));
+        }
+        validate_point(&point).with_context(|| format!("feature {}", i))?;
+        points.push(point);
+        fields.push(props);
+    }
+
+    if points.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok((points, fields))
+}
+
+/// Writes clustered output as a GeoJSON `FeatureCollection`: one `Point`
+/// feature per point at its first two coordinates (`[lon, lat]`), with a
+/// `cluster` property holding `cid` as in `write_clustered_csv`. If
+/// `properties` is given (one entry per point, e.g. from
+/// `read_points_geojson`), each feature keeps them, except for a previous
+/// `cluster` property.
+#[cfg(feature = "serde")]
+pub fn write_clustered_geojson(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    properties: Option<&[JsonFields]>,
+) -> Result<()> {
+    use serde_json::{json, Value};
+
+    if let Some(properties) = properties.filter(|p| p.len() != points.len()) {
+        anyhow::bail!(
+            "expected one set of properties per point: {} points, {} sets",
+            points.len(),
+            properties.len()
+        );
+    }
+    if let Some(i) = points.iter().position(|p| p.len() < 2) {
+        anyhow::bail!("point {} has fewer than 2 coordinates", i);
+    }
+
+    let features = points
+        .iter()
+        .zip(labels_in_order(points, classes))
+        .enumerate()
+        .map(|(i, (p, cid))| {
+            let mut props = properties.map_or_else(JsonFields::new, |props| props[i].clone());
+            props.insert("cluster".to_string(), json!(cid));
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [p[0].0, p[1].0]},
+                "properties": Value::Object(props),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut w = create_output(path)?;
+    serde_json::to_writer(
+        &mut w,
+        &json!({"type": "FeatureCollection", "features": features}),
+    )?;
+    writeln!(w)?;
+    w.finish()
+}
+
+/// What to read from a Parquet file with `read_points_parquet_with`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ParquetOptions {
+    /// Coordinate columns, in this order. `None` takes every top-level
+    /// integer or floating-point column except `label`, in file order.
+    pub columns: Option<Vec<String>>,
+    /// Integer column holding a label per point, e.g. a ground-truth class.
+    pub label: Option<String>,
+    /// What to do with rows in which a coordinate is null, NaN or infinite.
+    /// `Allow` keeps nulls as NaN. Null labels always fail.
+    pub invalid: InvalidRows,
+}
+
+/// Reads the float columns `columns` of a Parquet file (all numeric columns if
+/// `None`) into points, one per row. Integer and f32 columns are converted to
+/// f64. Rows with null, NaN or infinite values are rejected.
+#[cfg(feature = "parquet")]
+pub fn read_points_parquet(path: &str, columns: Option<&[String]>) -> Result<Vec<Point>> {
+    let options = ParquetOptions {
+        columns: columns.map(<[String]>::to_vec),
+        ..ParquetOptions::default()
+    };
+    Ok(read_points_parquet_with(path, &options)?.0)
+}
+
+/// Like `read_points_parquet`, with a label column and a policy for invalid
+/// rows. Returns the labels of the kept rows if `options.label` is set. The
+/// file is read one row group at a time.
+#[cfg(feature = "parquet")]
+pub fn read_points_parquet_with(
+    path: &str,
+    options: &ParquetOptions,
+) -> Result<(Vec<Point>, Option<Vec<isize>>)> {
+    use parquet::basic::Type as PhysicalType;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+    use parquet::schema::types::Type;
+    use std::sync::Arc;
+
+    if path == "-" {
+        anyhow::bail!("Parquet cannot be read from stdin");
+    }
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+    let reader = SerializedFileReader::new(file)
+        .with_context(|| format!("'{}' is not a valid Parquet file", path))?;
+
+    let schema = reader.metadata().file_metadata().schema();
+    let numeric = |t: &Type| {
+        t.is_primitive()
+            && matches!(
+                t.get_physical_type(),
+                PhysicalType::INT32
+                    | PhysicalType::INT64
+                    | PhysicalType::FLOAT
+                    | PhysicalType::DOUBLE
+            )
+    };
+    let find = |name: &str| {
+        let Some(field) = schema.get_fields().iter().find(|f| f.name() == name) else {
+            let names = schema
+                .get_fields()
+                .iter()
+                .map(|f| f.name())
+                .collect::<Vec<_>>();
+            anyhow::bail!("no column '{}'; available: {}", name, names.join(", "));
+        };
+        Ok(field)
+    };
+
+    let columns = match &options.columns {
+        Some(columns) => columns.clone(),
+        None => schema
+            .get_fields()
+            .iter()
+            .filter(|f| numeric(f) && Some(f.name()) != options.label.as_deref())
+            .map(|f| f.name().to_string())
+            .collect(),
+    };
+    if columns.is_empty() {
+        anyhow::bail!("no numeric columns to read");
+    }
+    let mut fields = Vec::new();
+    for name in columns.iter().chain(options.label.iter()) {
+        let field = find(name)?;
+        if !numeric(field) {
+            anyhow::bail!(
+                "column '{}' is not an integer or floating-point column",
+                name
+            );
+        }
+        fields.push(Arc::clone(field));
+    }
+    if let Some(label) = &options.label {
+        if columns.contains(label) {
+            anyhow::bail!(
+                "column '{}' cannot be both a coordinate and the label",
+                label
+            );
+        }
+        if !matches!(
+            find(label)?.get_physical_type(),
+            PhysicalType::INT32 | PhysicalType::INT64
+        ) {
+            anyhow::bail!("label column '{}' is not an integer column", label);
+        }
+    }
+    // Projected rows need not follow the requested column order, so values
+    // are placed by name.
+    let position: HashMap<&str, usize> = columns
+        .iter()
+        .chain(options.label.iter())
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect();
+    let projection = Type::group_type_builder(schema.name())
+        .with_fields(fields)
+        .build()?;
+
+    let mut points: Vec<Point> = Vec::new();
+    let mut labels = Vec::new();
+    let mut values = vec![0.0; position.len()];
+    let mut rowno = 0;
+    for i in 0..reader.num_row_groups() {
+        let row_group = reader.get_row_group(i)?;
+        for row in row_group.get_row_iter(Some(projection.clone()))? {
+            let row = row.with_context(|| format!("failed to read row {}", rowno))?;
+            for (name, field) in row.get_column_iter() {
+                let x = match *field {
+                    Field::Null => f64::NAN,
+                    Field::Byte(x) => x as f64,
+                    Field::Short(x) => x as f64,
+                    Field::Int(x) => x as f64,
+                    Field::Long(x) => x as f64,
+                    Field::UByte(x) => x as f64,
+                    Field::UShort(x) => x as f64,
+                    Field::UInt(x) => x as f64,
+                    Field::ULong(x) => x as f64,
+                    Field::Float(x) => x as f64,
+                    Field::Double(x) => x,
+                    _ => anyhow::bail!("row {}: unsupported value in column '{}'", rowno, name),
+                };
+                values[position[name.as_str()]] = x;
+            }
+            let (coords, label) = values.split_at(columns.len());
+            if label.first().is_some_and(|l| l.is_nan()) {
+                anyhow::bail!("row {}: null label", rowno);
+            }
+            let point: Point = coords.iter().copied().map(OrderedFloat).collect();
+            if options.invalid != InvalidRows::Allow && coords.iter().any(|x| !x.is_finite()) {
+                if options.invalid == InvalidRows::Skip {
+                    rowno += 1;
+                    continue;
+                }
+                if let Some(j) = coords.iter().position(|x| x.is_nan()) {
+                    anyhow::bail!("row {}: null or NaN in column '{}'", rowno, columns[j]);
+                }
+                validate_point(&point)
+                    .with_context(|| format!("invalid value at row {}", rowno))?;
+            }
+            points.push(point);
+            labels.extend(label.first().map(|&l| l as isize));
+            rowno += 1;
+        }
+    }
+
+    if points.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok((points, options.label.as_ref().map(|_| labels)))
+}
+
+// Rows per record batch written by `write_clustered_arrow`.
+#[cfg(feature = "arrow")]
+const ARROW_BATCH_ROWS: usize = 65_536;
+
+/// Reads the numeric columns `columns` of an Arrow IPC file (Feather v2), or
+/// all numeric columns if `None`, into points, one per row. Integer and f32
+/// columns are converted to f64. Files with several record batches are read
+/// one batch at a time. Rows with null, NaN or infinite values are rejected.
+#[cfg(feature = "arrow")]
+pub fn read_points_arrow(path: &str, columns: Option<&[String]>) -> Result<Vec<Point>> {
+    use arrow_ipc::reader::FileReader;
+
+    if path == "-" {
+        anyhow::bail!("Arrow IPC files cannot be read from stdin");
+    }
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to read '{}': not found or unreadable", path))?;
+    let reader = FileReader::try_new(BufReader::new(file), None)
+        .with_context(|| format!("'{}' is not a valid Arrow IPC file", path))?;
+
+    let schema = reader.schema();
+    let indices = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|name| {
+                schema.index_of(name).map_err(|_| {
+                    let names = schema
+                        .fields()
+                        .iter()
+                        .map(|f| f.name().as_str())
+                        .collect::<Vec<_>>();
+                    anyhow::anyhow!("no column '{}'; available: {}", name, names.join(", "))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => (0..schema.fields().len())
+            .filter(|&i| schema.field(i).data_type().is_numeric())
+            .collect(),
+    };
+    if indices.is_empty() {
+        anyhow::bail!("no numeric columns to read");
+    }
+
+    let mut points: Vec<Point> = Vec::new();
+    for batch in reader {
+        let batch = batch.with_context(|| format!("failed to read '{}'", path))?;
+        let cols = indices
+            .iter()
+            .map(|&i| arrow_f64_values(batch.column(i).as_ref(), schema.field(i).name()))
+            .collect::<Result<Vec<_>>>()?;
+        for row in 0..batch.num_rows() {
+            let rowno = points.len();
+            let point = cols
+                .iter()
+                .zip(indices.iter())
+                .map(|(col, &i)| {
+                    col[row].map(OrderedFloat).with_context(|| {
+                        format!("row {}: null in column '{}'", rowno, schema.field(i).name())
+                    })
+                })
+                .collect::<Result<Point>>()?;
+            validate_point(&point).with_context(|| format!("invalid value at row {}", rowno))?;
+            points.push(point);
+        }
+    }
+
+    if points.is_empty() {
+        anyhow::bail!("no points found in input");
+    }
+    Ok(points)
+}
+
+// Values of a numeric Arrow column as f64, `None` for nulls.
+#[cfg(feature = "arrow")]
+fn arrow_f64_values(array: &dyn arrow_array::Array, name: &str) -> Result<Vec<Option<f64>>> {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::*;
+    use arrow_array::ArrowPrimitiveType;
+    use arrow_schema::DataType;
+
+    fn values<T: ArrowPrimitiveType>(
+        array: &dyn arrow_array::Array,
+        f: impl Fn(T::Native) -> f64,
+    ) -> Vec<Option<f64>> {
+        array
+            .as_primitive::<T>()
+            .iter()
+            .map(|x| x.map(&f))
+            .collect()
+    }
+
+    Ok(match array.data_type() {
+        DataType::Float64 => values::<Float64Type>(array, |x| x),
+        DataType::Float32 => values::<Float32Type>(array, |x| x as f64),
+        DataType::Int64 => values::<Int64Type>(array, |x| x as f64),
+        DataType::Int32 => values::<Int32Type>(array, |x| x as f64),
+        DataType::Int16 => values::<Int16Type>(array, |x| x as f64),
+        DataType::Int8 => values::<Int8Type>(array, |x| x as f64),
+        DataType::UInt64 => values::<UInt64Type>(array, |x| x as f64),
+        DataType::UInt32 => values::<UInt32Type>(array, |x| x as f64),
+        DataType::UInt16 => values::<UInt16Type>(array, |x| x as f64),
+        DataType::UInt8 => values::<UInt8Type>(array, |x| x as f64),
+        other => anyhow::bail!("column '{}' has non-numeric type {}", name, other),
+    })
+}
+
+/// Writes clustered output as an Arrow IPC file: an Int64 `cluster_id` column
+/// (as in `write_clustered_csv`) followed by Float64 coordinate columns named
+/// by `schema`, or `x1`, `x2`, ... without one. Rows are written in record
+/// batches of at most 65536 rows.
+#[cfg(feature = "arrow")]
+pub fn write_clustered_arrow(
+    path: &str,
+    schema: Option<&Schema>,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> Result<()> {
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch};
+    use arrow_ipc::writer::FileWriter;
+    use arrow_schema::{DataType, Field};
+    use std::sync::Arc;
+
+    if path == "-" {
+        anyhow::bail!("Arrow IPC files cannot be written to stdout");
+    }
+    let dim = points.first().map_or(0, |p| p.len());
+    let names = match schema {
+        Some(schema) if schema.len() == dim => schema.clone(),
+        Some(schema) => anyhow::bail!("schema has {} columns, points have {}", schema.len(), dim),
+        None => Schema::numbered(dim),
+    };
+    let mut fields = vec![Field::new("cluster_id", DataType::Int64, false)];
+    fields.extend(
+        names
+            .names()
+            .iter()
+            .map(|n| Field::new(n, DataType::Float64, false)),
+    );
+    let arrow_schema = Arc::new(arrow_schema::Schema::new(fields));
+
+    let file = fs::File::create(path).with_context(|| {
+        format!(
+            "failed to create '{}': insufficient permissions or path invalid",
+            path
+        )
+    })?;
+    let mut w = FileWriter::try_new(BufWriter::new(file), &arrow_schema)?;
+    let labels = labels_in_order(points, classes);
+    for (chunk, labels) in points
+        .chunks(ARROW_BATCH_ROWS)
+        .zip(labels.chunks(ARROW_BATCH_ROWS))
+    {
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(Int64Array::from_iter_values(
+            labels.iter().map(|&cid| cid as i64),
+        ))];
+        columns.extend((0..dim).map(|j| {
+            Arc::new(Float64Array::from_iter_values(chunk.iter().map(|p| p[j].0))) as ArrayRef
+        }));
+        w.write(&RecordBatch::try_new(Arc::clone(&arrow_schema), columns)?)?;
+    }
+    w.finish()?;
+    Ok(())
+}
+
+/// Writes clustered output to a new table `table` of the SQLite database
+/// `path`, creating the file if needed: an INTEGER `cluster_id` column (as in
+/// `write_clustered_csv`) followed by REAL coordinate columns named by
+/// `schema`, or `x1`, `x2`, ... without one. Rows are inserted in input order
+/// in a single transaction. Fails if the table already exists.
+#[cfg(feature = "sqlite")]
+pub fn write_clustered_sqlite(
+    path: &str,
+    table: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    schema: Option<&Schema>,
+) -> Result<()> {
+    use rusqlite::{params_from_iter, Connection};
+
+    if path == "-" {
+        anyhow::bail!("SQLite databases cannot be written to stdout");
+    }
+    let dim = points.first().map_or(0, |p| p.len());
+    let names = match schema {
+        Some(schema) if schema.len() == dim => schema.clone(),
+        Some(schema) => anyhow::bail!("schema has {} columns, points have {}", schema.len(), dim),
+        None => Schema::numbered(dim),
+    };
+    // Identifiers are quoted, so that any header name is a valid column name.
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let columns = std::iter::once("cluster_id")
+        .chain(names.names().iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>();
+    let types = std::iter::once("INTEGER NOT NULL").chain(std::iter::repeat("REAL"));
+    let create = format!(
+        "CREATE TABLE {} ({})",
+        quote(table),
+        columns
+            .iter()
+            .zip(types)
+            .map(|(c, t)| format!("{} {}", c, t))
+            .join(", ")
+    );
+    let insert = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote(table),
+        columns.join(", "),
+        (1..=columns.len()).map(|i| format!("?{}", i)).join(", ")
+    );
+
+    let mut db = Connection::open(path)
+        .with_context(|| format!("failed to open SQLite database '{}'", path))?;
+    let tx = db.transaction()?;
+    tx.execute(&create, [])
+        .with_context(|| format!("failed to create table '{}' in '{}'", table, path))?;
+    {
+        let mut stmt = tx.prepare(&insert)?;
+        let labels = labels_in_order(points, classes);
+        for (p, cid) in points.iter().zip(labels) {
+            let coords = p.iter().map(|x| rusqlite::types::Value::Real(x.0));
+            let row = std::iter::once(rusqlite::types::Value::Integer(cid as i64)).chain(coords);
+            stmt.execute(params_from_iter(row))?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    fn read(text: &str, format: CsvFormat) -> Result<(Vec<Point>, Option<Schema>, char)> {
+        let data = read_csv(text.as_bytes(), &CsvOptions::new(format))?;
+        Ok((data.points, data.schema, data.delimiter))
+    }
+
+    // The message of `err` with its causes, as `main` prints them.
+    fn message(err: anyhow::Error) -> String {
+        format!("{:#}", err)
+    }
+
+    #[test]
+    fn reads_points_from_memory() {
+        let (points, schema, delimiter) =
+            read("1,2\n\n-0.5,1e3\r\n3,4", CsvFormat::default()).unwrap();
+        assert_eq!(
+            points,
+            [pt(&[1.0, 2.0]), pt(&[-0.5, 1000.0]), pt(&[3.0, 4.0])]
+        );
+        assert_eq!(schema, None);
+        assert_eq!(delimiter, ',');
+    }
+
+    #[test]
+    fn reads_a_header_and_detects_the_delimiter() {
+        let format = CsvFormat::new(Header::Auto, Delimiter::Auto);
+        let (points, schema, delimiter) = read("x\ty\n1\t2\n3\t4\n", format).unwrap();
+        assert_eq!(points, [pt(&[1.0, 2.0]), pt(&[3.0, 4.0])]);
+        assert_eq!(schema.unwrap().names(), ["x", "y"]);
+        assert_eq!(delimiter, '\t');
+
+        // An all-numeric first line is data.
+        let (points, schema, _) = read("1;2\n3;4\n", format).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(schema, None);
+    }
+
+    #[test]
+    fn header_modes_decide_what_the_first_line_is() {
+        let format = |header| CsvFormat::new(header, Delimiter::COMMA);
+        let named = "x,y\n1,2\n3,4\n";
+        let numeric = "1,2\n3,4\n";
+
+        let (points, schema, _) = read(named, format(Header::Present)).unwrap();
+        assert_eq!(points, [pt(&[1.0, 2.0]), pt(&[3.0, 4.0])]);
+        assert_eq!(schema.unwrap().names(), ["x", "y"]);
+        let err = read(named, format(Header::Absent)).unwrap_err();
+        assert!(message(err).starts_with("parse error at line 1"));
+        let (points, schema, _) = read(named, format(Header::Auto)).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(schema.unwrap().names(), ["x", "y"]);
+
+        // Auto keeps an all-numeric first row; Present takes it as names.
+        let (points, schema, _) = read(numeric, format(Header::Auto)).unwrap();
+        assert_eq!(points, [pt(&[1.0, 2.0]), pt(&[3.0, 4.0])]);
+        assert_eq!(schema, None);
+        let (points, schema, _) = read(numeric, format(Header::Present)).unwrap();
+        assert_eq!(points, [pt(&[3.0, 4.0])]);
+        assert_eq!(schema.unwrap().names(), ["1", "2"]);
+        // One non-numeric field is enough.
+        let (points, _, _) = read("1,y\n3,4\n", format(Header::Auto)).unwrap();
+        assert_eq!(points, [pt(&[3.0, 4.0])]);
+
+        let clustered = "cid,x,y\n0,1,2\n-1,3,4\n";
+        let (schema, rows) =
+            read_clustered_csv_from(clustered.as_bytes(), format(Header::Auto)).unwrap();
+        assert_eq!(schema.unwrap().names(), ["x", "y"]);
+        assert_eq!(rows.len(), 2);
+        let (schema, rows) =
+            read_clustered_csv_from("0,1,2\n".as_bytes(), format(Header::Auto)).unwrap();
+        assert_eq!(schema, None);
+        assert_eq!(rows, [(0, vec![1.0, 2.0])]);
+        assert!(read_clustered_csv_from(clustered.as_bytes(), format(Header::Absent)).is_err());
+
+        assert_eq!(Header::from_flags(false, false), Header::Absent);
+        assert_eq!(Header::from_flags(true, false), Header::Present);
+        assert_eq!(Header::from_flags(false, true), Header::Auto);
+    }
+
+    #[test]
+    fn tab_and_semicolon_files_are_read_and_written() {
+        let expected = [pt(&[1.5, -2.0]), pt(&[3.0, 4.0])];
+        for (text, delimiter) in [
+            ("1.5\t-2\n3\t4\n", Delimiter::TAB),
+            ("1.5;-2\n3;4\n", Delimiter::SEMICOLON),
+        ] {
+            for format in [Delimiter::Auto, delimiter].map(|d| CsvFormat::new(Header::Absent, d)) {
+                let (points, _, used) = read(text, format).unwrap();
+                assert_eq!(points, expected, "{:?}", text);
+                assert_eq!(Delimiter::Char(used), delimiter);
+            }
+        }
+        // Other characters are used when named.
+        let pipe = CsvFormat::new(Header::Absent, Delimiter::Char('|'));
+        assert_eq!(read("1.5|-2\n3|4\n", pipe).unwrap().0, expected);
+        // Another separator leaves one unparsable field per line.
+        let err = read("1;2\n", CsvFormat::default()).unwrap_err();
+        assert!(message(err).starts_with("parse error at line 1"));
+
+        let classes = HashMap::from([(&expected[0], Class::Classified(0))]);
+        let mut out = Vec::new();
+        write_clustered_csv_to(
+            &mut out,
+            None,
+            ';',
+            FloatFormat::Shortest,
+            &expected,
+            &classes,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "0;1.5;-2\n-1;3;4\n"
+        );
+        let format = CsvFormat::new(Header::Absent, Delimiter::Auto);
+        let (_, rows) = read_clustered_csv_from(out.as_slice(), format).unwrap();
+        assert_eq!(rows[1], (-1, vec![3.0, 4.0]));
+    }
+
+    #[test]
+    fn decimal_commas_are_reported_as_such() {
+        let format = CsvFormat::new(Header::Absent, Delimiter::SEMICOLON);
+        let err = read("1;2\n1,5;2\n", format).unwrap_err();
+        assert_eq!(
+            message(err),
+            "parse error at line 2, column 0: '1,5' uses a decimal comma; use '.' as the decimal separator"
+        );
+        // A quoted field in a comma-separated file, too.
+        let err = read("1,2\n\"1,5\",2\n", CsvFormat::default()).unwrap_err();
+        assert!(message(err).contains("'1,5' uses a decimal comma"));
+    }
+
+    #[test]
+    fn number_formats_combine() {
+        let read_with = |text: &str, delimiter, numbers: NumberFormat, reject| {
+            let format = CsvFormat::new(Header::Absent, delimiter)
+                .with_numbers(numbers.reject_non_finite(reject));
+            let options = CsvOptions::new(format).with_invalid(InvalidRows::Allow);
+            read_csv(text.as_bytes(), &options).map(|data| data.points)
+        };
+        let plain = "1.5E+003;-2e-3\n0.1;7\n";
+        let expected = [pt(&[1500.0, -0.002]), pt(&[0.1, 7.0])];
+        let commas = "1,5E+003;-2e-3\n0,1;7\n";
+        let specials = "nan;1\n2;-INF\n";
+        for numbers in [NumberFormat::STANDARD, NumberFormat::DECIMAL_COMMA] {
+            for reject in [false, true] {
+                let case = format!("{:?}", numbers.reject_non_finite(reject));
+                // Files without decimal commas read the same in every format.
+                assert_eq!(
+                    read_with(plain, Delimiter::SEMICOLON, numbers, reject).unwrap(),
+                    expected,
+                    "{}",
+                    case
+                );
+                let decimal = read_with(commas, Delimiter::SEMICOLON, numbers, reject);
+                if numbers.decimal_comma {
+                    assert_eq!(decimal.unwrap(), expected, "{}", case);
+                } else {
+                    let err = message(decimal.unwrap_err());
+                    assert!(err.contains("'1,5E+003' uses a decimal comma"), "{}", err);
+                }
+                let special = read_with(specials, Delimiter::SEMICOLON, numbers, reject);
+                if reject {
+                    assert_eq!(
+                        message(special.unwrap_err()),
+                        "parse error at line 1, column 0: 'nan' is not a finite number",
+                        "{}",
+                        case
+                    );
+                } else {
+                    let points = special.unwrap();
+                    assert!(points[0][0].is_nan(), "{}", case);
+                    assert_eq!(points[1][1].0, f64::NEG_INFINITY, "{}", case);
+                }
+            }
+        }
+
+        let comma = NumberFormat::DECIMAL_COMMA;
+        let err = read_with("1;2\n", Delimiter::COMMA, comma, false).unwrap_err();
+        assert_eq!(
+            message(err),
+            "decimal commas need a delimiter other than ','"
+        );
+        // A tab-separated file with decimal commas, detected.
+        let points = read_with("1,5\t2\n", Delimiter::Auto, comma, true);
+        assert_eq!(points.unwrap(), [pt(&[1.5, 2.0])]);
+    }
+
+    #[test]
+    fn delimiters_parse_from_names_and_characters() {
+        let parse = |s: &str| s.parse::<Delimiter>();
+        assert_eq!(parse("auto"), Ok(Delimiter::Auto));
+        assert_eq!(parse("tab"), Ok(Delimiter::TAB));
+        assert_eq!(parse("\\t"), Ok(Delimiter::TAB));
+        assert_eq!(parse("\t"), Ok(Delimiter::TAB));
+        assert_eq!(parse("semicolon"), Ok(Delimiter::SEMICOLON));
+        assert_eq!(parse(";"), Ok(Delimiter::SEMICOLON));
+        assert_eq!(parse("comma"), Ok(Delimiter::COMMA));
+        assert_eq!(parse("|"), Ok(Delimiter::Char('|')));
+        for bad in ["", ";;", ".", "-", "e", "5"] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(
+            parse("x").unwrap_err().to_string(),
+            "invalid delimiter 'x': expected one character that cannot be part of a number, 'tab' or 'auto'"
+        );
+
+        assert_eq!(Delimiter::Auto.resolve("1\t2;3"), '\t');
+        assert_eq!(Delimiter::Auto.resolve("1;2,3"), ';');
+        assert_eq!(Delimiter::Auto.resolve("1,2"), ',');
+        assert_eq!(Delimiter::Auto.resolve("1|2"), ',');
+        assert_eq!(Delimiter::SEMICOLON.resolve("1,2"), ';');
+        assert!(Delimiter::Auto.resolve_in("-").is_err());
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        for text in ["", "\n\n", "\r\n"] {
+            let err = read(text, CsvFormat::default()).unwrap_err();
+            assert_eq!(message(err), "no points found in input", "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_input_names_the_line_and_column() {
+        let err = read("1,2\n3,x\n", CsvFormat::default()).unwrap_err();
+        assert_eq!(
+            message(err),
+            "parse error at line 2, column 1: invalid float literal"
+        );
+        let err = read("1,2\n3,4,5\n", CsvFormat::default()).unwrap_err();
+        assert!(message(err).contains("line 2"));
+        let err = read("1,2\nnan,4\n", CsvFormat::default()).unwrap_err();
+        assert!(message(err).contains("line 2"));
+    }
+
+    #[test]
+    fn max_rows_stops_reading_early() {
+        // Counts the bytes taken from an endless stream of rows.
+        struct Endless(std::rc::Rc<std::cell::Cell<usize>>);
+        impl std::io::Read for Endless {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let row = b"1.5,2.5\n";
+                let n = buf.len() / row.len() * row.len();
+                for chunk in buf[..n].chunks_mut(row.len()) {
+                    chunk.copy_from_slice(row);
+                }
+                self.0.set(self.0.get() + n);
+                Ok(n)
+            }
+        }
+
+        let taken = std::rc::Rc::new(std::cell::Cell::new(0));
+        let format = CsvFormat::default().with_max_rows(Some(50_000));
+        let input = std::io::BufReader::new(Endless(taken.clone()));
+        let data = read_csv(input, &CsvOptions::new(format)).unwrap();
+        assert_eq!(data.points.len(), 50_000);
+        assert_eq!(data.line_numbers.last(), Some(&50_000));
+        // 50,000 rows are 400 KB; what is left unread is the read-ahead.
+        assert!(taken.get() < 600_000, "{}", taken.get());
+
+        // The header is not a row, and malformed rows are.
+        let text = "x,y\n0,0\nbad,1\n2,2\n3,3\n";
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA)
+            .with_on_error(OnError::Skip)
+            .with_max_rows(Some(3));
+        let data = read_csv(text.as_bytes(), &CsvOptions::new(format)).unwrap();
+        assert_eq!(data.points, [pt(&[0.0, 0.0]), pt(&[2.0, 2.0])]);
+        assert_eq!(data.bad_lines.skipped, 1);
+        let err = read(text, format.with_max_rows(Some(0))).unwrap_err();
+        assert_eq!(message(err), "no points found in input");
+    }
+
+    #[test]
+    fn reader_options_combine() {
+        let text = "x,class,w,y\n0,a,2,1\nbad,a,1,1\n 2 ,b,,3\n4,b,1\n";
+        let format =
+            CsvFormat::new(Header::Present, Delimiter::COMMA).with_on_error(OnError::Collect(5));
+        let options = CsvOptions::new(format)
+            .with_label(Some(ColumnRef::Name("class".to_string())))
+            .with_weight(Some(ColumnRef::Index(2)), MissingWeight::Unit)
+            .keeping(KeepRows::Fields);
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[0.0, 1.0]), pt(&[2.0, 3.0])]);
+        assert_eq!(data.schema.unwrap().names(), ["x", "y"]);
+        assert_eq!(data.line_numbers, [2, 4]);
+        let labels = data.labels.unwrap();
+        assert_eq!(labels.name.as_deref(), Some("class"));
+        assert_eq!(labels.values, ["a", "b"]);
+        let weights = data.weights.unwrap();
+        assert_eq!(weights.name.as_deref(), Some("w"));
+        assert_eq!(weights.values, [2.0, 1.0]);
+        let raw = data.raw.unwrap();
+        assert_eq!(raw.header.unwrap(), ["x", "class", "w", "y"]);
+        assert_eq!(raw.rows, [["0", "a", "2", "1"], ["2", "b", "", "3"]]);
+        // The row too short for the coordinates is dropped like the one
+        // with text.
+        assert_eq!(data.bad_lines.skipped, 2);
+        assert_eq!(data.bad_lines.lines[0].line, 3);
+        assert_eq!(data.bad_lines.lines[1].line, 5);
+    }
+
+    #[test]
+    fn every_kind_of_malformed_row_is_skipped() {
+        // Text for a number, too many and too few fields, NaN, invalid UTF-8,
+        // an infinite number and an unclosed quote.
+        let mut text = b"0,0\n1,x\n2,2,2\n3\n4,nan\n5,5\n6,\xff\n".to_vec();
+        text.extend(b"7,1e999\n8,8\n\"9,9\n");
+        let read_with = |on_error| {
+            let format = CsvFormat::default().with_on_error(on_error);
+            read_csv(text.as_slice(), &CsvOptions::new(format))
+        };
+
+        let err = read_with(OnError::Strict).unwrap_err();
+        assert_eq!(
+            message(err),
+            "parse error at line 2, column 1: invalid float literal"
+        );
+        let data = read_with(OnError::Skip).unwrap();
+        assert_eq!(
+            data.points,
+            [pt(&[0.0, 0.0]), pt(&[5.0, 5.0]), pt(&[8.0, 8.0])]
+        );
+        assert_eq!(data.line_numbers, [1, 6, 9]);
+        assert_eq!(data.bad_lines.skipped, 7);
+        assert!(data.bad_lines.lines.is_empty());
+
+        let data = read_with(OnError::Collect(3)).unwrap();
+        assert_eq!(data.line_numbers, [1, 6, 9]);
+        assert_eq!(data.bad_lines.skipped, 7);
+        let collected = data
+            .bad_lines
+            .lines
+            .iter()
+            .map(|bad| (bad.line, bad.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(collected, [(2, "1,x"), (3, "2,2,2"), (4, "3")]);
+        assert_eq!(
+            data.bad_lines.lines[1].error,
+            "dimension mismatch at line 3: expected 2, got 3"
+        );
+    }
+
+    #[test]
+    fn rows_without_a_label_are_malformed() {
+        let options = CsvOptions::default().with_label(Some(ColumnRef::Index(2)));
+        let err = read_csv("1,2,a\n3,4\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            message(err),
+            "line 2: label column 2 is out of range; the row has 2 columns"
+        );
+
+        let skip = CsvOptions {
+            format: CsvFormat::default().with_on_error(OnError::Skip),
+            ..options
+        };
+        let data = read_csv("1,2,a\n3,4\n5,6,b\n".as_bytes(), &skip).unwrap();
+        assert_eq!(data.labels.unwrap().values, ["a", "b"]);
+        assert_eq!(data.bad_lines.skipped, 1);
+    }
+
+    #[test]
+    fn bad_weights_are_malformed_rows() {
+        let format = CsvFormat::default().with_on_error(OnError::Skip);
+        let options =
+            CsvOptions::new(format).with_weight(Some(ColumnRef::Index(1)), MissingWeight::Reject);
+        let data = read_csv("0,1,0\n0,x,1\n0,-2,2\n0,,3\n0,2.5,4\n".as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[0.0, 0.0]), pt(&[0.0, 4.0])]);
+        assert_eq!(data.weights.unwrap().values, [1.0, 2.5]);
+        assert_eq!(data.bad_lines.skipped, 3);
+    }
+
+    #[test]
+    fn label_and_weight_need_other_columns() {
+        let options = CsvOptions::default()
+            .with_label(Some(ColumnRef::Index(0)))
+            .with_weight(Some(ColumnRef::Index(0)), MissingWeight::Reject);
+        let err = read_csv("a,1\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            message(err),
+            "column 0 cannot be both the label and the weight column"
+        );
+        let options = options.with_weight(Some(ColumnRef::Index(1)), MissingWeight::Reject);
+        let err = read_csv("a,1\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            message(err),
+            "no coordinate columns besides the label column 0 and the weight column 1"
+        );
+    }
+
+    #[test]
+    fn samples_keep_what_is_read_alongside() {
+        let text = (0..100)
+            .map(|i| format!("{},  {}  ,label{}", i, i, i))
+            .join("\n");
+        let options = CsvOptions::default()
+            .with_columns(Some(Columns::parse(&["0", "1"]).unwrap()))
+            .with_label(Some(ColumnRef::Index(2)))
+            .keeping(KeepRows::Lines)
+            .with_sample(Some(10), 7);
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(data.points.len(), 10);
+        let lines = data.lines.unwrap().lines;
+        let labels = data.labels.unwrap().values;
+        for (i, p) in data.points.iter().enumerate() {
+            let row = p[0].0 as usize;
+            assert_eq!(data.line_numbers[i], row + 1);
+            assert_eq!(labels[i], format!("label{}", row));
+            assert_eq!(lines[i], format!("{},  {}  ,label{}", row, row, row));
+        }
+        assert!(data.line_numbers.windows(2).all(|w| w[0] < w[1]));
+        // The same seed picks the same rows.
+        let again = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(again.line_numbers, data.line_numbers);
+    }
+
+    #[test]
+    fn many_files_are_read_and_sampled_as_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a.csv", "empty.csv", "b.csv"]
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string());
+        fs::write(&paths[0], "x,y,id\n0,0,a0\n1,1,a1\n").unwrap();
+        fs::write(&paths[1], "x,y,id\n").unwrap();
+        fs::write(&paths[2], "x,y,id\n2,2,b0\nbad\n3,3,b1\n").unwrap();
+        let paths = paths.iter().map(String::as_str).collect::<Vec<_>>();
+        let format =
+            CsvFormat::new(Header::Present, Delimiter::COMMA).with_on_error(OnError::Collect(5));
+        let options = CsvOptions::new(format).with_label(Some(ColumnRef::Name("id".to_string())));
+
+        let (data, sources) = read_csv_many(&paths, &options).unwrap();
+        assert_eq!(data.labels.unwrap().values, ["a0", "a1", "b0", "b1"]);
+        assert_eq!(sources, [0, 0, 2, 2]);
+        assert_eq!(data.line_numbers, [2, 3, 2, 4]);
+        assert_eq!(data.bad_lines.skipped, 1);
+        assert_eq!(data.bad_lines.lines[0].line, 3);
+
+        let sample = options.clone().with_sample(Some(3), 1);
+        let (data, sources) = read_csv_many(&paths, &sample).unwrap();
+        assert_eq!(data.points.len(), 3);
+        let labels = data.labels.unwrap().values;
+        for ((p, label), source) in data.points.iter().zip(&labels).zip(&sources) {
+            let expected = ["a0", "a1", "b0", "b1"][p[0].0 as usize];
+            assert_eq!(label, expected);
+            assert_eq!(*source, if label.starts_with('a') { 0 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn shards_must_have_the_same_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let (a, b, empty) = (path("a.csv"), path("b.csv"), path("empty.csv"));
+        fs::write(&a, "0,0\n1,1\n").unwrap();
+        fs::write(&empty, "\n").unwrap();
+
+        fs::write(&b, "2,2\n").unwrap();
+        let (points, sources) = read_points_csv_many(&[&a, &empty, &b]).unwrap();
+        assert_eq!(points, [pt(&[0.0, 0.0]), pt(&[1.0, 1.0]), pt(&[2.0, 2.0])]);
+        assert_eq!(sources, [0, 0, 2]);
+
+        fs::write(&b, "2,2,2\n").unwrap();
+        let err = read_points_csv_many(&[&a, &empty, &b]).unwrap_err();
+        assert_eq!(
+            message(err),
+            format!(
+                "dimension mismatch: '{}' has 3 coordinates per row, '{}' has 2",
+                b, a
+            )
+        );
+        let err = read_points_csv_many(&[&empty]).unwrap_err();
+        assert_eq!(message(err), "no points found in any of the 1 input files");
+
+        fs::write(&a, "x,y\n0,0\n").unwrap();
+        fs::write(&b, "x,z\n2,2\n").unwrap();
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let err = read_csv_many(&[&a, &b], &CsvOptions::new(format)).unwrap_err();
+        assert_eq!(
+            message(err),
+            format!("'{}' has columns x,z, but '{}' has x,y", b, a)
+        );
+    }
+
+    #[test]
+    fn verbatim_lines_keep_quotes_and_line_breaks() {
+        let text = "\u{feff}x;y\r\n1,5;\"2\"\r\n\r\n\"3\n\";4\n";
+        let format =
+            CsvFormat::new(Header::Auto, Delimiter::Auto).with_numbers(NumberFormat::DECIMAL_COMMA);
+        let options = CsvOptions::new(format).keeping(KeepRows::Lines);
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[1.5, 2.0]), pt(&[3.0, 4.0])]);
+        assert_eq!(data.line_numbers, [2, 4]);
+        let lines = data.lines.unwrap();
+        assert_eq!(lines.header.as_deref(), Some("x;y"));
+        assert_eq!(lines.lines, ["1,5;\"2\"", "\"3\n\";4"]);
+    }
+
+    #[test]
+    fn clustered_csv_round_trips_through_memory() {
+        let points = vec![pt(&[0.5, -1.0]), pt(&[2.0, 1e-9]), pt(&[0.1, 0.2])];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(1)),
+            (&points[2], Class::Unclassified),
+        ]);
+        let schema = Schema::new(vec!["a".to_string(), "b".to_string()]).unwrap();
+        let mut out = Vec::new();
+        write_clustered_csv_to(
+            &mut out,
+            Some(&schema),
+            ',',
+            FloatFormat::Shortest,
+            &points,
+            &classes,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "cid,a,b\n1,0.5,-1\n-1,2,0.000000001\n-2,0.1,0.2\n"
+        );
+
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let (read_schema, rows) = read_clustered_csv_from(out.as_slice(), format).unwrap();
+        assert_eq!(read_schema, Some(schema));
+        assert_eq!(
+            rows,
+            [
+                (1, vec![0.5, -1.0]),
+                (-1, vec![2.0, 1e-9]),
+                (-2, vec![0.1, 0.2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn gzipped_files_read_and_write_like_plain_ones() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let gzip = |path: &str, text: &str| {
+            let f = fs::File::create(path).unwrap();
+            let mut w = GzEncoder::new(f, flate2::Compression::default());
+            w.write_all(text.as_bytes()).unwrap();
+            w.finish().unwrap();
+        };
+        let text = "1,2\n\n-0.5,1e3\n3,4\n";
+        fs::write(file("points.csv"), text).unwrap();
+        gzip(&file("points.csv.gz"), text);
+        let points = read_points_csv(&file("points.csv")).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(read_points_csv(&file("points.csv.gz")).unwrap(), points);
+
+        // Concatenated gzip members read as one file.
+        gzip(&file("first.gz"), "1,2\n");
+        gzip(&file("second.gz"), "-0.5,1e3\n3,4\n");
+        let mut both = fs::read(file("first.gz")).unwrap();
+        both.extend(fs::read(file("second.gz")).unwrap());
+        fs::write(file("members.csv.gz"), both).unwrap();
+        assert_eq!(read_points_csv(&file("members.csv.gz")).unwrap(), points);
+
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(0)),
+            (&points[2], Class::Classified(0)),
+        ]);
+        for name in ["out.csv", "out.csv.gz"] {
+            write_clustered_csv(&file(name), &points, &classes).unwrap();
+        }
+        let plain = fs::read(file("out.csv")).unwrap();
+        assert_ne!(fs::read(file("out.csv.gz")).unwrap(), plain);
+        let mut unzipped = String::new();
+        MultiGzDecoder::new(fs::File::open(file("out.csv.gz")).unwrap())
+            .read_to_string(&mut unzipped)
+            .unwrap();
+        assert_eq!(unzipped.as_bytes(), plain);
+        assert_eq!(
+            read_clustered_csv(&file("out.csv.gz")).unwrap(),
+            read_clustered_csv(&file("out.csv")).unwrap()
+        );
+
+        // A plain file named .gz is corrupt gzip data.
+        fs::write(file("plain.csv.gz"), text).unwrap();
+        let err = read_points_csv(&file("plain.csv.gz")).unwrap_err();
+        assert_eq!(
+            message(err),
+            format!(
+                "failed to read line 1: failed to decompress '{}': invalid gzip header",
+                file("plain.csv.gz")
+            )
+        );
+    }
+
+    #[test]
+    fn file_formats_follow_the_extension() {
+        for (path, format) in [
+            ("points.csv", FileFormat::Csv),
+            ("points.txt.gz", FileFormat::Csv),
+            ("-", FileFormat::Csv),
+            ("points.jsonl", FileFormat::JsonLines),
+            ("points.ndjson.gz", FileFormat::JsonLines),
+            ("points.json", FileFormat::Json),
+            ("places.geojson.gz", FileFormat::GeoJson),
+            ("points.parquet", FileFormat::Parquet),
+            ("points.feather", FileFormat::Arrow),
+            ("points.ipc", FileFormat::Arrow),
+            ("points.jsonl.bak", FileFormat::Csv),
+        ] {
+            assert_eq!(FileFormat::from_path(path), format, "{}", path);
+        }
+    }
+
+    #[test]
+    fn clustered_writer_rejects_other_dimensions() {
+        let mut out = Vec::new();
+        let mut w = ClusteredCsvWriter::new(&mut out, None, '\t', FloatFormat::Fixed(2)).unwrap();
+        w.write_row(0, &pt(&[1.0, 2.0])).unwrap();
+        let err = w.write_row(0, &pt(&[1.0])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dimension mismatch: expected 2 coordinates, got 1"
+        );
+        w.flush().unwrap();
+        drop(w);
+        assert_eq!(String::from_utf8(out).unwrap(), "0\t1.00\t2.00\n");
+    }
+
+    #[test]
+    fn clustered_rows_are_written_as_they_come_and_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let coords = [pt(&[0.5, -1.0]), pt(&[2.0, 1e-9]), pt(&[0.1, 0.2])];
+        let cids = [1, -1, 0];
+
+        let batch = path("batch.csv");
+        let classes = HashMap::from([
+            (&coords[0], Class::Classified(1)),
+            (&coords[1], Class::Noise),
+            (&coords[2], Class::Classified(0)),
+        ]);
+        write_clustered_csv(&batch, &coords, &classes).unwrap();
+        let streamed = path("streamed.csv");
+        let mut w =
+            ClusteredCsvWriter::create(&streamed, None, ',', FloatFormat::Shortest).unwrap();
+        for (p, cid) in coords.iter().zip(cids) {
+            w.write_row(cid, p).unwrap();
+            w.flush().unwrap();
+        }
+        w.finish().unwrap();
+        let written = fs::read_to_string(&batch).unwrap();
+        assert_eq!(fs::read_to_string(&streamed).unwrap(), written);
+
+        // Appending to the file, to a file without a final line break, to a
+        // gzip file and to a file that does not exist yet.
+        let cut = path("cut.csv");
+        fs::write(&cut, written.trim_end()).unwrap();
+        let gz = path("streamed.csv.gz");
+        let mut w = ClusteredCsvWriter::create(&gz, None, ',', FloatFormat::Shortest).unwrap();
+        w.write_row(1, &coords[0]).unwrap();
+        w.finish().unwrap();
+        for (file, before) in [(&streamed, 3), (&cut, 3), (&gz, 1), (&path("new.csv"), 0)] {
+            let mut w = ClusteredCsvWriter::append(file, ',', FloatFormat::Shortest).unwrap();
+            w.write_row(2, &pt(&[7.0, 8.0])).unwrap();
+            w.finish().unwrap();
+            let (read, classes) = read_clustered_points(file).unwrap();
+            assert_eq!(read.len(), before + 1, "{}", file);
+            assert_eq!(read[..before], coords[..before], "{}", file);
+            assert_eq!(read[before], pt(&[7.0, 8.0]), "{}", file);
+            assert_eq!(classes[before], Class::Classified(2), "{}", file);
+        }
+
+        let mut w = ClusteredCsvWriter::append(&batch, ',', FloatFormat::Shortest).unwrap();
+        let err = w.write_row(0, &pt(&[1.0, 2.0, 3.0])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dimension mismatch: expected 2 coordinates, got 3"
+        );
+        let ids = path("ids.csv");
+        fs::write(&ids, "0\n1\n").unwrap();
+        let err = ClusteredCsvWriter::append(&ids, ',', FloatFormat::Shortest)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "'{}': the last line has 1 field(s); expected cid,x1,...",
+                ids
+            )
+        );
+        let err = ClusteredCsvWriter::append("-", ',', FloatFormat::Shortest)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "cannot append to stdout");
+    }
+
+    #[test]
+    fn non_finite_values_are_rejected_skipped_or_kept() {
+        let text = "0,1\nnan,2\n3,inf\n4,-Infinity\n5,6\n";
+        let format = CsvFormat::new(Header::Absent, Delimiter::COMMA);
+        let with = |invalid| {
+            read_csv(
+                text.as_bytes(),
+                &CsvOptions::new(format).with_invalid(invalid),
+            )
+        };
+
+        let err = with(InvalidRows::Reject).unwrap_err();
+        assert_eq!(
+            message(err),
+            "invalid value at line 2: column 0: coordinate is NaN in point (NaN, 2)"
+        );
+        assert_eq!(
+            with(InvalidRows::Skip).unwrap().points,
+            [pt(&[0.0, 1.0]), pt(&[5.0, 6.0])]
+        );
+        let kept = with(InvalidRows::Allow).unwrap().points;
+        assert_eq!(kept.len(), 5);
+        assert!(kept[1][0].is_nan());
+        assert_eq!(kept[2][1].0, f64::INFINITY);
+        assert_eq!(kept[3][1].0, f64::NEG_INFINITY);
+
+        // A number format that rejects them wins over `Allow`.
+        let strict = CsvFormat {
+            numbers: NumberFormat::STANDARD.reject_non_finite(true),
+            ..format
+        };
+        let options = CsvOptions::new(strict).with_invalid(InvalidRows::Allow);
+        let err = read_csv(text.as_bytes(), &options).unwrap_err();
+        assert!(message(err).contains("'nan' is not a finite number"));
+    }
+
+    #[test]
+    fn datasets_are_read_with_their_column_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "temperature,pressure,flow\n20,1,0.5\n21,1.5,0.25\n").unwrap();
+        let dataset = read_dataset_csv(path).unwrap();
+        assert_eq!(
+            dataset.schema().names(),
+            ["temperature", "pressure", "flow"]
+        );
+        assert_eq!(dataset.column("flow").unwrap(), [0.5, 0.25]);
+        assert_eq!(
+            dataset.points(),
+            [pt(&[20.0, 1.0, 0.5]), pt(&[21.0, 1.5, 0.25])]
+        );
+
+        std::fs::write(path, "temperature,pressure\n20,1\n21,nan\n").unwrap();
+        let err = read_dataset_csv(path).unwrap_err();
+        assert_eq!(
+            message(err),
+            "NaN in column 'pressure', row 1 (point (21, NaN))"
+        );
+    }
+
+    #[test]
+    fn missing_cells_are_read_as_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1,,3\nNA, 5 ,?\n7,8,9\n").unwrap();
+        assert_eq!(
+            read_points_csv_missing(path, &["NA", "?"]).unwrap(),
+            [
+                vec![Some(1.0), None, Some(3.0)],
+                vec![None, Some(5.0), None],
+                vec![Some(7.0), Some(8.0), Some(9.0)],
+            ]
+        );
+        // Without the sentinels, "NA" is not a number.
+        let err = read_points_csv_missing(path, &[]).unwrap_err();
+        assert!(message(err).starts_with("parse error at line 2"));
+
+        std::fs::write(path, "1,2\n3,inf\n").unwrap();
+        let err = read_points_csv_missing(path, &[]).unwrap_err();
+        assert_eq!(message(err), "invalid value at line 2: 'inf'");
+        std::fs::write(path, "1,2\n3\n").unwrap();
+        let err = read_points_csv_missing(path, &[]).unwrap_err();
+        assert_eq!(
+            message(err),
+            "dimension mismatch at line 2: expected 2, got 1"
+        );
+    }
+
+    #[test]
+    fn sparse_points_are_read_from_index_value_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sparse.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "3:0.5 0:-1\n\n7:2\n1:0\n").unwrap();
+        let points = read_sparse_points(path, None).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].dim(), 8);
+        assert_eq!(points[0].indices(), [0, 3]);
+        assert_eq!(points[0].values(), [-1.0, 0.5]);
+        assert_eq!(
+            points[1].to_dense(),
+            pt(&[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0])
+        );
+        assert_eq!(points[2].to_dense(), pt(&[0.0; 8]));
+        assert_eq!(read_sparse_points(path, Some(100)).unwrap()[0].dim(), 100);
+
+        let err = read_sparse_points(path, Some(5)).unwrap_err();
+        assert_eq!(
+            message(err),
+            "line 3: index 7 is out of range for dimension 5"
+        );
+        std::fs::write(path, "1:2 1:3\n").unwrap();
+        let err = read_sparse_points(path, None).unwrap_err();
+        assert_eq!(message(err), "line 1: duplicate index 1");
+        std::fs::write(path, "1:2 x\n").unwrap();
+        let err = read_sparse_points(path, None).unwrap_err();
+        assert_eq!(
+            message(err),
+            "line 1: expected index:value pairs with finite values"
+        );
+    }
+
+    #[test]
+    fn f32_points_are_rounded_and_checked_for_overflow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("single.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1,0.1\n-2.5,1e30\n").unwrap();
+        let points = read_points_csv_f32(path).unwrap();
+        assert_eq!(
+            points,
+            [
+                vec![OrderedFloat(1.0f32), OrderedFloat(0.1f32)],
+                vec![OrderedFloat(-2.5f32), OrderedFloat(1e30f32)],
+            ]
+        );
+
+        std::fs::write(path, "1,2\n3,1e39\n").unwrap();
+        let err = read_points_csv_f32(path).unwrap_err();
+        assert_eq!(
+            message(err),
+            "point 1 has a coordinate out of f32 range: (3, inf)"
+        );
+        std::fs::write(path, "1,2\n3\n").unwrap();
+        let err = read_points_csv_f32(path).unwrap_err();
+        assert_eq!(
+            message(err),
+            "dimension mismatch at line 2: expected 2, got 1"
+        );
+    }
+
+    #[test]
+    fn mixed_points_need_integer_codes() {
+        use crate::types::GowerColumn;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixed.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1,0,2\n3,1,2\n5,0,4\n").unwrap();
+        let (points, metric) = read_points_csv_mixed(path, &[1]).unwrap();
+        assert_eq!(points[2], pt(&[5.0, 0.0, 4.0]));
+        assert_eq!(
+            metric.columns(),
+            [
+                GowerColumn::Numeric { range: 4.0 },
+                GowerColumn::Categorical,
+                GowerColumn::Numeric { range: 2.0 },
+            ]
+        );
+
+        std::fs::write(path, "1,0,2\n3,0.5,2\n").unwrap();
+        let err = read_points_csv_mixed(path, &[1]).unwrap_err();
+        assert_eq!(
+            message(err),
+            "point 1: categorical column 1 holds 0.5, which is not an integer code"
+        );
+        let err = read_points_csv_mixed(path, &[3]).unwrap_err();
+        assert_eq!(
+            message(err),
+            "categorical column 3 is out of range for dimension 3"
+        );
+    }
+
+    #[test]
+    fn weights_are_read_from_their_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weighted.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1,2,3\n4,0.5,6\n").unwrap();
+        let points = read_weighted_points_csv(path, 1).unwrap();
+        assert_eq!(points[0].coords, pt(&[1.0, 3.0]));
+        assert_eq!(points[0].weight, 2.0);
+        assert_eq!(points[1].coords, pt(&[4.0, 6.0]));
+        assert_eq!(points[1].weight, 0.5);
+
+        let err = read_weighted_points_csv(path, 3).unwrap_err();
+        assert_eq!(
+            message(err),
+            "weight column 3 is out of range for 3 columns"
+        );
+        std::fs::write(path, "1,2,3\n4,0,6\n").unwrap();
+        let err = read_weighted_points_csv(path, 1).unwrap_err();
+        assert_eq!(
+            message(err),
+            "point 1: weight must be finite and positive, got 0"
+        );
+        std::fs::write(path, "1,2,3\n4,-1,6\n").unwrap();
+        assert!(read_weighted_points_csv(path, 1).is_err());
+    }
+
+    #[test]
+    fn weight_columns_are_split_off_and_written_back() {
+        let text = "w,x,y,z\n2,0,1,5\n,0.5,3,6\n0.25,1,1.5,7\n";
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let read_with = |weight: ColumnRef, columns: Option<&[&str]>| {
+            let options = CsvOptions::new(format)
+                .with_weight(Some(weight), MissingWeight::Unit)
+                .with_columns(columns.map(|c| Columns::parse(c).unwrap()));
+            read_csv(text.as_bytes(), &options)
+        };
+
+        // The weight first, then in the middle of the selected columns.
+        let data = read_with(ColumnRef::Index(0), None).unwrap();
+        assert_eq!(data.points[0], pt(&[0.0, 1.0, 5.0]));
+        assert_eq!(data.schema.unwrap().names(), ["x", "y", "z"]);
+        let weights = data.weights.unwrap();
+        assert_eq!(weights.name.as_deref(), Some("w"));
+        assert_eq!(weights.values, [2.0, 1.0, 0.25]);
+        let data = read_with(ColumnRef::Name("y".to_string()), Some(&["x", "z"])).unwrap();
+        assert_eq!(
+            data.points,
+            [pt(&[0.0, 5.0]), pt(&[0.5, 6.0]), pt(&[1.0, 7.0])]
+        );
+        assert_eq!(data.weights.as_ref().unwrap().values, [1.0, 3.0, 1.5]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        let classes = HashMap::from([
+            (&data.points[0], Class::Classified(0)),
+            (&data.points[2], Class::Classified(0)),
+        ]);
+        write_clustered_csv_weighted(
+            path,
+            data.schema.as_ref(),
+            ',',
+            FloatFormat::Shortest,
+            &data.points,
+            data.weights.as_ref().unwrap(),
+            &classes,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "cid,y,x,z\n0,1,0,5\n-1,3,0.5,6\n0,1.5,1,7\n"
+        );
+
+        let options =
+            CsvOptions::new(format).with_weight(Some(ColumnRef::Index(0)), MissingWeight::Reject);
+        let err = read_csv(text.as_bytes(), &options).unwrap_err();
+        assert_eq!(message(err), "line 3: weight column 0 is empty or missing");
+        let err = read_csv("w,x\n-1,0\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            message(err),
+            "line 2: weight must be finite and positive, got -1"
+        );
+    }
+
+    #[test]
+    fn bit_points_are_read_from_zero_one_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bits.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "1,0,1\n0,0,0\n").unwrap();
+        let points = read_bit_points_csv(path).unwrap();
+        assert_eq!(points[0].to_bits(), [true, false, true]);
+        assert_eq!(points[1].to_bits(), [false; 3]);
+
+        std::fs::write(path, "1,0,1\n0,2,0\n").unwrap();
+        let err = read_bit_points_csv(path).unwrap_err();
+        assert_eq!(message(err), "point 1: coordinate 1 is 2, expected 0 or 1");
+    }
+
+    #[test]
+    fn libsvm_rows_keep_their_labels_and_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.svm");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "# header comment\n\
+             +1 1:0.5 4:-2\n\
+             -1 qid:3 3:1e-3   2:7 # trailing comment\n\
+             \n\
+             5:1\n\
+             2.5\n",
+        )
+        .unwrap();
+        let (points, labels) = read_points_libsvm(path).unwrap();
+        assert_eq!(labels, [Some(1.0), Some(-1.0), None, Some(2.5)]);
+        assert!(points.iter().all(|p| p.dim() == 5));
+        // Indices are 0-based and sorted.
+        assert_eq!(points[1].indices(), [1, 2]);
+        assert_eq!(points[1].values(), [7.0, 1e-3]);
+        let dense = points.iter().map(SparsePoint::to_dense).collect::<Vec<_>>();
+        assert_eq!(
+            dense,
+            [
+                pt(&[0.5, 0.0, 0.0, -2.0, 0.0]),
+                pt(&[0.0, 7.0, 1e-3, 0.0, 0.0]),
+                pt(&[0.0, 0.0, 0.0, 0.0, 1.0]),
+                pt(&[0.0; 5]),
+            ]
+        );
+
+        let message = |text: &str| {
+            std::fs::write(path, text).unwrap();
+            message(read_points_libsvm(path).unwrap_err())
+        };
+        assert_eq!(
+            message("1 1:2\nyes 1:2\n"),
+            "line 2: invalid label 'yes': invalid float literal"
+        );
+        let pairs = "expected index:value pairs with 1-based indices and finite values";
+        assert_eq!(message("1 0:2\n"), format!("line 1: {}", pairs));
+        assert_eq!(message("1 1:inf\n"), format!("line 1: {}", pairs));
+        assert_eq!(message("1 1:2 2\n"), format!("line 1: {}", pairs));
+        assert_eq!(message("1 2:1 2:3\n"), "line 1: duplicate index 2");
+        assert_eq!(message("# only a comment\n"), "no points found in input");
+    }
+
+    #[test]
+    fn wkt_points_are_parsed_tolerantly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.wkt");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "POINT (1 2)\n  point(  -0.5   3e2 )  \nSRID=4326;Point M (5 6 7)\n",
+        )
+        .unwrap();
+        let points = read_points_wkt(path, false).unwrap();
+        assert_eq!(
+            points,
+            [pt(&[1.0, 2.0]), pt(&[-0.5, 300.0]), pt(&[5.0, 6.0])]
+        );
+
+        std::fs::write(
+            path,
+            "POINT Z (1 2 3)\nPOINT EMPTY\nPOINT ZM (4 5 6 7)\nPOINT (7 8 9 10)\n",
+        )
+        .unwrap();
+        let points = read_points_wkt(path, true).unwrap();
+        assert_eq!(
+            points,
+            [
+                pt(&[1.0, 2.0, 3.0]),
+                pt(&[4.0, 5.0, 6.0]),
+                pt(&[7.0, 8.0, 9.0])
+            ]
+        );
+        let err = read_points_wkt(path, false).unwrap_err();
+        assert_eq!(message(err), "line 2: POINT EMPTY");
+
+        let message = |text: &str| {
+            std::fs::write(path, text).unwrap();
+            message(read_points_wkt(path, true).unwrap_err())
+        };
+        assert_eq!(
+            message("POINT (1 2)\nLINESTRING (1 2, 3 4)\n"),
+            "line 2: unsupported geometry 'LINESTRING'; only POINT is supported"
+        );
+        assert_eq!(
+            message("POINT (1 2\n"),
+            "line 1: expected '(x y)' or EMPTY after POINT"
+        );
+        assert_eq!(
+            message("POINT (1 x)\n"),
+            "line 1: coordinates must be finite numbers"
+        );
+        assert_eq!(
+            message("POINT Z (1 2)\n"),
+            "line 1: expected 3 coordinates, got 2"
+        );
+        assert_eq!(
+            message("POINT (1)\n"),
+            "line 1: expected 2 coordinates, got 1"
+        );
+        assert_eq!(
+            message("POINT (1 2)\nPOINT Z (1 2 3)\n"),
+            "dimension mismatch at line 2: expected 2, got 3"
+        );
+        assert_eq!(message("POINT EMPTY\n"), "no points found in input");
+    }
+
+    #[test]
+    fn clustered_wkt_lines_start_with_the_cluster_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.wkt");
+        let path = path.to_str().unwrap();
+        let points = [pt(&[1.0, 2.0]), pt(&[-0.5, 3.25])];
+        let classes = HashMap::from([(&points[1], Class::Classified(4))]);
+        write_clustered_wkt(path, &points, &classes).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "-1\tPOINT (1 2)\n4\tPOINT (-0.5 3.25)\n"
+        );
+
+        let points = [pt(&[1.0, 2.0, 3.0])];
+        write_clustered_wkt(path, &points, &HashMap::new()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "-1\tPOINT Z (1 2 3)\n"
+        );
+        let err = write_clustered_wkt(path, &[pt(&[1.0])], &HashMap::new()).unwrap_err();
+        assert_eq!(
+            message(err),
+            "point 0 has dimension 1; WKT points need 2 or 3"
+        );
+    }
+
+    #[test]
+    fn streamed_points_match_the_points_read_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.csv");
+        let path = path.to_str().unwrap();
+        let mut rng = crate::sampling::SplitMix64::new(7);
+        let text = (0..20_000)
+            .map(|i| format!("{},{},-{}.5\n", rng.below(1000), i, rng.below(10)))
+            .collect::<String>();
+        std::fs::write(path, text).unwrap();
+        let streamed = stream_points_csv(path)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(streamed.len(), 20_000);
+        assert_eq!(streamed, read_points_csv(path).unwrap());
+
+        // Errors are those of `read_points_csv`, and end the stream.
+        let errors = |text: &str| {
+            std::fs::write(path, text).unwrap();
+            let streamed = stream_points_csv(path).unwrap().collect::<Vec<_>>();
+            let read = message(read_points_csv(path).unwrap_err());
+            (streamed, read)
+        };
+        let (streamed, read) = errors("1,2\n3,4\n5\n6,7\n");
+        assert_eq!(streamed.len(), 3);
+        assert_eq!(streamed[1].as_ref().unwrap(), &pt(&[3.0, 4.0]));
+        let err = streamed.into_iter().nth(2).unwrap().unwrap_err();
+        assert_eq!(message(err), read);
+        let (streamed, read) = errors("\n\n");
+        assert_eq!(streamed.len(), 1);
+        let err = streamed.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(message(err), read);
+        assert_eq!(read, "no points found in input");
+    }
+
+    #[test]
+    fn columns_are_selected_by_index_range_and_name() {
+        let text = "id,name,x,y,z\n7,a,1,2,3\n8,b,4,5,6\n";
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let select = |items: &[&str]| {
+            let options = CsvOptions::new(format).with_columns(Some(Columns::parse(items)?));
+            read_csv(text.as_bytes(), &options)
+        };
+        let data = select(&["4", "x"]).unwrap();
+        assert_eq!(data.points, [pt(&[3.0, 1.0]), pt(&[6.0, 4.0])]);
+        assert_eq!(data.schema.unwrap().names(), ["z", "x"]);
+        let data = select(&["0", "3..5"]).unwrap();
+        assert_eq!(data.points, [pt(&[7.0, 2.0, 3.0]), pt(&[8.0, 5.0, 6.0])]);
+        let excluded = CsvOptions::new(format)
+            .with_columns(Columns::from_flags(None, &["name", "0"]).unwrap());
+        let data = read_csv(text.as_bytes(), &excluded).unwrap();
+        assert_eq!(data.points, [pt(&[1.0, 2.0, 3.0]), pt(&[4.0, 5.0, 6.0])]);
+
+        let err = |items: &[&str]| message(select(items).unwrap_err());
+        assert_eq!(err(&["x", "w"]), "no column 'w' in header");
+        assert_eq!(err(&["3..3"]), "empty column range '3..3'");
+        assert_eq!(err(&["2..x"]), "invalid column range '2..x'");
+        assert_eq!(err(&[" "]), "empty column name");
+        assert!(err(&["name"]).contains("line 2"), "{}", err(&["name"]));
+
+        // Without a header, names cannot be looked up, and a short row is
+        // reported with its own width.
+        let options = CsvOptions::default().with_columns(Some(Columns::parse(&["x"]).unwrap()));
+        let err = read_csv("1,2\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(message(err), "selecting column 'x' by name needs a header");
+        let options =
+            CsvOptions::default().with_columns(Some(Columns::parse(&["0", "2"]).unwrap()));
+        let err = read_csv("1,2,3\n4,5\n".as_bytes(), &options).unwrap_err();
+        assert_eq!(
+            message(err),
+            "line 2: column 2 is out of range; the row has 2 columns"
+        );
+    }
+
+    #[test]
+    fn excluded_columns_are_checked_against_the_header_and_rows() {
+        let text = "id,x,leak,y\n1,0.5,9,2\n2,1.5,8,3\n";
+        let named = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let read = |format: CsvFormat, text: &str, include: Option<&[&str]>, exclude: &[&str]| {
+            let options =
+                CsvOptions::new(format).with_columns(Columns::from_flags(include, exclude)?);
+            read_csv(text.as_bytes(), &options)
+        };
+        let data = read(named, text, None, &["leak", "id"]).unwrap();
+        assert_eq!(data.points, [pt(&[0.5, 2.0]), pt(&[1.5, 3.0])]);
+        assert_eq!(data.schema.unwrap().names(), ["x", "y"]);
+        // Without a header, by index, with the same result.
+        let body = text.split_once('\n').unwrap().1;
+        let data = read(CsvFormat::default(), body, None, &["2", "0"]).unwrap();
+        assert_eq!(data.points, [pt(&[0.5, 2.0]), pt(&[1.5, 3.0])]);
+        assert_eq!(Columns::from_flags::<&str>(None, &[]).unwrap(), None);
+
+        let err = |format, text, include, exclude| {
+            message(read(format, text, include, exclude).unwrap_err())
+        };
+        assert_eq!(err(named, text, None, &["z"]), "no column 'z' in header");
+        assert_eq!(
+            err(CsvFormat::default(), body, None, &["leak"]),
+            "selecting column 'leak' by name needs a header"
+        );
+        assert_eq!(
+            err(named, text, Some(&["x", "leak"]), &["2"]),
+            "column 'leak' is both selected and excluded"
+        );
+        assert_eq!(
+            err(CsvFormat::default(), body, Some(&["1..3"]), &["2"]),
+            "column 2 is both selected and excluded"
+        );
+        assert_eq!(
+            err(named, text, None, &["4", "7"]),
+            "excluded column 4 is out of range; rows have 4 columns"
+        );
+        assert_eq!(
+            err(named, text, None, &["0..4"]),
+            "every column is excluded"
+        );
+    }
+
+    #[test]
+    fn passthrough_rows_keep_their_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        let text = "id,when,x,y\n007,2024-01-02T03:04,1.50,2e0\n0042,\"noon, sharp\",3,4.0\n";
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let options = CsvOptions::new(format)
+            .with_columns(Some(Columns::parse(&["2..4"]).unwrap()))
+            .keeping(KeepRows::Fields);
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[1.5, 2.0]), pt(&[3.0, 4.0])]);
+        let raw = data.raw.unwrap();
+        let classes = HashMap::from([(&data.points[1], Class::Classified(3))]);
+
+        // The coordinates are in the rows, as written in the input.
+        let write = |append| {
+            let schema = data.schema.as_ref();
+            write_clustered_csv_passthrough(
+                path,
+                &raw,
+                schema,
+                ',',
+                &data.points,
+                &classes,
+                append,
+            )
+            .unwrap();
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(
+            write(None),
+            "cid,id,when,x,y\n-1,007,2024-01-02T03:04,1.50,2e0\n3,0042,\"noon, sharp\",3,4.0\n"
+        );
+        assert_eq!(
+            write(Some(FloatFormat::Fixed(2))),
+            "cid,id,when,x,y,x,y\n\
+             -1,007,2024-01-02T03:04,1.50,2e0,1.50,2.00\n\
+             3,0042,\"noon, sharp\",3,4.0,3.00,4.00\n"
+        );
+
+        let err = write_clustered_csv_passthrough(
+            path,
+            &raw,
+            None,
+            ',',
+            &data.points[..1],
+            &classes,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            message(err),
+            "2 rows for 1 points; they must come from the same input"
+        );
+    }
+
+    #[test]
+    fn label_columns_are_split_off_and_written_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        // Numeric labels, with a selection that also lists the label column.
+        let options = CsvOptions::default()
+            .with_label(Some(ColumnRef::Index(1)))
+            .with_columns(Some(Columns::parse(&["0..3"]).unwrap()));
+        let data = read_csv("0.5,1,2,9\n1.5,-1,3,9\n".as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[0.5, 2.0]), pt(&[1.5, 3.0])]);
+        let labels = data.labels.unwrap();
+        assert_eq!(labels.name, None);
+        assert_eq!(labels.values, ["1", "-1"]);
+        let classes = HashMap::from([(&data.points[0], Class::Classified(0))]);
+        let floats = FloatFormat::Shortest;
+        write_clustered_csv_labeled(path, None, ',', floats, &data.points, &labels, &classes)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "0,1,0.5,2\n-1,-1,1.5,3\n"
+        );
+
+        // Text labels by name, in a header-named output.
+        let format = CsvFormat::new(Header::Present, Delimiter::SEMICOLON);
+        let options = CsvOptions::new(format)
+            .with_label(Some(ColumnRef::Name("species".to_string())))
+            .with_columns(Some(Columns::parse(&["y", "species"]).unwrap()));
+        let text = "x;species;y\n1;setosa;2\n3;\"iris virginica\";4\n";
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        assert_eq!(data.points, [pt(&[2.0]), pt(&[4.0])]);
+        let labels = data.labels.unwrap();
+        assert_eq!(labels.values, ["setosa", "iris virginica"]);
+        let schema = data.schema.as_ref();
+        write_clustered_csv_labeled(path, schema, ';', floats, &data.points, &labels, &classes)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "cid;species;y\n-1;setosa;2\n-1;iris virginica;4\n"
+        );
+    }
+
+    #[test]
+    fn label_lines_handle_a_bom_and_line_endings() {
+        let labels = read_label_lines(&b"\xEF\xBB\xBFa\r\nb c\rc \n\n\n"[..]).unwrap();
+        assert_eq!(labels.name, None);
+        assert_eq!(labels.values, ["a", "b c", "c"]);
+        let err = read_label_lines(&b"a\r\n\r\nb\r\n"[..]).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: empty label");
+    }
+
+    #[test]
+    fn float_formats_print_small_and_large_magnitudes() {
+        let points = [pt(&[0.1 + 0.2, 1e-7, -2.5e-5, 2.0, 1.5e22])];
+        let classes = HashMap::from([(&points[0], Class::Classified(0))]);
+        let print = |floats| {
+            let mut out = Vec::new();
+            write_clustered_csv_to(&mut out, None, ',', floats, &points, &classes).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let huge = format!("15{}", "0".repeat(21));
+        assert_eq!(
+            print(FloatFormat::Shortest),
+            format!("0,0.30000000000000004,0.0000001,-0.000025,2,{}\n", huge)
+        );
+        assert_eq!(
+            print(FloatFormat::Fixed(3)),
+            format!("0,0.300,0.000,-0.000,2.000,{}.000\n", huge)
+        );
+        assert_eq!(
+            print(FloatFormat::RoundTrip),
+            "0,0.30000000000000004,1e-7,-2.5e-5,2.0,1.5e22\n"
+        );
+        // Both shortest forms read back exactly.
+        for floats in [FloatFormat::Shortest, FloatFormat::RoundTrip] {
+            let (_, rows) =
+                read_clustered_csv_from(print(floats).as_bytes(), CsvFormat::default()).unwrap();
+            assert_eq!(pt(&rows[0].1), points[0]);
+        }
+
+        assert_eq!("shortest".parse(), Ok(FloatFormat::Shortest));
+        assert_eq!("roundtrip".parse(), Ok(FloatFormat::RoundTrip));
+        assert_eq!("6".parse(), Ok(FloatFormat::Fixed(6)));
+        let err = "-1".parse::<FloatFormat>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid float format '-1': expected 'shortest', 'roundtrip' or a number of decimal places"
+        );
+    }
+
+    #[test]
+    fn quoted_fields_are_unquoted_and_bad_quoting_is_located() {
+        let (points, _, _) =
+            read("\"1\",\" 2.5 \"\n\n\"-3\",\"4e1\"\n", CsvFormat::default()).unwrap();
+        assert_eq!(points, [pt(&[1.0, 2.5]), pt(&[-3.0, 40.0])]);
+
+        // A quote in the middle of a field is kept, so the field is no number.
+        let err = read("1,2\n3,4\n5,6\"x\"\n", CsvFormat::default()).unwrap_err();
+        assert_eq!(
+            message(err),
+            "parse error at line 3, column 1: invalid float literal"
+        );
+        // A quote left open runs to the end of the input, and a closed one
+        // may span lines; either way the row's first line is reported.
+        for (text, line, column) in [
+            ("1,2\n\"3,4\n5,6\n", 2, 0),
+            ("\"1 2\n", 1, 0),
+            ("1,2\n\n\"3\n\",x\n", 3, 1),
+        ] {
+            let err = read(text, CsvFormat::default()).unwrap_err();
+            assert_eq!(
+                message(err),
+                format!(
+                    "parse error at line {}, column {}: invalid float literal",
+                    line, column
+                ),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn passthrough_fields_are_quoted_again() {
+        let text = "id,note,x\n1,\"a, b\",0.5\n2,\"say \"\"hi\"\"\",1.5\n";
+        let format = CsvFormat::new(Header::Present, Delimiter::COMMA);
+        let options = CsvOptions::new(format)
+            .with_columns(Some(Columns::parse(&["x"]).unwrap()))
+            .keeping(KeepRows::Fields);
+        let data = read_csv(text.as_bytes(), &options).unwrap();
+        let raw = data.raw.unwrap();
+        assert_eq!(raw.rows[1], ["2", "say \"hi\"", "1.5"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        let classes = HashMap::new();
+        write_clustered_csv_passthrough(path, &raw, None, ',', &data.points, &classes, None)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "cid,id,note,x\n-1,1,\"a, b\",0.5\n-1,2,\"say \"\"hi\"\"\",1.5\n"
+        );
+    }
+
+    #[test]
+    fn bom_and_any_line_endings_read_alike() {
+        let format = CsvFormat::new(Header::Auto, Delimiter::COMMA);
+        let expected = [pt(&[1.0, 2.0]), pt(&[3.0, 4.0]), pt(&[5.0, 6.0])];
+        for text in [
+            &b"\xEF\xBB\xBFx,y\r\n1,2\r\n3,4\r\n5,6\r\n"[..],
+            b"x,y\r1,2\r3,4\r5,6\r",
+            b"\xEF\xBB\xBFx,y\n1,2\r\n3,4\r\r\n5,6",
+        ] {
+            // One byte at a time, too, so that a BOM or a `\r\n` is split
+            // between reads.
+            for capacity in [1, 1 << 13] {
+                let reader = BufReader::with_capacity(capacity, text);
+                let data = read_csv(reader, &CsvOptions::new(format)).unwrap();
+                assert_eq!(data.points, expected, "{:?}", text);
+                assert_eq!(data.schema.unwrap().names(), ["x", "y"]);
+            }
+        }
+
+        let (_, rows) =
+            read_clustered_csv_from(&b"\xEF\xBB\xBF0,1.5\r\n-1,2\r"[..], CsvFormat::default())
+                .unwrap();
+        assert_eq!(rows, [(0, vec![1.5]), (-1, vec![2.0])]);
+
+        // The CSV reader would skip a BOM itself; the line-based readers
+        // rely on `TextInput` alone.
+        let mut text = String::new();
+        TextInput::new(BufReader::with_capacity(1, &b"\xEF\xBB\xBFa\r\nb\rc"[..]))
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "a\nb\nc\n");
+
+        // A header and no rows.
+        let err = read("x,y\r\n", format).unwrap_err();
+        assert_eq!(message(err), "no points found in input");
+    }
+
+    #[test]
+    fn binary_points_round_trip_and_reject_bad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(file("points.csv"), "0.1,-2\n1e-300,3.5\n7,1e300\n").unwrap();
+        let points = read_points_csv(&file("points.csv")).unwrap();
+        for name in ["points.bin", "points.bin.gz"] {
+            write_points_bin(&file(name), &points).unwrap();
+            assert_eq!(read_points_bin(&file(name)).unwrap(), points, "{}", name);
+        }
+        let bin = std::fs::read(file("points.bin")).unwrap();
+        assert_eq!(bin.len(), BIN_HEADER_LEN + 6 * 8);
+
+        let read_bad = |bytes: &[u8]| {
+            std::fs::write(file("bad.bin"), bytes).unwrap();
+            message(read_points_bin(&file("bad.bin")).unwrap_err())
+        };
+        let path = file("bad.bin");
+        let mut newer = bin.clone();
+        newer[4] = 2;
+        assert_eq!(
+            read_bad(&newer),
+            format!(
+                "'{}' has binary point format version 2; only version 1 is supported",
+                path
+            )
+        );
+        assert_eq!(
+            read_bad(&bin[..bin.len() - 3]),
+            format!(
+                "'{}' is truncated: expected 3 points of dimension 2, found 5 coordinates",
+                path
+            )
+        );
+        assert_eq!(
+            read_bad(&bin[..10]),
+            format!(
+                "'{}' is truncated: no complete header: failed to fill whole buffer",
+                path
+            )
+        );
+        assert_eq!(
+            read_bad(b"0.1,-2\n1e-300,3.5\n7,1e300\n"),
+            format!("'{}' is not a binary point file", path)
+        );
+        let err = write_points_bin(&path, &[pt(&[1.0]), pt(&[1.0, 2.0])]).unwrap_err();
+        assert_eq!(
+            message(err),
+            "dimension mismatch at point 1: expected 1, got 2"
+        );
+        write_points_bin(&path, &[]).unwrap();
+        let err = read_points_bin(&path).unwrap_err();
+        assert_eq!(message(err), "no points found in input");
+    }
+
+    #[test]
+    fn sampled_points_come_with_their_line_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.csv");
+        let path = path.to_str().unwrap();
+        let text = (0..200).map(|i| format!("{},0\n\n", i)).collect::<String>();
+        std::fs::write(path, text).unwrap();
+
+        let sample = sample_points_csv(path, 20, 5).unwrap();
+        assert_eq!(sample.len(), 20);
+        for (line, p) in &sample {
+            assert_eq!(*line, 2 * p[0].0 as usize + 1);
+        }
+        assert_eq!(sample_points_csv(path, 20, 5).unwrap(), sample);
+        assert_ne!(sample_points_csv(path, 20, 6).unwrap(), sample);
+        let all = sample_points_csv(path, 500, 5).unwrap();
+        assert_eq!(all.len(), 200);
+        assert_eq!(all[199], (399, pt(&[199.0, 0.0])));
+    }
+
+    #[test]
+    fn clusters_are_split_into_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("split");
+        let out_str = out.to_str().unwrap();
+        let read = |name: &str| std::fs::read_to_string(out.join(name)).unwrap();
+        let names = || {
+            let mut names = std::fs::read_dir(&out)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let points = [
+            pt(&[0.0, 1.0]),
+            pt(&[2.0, 3.5]),
+            pt(&[4.0, 5.0]),
+            pt(&[6.0, 7.0]),
+        ];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(1)),
+            (&points[1], Class::Noise),
+            (&points[2], Class::Classified(1)),
+            (&points[3], Class::Unclassified),
+        ]);
+        write_clusters_split(out_str, &points, &classes, false).unwrap();
+        assert_eq!(names(), ["cluster_001.csv", "noise.csv"]);
+        assert_eq!(read("cluster_001.csv"), "0,1\n4,5\n");
+        assert_eq!(read("noise.csv"), "2,3.5\n6,7\n");
+
+        let err = write_clusters_split(out_str, &points, &classes, false).unwrap_err();
+        assert_eq!(
+            message(err),
+            format!(
+                "output directory '{}' is not empty; refusing to overwrite it",
+                out_str
+            )
+        );
+        // Forcing removes the old cluster files, and only those.
+        std::fs::write(out.join("notes.txt"), "keep").unwrap();
+        write_clusters_split(out_str, &points, &HashMap::new(), true).unwrap();
+        assert_eq!(names(), ["noise.csv", "notes.txt"]);
+        assert_eq!(read("noise.csv").lines().count(), 4);
+
+        // Ids get a fourth digit from cluster 1000 on.
+        let points = (0..=1000).map(|i| pt(&[i as f64])).collect::<Vec<_>>();
+        let classes = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p, Class::Classified(i)))
+            .collect::<HashMap<_, _>>();
+        let schema = Schema::new(vec!["x".to_string()]).unwrap();
+        std::fs::remove_dir_all(&out).unwrap();
+        write_clusters_split_with_format(
+            out_str,
+            Some(&schema),
+            ';',
+            FloatFormat::Fixed(1),
+            &points,
+            &classes,
+            false,
+        )
+        .unwrap();
+        let names = names();
+        assert_eq!(names.len(), 1002);
+        assert_eq!(names[0], "cluster_0000.csv");
+        assert_eq!(names[1000], "cluster_1000.csv");
+        assert_eq!(read("cluster_0999.csv"), "x\n999.0\n");
+        assert_eq!(read("noise.csv"), "x\n");
+        let rows = names
+            .iter()
+            .map(|name| read(name).lines().count() - 1)
+            .sum::<usize>();
+        assert_eq!(rows, points.len());
+    }
+
+    #[test]
+    fn clustered_points_read_back_as_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        let coords = [
+            pt(&[0.1 + 0.2, -1.0]),
+            pt(&[1e-300, 1e300]),
+            pt(&[0.1 + 0.2, -1.0]),
+            pt(&[-0.0, 7.25]),
+        ];
+        let points = crate::types::label_points(coords.to_vec());
+        let classes = HashMap::from([
+            (0, Class::Classified(3)),
+            (1, Class::Unclassified),
+            (2, Class::Noise),
+            (3, Class::Classified(0)),
+        ]);
+        write_labeled_clustered_csv(path, &points, &classes).unwrap();
+        let (read_points, read_classes) = read_clustered_points(path).unwrap();
+        assert_eq!(read_points, coords);
+        assert_eq!(
+            read_classes,
+            [
+                Class::Classified(3),
+                Class::Unclassified,
+                Class::Noise,
+                Class::Classified(0),
+            ]
+        );
+
+        std::fs::write(path, "0,1,2\n-3,1,2\n").unwrap();
+        let err = read_clustered_points(path).unwrap_err();
+        assert!(message(err).starts_with("line 2: invalid cid '-3'"));
+        // The raw reader takes any cid.
+        assert_eq!(read_clustered_csv(path).unwrap()[1], (-3, vec![1.0, 2.0]));
+        std::fs::write(path, "0,1,2\n1,1\n").unwrap();
+        let err = read_clustered_points(path).unwrap_err();
+        assert_eq!(
+            message(err),
+            "dimension mismatch at line 2: expected 2, got 1"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    fn path(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn json_lines_round_trip_with_their_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = path(&dir, "in.jsonl");
+        fs::write(
+            &input,
+            "{\"coords\": [0.1, -2e-9], \"id\": \"a\"}\n\n\
+             {\"coords\": [3, 4], \"tags\": [1, 2], \"cluster\": 9}\n",
+        )
+        .unwrap();
+        let (points, fields) = read_points_jsonl(&input).unwrap();
+        assert_eq!(points, [pt(&[0.1, -2e-9]), pt(&[3.0, 4.0])]);
+        assert_eq!(fields[0]["id"], json!("a"));
+        assert_eq!(fields[1].len(), 2);
+
+        let classes = HashMap::from([(&points[0], Class::Classified(2))]);
+        let output = path(&dir, "out.jsonl.gz");
+        write_clustered_jsonl(&output, &points, &classes, Some(&fields)).unwrap();
+        let (read_back, echoed) = read_points_jsonl(&output).unwrap();
+        assert_eq!(read_back, points);
+        // The old cluster field is replaced, the others follow `cluster`.
+        assert_eq!(echoed[0]["cluster"], json!(2));
+        assert_eq!(echoed[0]["id"], json!("a"));
+        assert_eq!(echoed[1]["cluster"], json!(-1));
+        assert_eq!(echoed[1]["tags"], json!([1, 2]));
+
+        let plain = path(&dir, "plain.jsonl");
+        write_clustered_jsonl(&plain, &points, &classes, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&plain).unwrap(),
+            "{\"cluster\":2,\"coords\":[0.1,-2e-9]}\n{\"cluster\":-1,\"coords\":[3.0,4.0]}\n"
+        );
+        let err = write_clustered_jsonl(&plain, &points, &classes, Some(&fields[..1]));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "expected one set of fields per point: 2 points, 1 sets"
+        );
+    }
+
+    #[test]
+    fn malformed_json_lines_name_the_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = path(&dir, "bad.jsonl");
+        let message = |text: &str| {
+            fs::write(&input, text).unwrap();
+            format!("{:#}", read_points_jsonl(&input).unwrap_err())
+        };
+        let err = message("{\"coords\": [1, 2]}\n{\"coords\": [1, \"x\"]}\n");
+        assert!(
+            err.starts_with("line 2: invalid record '{\"coords\": [1, \"x\"]}': "),
+            "{}",
+            err
+        );
+        let long = format!("{{\"point\": [1, 2], \"name\": \"{}\"}}", "n".repeat(50));
+        let err = message(&long);
+        assert!(
+            err.starts_with(&format!("line 1: invalid record '{}...': ", &long[..40])),
+            "{}",
+            err
+        );
+        assert!(err.contains("missing field `coords`"), "{}", err);
+        assert_eq!(
+            message("{\"coords\": [1, 2]}\n{\"coords\": [1]}\n"),
+            "dimension mismatch at line 2: expected 2, got 1"
+        );
+        assert_eq!(message("\n"), "no points found in input");
+    }
+
+    #[test]
+    fn clustered_json_is_flat_or_grouped_by_ascending_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = path(&dir, "out.json");
+        let points = [
+            pt(&[0.0, 0.0]),
+            pt(&[5.0, 5.0]),
+            pt(&[0.0, 0.5]),
+            pt(&[9.0, -9.0]),
+            pt(&[5.0, 5.5]),
+            pt(&[1.5, 1e-9]),
+        ];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(1)),
+            (&points[1], Class::Classified(0)),
+            (&points[2], Class::Classified(1)),
+            (&points[3], Class::Noise),
+            (&points[4], Class::Classified(0)),
+            (&points[5], Class::Unclassified),
+        ]);
+        let write = |style, pretty| {
+            write_clustered_json(&output, &points, &classes, style, pretty).unwrap();
+            fs::read_to_string(&output).unwrap()
+        };
+
+        assert_eq!(
+            write(JsonStyle::Grouped, false),
+            "{\"clusters\":[{\"id\":0,\"points\":[[5.0,5.0],[5.0,5.5]]},\
+             {\"id\":1,\"points\":[[0.0,0.0],[0.0,0.5]]}],\
+             \"noise\":[[9.0,-9.0],[1.5,1e-9]]}\n"
+        );
+        let grouped = write(JsonStyle::Grouped, true);
+        assert!(grouped.starts_with("{\n  \"clusters\": [\n"), "{}", grouped);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&grouped).unwrap(),
+            json!({
+                "clusters": [
+                    {"id": 0, "points": [[5.0, 5.0], [5.0, 5.5]]},
+                    {"id": 1, "points": [[0.0, 0.0], [0.0, 0.5]]},
+                ],
+                "noise": [[9.0, -9.0], [1.5, 1e-9]],
+            })
+        );
+
+        let flat = [
+            json!({"cluster": 1, "coords": [0.0, 0.0]}),
+            json!({"cluster": 0, "coords": [5.0, 5.0]}),
+            json!({"cluster": 1, "coords": [0.0, 0.5]}),
+            json!({"cluster": -1, "coords": [9.0, -9.0]}),
+            json!({"cluster": 0, "coords": [5.0, 5.5]}),
+            json!({"cluster": -2, "coords": [1.5, 1e-9]}),
+        ];
+        let lines = write(JsonStyle::Flat, false);
+        assert_eq!(
+            lines.lines().next(),
+            Some("{\"cluster\":1,\"coords\":[0.0,0.0]}")
+        );
+        let records = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records, flat);
+        let array = write(JsonStyle::Flat, true);
+        assert!(
+            array.starts_with("[\n  {\n    \"cluster\": 1,"),
+            "{}",
+            array
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&array).unwrap(),
+            json!(flat)
+        );
+    }
+
+    #[test]
+    fn geojson_round_trips_keep_the_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = path(&dir, "in.geojson");
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [139.7, 35.7, 40.0]},
+                    "properties": {"name": "tokyo", "pop": 14, "cluster": 7},
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [135.5, 34.7]},
+                    "properties": {"name": "osaka", "pop": 2.7, "tags": ["port"]},
+                },
+            ],
+        });
+        fs::write(&input, collection.to_string()).unwrap();
+        let (points, props) = read_points_geojson(&input, &[]).unwrap();
+        // The altitude is dropped.
+        assert_eq!(points, [pt(&[139.7, 35.7]), pt(&[135.5, 34.7])]);
+        let (with_pop, _) = read_points_geojson(&input, &["pop".to_string()]).unwrap();
+        assert_eq!(with_pop[1], pt(&[135.5, 34.7, 2.7]));
+
+        let classes = HashMap::from([(&points[1], Class::Classified(0))]);
+        let output = path(&dir, "out.geojson");
+        write_clustered_geojson(&output, &points, &classes, Some(&props)).unwrap();
+        let (read_back, echoed) = read_points_geojson(&output, &[]).unwrap();
+        assert_eq!(read_back, points);
+        assert_eq!(echoed[0]["cluster"], json!(-1));
+        assert_eq!(echoed[0]["name"], json!("tokyo"));
+        assert_eq!(echoed[0]["pop"], json!(14));
+        assert_eq!(echoed[1]["cluster"], json!(0));
+        assert_eq!(echoed[1]["tags"], json!(["port"]));
+
+        write_clustered_geojson(&output, &points, &classes, None).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(
+            written["features"][1],
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [135.5, 34.7]},
+                "properties": {"cluster": 0},
+            })
+        );
+        let err = write_clustered_geojson(&output, &[pt(&[1.0])], &classes, None);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "point 0 has fewer than 2 coordinates"
+        );
+    }
+
+    #[test]
+    fn geojson_features_that_are_not_points_are_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = path(&dir, "bad.geojson");
+        let message = |features: serde_json::Value, properties: &[&str]| {
+            let collection = json!({"type": "FeatureCollection", "features": features});
+            fs::write(&input, collection.to_string()).unwrap();
+            let properties = properties.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            format!(
+                "{:#}",
+                read_points_geojson(&input, &properties).unwrap_err()
+            )
+        };
+        let point = json!({
+            "type": "Feature",
+            "geometry": {"type": "Point", "coordinates": [1, 2]},
+            "properties": {"name": "a"},
+        });
+        let line = json!({
+            "type": "Feature",
+            "geometry": {"type": "LineString", "coordinates": [[1, 2], [3, 4]]},
+        });
+        assert_eq!(
+            message(json!([point, line]), &[]),
+            "feature 1: geometry type 'LineString' is not supported; only Point is"
+        );
+        assert_eq!(
+            message(json!([point, {"type": "Feature"}]), &[]),
+            "feature 1: missing geometry"
+        );
+        assert_eq!(
+            message(json!([point]), &["name"]),
+            "feature 0: property 'name' is missing or not a number"
+        );
+        let short = json!({"geometry": {"type": "Point", "coordinates": [1]}});
+        assert_eq!(
+            message(json!([short]), &[]),
+            "feature 0: expected [lon, lat] coordinates"
+        );
+        assert_eq!(message(json!([]), &[]), "no points found in input");
+
+        fs::write(&input, json!({"type": "Feature"}).to_string()).unwrap();
+        let err = read_points_geojson(&input, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "expected a GeoJSON FeatureCollection");
+        fs::write(&input, "{").unwrap();
+        let err = read_points_geojson(&input, &[]).unwrap_err();
+        assert_eq!(err.to_string(), format!("'{}' is not valid JSON", input));
+    }
+
+    #[test]
+    fn run_summaries_are_written_as_versioned_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = path(&dir, "run.json");
+        let points = [
+            pt(&[0.0, 0.0]),
+            pt(&[2.0, 1.0]),
+            pt(&[9.0, 9.0]),
+            pt(&[5.0, 5.0]),
+        ];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(0)),
+            (&points[1], Class::Classified(0)),
+            (&points[2], Class::Classified(1)),
+            (&points[3], Class::Noise),
+        ]);
+        let summary = crate::types::RunSummary {
+            input: "points.csv".to_string(),
+            eps: 2.5,
+            min_points: 1,
+            engine: "brute".to_string(),
+            elapsed_secs: 0.25,
+            ..crate::types::RunSummary::from_classes(&points, &classes)
+        };
+        write_summary_json(&output, &summary).unwrap();
+
+        let text = fs::read_to_string(&output).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["version"], json!(crate::types::RUN_SUMMARY_VERSION));
+        assert_eq!(json["input"], json!("points.csv"));
+        assert_eq!(json["eps"], json!(2.5));
+        assert_eq!(json["engine"], json!("brute"));
+        assert_eq!(json["n_points"], json!(4));
+        assert_eq!(json["n_clusters"], json!(2));
+        assert_eq!(json["n_noise"], json!(1));
+        assert_eq!(
+            json["clusters"],
+            json!([
+                {"id": 0, "size": 2, "weight": null, "centroid": [1.0, 0.5]},
+                {"id": 1, "size": 1, "weight": null, "centroid": [9.0, 9.0]},
+            ])
+        );
+        assert_eq!(json["ground_truth"], json!(null));
+        assert_eq!(
+            serde_json::from_str::<crate::types::RunSummary>(&text).unwrap(),
+            summary
+        );
+    }
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod parquet_tests {
+    use super::*;
+    use parquet::data_type::{
+        ByteArray, ByteArrayType, DoubleType, FloatType, Int32Type, Int64Type,
+    };
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    // One row of the fixture: x, optional y, z, label and name.
+    type Row = (f64, Option<f32>, i32, i64, &'static str);
+
+    // Writes `groups` as the row groups of a Parquet file at `path`.
+    fn write_fixture(path: &str, groups: &[&[Row]]) {
+        let schema = parse_message_type(
+            "message points {
+                REQUIRED DOUBLE x;
+                OPTIONAL FLOAT y;
+                REQUIRED INT32 z;
+                REQUIRED INT64 label;
+                REQUIRED BYTE_ARRAY name (UTF8);
+            }",
+        )
+        .unwrap();
+        let file = fs::File::create(path).unwrap();
+        let mut w = SerializedFileWriter::new(file, Arc::new(schema), Default::default()).unwrap();
+        for rows in groups {
+            let mut group = w.next_row_group().unwrap();
+            let mut column = 0;
+            while let Some(mut c) = group.next_column().unwrap() {
+                match column {
+                    0 => {
+                        let x = rows.iter().map(|r| r.0).collect::<Vec<_>>();
+                        c.typed::<DoubleType>().write_batch(&x, None, None).unwrap();
+                    }
+                    1 => {
+                        let y = rows.iter().filter_map(|r| r.1).collect::<Vec<_>>();
+                        let defined = rows
+                            .iter()
+                            .map(|r| r.1.is_some() as i16)
+                            .collect::<Vec<_>>();
+                        c.typed::<FloatType>()
+                            .write_batch(&y, Some(&defined), None)
+                            .unwrap();
+                    }
+                    2 => {
+                        let z = rows.iter().map(|r| r.2).collect::<Vec<_>>();
+                        c.typed::<Int32Type>().write_batch(&z, None, None).unwrap();
+                    }
+                    3 => {
+                        let label = rows.iter().map(|r| r.3).collect::<Vec<_>>();
+                        c.typed::<Int64Type>()
+                            .write_batch(&label, None, None)
+                            .unwrap();
+                    }
+                    _ => {
+                        let name = rows
+                            .iter()
+                            .map(|r| ByteArray::from(r.4))
+                            .collect::<Vec<_>>();
+                        c.typed::<ByteArrayType>()
+                            .write_batch(&name, None, None)
+                            .unwrap();
+                    }
+                }
+                c.close().unwrap();
+                column += 1;
+            }
+            group.close().unwrap();
+        }
+        w.close().unwrap();
+    }
+
+    const ROWS: [Row; 3] = [
+        (0.5, Some(1.5), 3, 0, "a"),
+        (1.0, Some(-2.25), 4, 1, "b"),
+        (2.0, Some(2.5), -5, 1, "c"),
+    ];
+
+    #[test]
+    fn selected_columns_are_read_across_row_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.parquet");
+        let path = path.to_str().unwrap();
+        write_fixture(path, &[&ROWS[..2], &ROWS[2..]]);
+
+        // Every numeric column, f32 and integers converted to f64.
+        assert_eq!(
+            read_points_parquet(path, None).unwrap(),
+            [
+                pt(&[0.5, 1.5, 3.0, 0.0]),
+                pt(&[1.0, -2.25, 4.0, 1.0]),
+                pt(&[2.0, 2.5, -5.0, 1.0]),
+            ]
+        );
+        // In the requested order.
+        let columns = ["z".to_string(), "x".to_string()];
+        assert_eq!(
+            read_points_parquet(path, Some(&columns)).unwrap(),
+            [pt(&[3.0, 0.5]), pt(&[4.0, 1.0]), pt(&[-5.0, 2.0])]
+        );
+        let options = ParquetOptions {
+            label: Some("label".to_string()),
+            ..ParquetOptions::default()
+        };
+        let (points, labels) = read_points_parquet_with(path, &options).unwrap();
+        assert_eq!(points[2], pt(&[2.0, 2.5, -5.0]));
+        assert_eq!(labels, Some(vec![0, 1, 1]));
+
+        let message = |columns: &[&str]| {
+            let columns = columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            format!(
+                "{:#}",
+                read_points_parquet(path, Some(&columns)).unwrap_err()
+            )
+        };
+        assert_eq!(
+            message(&["x", "w"]),
+            "no column 'w'; available: x, y, z, label, name"
+        );
+        assert_eq!(
+            message(&["name"]),
+            "column 'name' is not an integer or floating-point column"
+        );
+    }
+
+    #[test]
+    fn nulls_are_rejected_skipped_or_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nulls.parquet");
+        let path = path.to_str().unwrap();
+        let mut rows = ROWS;
+        rows[1].1 = None;
+        write_fixture(path, &[&rows]);
+
+        let err = read_points_parquet(path, None).unwrap_err();
+        assert_eq!(format!("{:#}", err), "row 1: null or NaN in column 'y'");
+        let read = |invalid| {
+            let options = ParquetOptions {
+                invalid,
+                ..ParquetOptions::default()
+            };
+            read_points_parquet_with(path, &options).unwrap().0
+        };
+        let skipped = read(InvalidRows::Skip);
+        assert_eq!(
+            skipped,
+            [pt(&[0.5, 1.5, 3.0, 0.0]), pt(&[2.0, 2.5, -5.0, 1.0])]
+        );
+        let kept = read(InvalidRows::Allow);
+        assert_eq!(kept.len(), 3);
+        assert!(kept[1][1].is_nan());
+
+        // A null outside the selected columns does not matter.
+        let columns = ["x".to_string()];
+        assert_eq!(read_points_parquet(path, Some(&columns)).unwrap().len(), 3);
+        let err = read_points_parquet(&format!("{}.missing", path), None).unwrap_err();
+        assert!(err.to_string().starts_with("failed to read"));
+        fs::write(path, "1,2\n").unwrap();
+        let err = read_points_parquet(path, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("'{}' is not a valid Parquet file", path)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "arrow"))]
+mod arrow_tests {
+    use super::*;
+    use arrow_array::{ArrayRef, Float32Array, Float64Array, Int32Array, RecordBatch, StringArray};
+    use arrow_ipc::writer::FileWriter;
+    use std::sync::Arc;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    #[test]
+    fn written_files_read_back_like_the_csv_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        // More rows than fit in one record batch.
+        let n = ARROW_BATCH_ROWS + 10;
+        let points = (0..n)
+            .map(|i| pt(&[i as f64 * 0.25, (i % 7) as f64 - 0.5]))
+            .collect::<Vec<_>>();
+        let classes = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match i % 5 {
+                0 => (p, Class::Noise),
+                _ => (p, Class::Classified(i % 3)),
+            })
+            .collect::<HashMap<_, _>>();
+        let schema = Schema::new(vec!["x".to_string(), "y".to_string()]).unwrap();
+
+        let csv = path("out.csv");
+        let arrow = path("out.arrow");
+        write_clustered_csv_with_header(&csv, Some(&schema), &points, &classes).unwrap();
+        write_clustered_arrow(&arrow, Some(&schema), &points, &classes).unwrap();
+        let (_, rows) = read_clustered_csv_with_header(&csv).unwrap();
+        let expected = rows
+            .iter()
+            .map(|(cid, coords)| {
+                let mut row = vec![*cid as f64];
+                row.extend(coords);
+                pt(&row)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(read_points_arrow(&arrow, None).unwrap(), expected);
+
+        let columns = ["y".to_string(), "x".to_string()];
+        let swapped = read_points_arrow(&arrow, Some(&columns)).unwrap();
+        assert_eq!(swapped.len(), n);
+        assert_eq!(
+            swapped[n - 1],
+            pt(&[(n - 1) as f64 % 7.0 - 0.5, (n - 1) as f64 * 0.25])
+        );
+
+        // Without a schema the coordinate columns are numbered.
+        write_clustered_arrow(&arrow, None, &points[..2], &classes).unwrap();
+        let columns = ["cluster_id".to_string(), "x2".to_string()];
+        assert_eq!(
+            read_points_arrow(&arrow, Some(&columns)).unwrap(),
+            [pt(&[-1.0, -0.5]), pt(&[1.0, 0.5])]
+        );
+        let err = write_clustered_arrow(&arrow, Some(&schema), &[pt(&[1.0])], &classes);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "schema has 2 columns, points have 1"
+        );
+        let err = write_clustered_arrow("-", None, &points, &classes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Arrow IPC files cannot be written to stdout"
+        );
+    }
+
+    #[test]
+    fn foreign_files_are_converted_or_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in.arrow");
+        let path = path.to_str().unwrap();
+        let columns: [(&str, ArrayRef); 3] = [
+            ("a", Arc::new(Int32Array::from(vec![1, -2]))),
+            ("b", Arc::new(Float32Array::from(vec![Some(0.5), None]))),
+            ("name", Arc::new(StringArray::from(vec!["p", "q"]))),
+        ];
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut w = FileWriter::try_new(fs::File::create(path).unwrap(), &batch.schema()).unwrap();
+        w.write(&batch).unwrap();
+        w.finish().unwrap();
+
+        let read = |columns: &[&str]| {
+            let columns = columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            read_points_arrow(path, Some(&columns)).map_err(|e| format!("{:#}", e))
+        };
+        assert_eq!(read(&["a"]).unwrap(), [pt(&[1.0]), pt(&[-2.0])]);
+        assert_eq!(read(&["a", "b"]).unwrap_err(), "row 1: null in column 'b'");
+        assert_eq!(
+            read(&["c"]).unwrap_err(),
+            "no column 'c'; available: a, b, name"
+        );
+        assert_eq!(
+            read(&["name"]).unwrap_err(),
+            "column 'name' has non-numeric type Utf8"
+        );
+        // Without a selection the string column is left out.
+        let err = read_points_arrow(path, None).unwrap_err();
+        assert_eq!(err.to_string(), "row 1: null in column 'b'");
+
+        let nan = Float64Array::from(vec![f64::NAN]);
+        let batch = RecordBatch::try_from_iter([("x", Arc::new(nan) as ArrayRef)]).unwrap();
+        let mut w = FileWriter::try_new(fs::File::create(path).unwrap(), &batch.schema()).unwrap();
+        w.write(&batch).unwrap();
+        w.finish().unwrap();
+        let err = read_points_arrow(path, None).unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid value at row 0"),
+            "{:#}",
+            err
+        );
+
+        fs::write(path, "1,2\n").unwrap();
+        let err = read_points_arrow(path, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("'{}' is not a valid Arrow IPC file", path)
+        );
+        let err = read_points_arrow("-", None).unwrap_err();
+        assert_eq!(err.to_string(), "Arrow IPC files cannot be read from stdin");
+    }
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod mmap_tests {
+    use super::*;
+    use crate::algo::dbscan_cloud;
+    use crate::brute_query::BruteForceQueryEngine;
+    use crate::types::PointCloud;
+
+    #[test]
+    fn mapped_points_cluster_like_points_read_to_the_heap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.bin");
+        let path = path.to_str().unwrap();
+        let mut rng = crate::sampling::SplitMix64::new(3);
+        let points = (0..300)
+            .map(|_| {
+                let blob = rng.below(3) as f64 * 10.0;
+                (0..3)
+                    .map(|_| OrderedFloat(blob + rng.below(1000) as f64 / 500.0))
+                    .collect::<Point>()
+            })
+            .collect::<Vec<_>>();
+        write_points_bin(path, &points).unwrap();
+
+        let mapped = mmap_points(path).unwrap();
+        let heap = PointCloud::from(&read_points_bin(path).unwrap()[..]);
+        let view = mapped.view();
+        assert_eq!((view.len(), view.dim()), (300, 3));
+        assert!(view.rows().eq(heap.rows()));
+        let (on_map, on_heap) = (
+            BruteForceQueryEngine::from_view(view),
+            BruteForceQueryEngine::from_cloud(&heap),
+        );
+        for i in [0, 150, 299] {
+            assert_eq!(
+                on_map.run_rows(view.row(i), 0.5),
+                on_heap.run_rows(heap.row(i), 0.5)
+            );
+        }
+        assert_eq!(
+            dbscan_cloud(view, 0.5, 4),
+            dbscan_cloud(heap.view(), 0.5, 4)
+        );
+    }
+
+    #[test]
+    fn only_whole_uncompressed_files_are_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let points = [vec![OrderedFloat(1.0), OrderedFloat(2.0)]];
+        write_points_bin(&file("points.bin.gz"), &points).unwrap();
+        let err = mmap_points(&file("points.bin.gz")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "cannot map '{}'; only uncompressed files can be mapped",
+                file("points.bin.gz")
+            )
+        );
+
+        let path = file("points.bin");
+        write_points_bin(&path, &points).unwrap();
+        let bin = fs::read(&path).unwrap();
+        let message = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            mmap_points(&path).err().unwrap().to_string()
+        };
+        assert_eq!(
+            message(&[&bin[..], &[0; 8]].concat()),
+            format!(
+                "'{}' has the wrong size: expected 1 points of dimension 2, found 3 coordinates",
+                path
+            )
+        );
+        assert_eq!(
+            message(&bin[..20]),
+            format!("'{}' is truncated: no complete header", path)
+        );
+        let mut other = bin.clone();
+        other[..4].copy_from_slice(b"KMDL");
+        assert_eq!(
+            message(&other),
+            format!("'{}' is not a binary point file", path)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod sqlite_tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    #[test]
+    fn clustered_tables_have_typed_named_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.db");
+        let path = path.to_str().unwrap();
+        let points = [
+            pt(&[0.0, 0.0]),
+            pt(&[0.0, 0.5]),
+            pt(&[5.0, 5.0]),
+            pt(&[9.0, -9.0]),
+            pt(&[0.5, 0.0]),
+        ];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(0)),
+            (&points[1], Class::Classified(0)),
+            (&points[2], Class::Classified(1)),
+            (&points[4], Class::Classified(0)),
+        ]);
+        let schema = Schema::new(vec!["lat".to_string(), "say \"lon\"".to_string()]).unwrap();
+        write_clustered_sqlite(path, "runs", &points, &classes, Some(&schema)).unwrap();
+        write_clustered_sqlite(path, "plain", &points, &classes, None).unwrap();
+
+        let db = Connection::open(path).unwrap();
+        let columns = |table: &str| {
+            let mut stmt = db
+                .prepare(&format!(
+                    "SELECT name, type FROM pragma_table_info('{}')",
+                    table
+                ))
+                .unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<(String, String)>>>()
+                .unwrap()
+        };
+        let expected = |x: &str, y: &str| {
+            [("cluster_id", "INTEGER"), (x, "REAL"), (y, "REAL")]
+                .map(|(name, ty)| (name.to_string(), ty.to_string()))
+        };
+        assert_eq!(columns("runs"), expected("lat", "say \"lon\""));
+        assert_eq!(columns("plain"), expected("x1", "x2"));
+
+        let mut stmt = db
+            .prepare(
+                "SELECT cluster_id, COUNT(*) FROM runs GROUP BY cluster_id ORDER BY cluster_id",
+            )
+            .unwrap();
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, i64)>>>()
+            .unwrap();
+        assert_eq!(counts, [(-1, 1), (0, 3), (1, 1)]);
+        let rows = db
+            .prepare("SELECT cluster_id, x1, x2 FROM plain ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, f64, f64)>>>()
+            .unwrap();
+        assert_eq!(rows[3], (-1, 9.0, -9.0));
+        assert_eq!(rows[4], (0, 0.5, 0.0));
+
+        let err = write_clustered_sqlite(path, "runs", &points, &classes, None).unwrap_err();
+        assert!(
+            format!("{:#}", err)
+                .starts_with(&format!("failed to create table 'runs' in '{}': ", path)),
+            "{:#}",
+            err
+        );
+    }
 }
@@ -1,5 +1,15 @@
 pub mod algo;
+pub mod brute_query;
+pub mod cli;
+pub mod engine;
 pub mod fake_query;
+pub mod impute;
 pub mod io;
+pub mod pca;
+pub mod plot;
+pub mod predict;
 pub mod query;
+pub mod sampling;
+pub mod scaling;
 pub mod types;
+pub mod validation;
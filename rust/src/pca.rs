@@ -0,0 +1,298 @@
+use crate::types::Point;
+use ordered_float::OrderedFloat;
+
+// Jacobi sweeps stop once the off-diagonal mass is this small relative to the
+// whole matrix, or after `MAX_SWEEPS`.
+const REL_TOLERANCE: f64 = 1e-24;
+const MAX_SWEEPS: usize = 100;
+
+/// Principal component analysis fitted by `fit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pca {
+    mean: Vec<f64>,
+    // Unit principal axes, by decreasing variance.
+    components: Vec<Vec<f64>>,
+    explained_variance: Vec<f64>,
+    explained_variance_ratio: Vec<f64>,
+}
+
+/// Fits the top `k` principal components of `points` (population covariance).
+/// Fails on empty input, mixed dimensions, or `k` outside 1..=dim.
+///
+/// The covariance matrix is diagonalized with the cyclic Jacobi method, which
+/// is exact enough and cheap for the small dimensions this crate handles.
+pub fn fit(points: &[Point], k: usize) -> anyhow::Result<Pca> {
+    let Some(first) = points.first() else {
+        anyhow::bail!("cannot fit PCA to an empty point set");
+    };
+    let dim = first.len();
+    if k == 0 || k > dim {
+        anyhow::bail!("k must be in 1..={}; got k={}", dim, k);
+    }
+    if let Some(i) = points.iter().position(|p| p.len() != dim) {
+        anyhow::bail!(
+            "dimension mismatch at point {}: expected {}, got {}",
+            i,
+            dim,
+            points[i].len()
+        );
+    }
+
+    let n = points.len() as f64;
+    let mut mean = vec![0.0; dim];
+    for p in points.iter() {
+        for (m, x) in mean.iter_mut().zip(p.iter()) {
+            *m += x.0;
+        }
+    }
+    mean.iter_mut().for_each(|m| *m /= n);
+
+    let mut cov = vec![vec![0.0; dim]; dim];
+    for p in points.iter() {
+        let centered = p
+            .iter()
+            .zip(mean.iter())
+            .map(|(x, m)| x.0 - m)
+            .collect::<Vec<_>>();
+        for (row, ci) in cov.iter_mut().zip(centered.iter()) {
+            for (c, cj) in row.iter_mut().zip(centered.iter()) {
+                *c += ci * cj;
+            }
+        }
+    }
+    cov.iter_mut().flatten().for_each(|c| *c /= n);
+    let total = (0..dim).map(|i| cov[i][i]).sum::<f64>();
+
+    let (values, vectors) = symmetric_eigen(cov);
+    let mut order = (0..dim).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| values[b].total_cmp(&values[a]));
+    order.truncate(k);
+
+    let components = order
+        .iter()
+        .map(|&j| {
+            let mut axis = (0..dim).map(|i| vectors[i][j]).collect::<Vec<_>>();
+            // Fix the sign so that results are reproducible: the largest
+            // coordinate is positive.
+            let largest = axis
+                .iter()
+                .copied()
+                .fold(0.0, |a: f64, b| if b.abs() > a.abs() { b } else { a });
+            if largest < 0.0 {
+                axis.iter_mut().for_each(|x| *x = -*x);
+            }
+            axis
+        })
+        .collect();
+    // Rounding can leave tiny negative eigenvalues for flat directions.
+    let explained_variance = order
+        .iter()
+        .map(|&j| values[j].max(0.0))
+        .collect::<Vec<_>>();
+    let explained_variance_ratio = explained_variance
+        .iter()
+        .map(|v| if total > 0.0 { v / total } else { 0.0 })
+        .collect();
+
+    Ok(Pca {
+        mean,
+        components,
+        explained_variance,
+        explained_variance_ratio,
+    })
+}
+
+impl Pca {
+    /// Number of components `k`.
+    pub fn n_components(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Unit principal axes, by decreasing variance.
+    pub fn components(&self) -> &[Vec<f64>] {
+        &self.components
+    }
+
+    /// Variance of the data along each component.
+    pub fn explained_variance(&self) -> &[f64] {
+        &self.explained_variance
+    }
+
+    /// Share of the total variance along each component.
+    pub fn explained_variance_ratio(&self) -> &[f64] {
+        &self.explained_variance_ratio
+    }
+
+    /// Coordinates of `point` along the components, relative to the mean.
+    /// Panics if its dimension differs from the fitted data.
+    pub fn transform(&self, point: &Point) -> Point {
+        assert_eq!(
+            point.len(),
+            self.mean.len(),
+            "point dimension {} does not match fitted dimension {}",
+            point.len(),
+            self.mean.len()
+        );
+        self.components
+            .iter()
+            .map(|axis| {
+                let proj = point
+                    .iter()
+                    .zip(self.mean.iter())
+                    .zip(axis.iter())
+                    .map(|((x, m), a)| (x.0 - m) * a)
+                    .sum::<f64>();
+                OrderedFloat(proj)
+            })
+            .collect()
+    }
+}
+
+// Eigen decomposition of the symmetric matrix `a`: the eigenvalues, and the
+// eigenvectors as the columns of the returned matrix.
+fn symmetric_eigen(mut a: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = a.len();
+    let mut v = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect::<Vec<Vec<f64>>>();
+    let norm = a.iter().flatten().map(|x| x * x).sum::<f64>();
+
+    for _ in 0..MAX_SWEEPS {
+        let off = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum::<f64>();
+        if off <= REL_TOLERANCE * norm {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                // Rotation in the (p, q) plane that zeroes a[p][q].
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (xp, xq) = (row[p], row[q]);
+                    row[p] = c * xp - s * xq;
+                    row[q] = s * xp + c * xq;
+                }
+                let (head, tail) = a.split_at_mut(q);
+                for (xp, xq) in head[p].iter_mut().zip(tail[0].iter_mut()) {
+                    (*xp, *xq) = (c * *xp - s * *xq, s * *xp + c * *xq);
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::SplitMix64;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    fn dot(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    // Points of a tilted plane through (1, 2, 3, 4, 5) in 5-D, with a little
+    // noise off the plane.
+    fn plane(seed: u64, n: usize) -> Vec<Point> {
+        let u = [1.0, 2.0, 0.0, -1.0, 0.5];
+        let v = [0.0, 1.0, 1.0, 1.0, -2.0];
+        let mut rng = SplitMix64::new(seed);
+        let mut uniform = |scale: f64| (rng.below(2001) as f64 / 1000.0 - 1.0) * scale;
+        (0..n)
+            .map(|_| {
+                let (a, b) = (uniform(10.0), uniform(5.0));
+                let coords = (0..5)
+                    .map(|i| (i + 1) as f64 + a * u[i] + b * v[i] + uniform(0.01))
+                    .collect::<Vec<_>>();
+                pt(&coords)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_plane_in_five_dimensions_needs_two_components() {
+        let points = plane(352, 500);
+        let pca = fit(&points, 2).unwrap();
+        assert_eq!(pca.n_components(), 2);
+        let ratio = pca.explained_variance_ratio();
+        assert!(ratio[0] >= ratio[1]);
+        assert!(ratio.iter().sum::<f64>() >= 0.99, "{:?}", ratio);
+
+        // The axes are orthonormal and span the plane.
+        let axes = pca.components();
+        assert!((dot(&axes[0], &axes[0]) - 1.0).abs() < 1e-9);
+        assert!((dot(&axes[1], &axes[1]) - 1.0).abs() < 1e-9);
+        assert!(dot(&axes[0], &axes[1]).abs() < 1e-9);
+        let all = fit(&points, 5).unwrap();
+        assert!(all.explained_variance()[2..].iter().all(|&v| v < 1e-3));
+        assert!((all.explained_variance_ratio().iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // Projecting onto the plane keeps the distances between its points.
+        for pair in points.windows(2).take(20) {
+            let (a, b) = (pca.transform(&pair[0]), pca.transform(&pair[1]));
+            assert_eq!(a.len(), 2);
+            let projected = crate::types::dist(&a, &b);
+            assert!((projected - crate::types::dist(&pair[0], &pair[1])).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn components_of_independent_axes() {
+        // Variances 4 along x, 1 along z and none along y.
+        let points = [
+            pt(&[-2.0, 7.0, 0.0]),
+            pt(&[2.0, 7.0, 0.0]),
+            pt(&[0.0, 7.0, -1.0]),
+            pt(&[0.0, 7.0, 1.0]),
+        ];
+        let pca = fit(&points, 3).unwrap();
+        assert_eq!(pca.explained_variance(), [2.0, 0.5, 0.0]);
+        assert_eq!(pca.explained_variance_ratio(), [0.8, 0.2, 0.0]);
+        assert_eq!(pca.components()[0], [1.0, 0.0, 0.0]);
+        assert_eq!(pca.components()[1], [0.0, 0.0, 1.0]);
+        // Coordinates are relative to the mean (0, 7, 0).
+        assert_eq!(
+            pca.transform(&pt(&[3.0, 7.0, -2.0]))[..2],
+            pt(&[3.0, -2.0])[..]
+        );
+        assert_eq!(
+            pca.transform(&pt(&[0.0, 7.0, 0.0]))[..2],
+            pt(&[0.0, 0.0])[..]
+        );
+    }
+
+    #[test]
+    fn identical_points_have_no_variance() {
+        let points = vec![pt(&[1.0, 2.0]); 3];
+        let pca = fit(&points, 1).unwrap();
+        assert_eq!(pca.explained_variance(), [0.0]);
+        assert_eq!(pca.explained_variance_ratio(), [0.0]);
+        assert_eq!(pca.transform(&points[0]), pt(&[0.0]));
+    }
+
+    #[test]
+    fn fitting_checks_the_input() {
+        let message = |points: &[Point], k| fit(points, k).unwrap_err().to_string();
+        assert_eq!(message(&[], 1), "cannot fit PCA to an empty point set");
+        let points = [pt(&[0.0, 1.0]), pt(&[1.0, 0.0])];
+        assert_eq!(message(&points, 0), "k must be in 1..=2; got k=0");
+        assert_eq!(message(&points, 3), "k must be in 1..=2; got k=3");
+        assert_eq!(
+            message(&[pt(&[0.0, 1.0]), pt(&[1.0])], 1),
+            "dimension mismatch at point 1: expected 2, got 1"
+        );
+    }
+}
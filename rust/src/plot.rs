@@ -0,0 +1,252 @@
+use crate::io::write_rgb_png;
+use crate::types::{centroids, Class, Point, Schema};
+use anyhow::{Context, Result};
+use plotters::prelude::*;
+use std::collections::HashMap;
+
+/// What `render_rgb` draws: which coordinates span the axes, the image size
+/// and the decorations. The default matches the `plot` binary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlotOptions {
+    /// Coordinate of the points on the x axis (0-based).
+    pub x_col: usize,
+    /// Coordinate of the points on the y axis (0-based).
+    pub y_col: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Point radius in pixels.
+    pub point_size: i32,
+    /// Mark the centroid of every cluster with a cross.
+    pub centroids: bool,
+    pub title: String,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        Self {
+            x_col: 0,
+            y_col: 1,
+            width: 1000,
+            height: 800,
+            point_size: 2,
+            centroids: false,
+            title: "Clustering Plot".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    class: Class,
+    x: f64,
+    y: f64,
+}
+
+/// Scatter plot of `points` colored by cluster, with noise (and points
+/// missing from `classes`) in light gray, as an RGB buffer of
+/// `width * height * 3` bytes, row by row. The axes are labeled with the
+/// column names of `schema`, or x1, x2, ... without one. Fails on empty
+/// input or an axis beyond the point dimension.
+pub fn render_rgb(
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    schema: Option<&Schema>,
+    options: &PlotOptions,
+) -> Result<Vec<u8>> {
+    let Some(first) = points.first() else {
+        anyhow::bail!("no points to plot");
+    };
+    let dim = first.len();
+    let (px, py) = (options.x_col, options.y_col);
+    if px >= dim || py >= dim {
+        anyhow::bail!("x_col/y_col out of bounds for {} data columns", dim);
+    }
+    let numbered;
+    let schema = match schema {
+        Some(schema) => schema,
+        None => {
+            numbered = Schema::numbered(dim);
+            &numbered
+        }
+    };
+
+    let samples = points
+        .iter()
+        .map(|p| Sample {
+            class: classes.get(p).copied().unwrap_or(Class::Noise),
+            x: p[px].0,
+            y: p[py].0,
+        })
+        .collect::<Vec<_>>();
+    let marks = if options.centroids {
+        let mut marks = centroids(points, classes)
+            .into_iter()
+            .map(|(id, c)| Sample {
+                class: Class::Classified(id),
+                x: c[px].0,
+                y: c[py].0,
+            })
+            .collect::<Vec<_>>();
+        marks.sort_by_key(|s| s.class.cid());
+        marks
+    } else {
+        Vec::new()
+    };
+
+    let size = (options.width, options.height);
+    let mut rgb = vec![0; size.0 as usize * size.1 as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut rgb, size).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let ((x_min, x_max), (y_min, y_max)) = compute_ranges(&samples);
+        let mut chart = ChartBuilder::on(&root)
+            .margin(15)
+            .caption(options.title.clone(), ("sans-serif", 20))
+            .set_label_area_size(LabelAreaPosition::Left, 40)
+            .set_label_area_size(LabelAreaPosition::Bottom, 40)
+            .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+
+        chart
+            .configure_mesh()
+            .x_desc(schema.name(px))
+            .y_desc(schema.name(py))
+            .draw()?;
+
+        chart.draw_series(samples.iter().map(|s| {
+            let style = color_for(s.class);
+            Circle::new((s.x, s.y), options.point_size, style)
+        }))?;
+        chart.draw_series(marks.iter().map(|s| {
+            let style = color_for(s.class).stroke_width(3);
+            Cross::new((s.x, s.y), options.point_size * 4, style)
+        }))?;
+
+        root.present().context("failed to render image")?;
+    }
+    Ok(rgb)
+}
+
+/// Renders the plot of `render_rgb` and writes it as PNG to `path`, or to
+/// stdout for `-`.
+pub fn write_png(
+    path: &str,
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    schema: Option<&Schema>,
+    options: &PlotOptions,
+) -> Result<()> {
+    let rgb = render_rgb(points, classes, schema, options)?;
+    write_rgb_png(path, &rgb, options.width, options.height)
+}
+
+fn compute_ranges(samples: &[Sample]) -> ((f64, f64), (f64, f64)) {
+    let mut x_min = f64::INFINITY;
+    let mut x_max = f64::NEG_INFINITY;
+    let mut y_min = f64::INFINITY;
+    let mut y_max = f64::NEG_INFINITY;
+    for s in samples {
+        x_min = x_min.min(s.x);
+        x_max = x_max.max(s.x);
+        y_min = y_min.min(s.y);
+        y_max = y_max.max(s.y);
+    }
+    // Add a small margin
+    let x_span = (x_max - x_min).abs();
+    let y_span = (y_max - y_min).abs();
+    let mx = if x_span == 0.0 { 1.0 } else { x_span * 0.05 };
+    let my = if y_span == 0.0 { 1.0 } else { y_span * 0.05 };
+    ((x_min - mx, x_max + mx), (y_min - my, y_max + my))
+}
+
+fn color_for(class: Class) -> ShapeStyle {
+    let Class::Classified(id) = class else {
+        return BLACK.mix(0.3).filled();
+    };
+    // Map cluster id to a palette color deterministically.
+    let idx = id % Palette99::COLORS.len();
+    let c = Palette99::pick(idx).mix(0.9);
+    c.filled()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ordered_float::OrderedFloat;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    #[test]
+    fn axes_are_labeled_with_the_schema() {
+        let points = vec![pt(&[0.0, 0.0, 5.0]), pt(&[1.0, 2.0, 3.0])];
+        let classes = HashMap::from([(&points[0], Class::Classified(0))]);
+        let options = PlotOptions {
+            width: 200,
+            height: 150,
+            ..PlotOptions::default()
+        };
+        let render =
+            |schema: Option<&Schema>| render_rgb(&points, &classes, schema, &options).unwrap();
+
+        let numbered = render(None);
+        assert_eq!(numbered.len(), 200 * 150 * 3);
+        assert_eq!(render(Some(&Schema::numbered(3))), numbered);
+        let named =
+            Schema::new(vec!["temperature".into(), "pressure".into(), "flow".into()]).unwrap();
+        assert_ne!(render(Some(&named)), numbered);
+    }
+
+    #[test]
+    fn png_files_have_the_requested_size() {
+        let points = vec![
+            pt(&[0.0, 0.0]),
+            pt(&[0.1, 0.0]),
+            pt(&[3.0, 3.0]),
+            pt(&[3.1, 3.0]),
+            pt(&[9.0, -9.0]),
+        ];
+        let classes = points
+            .iter()
+            .zip([0, 0, 1, 1])
+            .map(|(p, id)| (p, Class::Classified(id)))
+            .collect::<HashMap<_, _>>();
+        let options = PlotOptions {
+            width: 320,
+            height: 240,
+            centroids: true,
+            ..PlotOptions::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plot.png");
+        let path = path.to_str().unwrap();
+        write_png(path, &points, &classes, None, &options).unwrap();
+
+        let png = std::fs::read(path).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let image = image::open(path).unwrap().into_rgb8();
+        assert_eq!(image.dimensions(), (320, 240));
+        let rgb = render_rgb(&points, &classes, None, &options).unwrap();
+        assert_eq!(image.into_raw(), rgb);
+        // Something besides the white background was drawn.
+        assert!(rgb.iter().any(|&c| c != 255));
+    }
+
+    #[test]
+    fn plots_need_points_and_existing_axes() {
+        let points = vec![pt(&[0.0, 0.0])];
+        let classes = HashMap::new();
+        let options = PlotOptions {
+            y_col: 2,
+            ..PlotOptions::default()
+        };
+        let err = render_rgb(&points, &classes, None, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "x_col/y_col out of bounds for 2 data columns"
+        );
+        let err = render_rgb(&[], &classes, None, &PlotOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "no points to plot");
+    }
+}
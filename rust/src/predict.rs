@@ -0,0 +1,424 @@
+use crate::algo::RegionQuery;
+use crate::query::{RTreeQueryEngine, RTREE_MAX_DIM};
+use crate::types::{Class, MetricKind, Point};
+use anyhow::Result;
+use ordered_float::OrderedFloat;
+use std::collections::{HashMap, HashSet};
+
+/// Secondary index over the core points of a fitted clustering, used to assign
+/// arbitrary locations (e.g. grid cells of a heatmap) to clusters after the fact.
+pub struct CoreIndex<'a> {
+    engine: RTreeQueryEngine<'a>,
+    cluster_of: HashMap<&'a Point, usize>,
+    metric: MetricKind,
+}
+
+impl<'a> CoreIndex<'a> {
+    /// `cores` and `classes` are the outputs of `Algo::dbscan_with_cores`.
+    pub fn new(cores: &'a HashSet<&'a Point>, classes: &HashMap<&'a Point, Class>) -> Self {
+        Self::with_metric(cores, classes, MetricKind::Euclidean)
+    }
+
+    /// Like `new`, measuring distances with `metric`, which must be an Lp
+    /// metric as for `RTreeQueryEngine::with_metric`.
+    pub fn with_metric(
+        cores: &'a HashSet<&'a Point>,
+        classes: &HashMap<&'a Point, Class>,
+        metric: MetricKind,
+    ) -> Self {
+        let cluster_of = cores
+            .iter()
+            .map(|&p| match classes.get(p) {
+                Some(Class::Classified(id)) => (p, *id),
+                other => panic!("core point must be classified, got {:?}", other),
+            })
+            .collect();
+
+        let mut engine = RTreeQueryEngine::with_metric(metric);
+        engine.init(cores);
+
+        Self {
+            engine,
+            cluster_of,
+            metric,
+        }
+    }
+
+    /// Returns the cluster id of the core point nearest to `point` and the
+    /// distance to it, or `None` if the clustering has no core points.
+    pub fn nearest_core(&self, point: &Point) -> Option<(usize, f64)> {
+        let core = self.engine.nearest(point)?;
+        Some((self.cluster_of[core], self.metric.distance(point, core)))
+    }
+}
+
+/// A fitted clustering kept to label new points without clustering again:
+/// the core points of every cluster and the parameters they were found with.
+/// `io::write_model` and `io::read_model` store it in a file.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Model {
+    pub eps: f64,
+    pub min_points: usize,
+    pub metric: MetricKind,
+    /// Dimension of the points.
+    pub dim: usize,
+    /// The core points of every cluster, indexed by cluster id, in order.
+    pub clusters: Vec<Vec<Vec<f64>>>,
+}
+
+impl Model {
+    /// Keeps the core points of the clusters in `classes`; `cores` and
+    /// `classes` are the outputs of `Algo::dbscan_with_cores`, possibly
+    /// relabeled by `types::drop_small_clusters` (cores relabeled as noise
+    /// are left out). Fails unless `metric` is an Lp metric and the points
+    /// have at most `RTREE_MAX_DIM` dimensions, as `predict` needs.
+    pub fn fit(
+        cores: &HashSet<&Point>,
+        classes: &HashMap<&Point, Class>,
+        eps: f64,
+        min_points: usize,
+        metric: MetricKind,
+    ) -> Result<Self> {
+        let dim = classes.keys().next().map_or(0, |p| p.len());
+        let mut clusters: Vec<Vec<&Point>> = Vec::new();
+        for (&p, &class) in classes.iter() {
+            if let Class::Classified(id) = class {
+                if clusters.len() <= id {
+                    clusters.resize(id + 1, Vec::new());
+                }
+                if cores.contains(p) {
+                    clusters[id].push(p);
+                }
+            }
+        }
+        let model = Self {
+            eps,
+            min_points,
+            metric,
+            dim,
+            clusters: clusters
+                .into_iter()
+                .map(|mut members| {
+                    members.sort();
+                    members
+                        .into_iter()
+                        .map(|p| p.iter().map(|x| x.0).collect())
+                        .collect()
+                })
+                .collect(),
+        };
+        model.check()?;
+        Ok(model)
+    }
+
+    /// The class of each of `points`: the cluster of the nearest core point
+    /// if that is within `eps`, otherwise noise. Fails if a point's dimension
+    /// is not `dim`.
+    pub fn predict(&self, points: &[Point]) -> Result<Vec<Class>> {
+        self.check()?;
+        if let Some((i, p)) = points.iter().enumerate().find(|(_, p)| p.len() != self.dim) {
+            anyhow::bail!(
+                "point {} has {} coordinates, but the model was fitted on {}",
+                i,
+                p.len(),
+                self.dim
+            );
+        }
+        let cores = self
+            .clusters
+            .iter()
+            .enumerate()
+            .flat_map(|(id, members)| {
+                members
+                    .iter()
+                    .map(move |c| (c.iter().copied().map(OrderedFloat).collect::<Point>(), id))
+            })
+            .collect::<Vec<_>>();
+        let refs = cores.iter().map(|(p, _)| p).collect::<HashSet<_>>();
+        let classes = cores
+            .iter()
+            .map(|(p, id)| (p, Class::Classified(*id)))
+            .collect::<HashMap<_, _>>();
+        let index = CoreIndex::with_metric(&refs, &classes, self.metric);
+        Ok(points
+            .iter()
+            .map(|p| match index.nearest_core(p) {
+                Some((id, d)) if d <= self.eps => Class::Classified(id),
+                _ => Class::Noise,
+            })
+            .collect())
+    }
+
+    // Whether `predict` can use the model: as `fit` makes it, or as read by
+    // `io::read_model` from a file that may have been edited.
+    pub(crate) fn check(&self) -> Result<()> {
+        if !self.metric.is_lp() {
+            anyhow::bail!(
+                "a model needs the euclidean, manhattan, chebyshev or minkowski metric, not {:?}",
+                self.metric
+            );
+        }
+        if self.dim > RTREE_MAX_DIM {
+            anyhow::bail!(
+                "a model supports up to {} dimensions, not {}",
+                RTREE_MAX_DIM,
+                self.dim
+            );
+        }
+        for (id, members) in self.clusters.iter().enumerate() {
+            for (i, core) in members.iter().enumerate() {
+                if core.len() != self.dim {
+                    anyhow::bail!(
+                        "core point {} of cluster {} has {} coordinates, but the model has {}",
+                        i,
+                        id,
+                        core.len(),
+                        self.dim
+                    );
+                }
+                if !core.iter().all(|x| x.is_finite()) {
+                    anyhow::bail!(
+                        "core point {} of cluster {} has a coordinate that is not finite",
+                        i,
+                        id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::Algo;
+    use crate::fake_query::FakeQueryEngine;
+
+    fn point(xs: &[f64]) -> Point {
+        xs.iter().copied().map(OrderedFloat).collect()
+    }
+
+    // Two blobs of five core points around (0, 0) and (10, 10) at eps 0.5,
+    // and one point far from both. The coordinates are exact in binary.
+    fn training() -> Vec<Point> {
+        let mut points = Vec::new();
+        for (cx, cy) in [(0.0, 0.0), (10.0, 10.0)] {
+            for (dx, dy) in [
+                (0.0, 0.0),
+                (0.25, 0.0),
+                (0.0, 0.25),
+                (-0.25, 0.0),
+                (0.0, -0.25),
+            ] {
+                points.push(point(&[cx + dx, cy + dy]));
+            }
+        }
+        points.push(point(&[5.0, -20.0]));
+        points
+    }
+
+    fn fitted(points: &[Point]) -> Model {
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = FakeQueryEngine::new();
+        let (classes, cores) = Algo::new(&mut engine, &refs, 0.5, 3).dbscan_with_cores();
+        Model::fit(&cores, &classes, 0.5, 3, MetricKind::Euclidean).unwrap()
+    }
+
+    #[test]
+    fn fit_keeps_the_core_points_of_every_cluster() {
+        let model = fitted(&training());
+        assert_eq!((model.eps, model.min_points, model.dim), (0.5, 3, 2));
+        assert_eq!(model.clusters.len(), 2);
+        assert!(model.clusters.iter().all(|cores| cores.len() == 5));
+    }
+
+    #[test]
+    fn predict_labels_the_training_points_as_clustered() {
+        let points = training();
+        let model = fitted(&points);
+        let predicted = model.predict(&points).unwrap();
+        assert_eq!(predicted[..5], [predicted[0]; 5]);
+        assert_eq!(predicted[5..10], [predicted[5]; 5]);
+        assert_ne!(predicted[0], predicted[5]);
+        assert!(matches!(predicted[0], Class::Classified(_)));
+        assert_eq!(predicted[10], Class::Noise);
+    }
+
+    #[test]
+    fn predict_points_inside_and_outside_the_clusters() {
+        let points = training();
+        let model = fitted(&points);
+        let first = model.predict(&points[..1]).unwrap()[0];
+        let second = model.predict(&points[5..6]).unwrap()[0];
+        let new = [
+            point(&[0.3, 0.3]),
+            point(&[9.6, 10.2]),
+            // Exactly eps from the core point (0.25, 0): inclusive.
+            point(&[0.75, 0.0]),
+            point(&[5.0, 5.0]),
+            point(&[0.0, 0.8]),
+        ];
+        assert_eq!(
+            model.predict(&new).unwrap(),
+            [first, second, first, Class::Noise, Class::Noise]
+        );
+    }
+
+    #[test]
+    fn predict_rejects_points_of_another_dimension() {
+        let model = fitted(&training());
+        let err = model
+            .predict(&[point(&[0.0, 0.0]), point(&[0.0, 0.0, 0.0])])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "point 1 has 3 coordinates, but the model was fitted on 2"
+        );
+    }
+
+    #[test]
+    fn models_need_an_lp_metric() {
+        let model = Model {
+            metric: MetricKind::Cosine,
+            ..fitted(&training())
+        };
+        assert!(model.predict(&training()).is_err());
+    }
+
+    #[test]
+    fn nearest_core_inside_between_and_far_from_the_clusters() {
+        let points = training();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = FakeQueryEngine::new();
+        let (classes, cores) = Algo::new(&mut engine, &refs, 0.5, 3).dbscan_with_cores();
+        let id = |p: &Point| match classes[p] {
+            Class::Classified(id) => id,
+            other => panic!("{:?}", other),
+        };
+        let (first, second) = (id(&points[0]), id(&points[5]));
+        let index = CoreIndex::new(&cores, &classes);
+
+        // Inside a blob, next to its center.
+        assert_eq!(
+            index.nearest_core(&point(&[0.0, 0.0625])),
+            Some((first, 0.0625))
+        );
+        assert_eq!(
+            index.nearest_core(&point(&[10.0, 10.0])),
+            Some((second, 0.0))
+        );
+        // Between the blobs, a little closer to the first: (0, 0.25).
+        assert_eq!(
+            index.nearest_core(&point(&[4.0, 5.0])),
+            Some((first, 38.5625f64.sqrt()))
+        );
+        // Far from both; the noise point at (5, -20) is not a core point.
+        assert_eq!(
+            index.nearest_core(&point(&[5.0, -20.0])),
+            Some((first, 415.0625f64.sqrt()))
+        );
+
+        let manhattan = CoreIndex::with_metric(&cores, &classes, MetricKind::Manhattan);
+        assert_eq!(
+            manhattan.nearest_core(&point(&[4.0, 5.0])),
+            Some((first, 8.75))
+        );
+    }
+
+    #[test]
+    fn nearest_core_is_none_without_core_points() {
+        let points = training();
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = FakeQueryEngine::new();
+        let (classes, cores) = Algo::new(&mut engine, &refs, 0.1, 3).dbscan_with_cores();
+        assert!(cores.is_empty());
+        let index = CoreIndex::new(&cores, &classes);
+        assert_eq!(index.nearest_core(&point(&[0.0, 0.0])), None);
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use crate::io::{read_model, write_model};
+
+    fn model() -> Model {
+        Model {
+            eps: 0.5,
+            min_points: 3,
+            metric: MetricKind::Minkowski(3.0),
+            dim: 2,
+            clusters: vec![vec![vec![0.0, 0.0], vec![0.1, 0.0]], vec![vec![10.0, 10.0]]],
+        }
+    }
+
+    fn path(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn model_files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path(&dir, "model.bin");
+        write_model(&file, &model()).unwrap();
+        let read = read_model(&file).unwrap();
+        assert_eq!(read, model());
+        let points = [vec![OrderedFloat(10.2), OrderedFloat(10.0)]];
+        assert_eq!(
+            read.predict(&points).unwrap(),
+            model().predict(&points).unwrap()
+        );
+    }
+
+    #[test]
+    fn read_model_rejects_models_it_cannot_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path(&dir, "model.bin");
+        let mut ragged = model();
+        ragged.clusters[1].push(vec![10.0]);
+        let mut infinite = model();
+        infinite.clusters[0][1][0] = f64::INFINITY;
+        for (model, message) in [
+            (
+                ragged,
+                "core point 1 of cluster 1 has 1 coordinates, but the model has 2",
+            ),
+            (
+                infinite,
+                "core point 1 of cluster 0 has a coordinate that is not finite",
+            ),
+        ] {
+            write_model(&file, &model).unwrap();
+            let err = read_model(&file).unwrap_err();
+            assert_eq!(
+                format!("{:#}", err),
+                format!("'{}' is a corrupt model file: {}", file, message)
+            );
+            assert_eq!(model.predict(&[]).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn read_model_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path(&dir, "points.csv");
+        std::fs::write(&file, "1,2\n3,4\n").unwrap();
+        let err = read_model(&file).unwrap_err();
+        assert_eq!(err.to_string(), format!("'{}' is not a model file", file));
+
+        let file = path(&dir, "future.bin");
+        let mut bytes = b"KMDL".to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        std::fs::write(&file, bytes).unwrap();
+        let err = read_model(&file).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "'{}' has model format version 2; only version 1 is supported",
+                file
+            )
+        );
+    }
+}
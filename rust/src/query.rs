@@ -1,12 +1,26 @@
-use crate::algo::RegionQuery;
-use crate::types::{dist, Point};
+use crate::algo::{QueryError, RegionQuery, UsesMetric};
+use crate::types::{
+    aniso_dist_sq, check_aniso_eps, dist, dist_sq, eps_sq_threshold, MetricKind, Point,
+};
+use itertools::{Either, Itertools};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use rstar::primitives::GeomWithData;
-use rstar::RTree;
-use std::collections::HashSet;
+use rstar::{DefaultParams, RTree, RTreeNode, RTreeParams, AABB};
+use std::collections::{HashMap, HashSet};
+
+/// Largest dimensionality supported by `RTreeQueryEngine`.
+pub const RTREE_MAX_DIM: usize = 16;
 
 pub struct RTreeQueryEngine<'a> {
+    // `None` before `init` and after initializing with an empty point set.
     inner: Option<RTreeAnyDim<'a>>,
     dim: usize,
+    initialized: bool,
+    // Every `init` hands a copy to the per-dimension engine, so they survive
+    // re-initialization.
+    weights: Option<HashMap<&'a Point, f64>>,
+    metric: MetricKind,
 }
 
 impl<'a> RTreeQueryEngine<'a> {
@@ -14,129 +28,513 @@ impl<'a> RTreeQueryEngine<'a> {
         Self {
             inner: None,
             dim: 0,
+            initialized: false,
+            weights: None,
+            metric: MetricKind::Euclidean,
+        }
+    }
+
+    /// Engine whose weight queries use `weights` (keyed by indexed point)
+    /// instead of counting points. Points without an entry weigh 1.
+    pub fn with_weights(weights: HashMap<&'a Point, f64>) -> Self {
+        Self {
+            weights: Some(weights),
+            ..Self::new()
         }
     }
+
+    /// Engine that measures distances with `metric` instead of Euclidean. See
+    /// `RTreeQueryEngineN::with_metric`.
+    pub fn with_metric(metric: MetricKind) -> Self {
+        check_metric(metric);
+        Self {
+            metric,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for RTreeQueryEngine<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 enum RTreeAnyDim<'a> {
-    D1(RTree<GeomWithData<[f64; 1], &'a Point>>),
-    D2(RTree<GeomWithData<[f64; 2], &'a Point>>),
-    D3(RTree<GeomWithData<[f64; 3], &'a Point>>),
-    D4(RTree<GeomWithData<[f64; 4], &'a Point>>),
-    D5(RTree<GeomWithData<[f64; 5], &'a Point>>),
-    D6(RTree<GeomWithData<[f64; 6], &'a Point>>),
-    D7(RTree<GeomWithData<[f64; 7], &'a Point>>),
-    D8(RTree<GeomWithData<[f64; 8], &'a Point>>),
-    D9(RTree<GeomWithData<[f64; 9], &'a Point>>),
-    D10(RTree<GeomWithData<[f64; 10], &'a Point>>),
-    D11(RTree<GeomWithData<[f64; 11], &'a Point>>),
-    D12(RTree<GeomWithData<[f64; 12], &'a Point>>),
-    D13(RTree<GeomWithData<[f64; 13], &'a Point>>),
-    D14(RTree<GeomWithData<[f64; 14], &'a Point>>),
-    D15(RTree<GeomWithData<[f64; 15], &'a Point>>),
-    D16(RTree<GeomWithData<[f64; 16], &'a Point>>),
+    // rstar needs at least two dimensions, so 1-D points are indexed on the x
+    // axis of a 2-D tree.
+    D1(RTreeQueryEngineN<'a, 2>),
+    D2(RTreeQueryEngineN<'a, 2>),
+    D3(RTreeQueryEngineN<'a, 3>),
+    D4(RTreeQueryEngineN<'a, 4>),
+    D5(RTreeQueryEngineN<'a, 5>),
+    D6(RTreeQueryEngineN<'a, 6>),
+    D7(RTreeQueryEngineN<'a, 7>),
+    D8(RTreeQueryEngineN<'a, 8>),
+    D9(RTreeQueryEngineN<'a, 9>),
+    D10(RTreeQueryEngineN<'a, 10>),
+    D11(RTreeQueryEngineN<'a, 11>),
+    D12(RTreeQueryEngineN<'a, 12>),
+    D13(RTreeQueryEngineN<'a, 13>),
+    D14(RTreeQueryEngineN<'a, 14>),
+    D15(RTreeQueryEngineN<'a, 15>),
+    D16(RTreeQueryEngineN<'a, 16>),
+}
+
+// Number of `with_dim!` dispatches on this thread, so that tests can check
+// that the statically-dimensioned engine never goes through the match.
+#[cfg(test)]
+thread_local! {
+    static DISPATCHES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
 }
 
 // Small helper macro to dispatch over the concrete dimensionality at runtime
-// and run a block with bindings: `$tree` (the RTree) and const `$N` (usize).
+// and run `$body` with `$engine` bound to the statically-dimensioned engine.
 macro_rules! with_dim {
-    ($inner:expr, |$tree:ident, $N:ident| $body:block) => {
+    ($inner:expr, |$engine:ident| $body:expr) => {{
+        #[cfg(test)]
+        DISPATCHES.with(|n| n.set(n.get() + 1));
         match $inner {
-            RTreeAnyDim::D1($tree) => {
-                const $N: usize = 1;
-                $body
-            }
-            RTreeAnyDim::D2($tree) => {
-                const $N: usize = 2;
-                $body
-            }
-            RTreeAnyDim::D3($tree) => {
-                const $N: usize = 3;
-                $body
-            }
-            RTreeAnyDim::D4($tree) => {
-                const $N: usize = 4;
-                $body
-            }
-            RTreeAnyDim::D5($tree) => {
-                const $N: usize = 5;
-                $body
-            }
-            RTreeAnyDim::D6($tree) => {
-                const $N: usize = 6;
-                $body
-            }
-            RTreeAnyDim::D7($tree) => {
-                const $N: usize = 7;
-                $body
-            }
-            RTreeAnyDim::D8($tree) => {
-                const $N: usize = 8;
-                $body
-            }
-            RTreeAnyDim::D9($tree) => {
-                const $N: usize = 9;
-                $body
-            }
-            RTreeAnyDim::D10($tree) => {
-                const $N: usize = 10;
-                $body
-            }
-            RTreeAnyDim::D11($tree) => {
-                const $N: usize = 11;
-                $body
-            }
-            RTreeAnyDim::D12($tree) => {
-                const $N: usize = 12;
-                $body
-            }
-            RTreeAnyDim::D13($tree) => {
-                const $N: usize = 13;
-                $body
-            }
-            RTreeAnyDim::D14($tree) => {
-                const $N: usize = 14;
-                $body
-            }
-            RTreeAnyDim::D15($tree) => {
-                const $N: usize = 15;
-                $body
-            }
-            RTreeAnyDim::D16($tree) => {
-                const $N: usize = 16;
-                $body
-            }
+            RTreeAnyDim::D1($engine) => $body,
+            RTreeAnyDim::D2($engine) => $body,
+            RTreeAnyDim::D3($engine) => $body,
+            RTreeAnyDim::D4($engine) => $body,
+            RTreeAnyDim::D5($engine) => $body,
+            RTreeAnyDim::D6($engine) => $body,
+            RTreeAnyDim::D7($engine) => $body,
+            RTreeAnyDim::D8($engine) => $body,
+            RTreeAnyDim::D9($engine) => $body,
+            RTreeAnyDim::D10($engine) => $body,
+            RTreeAnyDim::D11($engine) => $body,
+            RTreeAnyDim::D12($engine) => $body,
+            RTreeAnyDim::D13($engine) => $body,
+            RTreeAnyDim::D14($engine) => $body,
+            RTreeAnyDim::D15($engine) => $body,
+            RTreeAnyDim::D16($engine) => $body,
         }
-    };
+    }};
 }
 
+// Points with fewer than `N` coordinates are padded with zeros, which leaves
+// every distance unchanged.
 fn to_array<const N: usize>(p: &Point) -> [f64; N] {
-    assert_eq!(
-        p.len(),
-        N,
-        "point dimension mismatch: expected {}, got {}",
+    assert!(
+        p.len() <= N,
+        "point dimension mismatch: expected at most {}, got {}",
         N,
         p.len()
     );
     let mut arr = [0.0_f64; N];
-    for i in 0..N {
-        arr[i] = p[i].0;
+    for (a, x) in arr.iter_mut().zip(p.iter()) {
+        *a = x.0;
     }
     arr
 }
 
-fn build_tree<'a, const N: usize>(
+fn check_metric(metric: MetricKind) {
+    metric.check();
+    assert!(
+        metric.is_lp(),
+        "RTreeQueryEngine only supports Lp metrics, got {:?}; for cosine or angular distance, normalize the points with types::normalize_l2 and use Euclidean",
+        metric
+    );
+}
+
+/// R-tree engine for points whose dimensionality `N` is known at compile time.
+/// `RTreeQueryEngine` delegates to this after picking `N` at runtime.
+///
+/// rstar requires `N >= 2`. Indexed points may have fewer than `N` coordinates
+/// (they are padded with zeros), as long as they all have the same number.
+pub struct RTreeQueryEngineN<'a, const N: usize> {
+    tree: Option<RTree<GeomWithData<[f64; N], &'a Point>>>,
+    // Dimensionality of the indexed points.
+    dim: usize,
+    weights: Option<HashMap<&'a Point, f64>>,
+    metric: MetricKind,
+}
+
+impl<'a, const N: usize> RTreeQueryEngineN<'a, N> {
+    pub fn new() -> Self {
+        Self {
+            tree: None,
+            dim: N,
+            weights: None,
+            metric: MetricKind::Euclidean,
+        }
+    }
+
+    /// Engine whose weight queries use `weights` (keyed by indexed point)
+    /// instead of counting points. Points without an entry weigh 1.
+    pub fn with_weights(weights: HashMap<&'a Point, f64>) -> Self {
+        Self {
+            weights: Some(weights),
+            ..Self::new()
+        }
+    }
+
+    /// Engine that measures distances with `metric` instead of Euclidean. The
+    /// tree itself stays Euclidean; other metrics are answered with box queries
+    /// followed by an exact filter, so only Lp metrics are supported.
+    pub fn with_metric(metric: MetricKind) -> Self {
+        check_metric(metric);
+        Self {
+            metric,
+            ..Self::new()
+        }
+    }
+
+    /// Returns the indexed point closest to `point`, which does not need to be
+    /// part of the indexed set. `None` if nothing is indexed, including before
+    /// `init`.
+    pub fn nearest(&self, point: &Point) -> Option<&'a Point> {
+        let tree = self.tree.as_ref()?;
+        self.check_dim(point);
+        let nearest = tree
+            .nearest_neighbor(&to_array::<N>(point))
+            .map(|it| it.data)?;
+        if self.metric == MetricKind::Euclidean {
+            return Some(nearest);
+        }
+        self.locate_within(point, self.dist(point, nearest))
+            .min_by(|a, b| self.dist(point, a).total_cmp(&self.dist(point, b)))
+    }
+
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.metric.distance(a, b)
+    }
+
+    // The squared distance for Euclidean, `dist` otherwise; see `locate_within`.
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        match self.metric {
+            MetricKind::Euclidean => dist_sq(a, b),
+            metric => metric.distance(a, b),
+        }
+    }
+
+    fn check_query(&self, point: &Point) -> Result<(), QueryError> {
+        if self.tree.is_none() {
+            return Err(QueryError::NotInitialized);
+        }
+        if point.len() != self.dim {
+            return Err(QueryError::dimension_mismatch(point, self.dim));
+        }
+        Ok(())
+    }
+
+    // Panics like `run` if `check_query` fails. Without it, `to_array` would
+    // pad a query with too few coordinates and answer it.
+    fn check_dim(&self, point: &Point) {
+        if let Err(e) = self.check_query(point) {
+            panic!("{}", e);
+        }
+    }
+
+    fn tree(&self) -> &RTree<GeomWithData<[f64; N], &'a Point>> {
+        self.tree
+            .as_ref()
+            .expect("RTreeQueryEngine is not initialized")
+    }
+
+    fn weight_of(&self, point: &Point) -> f64 {
+        match &self.weights {
+            Some(weights) => weights.get(point).copied().unwrap_or(1.0),
+            None => 1.0,
+        }
+    }
+
+    // Indexed points `p` with `dist(point, p) <= eps`. rstar computes squared
+    // distances its own way, which can round differently at the boundary, so the
+    // tree is searched with a slightly larger radius and `eps_sq_threshold`
+    // decides, exactly as in the other engines. Any other metric is at least the
+    // largest coordinate difference, so its ball fits in the box of half-width
+    // eps.
+    fn locate_within(&self, point: &Point, eps: f64) -> impl Iterator<Item = &'a Point> {
+        let (candidates, bound) = if self.metric == MetricKind::Euclidean {
+            let bound = eps_sq_threshold(eps);
+            let radius_sq = bound * (1.0 + 4.0 * f64::EPSILON);
+            let candidates = self
+                .tree()
+                .locate_within_distance(to_array::<N>(point), radius_sq);
+            (Either::Left(candidates), bound)
+        } else {
+            let half_width = eps * (1.0 + 4.0 * f64::EPSILON);
+            let q = to_array::<N>(point);
+            let lo = q.map(|x| (x - half_width).next_down());
+            let hi = q.map(|x| (x + half_width).next_up());
+            let candidates = self.tree().locate_in_envelope(&AABB::from_corners(lo, hi));
+            (Either::Right(candidates), eps)
+        };
+        candidates
+            .map(|it| it.data)
+            .filter(move |p| self.key(point, p) <= bound)
+    }
+
+    // Under a non-Euclidean metric, the neighbors of `point` (excluding the
+    // point itself) sorted by distance, including every neighbor tied with the
+    // k-th. The k Euclidean-nearest neighbors bound the k-th distance by r, and
+    // `locate_within(r)` then returns every candidate.
+    fn metric_neighbors(&self, point: &Point, k: usize) -> Vec<(&'a Point, f64)> {
+        let r = self
+            .tree()
+            .nearest_neighbor_iter(&to_array::<N>(point))
+            .map(|it| it.data)
+            .filter(|&other| !std::ptr::eq(other, point))
+            .take(k)
+            .map(|other| self.dist(point, other))
+            .fold(0.0, f64::max);
+        self.locate_within(point, r)
+            .filter(|&other| !std::ptr::eq(other, point))
+            .map(|other| (other, self.dist(point, other)))
+            .sorted_by(|a, b| a.1.total_cmp(&b.1))
+            .collect()
+    }
+}
+
+impl<const N: usize> Default for RTreeQueryEngineN<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Every entry lives in its parent's children vector as an `RTreeNode`. With
+// bulk loading, nodes are close to full, so there is roughly one parent per
+// `MAX_SIZE - 1` children at each level.
+fn estimate_tree_bytes<const N: usize>(len: usize) -> usize {
+    let node = size_of::<RTreeNode<GeomWithData<[f64; N], &Point>>>();
+    let fanout = DefaultParams::MAX_SIZE - 1;
+    let mut nodes = len;
+    let mut level = len;
+    while level > 1 {
+        level = level.div_ceil(fanout);
+        nodes += level;
+    }
+    nodes * node
+}
+
+impl<const N: usize> UsesMetric<MetricKind> for RTreeQueryEngineN<'_, N> {}
+
+impl<'a, const N: usize> RegionQuery<'a> for RTreeQueryEngineN<'a, N> {
+    fn init(&mut self, points: &'a HashSet<&'a Point>) {
+        let entries = points
+            .iter()
+            .map(|&p| GeomWithData::new(to_array::<N>(p), p))
+            .collect::<Vec<_>>();
+        self.dim = points.iter().next().map_or(N, |p| p.len());
+        self.tree = Some(RTree::bulk_load(entries));
+    }
+
+    fn run(&self, point: &Point, eps: f64) -> HashSet<&'a Point> {
+        self.try_run(point, eps).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_run(&self, point: &Point, eps: f64) -> Result<HashSet<&'a Point>, QueryError> {
+        self.check_query(point)?;
+        Ok(self.run_filtered(point, eps, &|_| true))
+    }
+
+    // The predicate is applied while walking the tree, so rejected points never
+    // enter the result set.
+    fn run_filtered(
+        &self,
+        point: &Point,
+        eps: f64,
+        pred: &dyn Fn(&Point) -> bool,
+    ) -> HashSet<&'a Point> {
+        self.check_dim(point);
+        self.locate_within(point, eps).filter(|p| pred(p)).collect()
+    }
+
+    fn k_dist(&self, point: &Point, k: usize) -> f64 {
+        self.check_dim(point);
+        assert!(k > 0, "k must be >= 1");
+        if self.metric != MetricKind::Euclidean {
+            return self.k_dist_with_count(point, k).0;
+        }
+
+        let q = to_array::<N>(point);
+        let mut seen = 0usize;
+        for item in self.tree().nearest_neighbor_iter(&q) {
+            let other = item.data;
+            if std::ptr::eq(other, point) {
+                continue;
+            }
+            seen += 1;
+            if seen == k {
+                return dist(point, other);
+            }
+        }
+        panic!("k={} is out of range for dataset", k);
+    }
+
+    fn k_dist_with_count(&self, point: &Point, k: usize) -> (f64, usize) {
+        self.check_dim(point);
+        assert!(k > 0, "k must be >= 1");
+        if self.metric != MetricKind::Euclidean {
+            let neighbors = self.metric_neighbors(point, k);
+            let Some(&(_, d)) = neighbors.get(k - 1) else {
+                panic!("k={} is out of range for dataset", k);
+            };
+            return (d, neighbors.partition_point(|&(_, x)| x <= d));
+        }
+
+        let q = to_array::<N>(point);
+        // The k-th distance and the matching `eps_sq_threshold`.
+        let mut kth = None;
+        let mut seen = 0usize;
+        for item in self.tree().nearest_neighbor_iter(&q) {
+            let other = item.data;
+            if std::ptr::eq(other, point) {
+                continue;
+            }
+            // Keep counting past the k-th neighbor while distances tie.
+            if matches!(kth, Some((_, bound)) if dist_sq(point, other) > bound) {
+                break;
+            }
+            seen += 1;
+            if seen == k {
+                let d = dist(point, other);
+                kth = Some((d, eps_sq_threshold(d)));
+            }
+        }
+        match kth {
+            Some((d, _)) => (d, seen),
+            None => panic!("k={} is out of range for dataset", k),
+        }
+    }
+
+    // Walks the bounding box of the ellipsoid, then filters by scaled distance.
+    fn run_aniso(&self, point: &Point, eps_per_dim: &[f64]) -> HashSet<&'a Point> {
+        self.check_dim(point);
+        check_aniso_eps(point, eps_per_dim);
+
+        let q = to_array::<N>(point);
+        let mut lo = q;
+        let mut hi = q;
+        for (i, e) in eps_per_dim.iter().enumerate() {
+            lo[i] -= e;
+            hi[i] += e;
+        }
+        self.tree()
+            .locate_in_envelope(&AABB::from_corners(lo, hi))
+            .map(|it| it.data)
+            .filter(|p| aniso_dist_sq(point, p, eps_per_dim) <= 1.0)
+            .collect()
+    }
+
+    fn weight_within(&self, point: &Point, eps: f64) -> f64 {
+        self.check_dim(point);
+        self.locate_within(point, eps)
+            .map(|p| self.weight_of(p))
+            .sum()
+    }
+
+    fn weight_within_at_least(&self, point: &Point, eps: f64, threshold: f64) -> bool {
+        self.check_dim(point);
+        if threshold <= 0.0 {
+            return true;
+        }
+
+        let mut total = 0.0;
+        for p in self.locate_within(point, eps) {
+            total += self.weight_of(p);
+            if total >= threshold {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn knn(&self, point: &Point, k: usize) -> Vec<(&'a Point, f64)> {
+        self.check_dim(point);
+        if self.metric != MetricKind::Euclidean {
+            let mut neighbors = self.metric_neighbors(point, k);
+            neighbors.truncate(k);
+            return neighbors;
+        }
+
+        self.tree()
+            .nearest_neighbor_iter(&to_array::<N>(point))
+            .map(|it| it.data)
+            .filter(|&other| !std::ptr::eq(other, point))
+            .take(k)
+            .map(|other| (other, dist(point, other)))
+            .collect()
+    }
+
+    // The tree is immutable during queries, so independent queries can be
+    // answered in parallel.
+    #[cfg(feature = "parallel")]
+    fn run_many(&self, queries: &[&Point], eps: f64) -> Vec<HashSet<&'a Point>> {
+        queries.par_iter().map(|&q| self.run(q, eps)).collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn knn_many(&self, queries: &[&Point], k: usize) -> Vec<Vec<(&'a Point, f64)>> {
+        queries.par_iter().map(|&q| self.knn(q, k)).collect()
+    }
+
+    fn estimated_memory_bytes(&self) -> usize {
+        self.tree
+            .as_ref()
+            .map_or(0, |tree| estimate_tree_bytes::<N>(tree.size()))
+    }
+}
+
+fn build<'a, const N: usize>(
     points: &'a HashSet<&'a Point>,
-) -> RTree<GeomWithData<[f64; N], &'a Point>> {
-    let entries = points
-        .iter()
-        .map(|&p| GeomWithData::new(to_array::<N>(p), p))
-        .collect::<Vec<_>>();
-    RTree::bulk_load(entries)
+    weights: Option<HashMap<&'a Point, f64>>,
+    metric: MetricKind,
+) -> RTreeQueryEngineN<'a, N> {
+    let mut engine = RTreeQueryEngineN {
+        weights,
+        metric,
+        ..RTreeQueryEngineN::new()
+    };
+    engine.init(points);
+    engine
+}
+
+impl<'a> RTreeQueryEngine<'a> {
+    /// Returns the indexed point closest to `point`, which does not need to be
+    /// part of the indexed set. `None` if nothing is indexed.
+    pub fn nearest(&self, point: &Point) -> Option<&'a Point> {
+        let inner = self.inner.as_ref()?;
+        self.check_dim(point);
+        with_dim!(inner, |engine| engine.nearest(point))
+    }
+
+    fn check_query(&self, point: &Point) -> Result<(), QueryError> {
+        if !self.initialized {
+            return Err(QueryError::NotInitialized);
+        }
+        if self.inner.is_some() && point.len() != self.dim {
+            return Err(QueryError::dimension_mismatch(point, self.dim));
+        }
+        Ok(())
+    }
+
+    fn check_dim(&self, point: &Point) {
+        if let Err(e) = self.check_query(point) {
+            panic!("{}", e);
+        }
+    }
+
+    // The tree of an initialized engine; an empty one has no k-th neighbor.
+    fn nonempty(&self, k: usize) -> &RTreeAnyDim<'a> {
+        assert!(k > 0, "k must be >= 1");
+        self.inner
+            .as_ref()
+            .unwrap_or_else(|| panic!("k={} is out of range for dataset", k))
+    }
 }
 
+impl UsesMetric<MetricKind> for RTreeQueryEngine<'_> {}
+
 impl<'a> RegionQuery<'a> for RTreeQueryEngine<'a> {
     fn init(&mut self, points: &'a HashSet<&'a Point>) {
+        let weights = self.weights.clone();
+
+        self.initialized = true;
         let Some(&first) = points.iter().next() else {
             self.inner = None;
             self.dim = 0;
@@ -147,22 +545,22 @@ impl<'a> RegionQuery<'a> for RTreeQueryEngine<'a> {
         debug_assert!(points.iter().all(|&p| p.len() == d));
 
         self.inner = Some(match d {
-            1 => RTreeAnyDim::D1(build_tree::<1>(points)),
-            2 => RTreeAnyDim::D2(build_tree::<2>(points)),
-            3 => RTreeAnyDim::D3(build_tree::<3>(points)),
-            4 => RTreeAnyDim::D4(build_tree::<4>(points)),
-            5 => RTreeAnyDim::D5(build_tree::<5>(points)),
-            6 => RTreeAnyDim::D6(build_tree::<6>(points)),
-            7 => RTreeAnyDim::D7(build_tree::<7>(points)),
-            8 => RTreeAnyDim::D8(build_tree::<8>(points)),
-            9 => RTreeAnyDim::D9(build_tree::<9>(points)),
-            10 => RTreeAnyDim::D10(build_tree::<10>(points)),
-            11 => RTreeAnyDim::D11(build_tree::<11>(points)),
-            12 => RTreeAnyDim::D12(build_tree::<12>(points)),
-            13 => RTreeAnyDim::D13(build_tree::<13>(points)),
-            14 => RTreeAnyDim::D14(build_tree::<14>(points)),
-            15 => RTreeAnyDim::D15(build_tree::<15>(points)),
-            16 => RTreeAnyDim::D16(build_tree::<16>(points)),
+            1 => RTreeAnyDim::D1(build(points, weights, self.metric)),
+            2 => RTreeAnyDim::D2(build(points, weights, self.metric)),
+            3 => RTreeAnyDim::D3(build(points, weights, self.metric)),
+            4 => RTreeAnyDim::D4(build(points, weights, self.metric)),
+            5 => RTreeAnyDim::D5(build(points, weights, self.metric)),
+            6 => RTreeAnyDim::D6(build(points, weights, self.metric)),
+            7 => RTreeAnyDim::D7(build(points, weights, self.metric)),
+            8 => RTreeAnyDim::D8(build(points, weights, self.metric)),
+            9 => RTreeAnyDim::D9(build(points, weights, self.metric)),
+            10 => RTreeAnyDim::D10(build(points, weights, self.metric)),
+            11 => RTreeAnyDim::D11(build(points, weights, self.metric)),
+            12 => RTreeAnyDim::D12(build(points, weights, self.metric)),
+            13 => RTreeAnyDim::D13(build(points, weights, self.metric)),
+            14 => RTreeAnyDim::D14(build(points, weights, self.metric)),
+            15 => RTreeAnyDim::D15(build(points, weights, self.metric)),
+            16 => RTreeAnyDim::D16(build(points, weights, self.metric)),
             _ => panic!(
                 "RTreeQueryEngine supports dimensions 1..=16; got {}. Consider using FakeQueryEngine or extend support.",
                 d
@@ -171,47 +569,345 @@ impl<'a> RegionQuery<'a> for RTreeQueryEngine<'a> {
         self.dim = d;
     }
 
-    fn run(&self, point: &'a Point, eps: f64) -> HashSet<&'a Point> {
+    fn run(&self, point: &Point, eps: f64) -> HashSet<&'a Point> {
+        self.try_run(point, eps).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_run(&self, point: &Point, eps: f64) -> Result<HashSet<&'a Point>, QueryError> {
+        self.check_query(point)?;
+        Ok(self.run_filtered(point, eps, &|_| true))
+    }
+
+    fn run_filtered(
+        &self,
+        point: &Point,
+        eps: f64,
+        pred: &dyn Fn(&Point) -> bool,
+    ) -> HashSet<&'a Point> {
+        self.check_dim(point);
         let Some(ref inner) = self.inner else {
             return HashSet::new();
         };
+        with_dim!(inner, |engine| engine.run_filtered(point, eps, pred))
+    }
 
-        assert_eq!(
-            point.len(),
-            self.dim,
-            "query point dimension {} does not match tree dimension {}",
-            point.len(),
-            self.dim
-        );
+    fn k_dist(&self, point: &Point, k: usize) -> f64 {
+        self.check_dim(point);
+        with_dim!(self.nonempty(k), |engine| engine.k_dist(point, k))
+    }
 
-        let eps_sq = eps * eps;
-        with_dim!(inner, |tree, N| {
-            tree.locate_within_distance(to_array::<N>(point), eps_sq)
-                .map(|it| it.data)
-                .collect()
+    fn k_dist_with_count(&self, point: &Point, k: usize) -> (f64, usize) {
+        self.check_dim(point);
+        with_dim!(self.nonempty(k), |engine| {
+            engine.k_dist_with_count(point, k)
         })
     }
 
-    fn k_dist(&self, point: &'a Point, k: usize) -> f64 {
+    fn run_aniso(&self, point: &Point, eps_per_dim: &[f64]) -> HashSet<&'a Point> {
+        self.check_dim(point);
         let Some(ref inner) = self.inner else {
-            panic!("RTreeQueryEngine is not initialized");
+            return HashSet::new();
         };
-        assert!(k > 0, "k must be >= 1");
+        with_dim!(inner, |engine| engine.run_aniso(point, eps_per_dim))
+    }
 
-        with_dim!(inner, |tree, N| {
-            let q = to_array::<N>(point);
-            let mut seen = 0usize;
-            for item in tree.nearest_neighbor_iter(&q) {
-                let other = item.data;
-                if std::ptr::eq(other, point) {
-                    continue;
-                }
-                seen += 1;
-                if seen == k {
-                    return dist(point, other);
+    fn weight_within(&self, point: &Point, eps: f64) -> f64 {
+        self.check_dim(point);
+        let Some(ref inner) = self.inner else {
+            return 0.0;
+        };
+        with_dim!(inner, |engine| engine.weight_within(point, eps))
+    }
+
+    fn weight_within_at_least(&self, point: &Point, eps: f64, threshold: f64) -> bool {
+        self.check_dim(point);
+        let Some(ref inner) = self.inner else {
+            return threshold <= 0.0;
+        };
+        with_dim!(inner, |engine| {
+            engine.weight_within_at_least(point, eps, threshold)
+        })
+    }
+
+    fn knn(&self, point: &Point, k: usize) -> Vec<(&'a Point, f64)> {
+        self.check_dim(point);
+        let Some(ref inner) = self.inner else {
+            return Vec::new();
+        };
+        with_dim!(inner, |engine| engine.knn(point, k))
+    }
+
+    fn run_many(&self, queries: &[&Point], eps: f64) -> Vec<HashSet<&'a Point>> {
+        queries.iter().for_each(|q| self.check_dim(q));
+        let Some(ref inner) = self.inner else {
+            return vec![HashSet::new(); queries.len()];
+        };
+        with_dim!(inner, |engine| engine.run_many(queries, eps))
+    }
+
+    fn knn_many(&self, queries: &[&Point], k: usize) -> Vec<Vec<(&'a Point, f64)>> {
+        queries.iter().for_each(|q| self.check_dim(q));
+        let Some(ref inner) = self.inner else {
+            return vec![Vec::new(); queries.len()];
+        };
+        with_dim!(inner, |engine| engine.knn_many(queries, k))
+    }
+
+    fn estimated_memory_bytes(&self) -> usize {
+        let Some(ref inner) = self.inner else {
+            return 0;
+        };
+        with_dim!(inner, |engine| engine.estimated_memory_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::SplitMix64;
+    use ordered_float::OrderedFloat;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    #[test]
+    fn nearest_is_none_before_init() {
+        let engine = RTreeQueryEngineN::<2>::new();
+        assert_eq!(engine.nearest(&pt(&[0.0, 0.0])), None);
+        let engine = RTreeQueryEngine::new();
+        assert_eq!(engine.nearest(&pt(&[0.0, 0.0])), None);
+    }
+
+    #[test]
+    fn weights_survive_reinit() {
+        let points = [pt(&[0.0, 0.0]), pt(&[1.0, 0.0]), pt(&[5.0, 0.0])];
+        let refs = points.iter().collect::<HashSet<_>>();
+        let weights = HashMap::from([(&points[0], 2.5), (&points[1], 4.0)]);
+        let mut engine = RTreeQueryEngine::with_weights(weights);
+        for _ in 0..3 {
+            engine.init(&refs);
+            assert_eq!(engine.weight_within(&points[0], 1.0), 6.5);
+            assert_eq!(engine.weight_within(&points[2], 1.0), 1.0);
+        }
+
+        // An empty set in between does not lose them either.
+        let empty = HashSet::new();
+        engine.init(&empty);
+        engine.init(&refs);
+        assert_eq!(engine.weight_within(&points[0], 1.0), 6.5);
+    }
+
+    // `n` random points of dimension `dim`, on a grid so that distances tie.
+    fn random_points(seed: u64, n: usize, dim: usize) -> Vec<Point> {
+        let mut rng = SplitMix64::new(seed);
+        (0..n)
+            .map(|_| {
+                let coords = (0..dim)
+                    .map(|_| (rng.below(1000) as f64) / 100.0)
+                    .collect::<Vec<_>>();
+                pt(&coords)
+            })
+            .collect()
+    }
+
+    // Every query of the static engine `fixed` against the dynamic engine over
+    // the same points, indexed points and others alike.
+    fn assert_same_answers<const N: usize>(points: &[Point], metric: MetricKind) {
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut dynamic = RTreeQueryEngine::with_metric(metric);
+        dynamic.init(&refs);
+        let mut fixed = RTreeQueryEngineN::<N>::with_metric(metric);
+        fixed.init(&refs);
+        let dim = points[0].len();
+        let others = random_points(N as u64, 20, dim);
+        let queries = points.iter().chain(&others).collect::<Vec<_>>();
+
+        for &p in queries.iter() {
+            for eps in [0.5, 1.0, 2.5] {
+                assert_eq!(dynamic.run(p, eps), fixed.run(p, eps), "{:?}", p);
+                assert_eq!(dynamic.try_run(p, eps), fixed.try_run(p, eps));
+                assert_eq!(dynamic.weight_within(p, eps), fixed.weight_within(p, eps));
+                assert_eq!(
+                    dynamic.weight_within_at_least(p, eps, 3.0),
+                    fixed.weight_within_at_least(p, eps, 3.0)
+                );
+            }
+            let axes = (1..=dim).map(|i| i as f64 / 2.0).collect::<Vec<_>>();
+            assert_eq!(dynamic.run_aniso(p, &axes), fixed.run_aniso(p, &axes));
+            let odd = |q: &Point| q[0].0 * 100.0 % 2.0 == 1.0;
+            assert_eq!(
+                dynamic.run_filtered(p, 2.0, &odd),
+                fixed.run_filtered(p, 2.0, &odd)
+            );
+            for k in [1, 4] {
+                assert_eq!(dynamic.k_dist(p, k), fixed.k_dist(p, k));
+                assert_eq!(
+                    dynamic.k_dist_with_count(p, k),
+                    fixed.k_dist_with_count(p, k)
+                );
+                assert_eq!(dynamic.knn(p, k), fixed.knn(p, k));
+            }
+            assert_eq!(dynamic.nearest(p), fixed.nearest(p));
+        }
+        assert_eq!(
+            dynamic.run_many(&queries, 1.0),
+            fixed.run_many(&queries, 1.0)
+        );
+        assert_eq!(dynamic.knn_many(&queries, 3), fixed.knn_many(&queries, 3));
+        assert_eq!(
+            dynamic.estimated_memory_bytes(),
+            fixed.estimated_memory_bytes()
+        );
+    }
+
+    #[test]
+    fn static_engine_matches_dynamic_engine() {
+        for metric in [
+            MetricKind::Euclidean,
+            MetricKind::Manhattan,
+            MetricKind::Chebyshev,
+        ] {
+            assert_same_answers::<2>(&random_points(2, 200, 2), metric);
+            assert_same_answers::<3>(&random_points(3, 200, 3), metric);
+            // 1-D points are padded onto the x axis of a 2-D tree.
+            assert_same_answers::<2>(&random_points(1, 200, 1), metric);
+        }
+    }
+
+    #[test]
+    fn only_the_dynamic_engine_dispatches_on_the_dimension() {
+        let dispatches = || DISPATCHES.with(|n| n.get());
+        let points = random_points(4, 50, 2);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut dynamic = RTreeQueryEngine::new();
+        dynamic.init(&refs);
+        let mut fixed = RTreeQueryEngineN::<2>::new();
+        fixed.init(&refs);
+        let queries = points.iter().collect::<Vec<_>>();
+
+        // One match per query on the dynamic engine, none on the static one.
+        let before = dispatches();
+        for p in points.iter() {
+            dynamic.run(p, 1.0);
+            dynamic.k_dist(p, 2);
+            dynamic.knn(p, 2);
+        }
+        dynamic.run_many(&queries, 1.0);
+        assert_eq!(dispatches() - before, 3 * points.len() + 1);
+
+        let before = dispatches();
+        for p in points.iter() {
+            fixed.run(p, 1.0);
+            fixed.k_dist(p, 2);
+            fixed.knn(p, 2);
+        }
+        fixed.run_many(&queries, 1.0);
+        assert_eq!(dispatches(), before);
+    }
+
+    #[test]
+    fn lp_metrics_match_brute_force() {
+        use crate::brute_query::BruteForceQueryEngine;
+
+        let points = random_points(339, 150, 3);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let metrics = [
+            MetricKind::Manhattan,
+            MetricKind::Chebyshev,
+            MetricKind::Minkowski(3.0),
+        ];
+        for metric in metrics {
+            let mut rtree = RTreeQueryEngine::with_metric(metric);
+            rtree.init(&refs);
+            let mut brute = BruteForceQueryEngine::with_metric(metric);
+            brute.init(&refs);
+            for p in points.iter() {
+                for eps in [0.5, 1.5, 3.0] {
+                    assert_eq!(rtree.run(p, eps), brute.run(p, eps), "{:?}", metric);
                 }
+                assert_eq!(rtree.k_dist(p, 3), brute.k_dist(p, 3), "{:?}", metric);
+                let nearest = rtree.nearest(p).unwrap();
+                assert_eq!(nearest, p, "{:?}", metric);
             }
-            panic!("k={} is out of range for dataset", k);
-        })
+        }
+    }
+
+    #[test]
+    fn memory_estimates_scale_with_the_number_of_points() {
+        let estimate = |n: usize, dim: usize| {
+            let points = (0..n).map(|i| pt(&vec![i as f64; dim])).collect::<Vec<_>>();
+            let refs = points.iter().collect::<HashSet<_>>();
+            let mut rtree = RTreeQueryEngine::new();
+            rtree.init(&refs);
+            let mut fake = crate::fake_query::FakeQueryEngine::new();
+            fake.init(&refs);
+            (
+                rtree.estimated_memory_bytes() as f64,
+                fake.estimated_memory_bytes() as f64,
+            )
+        };
+        assert_eq!(RTreeQueryEngine::new().estimated_memory_bytes(), 0);
+
+        // Doubling the points doubles the tree and quadruples the lists.
+        let (rtree, fake) = estimate(300, 2);
+        let (rtree2, fake2) = estimate(600, 2);
+        assert!((1.6..2.4).contains(&(rtree2 / rtree)), "{}", rtree2 / rtree);
+        assert!((3.6..4.4).contains(&(fake2 / fake)), "{}", fake2 / fake);
+        assert!(rtree2 < fake2 / 50.0);
+
+        // Wider points take more room in the tree.
+        let (rtree3, _) = estimate(600, 3);
+        assert!(rtree3 > rtree2);
+    }
+
+    #[test]
+    fn batch_queries_match_one_query_at_a_time() {
+        let points = random_points(323, 2000, 2);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = RTreeQueryEngine::new();
+        engine.init(&refs);
+        // Indexed points and as many others, interleaved.
+        let others = random_points(324, 2000, 2);
+        let queries = points
+            .iter()
+            .zip(&others)
+            .flat_map(|(p, q)| [p, q])
+            .collect::<Vec<_>>();
+
+        let runs = engine.run_many(&queries, 0.3);
+        assert_eq!(runs.len(), queries.len());
+        for (q, run) in queries.iter().zip(&runs) {
+            assert_eq!(run, &engine.run(q, 0.3), "{:?}", q);
+        }
+        let knns = engine.knn_many(&queries, 5);
+        assert_eq!(knns.len(), queries.len());
+        for (q, knn) in queries.iter().zip(&knns) {
+            assert_eq!(knn, &engine.knn(q, 5), "{:?}", q);
+        }
+        let k_dists = crate::algo::k_distances(&engine, &queries, 5);
+        for (q, d) in queries.iter().zip(k_dists) {
+            assert_eq!(d, engine.knn(q, 5)[4].1, "{:?}", q);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn batch_queries_do_not_depend_on_the_thread_count() {
+        let points = random_points(325, 3000, 3);
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = RTreeQueryEngine::new();
+        engine.init(&refs);
+        let queries = points.iter().collect::<Vec<_>>();
+        let in_pool = |threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| (engine.run_many(&queries, 0.8), engine.knn_many(&queries, 4)))
+        };
+        let serial = in_pool(1);
+        assert_eq!(in_pool(4), serial);
+        assert_eq!(in_pool(8), serial);
     }
 }
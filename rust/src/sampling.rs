@@ -0,0 +1,287 @@
+use crate::algo::RegionQuery;
+use crate::query::RTreeQueryEngine;
+use crate::types::{dist, Point};
+use std::collections::HashSet;
+
+/// Greedy eps-net: every point ends up within `eps` of a selected point, and
+/// selected points are pairwise more than `eps` apart.
+///
+/// Points are visited in slice order starting at index `seed % N`, so the
+/// result is deterministic. Coverage is tracked with region queries on an
+/// R-tree instead of comparing every pair of points.
+pub fn eps_net(points: &[Point], eps: f64, seed: usize) -> Vec<&Point> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+
+    let refs: HashSet<&Point> = points.iter().collect();
+    let mut engine = RTreeQueryEngine::new();
+    engine.init(&refs);
+
+    let start = seed % points.len();
+    let mut covered = HashSet::new();
+    let mut selected = Vec::new();
+    for p in points[start..].iter().chain(points[..start].iter()) {
+        if covered.contains(p) {
+            continue;
+        }
+        // `p` is farther than eps from every selected point, otherwise one of
+        // their queries would have covered it.
+        selected.push(p);
+        covered.extend(engine.run(p, eps));
+    }
+
+    selected
+}
+
+/// Farthest-point sampling: starting from index `seed % N`, repeatedly picks
+/// the point farthest from everything picked so far until `m` points are
+/// selected (or the input is exhausted). Ties go to the lowest index.
+///
+/// This takes O(N * m) distance evaluations.
+pub fn fps(points: &[Point], m: usize, seed: usize) -> Vec<&Point> {
+    if points.is_empty() || m == 0 {
+        return Vec::new();
+    }
+
+    let mut current = seed % points.len();
+    let mut selected = vec![&points[current]];
+    // Distance from each point to its nearest selected point.
+    let mut nearest = vec![f64::INFINITY; points.len()];
+
+    while selected.len() < m.min(points.len()) {
+        for (i, p) in points.iter().enumerate() {
+            nearest[i] = nearest[i].min(dist(p, &points[current]));
+        }
+        let (i, d) = nearest
+            .iter()
+            .enumerate()
+            .fold((0, f64::NEG_INFINITY), |best, (i, &d)| {
+                if d > best.1 {
+                    (i, d)
+                } else {
+                    best
+                }
+            });
+
+        // Every remaining point coincides with a selected one.
+        if d == 0.0 {
+            break;
+        }
+        current = i;
+        selected.push(&points[current]);
+    }
+
+    selected
+}
+
+/// Small seeded pseudo-random generator (SplitMix64). A seed gives the same
+/// sequence on every platform and in every version, so seeded results can be
+/// reproduced.
+#[derive(Debug, Clone)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `0..n`. Panics if `n` is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "empty range");
+        // Lemire's method: take the high word of a 128-bit product, rejecting
+        // the few low words that would make some results more likely.
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let m = u128::from(self.next_u64()) * u128::from(n);
+            if (m as u64) >= threshold {
+                return (m >> 64) as u64;
+            }
+        }
+    }
+}
+
+/// Uniform random sample of `n` of `items` (all of them if there are fewer),
+/// taken in one pass without holding the others (reservoir sampling). The
+/// same `seed` gives the same sample. The sample keeps the order of `items`.
+pub fn reservoir_sample<T>(items: impl IntoIterator<Item = T>, n: usize, seed: u64) -> Vec<T> {
+    let mut rng = SplitMix64::new(seed);
+    let mut reservoir: Vec<(usize, T)> = Vec::with_capacity(n.min(1 << 20));
+    for (i, item) in items.into_iter().enumerate() {
+        if i < n {
+            reservoir.push((i, item));
+        } else {
+            let j = rng.below(i as u64 + 1) as usize;
+            if j < n {
+                reservoir[j] = (i, item);
+            }
+        }
+    }
+    reservoir.sort_unstable_by_key(|&(i, _)| i);
+    reservoir.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Random permutation of `0..n` (Fisher-Yates); the same `seed` gives the same
+/// permutation.
+pub fn permutation(n: usize, seed: u64) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed);
+    let mut perm = (0..n).collect::<Vec<_>>();
+    for i in (1..n).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        perm.swap(i, j);
+    }
+    perm
+}
+
+/// Reorders `items` so that `items[i]` is the item formerly at `perm[i]`.
+/// Panics unless `perm` is a permutation of the indices of `items`.
+pub fn permute<T>(items: &mut Vec<T>, perm: &[usize]) {
+    assert_eq!(perm.len(), items.len(), "permutation length mismatch");
+    let mut old = items.drain(..).map(Some).collect::<Vec<_>>();
+    items.extend(
+        perm.iter()
+            .map(|&i| old[i].take().expect("not a permutation")),
+    );
+}
+
+/// Undoes `permute(items, perm)`.
+pub fn unpermute<T>(items: &mut Vec<T>, perm: &[usize]) {
+    assert_eq!(perm.len(), items.len(), "permutation length mismatch");
+    let mut slots = (0..items.len()).map(|_| None).collect::<Vec<_>>();
+    for (item, &i) in items.drain(..).zip(perm) {
+        slots[i] = Some(item);
+    }
+    items.extend(slots.into_iter().map(|s| s.expect("not a permutation")));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ordered_float::OrderedFloat;
+
+    fn random_points(seed: u64, n: usize) -> Vec<Point> {
+        let mut rng = SplitMix64::new(seed);
+        (0..n)
+            .map(|_| {
+                let mut c = || OrderedFloat(rng.below(1000) as f64 / 100.0);
+                vec![c(), c()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eps_nets_cover_every_point_and_are_separated() {
+        let points = random_points(321, 500);
+        for eps in [0.25, 1.0, 3.0] {
+            for seed in [0, 7, 499] {
+                let net = eps_net(&points, eps, seed);
+                assert_eq!(net[0], &points[seed]);
+                for p in points.iter() {
+                    assert!(
+                        net.iter().any(|q| dist(p, q) <= eps),
+                        "{:?} uncovered at eps {}",
+                        p,
+                        eps
+                    );
+                }
+                for (a, b) in net
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, a)| net[i + 1..].iter().map(move |b| (a, b)))
+                {
+                    assert!(dist(a, b) > eps, "{:?} {:?} at eps {}", a, b, eps);
+                }
+                assert_eq!(net, eps_net(&points, eps, seed));
+            }
+        }
+        assert!(eps_net(&[], 1.0, 0).is_empty());
+    }
+
+    #[test]
+    fn fps_picks_the_farthest_point_each_time() {
+        let points = random_points(322, 300);
+        let sample = fps(&points, 20, 5);
+        assert_eq!(sample.len(), 20);
+        assert_eq!(sample[0], &points[5]);
+        assert_eq!(sample, fps(&points, 20, 5));
+        assert_ne!(sample, fps(&points, 20, 6));
+
+        // Each pick is as far from the earlier picks as any point is.
+        for i in 1..sample.len() {
+            let gap = |p: &Point| {
+                sample[..i]
+                    .iter()
+                    .map(|q| dist(p, q))
+                    .fold(f64::INFINITY, f64::min)
+            };
+            let farthest = points.iter().map(gap).fold(0.0, f64::max);
+            assert_eq!(gap(sample[i]), farthest, "pick {}", i);
+        }
+    }
+
+    #[test]
+    fn fps_stops_when_every_point_is_picked() {
+        let one = |x: f64| vec![OrderedFloat(x), OrderedFloat(0.0)];
+        let points = vec![one(0.0), one(1.0), one(0.0), one(3.0)];
+        // The copy of the first point is never picked.
+        assert_eq!(fps(&points, 10, 0), [&points[0], &points[3], &points[1]]);
+        assert!(fps(&points, 0, 0).is_empty());
+        assert!(fps(&[], 3, 0).is_empty());
+    }
+
+    #[test]
+    fn reservoir_samples_are_reproducible_and_in_order() {
+        assert_eq!(reservoir_sample(0..5, 10, 1), [0, 1, 2, 3, 4]);
+        assert!(reservoir_sample(0..5, 0, 1).is_empty());
+        let sample = reservoir_sample(0..1000, 10, 42);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(reservoir_sample(0..1000, 10, 42), sample);
+        assert_ne!(reservoir_sample(0..1000, 10, 43), sample);
+    }
+
+    #[test]
+    fn reservoir_samples_are_roughly_uniform() {
+        // 2000 samples of 5 of 20 items: each item is expected 500 times,
+        // with a standard deviation of about 19.
+        let mut counts = [0; 20];
+        for seed in 0..2000 {
+            for i in reservoir_sample(0..20, 5, seed) {
+                counts[i] += 1;
+            }
+        }
+        assert!(
+            counts.iter().all(|&c| (400..600).contains(&c)),
+            "{:?}",
+            counts
+        );
+    }
+
+    #[test]
+    fn permutations_follow_the_seed_and_can_be_undone() {
+        let perm = permutation(100, 9);
+        let mut sorted = perm.clone();
+        sorted.sort_unstable();
+        assert!(sorted.iter().copied().eq(0..100));
+        assert_eq!(permutation(100, 9), perm);
+        assert_ne!(permutation(100, 10), perm);
+        assert_eq!(permutation(1, 9), [0]);
+        assert!(permutation(0, 9).is_empty());
+
+        let items = (0..100).map(|i| format!("p{}", i)).collect::<Vec<_>>();
+        let mut shuffled = items.clone();
+        permute(&mut shuffled, &perm);
+        assert_eq!(shuffled[0], items[perm[0]]);
+        assert_eq!(shuffled[99], items[perm[99]]);
+        unpermute(&mut shuffled, &perm);
+        assert_eq!(shuffled, items);
+    }
+}
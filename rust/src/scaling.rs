@@ -0,0 +1,224 @@
+use crate::types::Point;
+use ordered_float::OrderedFloat;
+
+/// Per-column affine map `x -> (x - offset) / scale`, shared by the scalers.
+#[derive(Debug, Clone, PartialEq)]
+struct Affine {
+    offset: Vec<f64>,
+    scale: Vec<f64>,
+}
+
+impl Affine {
+    fn transform(&self, point: &Point) -> Point {
+        self.check(point);
+        point
+            .iter()
+            .zip(self.offset.iter().zip(self.scale.iter()))
+            .map(|(x, (o, s))| OrderedFloat((x.0 - o) / s))
+            .collect()
+    }
+
+    fn inverse_transform(&self, point: &Point) -> Point {
+        self.check(point);
+        point
+            .iter()
+            .zip(self.offset.iter().zip(self.scale.iter()))
+            .map(|(x, (o, s))| OrderedFloat(x.0 * s + o))
+            .collect()
+    }
+
+    fn check(&self, point: &Point) {
+        assert_eq!(
+            point.len(),
+            self.offset.len(),
+            "point dimension {} does not match fitted dimension {}",
+            point.len(),
+            self.offset.len()
+        );
+    }
+}
+
+// Columns of `points` as vectors, checking that the input is non-empty and of
+// a single dimension.
+fn columns(points: &[Point]) -> anyhow::Result<Vec<Vec<f64>>> {
+    let Some(first) = points.first() else {
+        anyhow::bail!("cannot fit a scaler to an empty point set");
+    };
+    let mut cols = vec![Vec::with_capacity(points.len()); first.len()];
+    for (i, p) in points.iter().enumerate() {
+        if p.len() != first.len() {
+            anyhow::bail!(
+                "dimension mismatch at point {}: expected {}, got {}",
+                i,
+                first.len(),
+                p.len()
+            );
+        }
+        for (col, x) in cols.iter_mut().zip(p.iter()) {
+            col.push(x.0);
+        }
+    }
+    Ok(cols)
+}
+
+/// Maps every column to [0, 1] using the minimum and maximum seen by
+/// `fit_minmax`. Constant columns map to 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinMaxScaler(Affine);
+
+impl MinMaxScaler {
+    pub fn transform(&self, point: &Point) -> Point {
+        self.0.transform(point)
+    }
+
+    /// Undoes `transform`; constant columns come back as their value.
+    pub fn inverse_transform(&self, point: &Point) -> Point {
+        self.0.inverse_transform(point)
+    }
+}
+
+/// Fits a `MinMaxScaler`. Fails on empty input or mixed dimensions.
+pub fn fit_minmax(points: &[Point]) -> anyhow::Result<MinMaxScaler> {
+    let (offset, scale) = columns(points)?
+        .iter()
+        .map(|col| {
+            let min = col.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = col.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (min, nonzero(max - min))
+        })
+        .unzip();
+    Ok(MinMaxScaler(Affine { offset, scale }))
+}
+
+/// Centers every column on its mean and divides by its (population) standard
+/// deviation, as seen by `fit_zscore`. Constant columns map to 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZScoreScaler(Affine);
+
+impl ZScoreScaler {
+    pub fn transform(&self, point: &Point) -> Point {
+        self.0.transform(point)
+    }
+
+    /// Undoes `transform`; constant columns come back as their value.
+    pub fn inverse_transform(&self, point: &Point) -> Point {
+        self.0.inverse_transform(point)
+    }
+}
+
+/// Fits a `ZScoreScaler`. Fails on empty input or mixed dimensions.
+pub fn fit_zscore(points: &[Point]) -> anyhow::Result<ZScoreScaler> {
+    let (offset, scale) = columns(points)?
+        .iter()
+        .map(|col| {
+            let n = col.len() as f64;
+            let mean = col.iter().sum::<f64>() / n;
+            let var = col.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+            (mean, nonzero(var.sqrt()))
+        })
+        .unzip();
+    Ok(ZScoreScaler(Affine { offset, scale }))
+}
+
+// A zero spread would divide by zero; such columns are only shifted.
+fn nonzero(spread: f64) -> f64 {
+    if spread > 0.0 {
+        spread
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    fn training() -> Vec<Point> {
+        vec![
+            pt(&[1.0, 10.0, 5.0]),
+            pt(&[3.0, 20.0, 5.0]),
+            pt(&[5.0, 60.0, 5.0]),
+        ]
+    }
+
+    fn assert_close(a: &Point, b: &Point) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x.0 - y.0).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn minmax_maps_every_column_to_the_unit_interval() {
+        let points = training();
+        let scaler = fit_minmax(&points).unwrap();
+        // The constant third column maps to 0.
+        assert_eq!(scaler.transform(&points[0]), pt(&[0.0, 0.0, 0.0]));
+        assert_eq!(scaler.transform(&points[1]), pt(&[0.5, 0.2, 0.0]));
+        assert_eq!(scaler.transform(&points[2]), pt(&[1.0, 1.0, 0.0]));
+        // Points outside the fitted range leave the interval.
+        assert_eq!(
+            scaler.transform(&pt(&[7.0, 0.0, 6.0])),
+            pt(&[1.5, -0.2, 1.0])
+        );
+    }
+
+    #[test]
+    fn zscore_centers_and_scales_every_column() {
+        let points = training();
+        let scaler = fit_zscore(&points).unwrap();
+        // Means 3 and 30, standard deviations sqrt(8/3) and sqrt(1400/3).
+        let sd = [(8.0f64 / 3.0).sqrt(), (1400.0f64 / 3.0).sqrt()];
+        let scaled = points
+            .iter()
+            .map(|p| scaler.transform(p))
+            .collect::<Vec<_>>();
+        assert_close(&scaled[0], &pt(&[-2.0 / sd[0], -20.0 / sd[1], 0.0]));
+        for col in 0..3 {
+            let mean = scaled.iter().map(|p| p[col].0).sum::<f64>() / 3.0;
+            let var = scaled.iter().map(|p| p[col].0.powi(2)).sum::<f64>() / 3.0;
+            assert!(mean.abs() < 1e-12, "column {}: mean {}", col, mean);
+            let expected = if col == 2 { 0.0 } else { 1.0 };
+            assert!(
+                (var - expected).abs() < 1e-12,
+                "column {}: var {}",
+                col,
+                var
+            );
+        }
+        assert!(scaled.iter().flatten().all(|x| x.0.is_finite()));
+    }
+
+    #[test]
+    fn inverse_transforms_undo_the_scaling() {
+        let points = training();
+        let minmax = fit_minmax(&points).unwrap();
+        let zscore = fit_zscore(&points).unwrap();
+        for p in points.iter().chain([&pt(&[-4.0, 1e3, 2.5])]) {
+            assert_close(&minmax.inverse_transform(&minmax.transform(p)), p);
+            assert_close(&zscore.inverse_transform(&zscore.transform(p)), p);
+        }
+    }
+
+    #[test]
+    fn fitting_needs_points_of_one_dimension() {
+        let err = fit_minmax(&[]).unwrap_err();
+        assert_eq!(err.to_string(), "cannot fit a scaler to an empty point set");
+        assert!(fit_zscore(&[]).is_err());
+        let err = fit_zscore(&[pt(&[1.0, 2.0]), pt(&[1.0])]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dimension mismatch at point 1: expected 2, got 1"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "point dimension 1 does not match fitted dimension 3")]
+    fn transform_rejects_other_dimensions() {
+        fit_minmax(&training()).unwrap().transform(&pt(&[0.0]));
+    }
+}
@@ -1,17 +1,1570 @@
-use ordered_float::OrderedFloat;
+use itertools::Itertools;
+use ordered_float::{FloatCore, OrderedFloat, PrimitiveFloat};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-pub type Point = Vec<OrderedFloat<f64>>;
+/// Coordinates of a point. The scalar defaults to `f64`; `Point<f32>` halves
+/// the memory for inputs that are single precision anyway.
+pub type Point<T = f64> = Vec<OrderedFloat<T>>;
+
+/// Scalar type of point coordinates: `f32` or `f64`. Distances are always
+/// computed and returned in `f64`.
+pub trait Float:
+    FloatCore + PrimitiveFloat + fmt::Display + fmt::Debug + Default + Send + Sync + 'static
+{
+    fn into_f64(self) -> f64;
+
+    /// Rounds to the nearest representable value.
+    fn from_f64(x: f64) -> Self;
+
+    /// `xs` as `f64`s, converted into `buf` unless no conversion is needed.
+    fn as_f64s<'b>(xs: &'b [Self], buf: &'b mut Vec<f64>) -> &'b [f64];
+}
+
+impl Float for f64 {
+    fn into_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+
+    fn as_f64s<'b>(xs: &'b [Self], _buf: &'b mut Vec<f64>) -> &'b [f64] {
+        xs
+    }
+}
+
+impl Float for f32 {
+    fn into_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+
+    fn as_f64s<'b>(xs: &'b [Self], buf: &'b mut Vec<f64>) -> &'b [f64] {
+        buf.clear();
+        buf.extend(xs.iter().map(|&x| x as f64));
+        buf
+    }
+}
+
+/// A point together with an id, typically its row in the input. Unlike plain
+/// `Point`s, which are keyed by their coordinates, two rows with the same
+/// coordinates stay distinct.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LabeledPoint {
+    pub id: usize,
+    pub coords: Point,
+}
+
+/// Labels `points` with their index in the vector.
+pub fn label_points(points: Vec<Point>) -> Vec<LabeledPoint> {
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(id, coords)| LabeledPoint { id, coords })
+        .collect()
+}
+
+/// A point with a positive weight, counting towards density as if it were
+/// `weight` unit points at the same coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedPoint {
+    pub coords: Point,
+    pub weight: f64,
+}
+
+impl WeightedPoint {
+    /// Fails unless `weight` is finite and positive.
+    pub fn new(coords: Point, weight: f64) -> anyhow::Result<Self> {
+        if !(weight.is_finite() && weight > 0.0) {
+            anyhow::bail!("weight must be finite and positive, got {}", weight);
+        }
+        Ok(Self { coords, weight })
+    }
+
+    /// Weight 1.
+    pub fn unit(coords: Point) -> Self {
+        Self {
+            coords,
+            weight: 1.0,
+        }
+    }
+}
+
+impl From<Point> for WeightedPoint {
+    fn from(coords: Point) -> Self {
+        Self::unit(coords)
+    }
+}
+
+impl From<WeightedPoint> for Point {
+    fn from(point: WeightedPoint) -> Self {
+        point.coords
+    }
+}
+
+/// One `WeightedPoint` per distinct coordinate, weighted by its multiplicity,
+/// in order of first occurrence.
+pub fn collapse_duplicates(points: &[Point]) -> Vec<WeightedPoint> {
+    let mut index: HashMap<&Point, usize> = HashMap::new();
+    let mut out: Vec<WeightedPoint> = Vec::new();
+    for p in points {
+        match index.get(p) {
+            Some(&i) => out[i].weight += 1.0,
+            None => {
+                index.insert(p, out.len());
+                out.push(WeightedPoint::unit(p.clone()));
+            }
+        }
+    }
+    out
+}
+
+/// Total weight per coordinate, as taken by the engines' `with_weights`
+/// constructors. Weights of points with equal coordinates add up.
+pub fn weight_map(points: &[WeightedPoint]) -> HashMap<&Point, f64> {
+    let mut weights = HashMap::new();
+    for p in points {
+        *weights.entry(&p.coords).or_insert(0.0) += p.weight;
+    }
+    weights
+}
+
+/// `n` points of dimension `dim` stored row-major in one contiguous buffer,
+/// instead of one heap allocation per `Point`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointCloud<T: Float = f64> {
+    coords: Vec<T>,
+    n: usize,
+    dim: usize,
+}
+
+impl<T: Float> PointCloud<T> {
+    /// Empty cloud of dimension `dim`.
+    pub fn new(dim: usize) -> Self {
+        Self {
+            coords: Vec::new(),
+            n: 0,
+            dim,
+        }
+    }
+
+    /// Copies `points` into a cloud. Panics if their dimensions differ.
+    pub fn from_points<'p>(points: impl IntoIterator<Item = &'p Point<T>>) -> Self {
+        let mut points = points.into_iter().peekable();
+        let dim = points.peek().map_or(0, |p| p.len());
+        let mut cloud = Self::new(dim);
+        cloud.coords.reserve(points.size_hint().0 * dim);
+        for p in points {
+            cloud.push(p.iter().map(|x| x.0));
+        }
+        cloud
+    }
+
+    /// Appends a row. Panics unless it has `dim` coordinates.
+    pub fn push(&mut self, row: impl IntoIterator<Item = T>) {
+        let len = self.coords.len();
+        self.coords.extend(row);
+        assert_eq!(
+            self.coords.len() - len,
+            self.dim,
+            "point dimension mismatch: expected {}, got {}",
+            self.dim,
+            self.coords.len() - len
+        );
+        self.n += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Coordinates of the `i`-th point.
+    pub fn row(&self, i: usize) -> &[T] {
+        &self.coords[i * self.dim..(i + 1) * self.dim]
+    }
+
+    /// The rows in order. Zero-dimensional clouds yield `len()` empty rows.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &[T]> {
+        (0..self.n).map(|i| self.row(i))
+    }
+
+    /// The whole row-major buffer, `len() * dim()` values.
+    pub fn as_slice(&self) -> &[T] {
+        &self.coords
+    }
+
+    pub fn to_points(&self) -> Vec<Point<T>> {
+        self.view().to_points()
+    }
+
+    /// Borrowed view of the cloud.
+    pub fn view(&self) -> CloudView<'_, T> {
+        CloudView {
+            coords: &self.coords,
+            n: self.n,
+            dim: self.dim,
+        }
+    }
+}
+
+/// Read-only `PointCloud` over coordinates stored elsewhere, e.g. a
+/// memory-mapped file (see `io::mmap_points`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CloudView<'a, T: Float = f64> {
+    coords: &'a [T],
+    n: usize,
+    dim: usize,
+}
+
+impl<'a, T: Float> CloudView<'a, T> {
+    /// View of the row-major buffer `coords` as points of dimension `dim`.
+    /// Panics unless `dim` is positive and divides its length.
+    pub fn new(coords: &'a [T], dim: usize) -> Self {
+        assert!(
+            dim > 0 && coords.len().is_multiple_of(dim),
+            "{} coordinates do not make points of dimension {}",
+            coords.len(),
+            dim
+        );
+        Self {
+            coords,
+            n: coords.len() / dim,
+            dim,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Coordinates of the `i`-th point.
+    pub fn row(&self, i: usize) -> &'a [T] {
+        &self.coords[i * self.dim..(i + 1) * self.dim]
+    }
+
+    /// The rows in order. Zero-dimensional views yield `len()` empty rows.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = &'a [T]> + use<'a, T> {
+        let view = *self;
+        (0..self.n).map(move |i| view.row(i))
+    }
+
+    /// The whole row-major buffer, `len() * dim()` values.
+    pub fn as_slice(&self) -> &'a [T] {
+        self.coords
+    }
+
+    pub fn to_points(&self) -> Vec<Point<T>> {
+        self.rows()
+            .map(|row| row.iter().copied().map(OrderedFloat).collect())
+            .collect()
+    }
+}
+
+impl<T: Float> From<&[Point<T>]> for PointCloud<T> {
+    fn from(points: &[Point<T>]) -> Self {
+        Self::from_points(points)
+    }
+}
+
+impl<T: Float> From<&PointCloud<T>> for Vec<Point<T>> {
+    fn from(cloud: &PointCloud<T>) -> Self {
+        cloud.to_points()
+    }
+}
+
+/// A point whose dimension `D` is known at compile time, stored inline. Used
+/// by the statically-dimensioned entry points such as `algo::dbscan_static`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointN<const D: usize>(pub [f64; D]);
+
+impl<const D: usize> PointN<D> {
+    /// Squared Euclidean distance, computed like `dist_sq`.
+    pub fn dist_sq(&self, other: &Self) -> f64 {
+        let mut sum = 0.0;
+        for i in 0..D {
+            let d = self.0[i] - other.0[i];
+            sum += d * d;
+        }
+        sum
+    }
+
+    pub fn dist(&self, other: &Self) -> f64 {
+        self.dist_sq(other).sqrt()
+    }
+
+    /// `None` if `point` does not have `D` coordinates.
+    pub fn from_point(point: &Point) -> Option<Self> {
+        if point.len() != D {
+            return None;
+        }
+        Some(Self(std::array::from_fn(|i| point[i].0)))
+    }
+
+    pub fn to_point(&self) -> Point {
+        self.0.iter().copied().map(OrderedFloat).collect()
+    }
+}
+
+impl<const D: usize> From<[f64; D]> for PointN<D> {
+    fn from(coords: [f64; D]) -> Self {
+        Self(coords)
+    }
+}
+
+impl<const D: usize> From<PointN<D>> for Point {
+    fn from(point: PointN<D>) -> Self {
+        point.to_point()
+    }
+}
+
+/// A point of dimension `dim` that stores only its nonzero coordinates:
+/// `values[i]` is the coordinate at `indices[i]`, with `indices` strictly
+/// increasing. Suited to high-dimensional data with few nonzeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparsePoint {
+    indices: Vec<u32>,
+    values: Vec<f64>,
+    dim: u32,
+}
+
+impl SparsePoint {
+    /// Fails unless `indices` is strictly increasing, below `dim`, and as long
+    /// as `values`.
+    pub fn new(dim: u32, indices: Vec<u32>, values: Vec<f64>) -> anyhow::Result<Self> {
+        if indices.len() != values.len() {
+            anyhow::bail!("{} indices but {} values", indices.len(), values.len());
+        }
+        if let Some(w) = indices.windows(2).find(|w| w[0] >= w[1]) {
+            if w[0] == w[1] {
+                anyhow::bail!("duplicate index {}", w[0]);
+            }
+            anyhow::bail!(
+                "indices must be strictly increasing; got {} before {}",
+                w[0],
+                w[1]
+            );
+        }
+        if let Some(&i) = indices.last().filter(|&&i| i >= dim) {
+            anyhow::bail!("index {} is out of range for dimension {}", i, dim);
+        }
+        Ok(Self {
+            indices,
+            values,
+            dim,
+        })
+    }
+
+    /// Keeps the nonzero coordinates of `point`.
+    pub fn from_dense(point: &Point) -> Self {
+        let (indices, values) = point
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.0 != 0.0)
+            .map(|(i, x)| (i as u32, x.0))
+            .unzip();
+        Self {
+            indices,
+            values,
+            dim: point.len() as u32,
+        }
+    }
+
+    pub fn to_dense(&self) -> Point {
+        let mut point = vec![OrderedFloat(0.0); self.dim as usize];
+        for (&i, &x) in self.indices.iter().zip(self.values.iter()) {
+            point[i as usize] = OrderedFloat(x);
+        }
+        point
+    }
+
+    pub fn dim(&self) -> u32 {
+        self.dim
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// Squared Euclidean distance, merging the two index lists. The nonzero
+    /// terms are added in the same order as `dist_sq` on the dense points, so
+    /// the result is identical. Panics if the dimensions differ.
+    pub fn dist_sq(&self, other: &Self) -> f64 {
+        assert_eq!(
+            self.dim, other.dim,
+            "sparse point dimension mismatch: {} vs {}",
+            self.dim, other.dim
+        );
+        let (a, b) = (&self.indices, &other.indices);
+        let (mut i, mut j) = (0, 0);
+        let mut sum = 0.0;
+        while i < a.len() || j < b.len() {
+            let d = if j == b.len() || (i < a.len() && a[i] < b[j]) {
+                i += 1;
+                self.values[i - 1]
+            } else if i == a.len() || b[j] < a[i] {
+                j += 1;
+                other.values[j - 1]
+            } else {
+                i += 1;
+                j += 1;
+                self.values[i - 1] - other.values[j - 1]
+            };
+            sum += d * d;
+        }
+        sum
+    }
+
+    pub fn dist(&self, other: &Self) -> f64 {
+        self.dist_sq(other).sqrt()
+    }
+}
+
+/// A binary vector of `len` bits packed into 64-bit words, e.g. a chemical
+/// fingerprint. Distances are Hamming distances computed with popcount.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BitPoint {
+    // Bit `i` is bit `i % 64` of `words[i / 64]`; bits past `len` are 0.
+    words: Vec<u64>,
+    len: u32,
+}
+
+impl BitPoint {
+    pub fn from_bits(bits: &[bool]) -> Self {
+        let mut words = vec![0u64; bits.len().div_ceil(64)];
+        for (i, _) in bits.iter().enumerate().filter(|(_, b)| **b) {
+            words[i / 64] |= 1 << (i % 64);
+        }
+        Self {
+            words,
+            len: bits.len() as u32,
+        }
+    }
+
+    /// Fails unless every coordinate of `point` is 0 or 1.
+    pub fn from_dense(point: &Point) -> anyhow::Result<Self> {
+        if let Some((i, x)) = point
+            .iter()
+            .enumerate()
+            .find(|(_, x)| x.0 != 0.0 && x.0 != 1.0)
+        {
+            anyhow::bail!("coordinate {} is {}, expected 0 or 1", i, x);
+        }
+        let bits = point.iter().map(|x| x.0 == 1.0).collect_vec();
+        Ok(Self::from_bits(&bits))
+    }
+
+    pub fn to_bits(&self) -> Vec<bool> {
+        (0..self.len as usize).map(|i| self.get(i)).collect()
+    }
+
+    pub fn to_dense(&self) -> Point {
+        (0..self.len as usize)
+            .map(|i| OrderedFloat(if self.get(i) { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    /// Number of bits.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Panics if `i >= len()`.
+    pub fn get(&self, i: usize) -> bool {
+        assert!(
+            i < self.len as usize,
+            "bit {} is out of range for length {}",
+            i,
+            self.len
+        );
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// The packed words, least significant bit first.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Number of positions at which the two vectors differ. Panics if the
+    /// lengths differ.
+    pub fn hamming(&self, other: &Self) -> u32 {
+        assert_eq!(
+            self.len, other.len,
+            "bit point length mismatch: {} vs {}",
+            self.len, other.len
+        );
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
 
 /// O(d) where d is the dimensionality of the points.
-pub fn dist(a: &Point, b: &Point) -> f64 {
+pub fn dist<T: Float>(a: &Point<T>, b: &Point<T>) -> f64 {
+    dist_sq(a, b).sqrt()
+}
+
+/// Squared Euclidean distance; `dist(a, b)` is exactly its square root. Cheaper
+/// when only the order of distances matters. Computed in `f64` for any scalar.
+pub fn dist_sq<T: Float>(a: &Point<T>, b: &Point<T>) -> f64 {
     a.iter()
         .zip(b.iter())
         .map(|(x, y)| {
-            let d = x.0 - y.0;
+            let d = x.0.into_f64() - y.0.into_f64();
             d * d
         })
         .sum::<f64>()
-        .sqrt()
+}
+
+/// The largest `s` with `s.sqrt() <= eps`, so that `dist(a, b) <= eps` exactly
+/// when `dist_sq(a, b) <= eps_sq_threshold(eps)`. Comparing against `eps * eps`
+/// instead can disagree with `dist` at the boundary because of rounding.
+pub fn eps_sq_threshold(eps: f64) -> f64 {
+    if eps.is_nan() || eps < 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if eps.is_infinite() {
+        return f64::INFINITY;
+    }
+    // `sqrt` is correctly rounded and monotone, so `eps * eps` is within an ulp
+    // or two of the answer.
+    let mut s = eps * eps;
+    while s.sqrt() > eps {
+        s = s.next_down();
+    }
+    while s.next_up().sqrt() <= eps {
+        s = s.next_up();
+    }
+    s
+}
+
+/// A distance function for the generic engines (`FakeQueryEngine`,
+/// `BruteForceQueryEngine`). Closures `Fn(&Point, &Point) -> f64` implement it.
+///
+/// Engines sort and compare "keys" rather than distances so that a metric can
+/// skip work that does not change the order, like the square root of
+/// Euclidean distance. The defaults use the distance itself as the key.
+pub trait Metric: Send + Sync {
+    fn dist(&self, a: &Point, b: &Point) -> f64;
+
+    /// A value ordered like `dist(a, b)`.
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        self.dist(a, b)
+    }
+
+    /// `key` for raw coordinate rows, as stored by the brute-force engine.
+    /// The default copies them into `Point`s, so override it when the metric
+    /// can work on slices.
+    fn key_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        let to_point = |c: &[f64]| c.iter().copied().map(OrderedFloat).collect::<Point>();
+        self.key(&to_point(a), &to_point(b))
+    }
+
+    /// The bound such that `key(a, b) <= key_bound(eps)` exactly when
+    /// `dist(a, b) <= eps`.
+    fn key_bound(&self, eps: f64) -> f64 {
+        eps
+    }
+
+    /// Converts a key back to the distance.
+    fn key_to_dist(&self, key: f64) -> f64 {
+        key
+    }
+}
+
+impl<F: Fn(&Point, &Point) -> f64 + Send + Sync> Metric for F {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self(a, b)
+    }
+}
+
+/// Euclidean distance (`dist`), keyed by the squared distance. The default
+/// metric of every engine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Euclidean;
+
+fn dist_sq_coords(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| {
+            let d = x - y;
+            d * d
+        })
+        .sum()
+}
+
+impl Metric for Euclidean {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        dist(a, b)
+    }
+
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        dist_sq(a, b)
+    }
+
+    fn key_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        dist_sq_coords(a, b)
+    }
+
+    fn key_bound(&self, eps: f64) -> f64 {
+        eps_sq_threshold(eps)
+    }
+
+    fn key_to_dist(&self, key: f64) -> f64 {
+        key.sqrt()
+    }
+}
+
+/// Built-in distance functions. Every variant except `Cosine`, `Angular` and
+/// `Haversine` is an Lp norm of the coordinate differences, which the R-tree engine needs.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetricKind {
+    /// L2, computed exactly like `dist`.
+    #[default]
+    Euclidean,
+    /// L1: sum of absolute differences.
+    Manhattan,
+    /// L-infinity: largest absolute difference.
+    Chebyshev,
+    /// Lp for the given `p >= 1`.
+    Minkowski(f64),
+    /// 1 - cosine similarity, in [0, 2]. A zero vector has no direction: it is
+    /// at distance 1 from every nonzero vector and 0 from another zero vector.
+    Cosine,
+    /// Angle between the vectors in radians, `acos` of the cosine similarity,
+    /// in [0, pi]. Unlike `Cosine` it satisfies the triangle inequality. A zero
+    /// vector is at pi/2 from every nonzero vector and 0 from another zero
+    /// vector.
+    Angular,
+    /// Great-circle distance between 2-D (latitude, longitude) points in
+    /// degrees, on a sphere of the given radius. The result is in the unit of
+    /// the radius; see `haversine_with_radius`.
+    Haversine(f64),
+}
+
+impl MetricKind {
+    /// Haversine distance in kilometers on the Earth.
+    pub fn haversine_km() -> Self {
+        MetricKind::Haversine(EARTH_RADIUS_KM)
+    }
+
+    pub fn distance(&self, a: &Point, b: &Point) -> f64 {
+        self.from_pairs(a.iter().zip(b.iter()).map(|(x, y)| (x.0, y.0)))
+    }
+
+    /// Distance given the coordinates of both points, one dimension at a time.
+    pub fn from_pairs(&self, pairs: impl Iterator<Item = (f64, f64)>) -> f64 {
+        match *self {
+            MetricKind::Euclidean => pairs
+                .map(|(x, y)| {
+                    let d = x - y;
+                    d * d
+                })
+                .sum::<f64>()
+                .sqrt(),
+            MetricKind::Manhattan => pairs.map(|(x, y)| (x - y).abs()).sum(),
+            MetricKind::Chebyshev => pairs.map(|(x, y)| (x - y).abs()).fold(0.0, f64::max),
+            MetricKind::Minkowski(p) => pairs
+                .map(|(x, y)| (x - y).abs().powf(p))
+                .sum::<f64>()
+                .powf(p.recip()),
+            MetricKind::Cosine => 1.0 - cosine_similarity(pairs),
+            MetricKind::Angular => angle_from_pairs(pairs),
+            MetricKind::Haversine(radius) => {
+                let (a, b): (Vec<f64>, Vec<f64>) = pairs.unzip();
+                haversine_coords(&a, &b, radius)
+            }
+        }
+    }
+
+    /// Whether this is an Lp metric, i.e. at least the largest coordinate
+    /// difference. The R-tree engine relies on that bound.
+    pub fn is_lp(&self) -> bool {
+        !matches!(
+            self,
+            MetricKind::Cosine | MetricKind::Angular | MetricKind::Haversine(_)
+        )
+    }
+
+    /// Panics unless the metric is well defined (`p >= 1` for Minkowski, a
+    /// positive radius for Haversine).
+    pub fn check(&self) {
+        match *self {
+            MetricKind::Minkowski(p) => assert!(p >= 1.0, "Minkowski p must be >= 1, got {}", p),
+            MetricKind::Haversine(radius) => assert!(
+                radius > 0.0,
+                "Haversine radius must be positive, got {}",
+                radius
+            ),
+            _ => {}
+        }
+    }
+
+    /// Fails on the first of `points` this metric cannot measure: Haversine
+    /// needs (latitude, longitude) points with latitude in [-90, 90] and
+    /// longitude in [-180, 180]. Other metrics take any points.
+    pub fn check_points(&self, points: &[Point]) -> anyhow::Result<()> {
+        if let MetricKind::Haversine(_) = self {
+            for (i, p) in points.iter().enumerate() {
+                if p.len() != 2 {
+                    anyhow::bail!(
+                        "point {}: haversine needs (latitude, longitude) points, got dimension {}",
+                        i,
+                        p.len()
+                    );
+                }
+                if !(-90.0..=90.0).contains(&p[0].0) || !(-180.0..=180.0).contains(&p[1].0) {
+                    anyhow::bail!(
+                        "point {}: invalid (latitude, longitude) ({}, {})",
+                        i,
+                        p[0],
+                        p[1]
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Metric for MetricKind {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.distance(a, b)
+    }
+
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        match self {
+            MetricKind::Euclidean => Euclidean.key(a, b),
+            _ => self.distance(a, b),
+        }
+    }
+
+    fn key_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            MetricKind::Euclidean => Euclidean.key_coords(a, b),
+            _ => self.from_pairs(a.iter().copied().zip(b.iter().copied())),
+        }
+    }
+
+    fn key_bound(&self, eps: f64) -> f64 {
+        match self {
+            MetricKind::Euclidean => Euclidean.key_bound(eps),
+            _ => eps,
+        }
+    }
+
+    fn key_to_dist(&self, key: f64) -> f64 {
+        match self {
+            MetricKind::Euclidean => Euclidean.key_to_dist(key),
+            _ => key,
+        }
+    }
+}
+
+// `acos` of the cosine similarity loses precision near 0 and pi, so the angle
+// is computed as 2 * atan2(|u - v|, |u + v|) on the unit vectors u and v.
+fn angle_from_pairs(pairs: impl Iterator<Item = (f64, f64)>) -> f64 {
+    let pairs = pairs.collect_vec();
+    let (aa, bb) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(aa, bb), (x, y)| (aa + x * x, bb + y * y));
+    match (aa == 0.0, bb == 0.0) {
+        (true, true) => 0.0,
+        (true, false) | (false, true) => std::f64::consts::FRAC_PI_2,
+        (false, false) => {
+            let (na, nb) = (aa.sqrt(), bb.sqrt());
+            let (diff, sum) = pairs.iter().fold((0.0, 0.0), |(diff, sum), (x, y)| {
+                let (u, v) = (x / na, y / nb);
+                (diff + (u - v) * (u - v), sum + (u + v) * (u + v))
+            });
+            2.0 * diff.sqrt().atan2(sum.sqrt())
+        }
+    }
+}
+
+// Cosine similarity clamped to [-1, 1]: 1 for two zero vectors, 0 for a zero
+// and a nonzero vector.
+fn cosine_similarity(pairs: impl Iterator<Item = (f64, f64)>) -> f64 {
+    let (dot, aa, bb) = pairs.fold((0.0, 0.0, 0.0), |(dot, aa, bb), (x, y)| {
+        (dot + x * y, aa + x * x, bb + y * y)
+    });
+    match (aa == 0.0, bb == 0.0) {
+        (true, true) => 1.0,
+        (true, false) | (false, true) => 0.0,
+        // Rounding can push the similarity slightly outside [-1, 1].
+        (false, false) => (dot / (aa.sqrt() * bb.sqrt())).clamp(-1.0, 1.0),
+    }
+}
+
+/// Mahalanobis distance sqrt((a - b)^T S^-1 (a - b)) for an inverse
+/// covariance matrix S^-1, which stretches space along correlated axes. With
+/// the identity matrix it equals Euclidean distance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MahalanobisMetric {
+    dim: usize,
+    // Row-major dim x dim.
+    inv_cov: Vec<f64>,
+}
+
+impl MahalanobisMetric {
+    /// Uses the given inverse covariance matrix (rows of equal length).
+    pub fn from_inverse_covariance(inv_cov: Vec<Vec<f64>>) -> anyhow::Result<Self> {
+        let dim = inv_cov.len();
+        if dim == 0 || inv_cov.iter().any(|row| row.len() != dim) {
+            anyhow::bail!("inverse covariance must be a non-empty square matrix");
+        }
+        if inv_cov.iter().flatten().any(|x| !x.is_finite()) {
+            anyhow::bail!("inverse covariance must be finite");
+        }
+        Ok(Self {
+            dim,
+            inv_cov: inv_cov.into_iter().flatten().collect(),
+        })
+    }
+
+    /// Estimates the sample covariance of `points`, adds `ridge` to its
+    /// diagonal and inverts it. A small positive `ridge` keeps near-singular
+    /// estimates (e.g. collinear points) invertible.
+    pub fn estimate_from(points: &[Point], ridge: f64) -> anyhow::Result<Self> {
+        if points.len() < 2 {
+            anyhow::bail!("need at least 2 points to estimate a covariance matrix");
+        }
+        let dim = points[0].len();
+        if dim == 0 || points.iter().any(|p| p.len() != dim) {
+            anyhow::bail!("points must share a positive dimension");
+        }
+
+        let n = points.len() as f64;
+        let mean = (0..dim)
+            .map(|i| points.iter().map(|p| p[i].0).sum::<f64>() / n)
+            .collect_vec();
+        let mut cov = vec![0.0; dim * dim];
+        for p in points {
+            for i in 0..dim {
+                for j in 0..dim {
+                    cov[i * dim + j] += (p[i].0 - mean[i]) * (p[j].0 - mean[j]);
+                }
+            }
+        }
+        for (k, c) in cov.iter_mut().enumerate() {
+            *c /= n - 1.0;
+            if k % (dim + 1) == 0 {
+                *c += ridge;
+            }
+        }
+
+        let inv_cov = invert(cov, dim).ok_or_else(|| {
+            anyhow::anyhow!("covariance matrix is singular; use a positive ridge term")
+        })?;
+        Ok(Self { dim, inv_cov })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Panics if `a` or `b` does not have the metric's dimension.
+    pub fn distance(&self, a: &Point, b: &Point) -> f64 {
+        assert!(
+            a.len() == self.dim && b.len() == self.dim,
+            "Mahalanobis metric has dimension {}, got points of dimension {} and {}",
+            self.dim,
+            a.len(),
+            b.len()
+        );
+        let d = a.iter().zip(b.iter()).map(|(x, y)| x.0 - y.0).collect_vec();
+        let q = self
+            .inv_cov
+            .chunks_exact(self.dim)
+            .zip(d.iter())
+            .map(|(row, di)| di * row.iter().zip(d.iter()).map(|(s, dj)| s * dj).sum::<f64>())
+            .sum::<f64>();
+        // Rounding can make the quadratic form slightly negative near 0.
+        q.max(0.0).sqrt()
+    }
+}
+
+// Gauss-Jordan elimination with partial pivoting on a row-major n x n matrix.
+// `None` if a pivot is (numerically) zero.
+fn invert(mut m: Vec<f64>, n: usize) -> Option<Vec<f64>> {
+    let scale = m.iter().fold(0.0_f64, |acc, x| acc.max(x.abs()));
+    let tolerance = scale * n as f64 * f64::EPSILON;
+    let mut inv = vec![0.0; n * n];
+    for i in 0..n {
+        inv[i * n + i] = 1.0;
+    }
+
+    for col in 0..n {
+        let pivot =
+            (col..n).max_by(|&a, &b| m[a * n + col].abs().total_cmp(&m[b * n + col].abs()))?;
+        if m[pivot * n + col].abs() <= tolerance {
+            return None;
+        }
+        for k in 0..n {
+            m.swap(col * n + k, pivot * n + k);
+            inv.swap(col * n + k, pivot * n + k);
+        }
+
+        let p = m[col * n + col];
+        for k in 0..n {
+            m[col * n + k] /= p;
+            inv[col * n + k] /= p;
+        }
+        for row in 0..n {
+            let f = m[row * n + col];
+            if row == col || f == 0.0 {
+                continue;
+            }
+            for k in 0..n {
+                m[row * n + k] -= f * m[col * n + k];
+                inv[row * n + k] -= f * inv[col * n + k];
+            }
+        }
+    }
+    Some(inv)
+}
+
+impl Metric for MahalanobisMetric {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.distance(a, b)
+    }
+}
+
+/// How `GowerMetric` compares one column.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GowerColumn {
+    /// Continuous values, contributing `|x - y| / range`.
+    Numeric { range: f64 },
+    /// Category codes, contributing 0 when equal and 1 otherwise.
+    Categorical,
+}
+
+/// Gower distance for rows mixing continuous columns and categorical codes:
+/// the mean over the columns of their per-column distances. With the ranges
+/// of the data, every column contributes at most 1, so the distance lies in
+/// [0, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GowerMetric {
+    columns: Vec<GowerColumn>,
+}
+
+impl GowerMetric {
+    /// Uses the given column types. Numeric ranges must be finite and positive.
+    pub fn new(columns: Vec<GowerColumn>) -> anyhow::Result<Self> {
+        if columns.is_empty() {
+            anyhow::bail!("Gower metric needs at least one column");
+        }
+        let bad_range = columns.iter().enumerate().find_map(|(i, c)| match *c {
+            GowerColumn::Numeric { range } if !(range.is_finite() && range > 0.0) => {
+                Some((i, range))
+            }
+            _ => None,
+        });
+        if let Some((i, range)) = bad_range {
+            anyhow::bail!(
+                "column {}: range must be finite and positive, got {}",
+                i,
+                range
+            );
+        }
+        Ok(Self { columns })
+    }
+
+    /// Treats the columns listed in `categorical` as categorical and the others
+    /// as numeric with the range (max - min) seen in `points`. Constant numeric
+    /// columns get range 1, which is irrelevant since they never differ.
+    pub fn fit(points: &[Point], categorical: &[usize]) -> anyhow::Result<Self> {
+        let Some(first) = points.first() else {
+            anyhow::bail!("cannot fit a Gower metric to an empty point set");
+        };
+        let dim = first.len();
+        if let Some(i) = points.iter().position(|p| p.len() != dim) {
+            anyhow::bail!(
+                "dimension mismatch at point {}: expected {}, got {}",
+                i,
+                dim,
+                points[i].len()
+            );
+        }
+        if let Some(&c) = categorical.iter().find(|&&c| c >= dim) {
+            anyhow::bail!(
+                "categorical column {} is out of range for dimension {}",
+                c,
+                dim
+            );
+        }
+
+        let columns = (0..dim)
+            .map(|i| {
+                if categorical.contains(&i) {
+                    return GowerColumn::Categorical;
+                }
+                let (min, max) = points
+                    .iter()
+                    .map(|p| p[i].0)
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+                        (lo.min(x), hi.max(x))
+                    });
+                let range = max - min;
+                GowerColumn::Numeric {
+                    range: if range > 0.0 { range } else { 1.0 },
+                }
+            })
+            .collect();
+        Self::new(columns)
+    }
+
+    pub fn columns(&self) -> &[GowerColumn] {
+        &self.columns
+    }
+
+    pub fn dim(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Panics if `a` or `b` does not have the metric's dimension.
+    pub fn distance(&self, a: &Point, b: &Point) -> f64 {
+        let a = a.iter().map(|x| x.0).collect_vec();
+        let b = b.iter().map(|x| x.0).collect_vec();
+        self.distance_coords(&a, &b)
+    }
+
+    fn distance_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        assert!(
+            a.len() == self.dim() && b.len() == self.dim(),
+            "Gower metric has dimension {}, got points of dimension {} and {}",
+            self.dim(),
+            a.len(),
+            b.len()
+        );
+        let total = self
+            .columns
+            .iter()
+            .zip(a.iter().zip(b.iter()))
+            .map(|(c, (x, y))| match *c {
+                GowerColumn::Numeric { range } => (x - y).abs() / range,
+                GowerColumn::Categorical => {
+                    if x == y {
+                        0.0
+                    } else {
+                        1.0
+                    }
+                }
+            })
+            .sum::<f64>();
+        total / self.dim() as f64
+    }
+}
+
+impl Metric for GowerMetric {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.distance(a, b)
+    }
+
+    fn key_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        self.distance_coords(a, b)
+    }
+}
+
+/// Mean Earth radius in kilometers.
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Great-circle distance in kilometers between two (latitude, longitude)
+/// points given in degrees.
+pub fn haversine(a: &Point, b: &Point) -> f64 {
+    haversine_with_radius(a, b, EARTH_RADIUS_KM)
+}
+
+/// Great-circle distance between two (latitude, longitude) points given in
+/// degrees, on a sphere of `radius`; the result has the unit of `radius`
+/// (e.g. 3958.8 for miles on the Earth). Panics unless both points are 2-D with
+/// latitude in [-90, 90] and longitude in [-180, 180].
+pub fn haversine_with_radius(a: &Point, b: &Point, radius: f64) -> f64 {
+    let a = a.iter().map(|x| x.0).collect_vec();
+    let b = b.iter().map(|x| x.0).collect_vec();
+    haversine_coords(&a, &b, radius)
+}
+
+fn haversine_coords(a: &[f64], b: &[f64], radius: f64) -> f64 {
+    for p in [a, b] {
+        assert_eq!(
+            p.len(),
+            2,
+            "haversine needs (latitude, longitude) points, got dimension {}",
+            p.len()
+        );
+        assert!(
+            (-90.0..=90.0).contains(&p[0]) && (-180.0..=180.0).contains(&p[1]),
+            "invalid (latitude, longitude) ({}, {})",
+            p[0],
+            p[1]
+        );
+    }
+
+    let (lat_a, lat_b) = (a[0].to_radians(), b[0].to_radians());
+    let half_dlat = (lat_b - lat_a) / 2.0;
+    let half_dlon = (b[1] - a[1]).to_radians() / 2.0;
+    let h = half_dlat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_dlon.sin().powi(2);
+    // Rounding can push `h` slightly above 1 for antipodal points.
+    2.0 * radius * h.sqrt().min(1.0).asin()
+}
+
+/// Default cap on the memory `pairwise_distances` may allocate: 1 GiB, about
+/// 16k points.
+pub const DEFAULT_MAX_MATRIX_BYTES: usize = 1 << 30;
+
+/// Symmetric matrix of pairwise distances between `n` points with a zero
+/// diagonal, storing only the upper triangle: the distances (i, j) for i < j,
+/// row by row, in a flat vector of n(n-1)/2 values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CondensedMatrix {
+    n: usize,
+    data: Vec<f64>,
+}
+
+impl CondensedMatrix {
+    /// Wraps condensed `data` for `n` points. Fails unless it holds exactly
+    /// n(n-1)/2 values.
+    pub fn new(n: usize, data: Vec<f64>) -> anyhow::Result<Self> {
+        let expected = n * n.saturating_sub(1) / 2;
+        if data.len() != expected {
+            anyhow::bail!(
+                "a condensed matrix for {} points has {} entries, got {}",
+                n,
+                expected,
+                data.len()
+            );
+        }
+        Ok(Self { n, data })
+    }
+
+    /// Number of points.
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Position of (i, j) in the condensed vector, in either order. `None` on
+    /// the diagonal, which is not stored. Panics if `i` or `j` is out of range.
+    pub fn index(&self, i: usize, j: usize) -> Option<usize> {
+        assert!(
+            i < self.n && j < self.n,
+            "index ({}, {}) is out of range for {} points",
+            i,
+            j,
+            self.n
+        );
+        let (i, j) = match i.cmp(&j) {
+            std::cmp::Ordering::Less => (i, j),
+            std::cmp::Ordering::Equal => return None,
+            std::cmp::Ordering::Greater => (j, i),
+        };
+        // Rows 0..i hold (n - 1) + (n - 2) + ... + (n - i) entries.
+        Some(i * (2 * self.n - i - 1) / 2 + (j - i - 1))
+    }
+
+    /// The pair (i, j) with i < j stored at condensed position `k`. Panics if
+    /// `k` is out of range.
+    pub fn pair(&self, k: usize) -> (usize, usize) {
+        assert!(
+            k < self.data.len(),
+            "condensed index {} is out of range for {} entries",
+            k,
+            self.data.len()
+        );
+        let mut i = 0;
+        let mut start = 0;
+        while start + (self.n - i - 1) <= k {
+            start += self.n - i - 1;
+            i += 1;
+        }
+        (i, i + 1 + (k - start))
+    }
+
+    /// Distance between points `i` and `j`; 0 when `i == j`.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        self.index(i, j).map_or(0.0, |k| self.data[k])
+    }
+
+    /// Distances from point `i` to every point, itself included (at 0).
+    pub fn row(&self, i: usize) -> impl Iterator<Item = f64> + '_ {
+        (0..self.n).map(move |j| self.get(i, j))
+    }
+
+    /// The condensed upper triangle.
+    pub fn as_slice(&self) -> &[f64] {
+        &self.data
+    }
+}
+
+/// All pairwise distances between `points` under `metric`. Fails when the
+/// matrix would exceed `DEFAULT_MAX_MATRIX_BYTES`; see
+/// `pairwise_distances_capped`.
+pub fn pairwise_distances(
+    points: &[Point],
+    metric: &impl Metric,
+) -> anyhow::Result<CondensedMatrix> {
+    pairwise_distances_capped(points, metric, DEFAULT_MAX_MATRIX_BYTES)
+}
+
+/// Like `pairwise_distances`, failing before allocating anything when the
+/// n(n-1)/2 distances would take more than `max_bytes`. With the `parallel`
+/// feature, rows are computed in parallel.
+pub fn pairwise_distances_capped(
+    points: &[Point],
+    metric: &impl Metric,
+    max_bytes: usize,
+) -> anyhow::Result<CondensedMatrix> {
+    let n = points.len();
+    // In u128 so that the size of absurdly large inputs cannot overflow.
+    let bytes = n as u128 * n.saturating_sub(1) as u128 / 2 * size_of::<f64>() as u128;
+    if bytes > max_bytes as u128 {
+        anyhow::bail!(
+            "pairwise distances for {} points need {} bytes, more than the cap of {} bytes",
+            n,
+            bytes,
+            max_bytes
+        );
+    }
+
+    let row = |i: usize| (i + 1..n).map(move |j| metric.dist(&points[i], &points[j]));
+    #[cfg(feature = "parallel")]
+    let data = (0..n).into_par_iter().flat_map_iter(row).collect();
+    #[cfg(not(feature = "parallel"))]
+    let data = (0..n).flat_map(row).collect();
+    Ok(CondensedMatrix { n, data })
+}
+
+/// Scales every nonzero point to unit Euclidean length; zero vectors are left
+/// as they are. For unit vectors `dist(a, b)^2 = 2 * cosine distance`, so a
+/// Euclidean engine with `eps = sqrt(2 * eps_cos)` finds the same neighbors as
+/// `MetricKind::Cosine` with `eps_cos`, and ranks them the same way.
+pub fn normalize_l2(points: &mut [Point]) {
+    for point in points.iter_mut() {
+        let norm = point.iter().map(|x| x.0 * x.0).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            for x in point.iter_mut() {
+                x.0 /= norm;
+            }
+        }
+    }
+}
+
+/// Like `normalize_l2`, but removes the zero vectors, which have no direction,
+/// instead of leaving them in place. Returns their indices in the original
+/// vector, in increasing order.
+pub fn project_to_unit_sphere(points: &mut Vec<Point>) -> Vec<usize> {
+    let mut zeros = Vec::new();
+    let mut i = 0;
+    points.retain(|p| {
+        i += 1;
+        let zero = p.iter().all(|x| x.0 == 0.0);
+        if zero {
+            zeros.push(i - 1);
+        }
+        !zero
+    });
+    normalize_l2(points);
+    zeros
+}
+
+/// Euclidean distance between two unit vectors at angle `theta` (radians).
+/// Since it grows with the angle, a Euclidean engine over unit vectors with
+/// `eps = angular_to_chord(theta)` finds the same neighbors as
+/// `MetricKind::Angular` with `theta`, ranked the same way.
+pub fn angular_to_chord(theta: f64) -> f64 {
+    2.0 * (theta / 2.0).sin()
+}
+
+/// Renders `point` as `(x1, x2, ...)` for error messages.
+pub fn format_point<T: Float>(point: &Point<T>) -> String {
+    format!("({})", point.iter().map(|x| x.0.to_string()).join(", "))
+}
+
+/// A non-finite coordinate value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NonFinite {
+    NaN,
+    PosInfinity,
+    NegInfinity,
+}
+
+impl NonFinite {
+    /// `None` if `x` is finite.
+    pub fn of(x: f64) -> Option<Self> {
+        if x.is_nan() {
+            Some(NonFinite::NaN)
+        } else if x == f64::INFINITY {
+            Some(NonFinite::PosInfinity)
+        } else if x == f64::NEG_INFINITY {
+            Some(NonFinite::NegInfinity)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NonFinite::NaN => write!(f, "NaN"),
+            NonFinite::PosInfinity => write!(f, "inf"),
+            NonFinite::NegInfinity => write!(f, "-inf"),
+        }
+    }
+}
+
+/// A point with a NaN or infinite coordinate. Such points break distance
+/// computations and sorting in every engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the point in the validated slice, if it came from one.
+    pub row: Option<usize>,
+    /// Index of the first offending coordinate.
+    pub column: usize,
+    pub kind: NonFinite,
+    /// The point, rendered by `format_point`.
+    pub point: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(row) = self.row {
+            write!(f, "row {}, ", row)?;
+        }
+        write!(
+            f,
+            "column {}: coordinate is {} in point {}",
+            self.column, self.kind, self.point
+        )
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks that every coordinate of `point` is finite. The error has no `row`.
+pub fn validate_point<T: Float>(point: &Point<T>) -> Result<(), ValidationError> {
+    match point
+        .iter()
+        .enumerate()
+        .find_map(|(column, x)| NonFinite::of(x.0.into_f64()).map(|kind| (column, kind)))
+    {
+        Some((column, kind)) => Err(ValidationError {
+            row: None,
+            column,
+            kind,
+            point: format_point(point),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks that every coordinate is finite, reporting the first offending
+/// row (index into `points`) and column.
+pub fn validate_points<T: Float>(points: &[Point<T>]) -> Result<(), ValidationError> {
+    for (row, p) in points.iter().enumerate() {
+        validate_point(p).map_err(|e| ValidationError {
+            row: Some(row),
+            ..e
+        })?;
+    }
+    Ok(())
+}
+
+/// Ordered names of the coordinate columns of a dataset.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Schema {
+    names: Vec<String>,
+}
+
+impl Schema {
+    /// Fails if a name is empty or appears twice.
+    pub fn new(names: Vec<String>) -> anyhow::Result<Self> {
+        for (i, name) in names.iter().enumerate() {
+            if name.is_empty() {
+                anyhow::bail!("column {} has an empty name", i);
+            }
+            if names[..i].contains(name) {
+                anyhow::bail!("duplicate column name '{}'", name);
+            }
+        }
+        Ok(Self { names })
+    }
+
+    /// `x1`, ..., `x{dim}`, the names used for input without a header.
+    pub fn numbered(dim: usize) -> Self {
+        Self {
+            names: (1..=dim).map(|i| format!("x{}", i)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Name of column `i`. Panics if out of range.
+    pub fn name(&self, i: usize) -> &str {
+        &self.names[i]
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Renders `error` with the column name, e.g. "NaN in column 'pressure',
+    /// row 42 (point (1, NaN))".
+    pub fn describe(&self, error: &ValidationError) -> String {
+        let column = match self.names.get(error.column) {
+            Some(name) => format!("'{}'", name),
+            None => error.column.to_string(),
+        };
+        let row = error
+            .row
+            .map(|r| format!(", row {}", r))
+            .unwrap_or_default();
+        format!(
+            "{} in column {}{} (point {})",
+            error.kind, column, row, error.point
+        )
+    }
+}
+
+/// Points together with the `Schema` naming their coordinates.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Dataset {
+    schema: Schema,
+    points: Vec<Point>,
+}
+
+impl Dataset {
+    /// Fails unless every point has one coordinate per schema column.
+    pub fn new(schema: Schema, points: Vec<Point>) -> anyhow::Result<Self> {
+        if let Some(i) = points.iter().position(|p| p.len() != schema.len()) {
+            anyhow::bail!(
+                "dimension mismatch at point {}: expected {} columns, got {}",
+                i,
+                schema.len(),
+                points[i].len()
+            );
+        }
+        Ok(Self { schema, points })
+    }
+
+    /// `points` with `Schema::numbered` column names.
+    pub fn from_points(points: Vec<Point>) -> Self {
+        let schema = Schema::numbered(points.first().map_or(0, |p| p.len()));
+        Self { schema, points }
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn into_points(self) -> Vec<Point> {
+        self.points
+    }
+
+    /// Values of the column called `name`.
+    pub fn column(&self, name: &str) -> anyhow::Result<Vec<f64>> {
+        let Some(i) = self.schema.index_of(name) else {
+            anyhow::bail!("no column named '{}'", name);
+        };
+        Ok(self.points.iter().map(|p| p[i].0).collect())
+    }
+
+    /// `validate_points`, with the error described by column name.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        validate_points(&self.points).map_err(|e| anyhow::anyhow!(self.schema.describe(&e)))
+    }
+}
+
+impl From<Vec<Point>> for Dataset {
+    fn from(points: Vec<Point>) -> Self {
+        Self::from_points(points)
+    }
+}
+
+impl From<Dataset> for Vec<Point> {
+    fn from(dataset: Dataset) -> Self {
+        dataset.into_points()
+    }
+}
+
+/// Scaled squared distance sum(((a_i - b_i) / eps_i)^2). `b` lies inside the
+/// axis-aligned ellipsoid around `a` with semi-axes `eps` iff this is <= 1.
+pub fn aniso_dist_sq<T: Float>(a: &Point<T>, b: &Point<T>, eps: &[f64]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .zip(eps.iter())
+        .map(|((x, y), e)| {
+            let d = (x.0.into_f64() - y.0.into_f64()) / e;
+            d * d
+        })
+        .sum::<f64>()
+}
+
+/// Panics unless `eps` has one positive entry per dimension of `point`.
+pub fn check_aniso_eps<T: Float>(point: &Point<T>, eps: &[f64]) {
+    assert_eq!(
+        eps.len(),
+        point.len(),
+        "eps vector length {} does not match point dimension {}",
+        eps.len(),
+        point.len()
+    );
+    assert!(
+        eps.iter().all(|&e| e > 0.0),
+        "every per-dimension eps must be positive"
+    );
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -20,3 +1573,1570 @@ pub enum Class {
     Classified(usize),
     Noise,
 }
+
+impl Class {
+    /// The cluster id used in clustered CSV files: the id for classified
+    /// points, -1 for noise and -2 for unclassified points.
+    pub fn cid(&self) -> isize {
+        match self {
+            Class::Classified(id) => *id as isize,
+            Class::Noise => -1,
+            Class::Unclassified => -2,
+        }
+    }
+
+    /// Inverse of `cid`; `None` for ids below -2.
+    pub fn from_cid(cid: isize) -> Option<Self> {
+        match cid {
+            -2 => Some(Class::Unclassified),
+            -1 => Some(Class::Noise),
+            _ if cid >= 0 => Some(Class::Classified(cid as usize)),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `cid()`.
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cid())
+    }
+}
+
+/// Error returned when parsing a `Class` from a string that is not a valid cid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseClassError(String);
+
+impl fmt::Display for ParseClassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cid '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseClassError {}
+
+/// Parses a cid as written by `Display`.
+impl FromStr for Class {
+    type Err = ParseClassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .ok()
+            .and_then(Class::from_cid)
+            .ok_or_else(|| ParseClassError(s.to_string()))
+    }
+}
+
+// With the `serde` feature, `Classified(3)` is `{"type":"classified","id":3}`,
+// and `Noise` / `Unclassified` are the strings "noise" / "unclassified".
+#[cfg(feature = "serde")]
+impl serde::Serialize for Class {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        match self {
+            Class::Unclassified => serializer.serialize_str("unclassified"),
+            Class::Noise => serializer.serialize_str("noise"),
+            Class::Classified(id) => {
+                let mut s = serializer.serialize_struct("Class", 2)?;
+                s.serialize_field("type", "classified")?;
+                s.serialize_field("id", id)?;
+                s.end()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Class {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Name(String),
+            Tagged {
+                #[serde(rename = "type")]
+                kind: String,
+                id: usize,
+            },
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Name(name) if name == "unclassified" => Ok(Class::Unclassified),
+            Repr::Name(name) if name == "noise" => Ok(Class::Noise),
+            Repr::Tagged { kind, id } if kind == "classified" => Ok(Class::Classified(id)),
+            Repr::Name(name) | Repr::Tagged { kind: name, .. } => {
+                Err(D::Error::custom(format!("unknown class '{}'", name)))
+            }
+        }
+    }
+}
+
+/// The cid (see `Class::cid`) of every point of `points`, in order. Points
+/// missing from `classes` are noise (-1). Points with equal coordinates share
+/// one entry of `classes` and therefore get the same label.
+pub fn labels_in_order(points: &[Point], classes: &HashMap<&Point, Class>) -> Vec<isize> {
+    points
+        .iter()
+        .map(|p| classes.get(p).copied().unwrap_or(Class::Noise).cid())
+        .collect()
+}
+
+/// Inverse of `labels_in_order`: the class of `points[i]` is parsed from
+/// `labels[i]` with `Class::from_cid`, ids below -2 counting as noise. If
+/// points with equal coordinates have different labels, the last one wins.
+/// Panics if the lengths differ.
+pub fn labels_to_classes<'p>(points: &'p [Point], labels: &[isize]) -> HashMap<&'p Point, Class> {
+    assert_eq!(
+        points.len(),
+        labels.len(),
+        "got {} labels for {} points",
+        labels.len(),
+        points.len()
+    );
+    points
+        .iter()
+        .zip(labels.iter())
+        .map(|(p, &cid)| (p, Class::from_cid(cid).unwrap_or(Class::Noise)))
+        .collect()
+}
+
+// Indices into `points` of the members of every cluster, in order.
+fn cluster_members(
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+) -> HashMap<usize, Vec<usize>> {
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, p) in points.iter().enumerate() {
+        if let Some(&Class::Classified(id)) = classes.get(p) {
+            members.entry(id).or_default().push(i);
+        }
+    }
+    members
+}
+
+/// Relabels every cluster with fewer than `min_size` members as noise and
+/// renumbers the others 0, 1, ... in the order of their old ids. Sizes count
+/// every element of `points`, as in `centroids`. Returns how many clusters
+/// were dropped.
+pub fn drop_small_clusters(
+    points: &[Point],
+    classes: &mut HashMap<&Point, Class>,
+    min_size: usize,
+) -> usize {
+    let members = cluster_members(points, classes);
+    let mut kept = members
+        .iter()
+        .filter(|(_, m)| m.len() >= min_size)
+        .map(|(&id, _)| id)
+        .collect::<Vec<_>>();
+    kept.sort_unstable();
+    let new_id = kept
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new))
+        .collect::<HashMap<_, _>>();
+    for class in classes.values_mut() {
+        if let Class::Classified(id) = *class {
+            *class = match new_id.get(&id) {
+                Some(&new) => Class::Classified(new),
+                None => Class::Noise,
+            };
+        }
+    }
+    members.len() - kept.len()
+}
+
+/// Renumbers the clusters in `classes` 0, 1, ... in the order their first
+/// point appears in `points`, so that cluster ids follow the input order.
+/// Clusters with no point in `points` are numbered after the others, in the
+/// order of their old ids.
+pub fn number_clusters_in_order(points: &[Point], classes: &mut HashMap<&Point, Class>) {
+    let mut new_id = HashMap::new();
+    for p in points.iter() {
+        if let Some(&Class::Classified(id)) = classes.get(p) {
+            let next = new_id.len();
+            new_id.entry(id).or_insert(next);
+        }
+    }
+    let mut rest = classes
+        .values()
+        .filter_map(|c| match c {
+            Class::Classified(id) if !new_id.contains_key(id) => Some(*id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    rest.sort_unstable();
+    rest.dedup();
+    for id in rest {
+        let next = new_id.len();
+        new_id.insert(id, next);
+    }
+    for class in classes.values_mut() {
+        if let Class::Classified(id) = *class {
+            *class = Class::Classified(new_id[&id]);
+        }
+    }
+}
+
+/// Indices into `points` of the points whose class passes `keep`, in order.
+/// Points missing from `classes` count as noise, as in `labels_in_order`.
+pub fn select_rows(
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    keep: impl Fn(Class) -> bool,
+) -> Vec<usize> {
+    points
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| keep(classes.get(p).copied().unwrap_or(Class::Noise)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Sorts `rows`, indices into `points`, by cluster id, then unclassified
+/// points, then noise (with points missing from `classes`), and every group
+/// by coordinates. The sort is stable: equal points keep their order in
+/// `rows`.
+pub fn sort_rows_by_cluster(
+    points: &[Point],
+    classes: &HashMap<&Point, Class>,
+    rows: &mut [usize],
+) {
+    rows.sort_by_key(|&i| {
+        let group = match classes.get(&points[i]).copied().unwrap_or(Class::Noise) {
+            Class::Classified(id) => (0, id),
+            Class::Unclassified => (1, 0),
+            Class::Noise => (2, 0),
+        };
+        (group, &points[i])
+    });
+}
+
+/// Mean of every cluster, keyed by cluster id. Noise, unclassified points and
+/// points missing from `classes` are skipped. Every element of `points`
+/// counts, so duplicate points weigh by their multiplicity.
+pub fn centroids(points: &[Point], classes: &HashMap<&Point, Class>) -> HashMap<usize, Point> {
+    cluster_members(points, classes)
+        .into_iter()
+        .map(|(id, members)| {
+            let mut sum = vec![0.0; points[members[0]].len()];
+            for &i in members.iter() {
+                for (s, x) in sum.iter_mut().zip(points[i].iter()) {
+                    *s += x.0;
+                }
+            }
+            let n = members.len() as f64;
+            (id, sum.into_iter().map(|s| OrderedFloat(s / n)).collect())
+        })
+        .collect()
+}
+
+/// Medoid of every cluster, keyed by cluster id: the member with the smallest
+/// total `metric` distance to the other members (including duplicates of
+/// itself, at distance 0). Ties go to the member first in `points`. Each
+/// distance is computed once and credited to both ends, so a cluster of `m`
+/// members costs m(m-1)/2 distance evaluations and O(m) memory.
+pub fn medoids<'p>(
+    points: &'p [Point],
+    classes: &HashMap<&Point, Class>,
+    metric: &impl Metric,
+) -> HashMap<usize, &'p Point> {
+    cluster_members(points, classes)
+        .into_iter()
+        .map(|(id, members)| {
+            let mut totals = vec![0.0; members.len()];
+            for (a, &i) in members.iter().enumerate() {
+                for (b, &j) in members.iter().enumerate().skip(a + 1) {
+                    let d = metric.dist(&points[i], &points[j]);
+                    totals[a] += d;
+                    totals[b] += d;
+                }
+            }
+            let best = (0..members.len())
+                .min_by(|&a, &b| totals[a].total_cmp(&totals[b]))
+                .unwrap();
+            (id, &points[members[best]])
+        })
+        .collect()
+}
+
+/// Owned clustering output: `labels[i]` is the class of `points[i]`. Unlike
+/// the map returned by `Algo::dbscan`, it does not borrow the input, and with
+/// the `serde` feature it can be serialized.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusteringResult {
+    pub labels: Vec<Class>,
+    pub points: Vec<Vec<f64>>,
+}
+
+impl ClusteringResult {
+    /// Collects `classes` in the order of `points`. Points missing from
+    /// `classes` are noise, as in `io::write_clustered_csv`.
+    pub fn from_classes(points: &[Point], classes: &HashMap<&Point, Class>) -> Self {
+        Self {
+            labels: points
+                .iter()
+                .map(|p| classes.get(p).copied().unwrap_or(Class::Noise))
+                .collect(),
+            points: points
+                .iter()
+                .map(|p| p.iter().map(|x| x.0).collect())
+                .collect(),
+        }
+    }
+}
+
+/// Version of the `RunSummary` layout, raised whenever a field is removed or
+/// changes meaning.
+pub const RUN_SUMMARY_VERSION: u32 = 1;
+
+/// Size and centroid of one cluster of a `RunSummary`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterSummary {
+    pub id: usize,
+    pub size: usize,
+    /// Total weight of the members, set by `RunSummary::with_weights`; `None`
+    /// for an unweighted run.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub weight: Option<f64>,
+    pub centroid: Vec<f64>,
+}
+
+/// Metadata about a clustering run, e.g. for a JSON sidecar written by
+/// `io::write_summary_json`. `from_classes` fills in the counts and clusters;
+/// the run parameters are left for the caller to set.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunSummary {
+    /// `RUN_SUMMARY_VERSION` when written.
+    pub version: u32,
+    pub input: String,
+    pub eps: f64,
+    pub min_points: usize,
+    pub engine: String,
+    /// Feature scaling applied before clustering, e.g. `zscore`, in which case
+    /// `eps` is a distance between scaled points; `None` if the points were
+    /// clustered as read. Centroids are in the original units either way.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub normalization: Option<String>,
+    /// Clusters smaller than this were relabeled as noise by
+    /// `drop_small_clusters`; `None` if no such filter ran.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_cluster_size: Option<usize>,
+    pub n_points: usize,
+    pub n_clusters: usize,
+    pub n_noise: usize,
+    /// Clusters dropped by the `min_cluster_size` filter; their points are
+    /// counted in `n_noise`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dropped_clusters: usize,
+    /// By increasing id.
+    pub clusters: Vec<ClusterSummary>,
+    /// Agreement with ground-truth labels (`validation::compare`), if there
+    /// were any.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ground_truth: Option<crate::validation::Scores>,
+    /// Wall-clock seconds from the start of the run until the clustering was
+    /// done, reading included.
+    pub elapsed_secs: f64,
+}
+
+impl RunSummary {
+    /// Counts the clusters and noise of `points`, with sizes and centroids as
+    /// in `centroids`: every element of `points` counts.
+    pub fn from_classes(points: &[Point], classes: &HashMap<&Point, Class>) -> Self {
+        let mut clusters = cluster_members(points, classes)
+            .into_iter()
+            .map(|(id, members)| ClusterSummary {
+                id,
+                size: members.len(),
+                weight: None,
+                centroid: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        clusters.sort_by_key(|c| c.id);
+        let mut centroids = centroids(points, classes);
+        for cluster in clusters.iter_mut() {
+            let centroid = centroids.remove(&cluster.id).unwrap_or_default();
+            cluster.centroid = centroid.into_iter().map(|x| x.0).collect();
+        }
+        let clustered = clusters.iter().map(|c| c.size).sum::<usize>();
+        Self {
+            version: RUN_SUMMARY_VERSION,
+            n_points: points.len(),
+            n_clusters: clusters.len(),
+            n_noise: points.len() - clustered,
+            clusters,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the total weight of every cluster, `weights` being aligned with
+    /// the `points` given to `from_classes`.
+    pub fn with_weights(
+        mut self,
+        points: &[Point],
+        classes: &HashMap<&Point, Class>,
+        weights: &[f64],
+    ) -> Self {
+        let members = cluster_members(points, classes);
+        for cluster in self.clusters.iter_mut() {
+            let total = members
+                .get(&cluster.id)
+                .map_or(0.0, |m| m.iter().map(|&i| weights[i]).sum());
+            cluster.weight = Some(total);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(coords: &[f64]) -> Point {
+        coords.iter().copied().map(OrderedFloat).collect()
+    }
+
+    #[test]
+    fn clusters_are_numbered_in_input_order() {
+        let points = vec![pt(&[5.0]), pt(&[0.0]), pt(&[9.0]), pt(&[1.0]), pt(&[7.0])];
+        let mut classes = HashMap::from([
+            (&points[0], Class::Classified(2)),
+            (&points[1], Class::Classified(0)),
+            (&points[2], Class::Noise),
+            (&points[3], Class::Classified(0)),
+            (&points[4], Class::Classified(5)),
+        ]);
+        number_clusters_in_order(&points, &mut classes);
+        let ids = points.iter().map(|p| classes[p]).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [
+                Class::Classified(0),
+                Class::Classified(1),
+                Class::Noise,
+                Class::Classified(1),
+                Class::Classified(2),
+            ]
+        );
+
+        // Clusters missing from `points` follow, by their old ids.
+        let other = pt(&[3.0]);
+        classes.insert(&other, Class::Classified(1));
+        number_clusters_in_order(&points[4..], &mut classes);
+        assert_eq!(classes[&points[4]], Class::Classified(0));
+        assert_eq!(classes[&points[0]], Class::Classified(1));
+        assert_eq!(classes[&points[1]], Class::Classified(2));
+        assert_eq!(classes[&other], Class::Classified(2));
+    }
+
+    #[test]
+    fn weighted_points_need_positive_weights() {
+        let point = WeightedPoint::new(pt(&[1.0, 2.0]), 2.5).unwrap();
+        assert_eq!(point.weight, 2.5);
+        assert_eq!(Point::from(point), pt(&[1.0, 2.0]));
+        assert_eq!(WeightedPoint::from(pt(&[3.0])).weight, 1.0);
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = WeightedPoint::new(pt(&[1.0]), weight).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("weight must be finite and positive, got {}", weight)
+            );
+        }
+    }
+
+    #[test]
+    fn duplicates_collapse_into_weights() {
+        let points = [pt(&[1.0]), pt(&[2.0]), pt(&[1.0]), pt(&[3.0]), pt(&[1.0])];
+        let collapsed = collapse_duplicates(&points);
+        let weights = collapsed
+            .iter()
+            .map(|p| (p.coords[0].0, p.weight))
+            .collect::<Vec<_>>();
+        assert_eq!(weights, [(1.0, 3.0), (2.0, 1.0), (3.0, 1.0)]);
+
+        let mut split = collapsed.clone();
+        split.push(WeightedPoint::new(pt(&[2.0]), 0.5).unwrap());
+        let map = weight_map(&split);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&pt(&[1.0])], 3.0);
+        assert_eq!(map[&pt(&[2.0])], 1.5);
+    }
+
+    #[test]
+    fn cloud_round_trips_points() {
+        let points = vec![
+            pt(&[1.0, 2.0, 3.0]),
+            pt(&[-4.0, 5.5, 0.0]),
+            pt(&[1.0, 2.0, 3.0]),
+        ];
+        let cloud = PointCloud::from(points.as_slice());
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.dim(), 3);
+        assert_eq!(cloud.row(1), [-4.0, 5.5, 0.0]);
+        assert_eq!(cloud.as_slice().len(), 9);
+        assert_eq!(cloud.rows().len(), 3);
+        assert!(cloud.rows().zip(points.iter()).all(|(row, p)| row
+            .iter()
+            .copied()
+            .map(OrderedFloat)
+            .eq(p.iter().copied())));
+        assert_eq!(Vec::<Point>::from(&cloud), points);
+        assert_eq!(cloud.view().to_points(), points);
+    }
+
+    #[test]
+    fn cloud_of_f32_points() {
+        let points = vec![
+            vec![OrderedFloat(0.5f32), OrderedFloat(1.0)],
+            vec![OrderedFloat(2.0), OrderedFloat(-1.0)],
+        ];
+        let cloud = PointCloud::from_points(&points);
+        assert_eq!(cloud.row(1), [2.0f32, -1.0]);
+        assert_eq!(cloud.to_points(), points);
+    }
+
+    #[test]
+    fn empty_and_zero_dimensional_clouds() {
+        let empty = PointCloud::<f64>::from_points(&[]);
+        assert!(empty.is_empty());
+        assert_eq!(empty.dim(), 0);
+        assert!(empty.to_points().is_empty());
+
+        let points: Vec<Point> = vec![Point::new(), Point::new()];
+        let cloud = PointCloud::from(points.as_slice());
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.rows().collect::<Vec<_>>(), [&[] as &[f64], &[]]);
+        assert_eq!(cloud.to_points(), points);
+    }
+
+    #[test]
+    fn pushed_rows_are_appended() {
+        let mut cloud = PointCloud::new(2);
+        cloud.push([1.0, 2.0]);
+        cloud.push(vec![3.0, 4.0]);
+        assert_eq!(cloud.as_slice(), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(cloud.to_points(), [pt(&[1.0, 2.0]), pt(&[3.0, 4.0])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "point dimension mismatch")]
+    fn push_rejects_other_dimensions() {
+        PointCloud::new(2).push([1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn static_points_convert_to_and_from_points() {
+        let p = PointN::from([3.0, -4.0, 0.5]);
+        assert_eq!(p.0, [3.0, -4.0, 0.5]);
+        let dynamic = Point::from(p);
+        assert_eq!(dynamic, pt(&[3.0, -4.0, 0.5]));
+        assert_eq!(p.to_point(), dynamic);
+        assert_eq!(PointN::<3>::from_point(&dynamic), Some(p));
+        assert_eq!(PointN::<2>::from_point(&dynamic), None);
+        assert_eq!(PointN::<4>::from_point(&dynamic), None);
+    }
+
+    #[test]
+    fn static_distances_match_dynamic_ones() {
+        let (a, b) = (PointN::from([1.0, 2.0]), PointN::from([4.0, 6.0]));
+        assert_eq!(a.dist_sq(&b), 25.0);
+        assert_eq!(a.dist(&b), 5.0);
+        assert_eq!(a.dist(&b), dist(&a.to_point(), &b.to_point()));
+        assert_eq!(PointN::<0>::from([]).dist(&PointN::from([])), 0.0);
+    }
+
+    #[test]
+    fn condensed_index_and_pair_are_inverse() {
+        for n in 0..9 {
+            let m = CondensedMatrix::new(n, vec![0.0; n * n.saturating_sub(1) / 2]).unwrap();
+            let mut k = 0;
+            for i in 0..n {
+                assert_eq!(m.index(i, i), None);
+                for j in i + 1..n {
+                    // Row-major upper triangle: positions count up.
+                    assert_eq!(m.index(i, j), Some(k));
+                    assert_eq!(m.index(j, i), Some(k));
+                    assert_eq!(m.pair(k), (i, j));
+                    k += 1;
+                }
+            }
+            assert_eq!(k, m.as_slice().len());
+        }
+        let m = CondensedMatrix::new(4, vec![0.0; 6]).unwrap();
+        assert_eq!(m.index(0, 3), Some(2));
+        assert_eq!(m.index(1, 2), Some(3));
+        assert_eq!(m.index(3, 2), Some(5));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn condensed_index_checks_bounds() {
+        CondensedMatrix::new(3, vec![0.0; 3]).unwrap().index(1, 3);
+    }
+
+    #[test]
+    fn condensed_matrix_needs_a_triangle() {
+        assert!(CondensedMatrix::new(4, vec![0.0; 6]).is_ok());
+        let err = CondensedMatrix::new(4, vec![0.0; 5]).unwrap_err();
+        assert!(err.to_string().contains("has 6 entries, got 5"), "{}", err);
+    }
+
+    #[test]
+    fn pairwise_distances_are_symmetric() {
+        let points = (0..7)
+            .map(|i| pt(&[i as f64, (i * i % 5) as f64, -0.5 * i as f64]))
+            .collect::<Vec<_>>();
+        for metric in [
+            MetricKind::Euclidean,
+            MetricKind::Manhattan,
+            MetricKind::Cosine,
+        ] {
+            let m = pairwise_distances(&points, &metric).unwrap();
+            assert_eq!(m.len(), 7);
+            for i in 0..7 {
+                assert_eq!(m.get(i, i), 0.0);
+                for j in 0..7 {
+                    assert_eq!(m.get(i, j), m.get(j, i));
+                    if i != j {
+                        assert_eq!(m.get(i, j), metric.dist(&points[i], &points[j]));
+                    }
+                }
+                assert!(m.row(i).eq((0..7).map(|j| m.get(i, j))));
+            }
+        }
+    }
+
+    #[test]
+    fn pairwise_distances_respect_the_cap() {
+        let points = vec![pt(&[0.0]); 5];
+        // 5 points: 10 distances of 8 bytes.
+        assert!(pairwise_distances_capped(&points, &Euclidean, 80).is_ok());
+        let err = pairwise_distances_capped(&points, &Euclidean, 79).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pairwise distances for 5 points need 80 bytes, more than the cap of 79 bytes"
+        );
+
+        // About 40 GB: refused up front, not by a failed allocation.
+        let many = vec![Point::new(); 100_000];
+        let err = pairwise_distances(&many, &Euclidean).unwrap_err();
+        assert!(err.to_string().contains("100000 points"), "{}", err);
+
+        assert!(pairwise_distances(&[], &Euclidean).unwrap().is_empty());
+        assert!(pairwise_distances_capped(&points[..1], &Euclidean, 0).is_ok());
+    }
+
+    #[test]
+    fn view_matches_the_buffer() {
+        let coords = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let view = CloudView::new(&coords, 3);
+        assert_eq!(view.len(), 2);
+        assert_eq!(view.row(1), [4.0, 5.0, 6.0]);
+        assert_eq!(
+            view.to_points(),
+            [pt(&[1.0, 2.0, 3.0]), pt(&[4.0, 5.0, 6.0])]
+        );
+    }
+
+    #[test]
+    fn lp_metrics_give_hand_computed_distances() {
+        // Differences of 3 and 4.
+        let (a, b) = (pt(&[1.0, 2.0]), pt(&[4.0, -2.0]));
+        assert_eq!(MetricKind::Euclidean.distance(&a, &b), 5.0);
+        assert_eq!(MetricKind::Euclidean.distance(&a, &b), dist(&a, &b));
+        assert_eq!(MetricKind::Manhattan.distance(&a, &b), 7.0);
+        assert_eq!(MetricKind::Chebyshev.distance(&a, &b), 4.0);
+        assert_eq!(MetricKind::Minkowski(1.0).distance(&a, &b), 7.0);
+        assert_eq!(MetricKind::Minkowski(2.0).distance(&a, &b), 5.0);
+        let cube = MetricKind::Minkowski(3.0).distance(&a, &b);
+        assert!((cube - 91f64.cbrt()).abs() < 1e-12, "{}", cube);
+
+        // Differences of 1, 2 and 2.
+        let (a, b) = (pt(&[0.0, 0.0, 0.0]), pt(&[1.0, -2.0, 2.0]));
+        assert_eq!(MetricKind::Euclidean.distance(&a, &b), 3.0);
+        assert_eq!(MetricKind::Manhattan.distance(&a, &b), 5.0);
+        assert_eq!(MetricKind::Chebyshev.distance(&a, &b), 2.0);
+        let large_p = MetricKind::Minkowski(50.0).distance(&a, &b);
+        assert!(large_p > 2.0 && large_p < 2.03, "{}", large_p);
+    }
+
+    #[test]
+    fn lp_metrics_are_symmetric_with_zero_self_distance() {
+        let mut rng = crate::sampling::SplitMix64::new(339);
+        let mut point = || pt(&[0, 1, 2].map(|_| rng.below(200) as f64 / 8.0 - 12.5));
+        let points = (0..30).map(|_| point()).collect::<Vec<_>>();
+        let metrics = [
+            MetricKind::Euclidean,
+            MetricKind::Manhattan,
+            MetricKind::Chebyshev,
+            MetricKind::Minkowski(1.5),
+            MetricKind::Minkowski(4.0),
+        ];
+        for metric in metrics {
+            for a in points.iter() {
+                assert_eq!(metric.distance(a, a), 0.0, "{:?}", metric);
+                for b in points.iter() {
+                    let d = metric.distance(a, b);
+                    assert_eq!(d, metric.distance(b, a), "{:?}", metric);
+                    assert_eq!(d > 0.0, a != b, "{:?}", metric);
+                    // An eps equal to the distance is inclusive.
+                    assert!(metric.key(a, b) <= metric.key_bound(d), "{:?}", metric);
+                    assert_eq!(metric.key_to_dist(metric.key(a, b)), d, "{:?}", metric);
+                    for c in points.iter().take(5) {
+                        let around = metric.distance(a, c) + metric.distance(c, b);
+                        assert!(d <= around * (1.0 + 1e-12), "{:?}", metric);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cosine_distance_of_zero_vectors() {
+        let cosine = MetricKind::Cosine;
+        let zero = pt(&[0.0, 0.0]);
+        let x = pt(&[2.0, 0.0]);
+        assert_eq!(cosine.distance(&zero, &zero), 0.0);
+        assert_eq!(cosine.distance(&zero, &x), 1.0);
+        assert_eq!(cosine.distance(&x, &zero), 1.0);
+        let angular = MetricKind::Angular;
+        assert_eq!(angular.distance(&zero, &zero), 0.0);
+        assert_eq!(angular.distance(&zero, &x), std::f64::consts::FRAC_PI_2);
+
+        // Same direction, orthogonal and opposite, whatever the lengths.
+        assert_eq!(cosine.distance(&x, &pt(&[0.5, 0.0])), 0.0);
+        assert_eq!(cosine.distance(&x, &pt(&[0.0, 3.0])), 1.0);
+        assert_eq!(cosine.distance(&x, &pt(&[-7.0, 0.0])), 2.0);
+        let diagonal = cosine.distance(&x, &pt(&[1.0, 1.0]));
+        assert!(
+            (diagonal - (1.0 - 0.5f64.sqrt())).abs() < 1e-15,
+            "{}",
+            diagonal
+        );
+    }
+
+    #[test]
+    fn normalized_points_rank_neighbors_like_cosine() {
+        let mut rng = crate::sampling::SplitMix64::new(340);
+        let mut point = || pt(&[0, 1, 2].map(|_| rng.below(21) as f64 - 10.0));
+        let mut points = (0..40).map(|_| point()).collect::<Vec<_>>();
+        points.push(pt(&[0.0, 0.0, 0.0]));
+        let original = points.clone();
+        normalize_l2(&mut points);
+
+        for (p, q) in points.iter().zip(original.iter()) {
+            let norm = p.iter().map(|x| x.0 * x.0).sum::<f64>().sqrt();
+            if q.iter().all(|x| x.0 == 0.0) {
+                assert_eq!(p, q);
+            } else {
+                assert!((norm - 1.0).abs() < 1e-12, "{:?}", p);
+            }
+        }
+        // For unit vectors, dist^2 = 2 * cosine distance, so both order the
+        // other points alike.
+        for (i, a) in original.iter().enumerate().take(40) {
+            let cosine = original[..40]
+                .iter()
+                .map(|b| MetricKind::Cosine.distance(a, b))
+                .collect::<Vec<_>>();
+            let euclid = points[..40]
+                .iter()
+                .map(|b| dist(&points[i], b))
+                .collect::<Vec<_>>();
+            for (c, e) in cosine.iter().zip(euclid.iter()) {
+                assert!((e * e - 2.0 * c).abs() < 1e-12, "{} {}", e, c);
+            }
+            let rank = |d: &[f64]| {
+                let mut order = (0..d.len()).collect::<Vec<_>>();
+                // Round so that distances equal up to rounding tie in both.
+                order.sort_by_key(|&j| ((d[j] * 1e9).round() as i64, j));
+                order
+            };
+            let squared = euclid.iter().map(|e| e * e / 2.0).collect::<Vec<_>>();
+            assert_eq!(rank(&cosine), rank(&squared));
+        }
+    }
+
+    #[test]
+    fn angular_distances_are_angles_in_radians() {
+        use std::f64::consts::{FRAC_PI_2, FRAC_PI_3, PI};
+
+        let angular = MetricKind::Angular;
+        let x = pt(&[2.0, 0.0]);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-15;
+        assert_eq!(angular.distance(&x, &pt(&[0.5, 0.0])), 0.0);
+        assert!(close(angular.distance(&x, &pt(&[0.0, 3.0])), FRAC_PI_2));
+        assert!(close(angular.distance(&x, &pt(&[-7.0, 0.0])), PI));
+        let sixty = pt(&[1.0, 3f64.sqrt()]);
+        assert!(close(angular.distance(&x, &sixty), FRAC_PI_3));
+        // Accurate where acos of the similarity is not.
+        let tiny = angular.distance(&x, &pt(&[1.0, 1e-9]));
+        assert!((tiny - 1e-9).abs() < 1e-22, "{}", tiny);
+
+        let mut rng = crate::sampling::SplitMix64::new(361);
+        let mut point = || pt(&[0, 1, 2].map(|_| rng.below(21) as f64 - 10.0));
+        let points = (0..30).map(|_| point()).collect::<Vec<_>>();
+        for a in &points {
+            for b in &points {
+                let ab = angular.distance(a, b);
+                assert!((0.0..=PI).contains(&ab));
+                assert_eq!(ab, angular.distance(b, a));
+                for c in &points {
+                    let (ac, cb) = (angular.distance(a, c), angular.distance(c, b));
+                    assert!(ab <= ac + cb + 1e-12, "{} > {} + {}", ab, ac, cb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn projected_points_rank_neighbors_like_angles() {
+        let mut rng = crate::sampling::SplitMix64::new(361);
+        let mut point = || pt(&[0, 1, 2, 3, 4].map(|_| rng.below(21) as f64 - 10.0));
+        let mut points = (0..60).map(|_| point()).collect::<Vec<_>>();
+        points.insert(3, pt(&[0.0; 5]));
+        points.insert(20, pt(&[0.0; 5]));
+        let original = points.clone();
+        assert_eq!(project_to_unit_sphere(&mut points), [3, 20]);
+        assert_eq!(points.len(), 60);
+        let original = original
+            .into_iter()
+            .filter(|p| p.iter().any(|x| x.0 != 0.0))
+            .collect::<Vec<_>>();
+
+        for (i, a) in original.iter().enumerate() {
+            let angles = original
+                .iter()
+                .map(|b| MetricKind::Angular.distance(a, b))
+                .collect::<Vec<_>>();
+            let euclid = points
+                .iter()
+                .map(|b| dist(&points[i], b))
+                .collect::<Vec<_>>();
+            let chords = angles
+                .iter()
+                .map(|&t| angular_to_chord(t))
+                .collect::<Vec<_>>();
+            for (c, e) in chords.iter().zip(euclid.iter()) {
+                assert!((c - e).abs() < 1e-12, "{} {}", c, e);
+            }
+            let rank = |d: &[f64]| {
+                let mut order = (0..d.len()).collect::<Vec<_>>();
+                // Round so that distances equal up to rounding tie in both.
+                order.sort_by_key(|&j| ((d[j] * 1e9).round() as i64, j));
+                order
+            };
+            assert_eq!(rank(&chords), rank(&euclid));
+        }
+        assert_eq!(angular_to_chord(0.0), 0.0);
+        assert!((angular_to_chord(std::f64::consts::PI) - 2.0).abs() < 1e-15);
+    }
+
+    #[test]
+    fn haversine_gives_city_distances_in_the_unit_of_the_radius() {
+        let paris = pt(&[48.8566, 2.3522]);
+        let london = pt(&[51.5074, -0.1278]);
+        let new_york = pt(&[40.7128, -74.0060]);
+        let los_angeles = pt(&[34.0522, -118.2437]);
+        let sydney = pt(&[-33.8688, 151.2093]);
+        let auckland = pt(&[-36.8485, 174.7633]);
+        for (a, b, km) in [
+            (&paris, &london, 343.6),
+            (&new_york, &los_angeles, 3935.8),
+            (&sydney, &auckland, 2155.9),
+        ] {
+            let d = haversine(a, b);
+            assert!((d - km).abs() < 0.1, "{} km, not {}", d, km);
+            assert_eq!(d, haversine(b, a));
+            assert_eq!(d, MetricKind::haversine_km().distance(a, b));
+            // The result scales with the radius: miles, or nautical miles
+            // at 60 to a degree of arc.
+            let miles = haversine_with_radius(a, b, 3958.7613);
+            assert!((miles - km / 1.609344).abs() < 0.1, "{} miles", miles);
+            let nautical = 180.0 * 60.0 / std::f64::consts::PI;
+            let ratio = MetricKind::Haversine(nautical).distance(a, b) / d;
+            assert!((ratio - nautical / EARTH_RADIUS_KM).abs() < 1e-12);
+        }
+        let nautical = 180.0 * 60.0 / std::f64::consts::PI;
+        let degree = haversine_with_radius(&pt(&[10.0, 20.0]), &pt(&[11.0, 20.0]), nautical);
+        assert!((degree - 60.0).abs() < 1e-9, "{}", degree);
+    }
+
+    #[test]
+    fn haversine_across_the_antimeridian_and_the_poles() {
+        let degree = EARTH_RADIUS_KM * std::f64::consts::PI / 180.0;
+        let half_way = EARTH_RADIUS_KM * std::f64::consts::PI;
+        let close = |d: f64, expected: f64| (d - expected).abs() < 1e-6;
+        // One degree apart over the antimeridian, not 359.
+        assert!(close(
+            haversine(&pt(&[0.0, 179.5]), &pt(&[0.0, -179.5])),
+            degree
+        ));
+        assert!(close(
+            haversine(&pt(&[0.0, 180.0]), &pt(&[0.0, -180.0])),
+            0.0
+        ));
+        // Longitude means nothing at a pole.
+        assert!(close(
+            haversine(&pt(&[90.0, 0.0]), &pt(&[90.0, 120.0])),
+            0.0
+        ));
+        assert!(close(
+            haversine(&pt(&[-90.0, -45.0]), &pt(&[-89.0, 135.0])),
+            degree
+        ));
+        assert!(close(
+            haversine(&pt(&[90.0, 0.0]), &pt(&[-90.0, 0.0])),
+            half_way
+        ));
+        assert!(close(
+            haversine(&pt(&[0.0, 0.0]), &pt(&[0.0, 180.0])),
+            half_way
+        ));
+        assert!(close(
+            haversine(&pt(&[30.0, 40.0]), &pt(&[-30.0, -140.0])),
+            half_way
+        ));
+    }
+
+    #[test]
+    fn haversine_rejects_points_off_the_globe() {
+        let metric = MetricKind::haversine_km();
+        let err = metric
+            .check_points(&[pt(&[0.0, 0.0]), pt(&[91.0, 0.0])])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "point 1: invalid (latitude, longitude) (91, 0)"
+        );
+        let err = metric.check_points(&[pt(&[0.0, 0.0, 0.0])]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "point 0: haversine needs (latitude, longitude) points, got dimension 3"
+        );
+        assert!(metric
+            .check_points(&[pt(&[-90.0, 180.0]), pt(&[90.0, -180.0])])
+            .is_ok());
+        // Other metrics take any points.
+        assert!(MetricKind::Euclidean
+            .check_points(&[pt(&[91.0, 0.0, 1.0])])
+            .is_ok());
+
+        for (a, b) in [
+            (pt(&[0.0, 181.0]), pt(&[0.0, 0.0])),
+            (pt(&[0.0, 0.0]), pt(&[-90.5, 0.0])),
+            (pt(&[0.0]), pt(&[0.0, 0.0])),
+        ] {
+            let panic = std::panic::catch_unwind(|| haversine(&a, &b));
+            assert!(panic.is_err(), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn mahalanobis_with_known_covariances() {
+        let identity = MahalanobisMetric::from_inverse_covariance(vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ])
+        .unwrap();
+        assert_eq!(identity.dim(), 3);
+        let mut rng = crate::sampling::SplitMix64::new(342);
+        for _ in 0..50 {
+            let mut point = || pt(&[0, 1, 2].map(|_| rng.below(100) as f64 / 7.0));
+            let (a, b) = (point(), point());
+            let d = identity.distance(&a, &b);
+            assert!((d - dist(&a, &b)).abs() < 1e-12, "{} {}", d, dist(&a, &b));
+        }
+
+        // Variances 4 and 9: 2 along x and 3 along y are one unit each.
+        let diagonal =
+            MahalanobisMetric::from_inverse_covariance(vec![vec![0.25, 0.0], vec![0.0, 1.0 / 9.0]])
+                .unwrap();
+        assert_eq!(diagonal.distance(&pt(&[1.0, 1.0]), &pt(&[3.0, 1.0])), 1.0);
+        assert_eq!(diagonal.distance(&pt(&[1.0, 1.0]), &pt(&[1.0, 4.0])), 1.0);
+        let both = diagonal.distance(&pt(&[0.0, 0.0]), &pt(&[2.0, -3.0]));
+        assert!((both - 2f64.sqrt()).abs() < 1e-15, "{}", both);
+    }
+
+    #[test]
+    fn mahalanobis_estimates_the_covariance() {
+        // Mean 0, variances 8/3 and 6, no correlation.
+        let points = [
+            pt(&[2.0, 0.0]),
+            pt(&[-2.0, 0.0]),
+            pt(&[0.0, 3.0]),
+            pt(&[0.0, -3.0]),
+        ];
+        let metric = MahalanobisMetric::estimate_from(&points, 0.0).unwrap();
+        let origin = pt(&[0.0, 0.0]);
+        let x = metric.distance(&origin, &pt(&[(8.0f64 / 3.0).sqrt(), 0.0]));
+        let y = metric.distance(&origin, &pt(&[0.0, 6f64.sqrt()]));
+        assert!(
+            (x - 1.0).abs() < 1e-12 && (y - 1.0).abs() < 1e-12,
+            "{} {}",
+            x,
+            y
+        );
+
+        // Along a correlation the same step counts for less than across it.
+        let mut rng = crate::sampling::SplitMix64::new(342);
+        let correlated = (0..200)
+            .map(|_| {
+                let t = rng.below(1000) as f64 / 100.0;
+                pt(&[t, t + rng.below(100) as f64 / 100.0])
+            })
+            .collect::<Vec<_>>();
+        let metric = MahalanobisMetric::estimate_from(&correlated, 0.0).unwrap();
+        let along = metric.distance(&origin, &pt(&[1.0, 1.0]));
+        let across = metric.distance(&origin, &pt(&[1.0, -1.0]));
+        assert!(across > 5.0 * along, "{} {}", along, across);
+        assert_eq!(
+            metric.distance(&pt(&[1.0, 2.0]), &pt(&[3.0, 5.0])),
+            metric.distance(&pt(&[3.0, 5.0]), &pt(&[1.0, 2.0]))
+        );
+    }
+
+    #[test]
+    fn mahalanobis_rejects_singular_and_malformed_matrices() {
+        // Collinear points have no spread across their line.
+        let line = [
+            pt(&[0.0, 0.0]),
+            pt(&[1.0, 2.0]),
+            pt(&[2.0, 4.0]),
+            pt(&[3.0, 6.0]),
+        ];
+        let err = MahalanobisMetric::estimate_from(&line, 0.0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "covariance matrix is singular; use a positive ridge term"
+        );
+        let ridged = MahalanobisMetric::estimate_from(&line, 0.1).unwrap();
+        assert!(ridged.distance(&line[0], &line[1]) > 0.0);
+
+        let err = MahalanobisMetric::estimate_from(&line[..1], 0.1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "need at least 2 points to estimate a covariance matrix"
+        );
+        let mixed = [pt(&[0.0, 0.0]), pt(&[1.0])];
+        assert!(MahalanobisMetric::estimate_from(&mixed, 0.1).is_err());
+        for matrix in [
+            vec![],
+            vec![vec![1.0, 0.0]],
+            vec![vec![1.0, 0.0], vec![0.0]],
+            vec![vec![1.0, f64::NAN], vec![0.0, 1.0]],
+        ] {
+            assert!(MahalanobisMetric::from_inverse_covariance(matrix).is_err());
+        }
+        let panic = std::panic::catch_unwind(|| ridged.distance(&pt(&[0.0]), &pt(&[1.0])));
+        assert!(panic.is_err());
+    }
+
+    #[test]
+    fn gower_distances_by_hand() {
+        let metric = GowerMetric::new(vec![
+            GowerColumn::Numeric { range: 10.0 },
+            GowerColumn::Categorical,
+            GowerColumn::Numeric { range: 2.0 },
+        ])
+        .unwrap();
+        assert_eq!(metric.dim(), 3);
+        let a = pt(&[1.0, 3.0, 0.0]);
+        // (5 / 10 + 0 + 1.5 / 2) / 3
+        let b = pt(&[6.0, 3.0, 1.5]);
+        assert!((metric.distance(&a, &b) - 1.25 / 3.0).abs() < 1e-15);
+        assert_eq!(metric.distance(&a, &b), metric.distance(&b, &a));
+        // Any other code is one unit, however far its number.
+        assert_eq!(metric.distance(&a, &pt(&[1.0, 4.0, 0.0])), 1.0 / 3.0);
+        assert_eq!(metric.distance(&a, &pt(&[1.0, 40.0, 0.0])), 1.0 / 3.0);
+        assert_eq!(metric.distance(&a, &pt(&[11.0, 4.0, 2.0])), 1.0);
+        assert_eq!(metric.distance(&a, &a), 0.0);
+        assert_eq!(
+            metric.key_coords(&[1.0, 3.0, 0.0], &[6.0, 3.0, 1.5]),
+            metric.distance(&a, &b)
+        );
+    }
+
+    #[test]
+    fn gower_ranges_are_fitted_to_the_data() {
+        let points = [
+            pt(&[0.0, 1.0, 5.0]),
+            pt(&[10.0, 2.0, 5.0]),
+            pt(&[-4.0, 1.0, 5.0]),
+        ];
+        let metric = GowerMetric::fit(&points, &[1]).unwrap();
+        assert_eq!(
+            metric.columns(),
+            [
+                GowerColumn::Numeric { range: 14.0 },
+                GowerColumn::Categorical,
+                GowerColumn::Numeric { range: 1.0 },
+            ]
+        );
+        // The data's extremes are one unit apart in the numeric column.
+        assert_eq!(metric.distance(&points[1], &points[2]), 2.0 / 3.0);
+
+        let message = |result: anyhow::Result<GowerMetric>| result.unwrap_err().to_string();
+        assert_eq!(
+            message(GowerMetric::new(vec![])),
+            "Gower metric needs at least one column"
+        );
+        assert_eq!(
+            message(GowerMetric::new(vec![
+                GowerColumn::Categorical,
+                GowerColumn::Numeric { range: 0.0 },
+            ])),
+            "column 1: range must be finite and positive, got 0"
+        );
+        assert_eq!(
+            message(GowerMetric::fit(&[], &[])),
+            "cannot fit a Gower metric to an empty point set"
+        );
+        assert_eq!(
+            message(GowerMetric::fit(&points, &[3])),
+            "categorical column 3 is out of range for dimension 3"
+        );
+        assert_eq!(
+            message(GowerMetric::fit(&[pt(&[1.0]), pt(&[1.0, 2.0])], &[])),
+            "dimension mismatch at point 1: expected 1, got 2"
+        );
+    }
+
+    #[test]
+    fn classes_print_and_parse_as_cids() {
+        let classes = [
+            (Class::Classified(0), "0"),
+            (Class::Classified(3), "3"),
+            (Class::Classified(12345), "12345"),
+            (Class::Noise, "-1"),
+            (Class::Unclassified, "-2"),
+        ];
+        for (class, text) in classes {
+            assert_eq!(class.to_string(), text);
+            assert_eq!(text.parse::<Class>(), Ok(class));
+            assert_eq!(Class::from_cid(class.cid()), Some(class));
+        }
+        // Noise and unclassified points stay apart.
+        assert_ne!(Class::Noise.cid(), Class::Unclassified.cid());
+        assert_eq!(" 7\n".parse::<Class>(), Ok(Class::Classified(7)));
+    }
+
+    #[test]
+    fn invalid_cids_are_rejected() {
+        assert_eq!(Class::from_cid(-3), None);
+        assert_eq!(Class::from_cid(isize::MIN), None);
+        for text in ["-3", "", "noise", "1.5", "+-1", "0x1"] {
+            let err = text.parse::<Class>().unwrap_err();
+            assert_eq!(err, ParseClassError(text.to_string()));
+            assert_eq!(err.to_string(), format!("invalid cid '{}'", text));
+        }
+    }
+
+    #[test]
+    fn labels_follow_the_input_order() {
+        let points = vec![pt(&[0.0]), pt(&[5.0]), pt(&[1.0]), pt(&[0.0]), pt(&[9.0])];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(1)),
+            (&points[1], Class::Classified(0)),
+            (&points[2], Class::Noise),
+            (&points[4], Class::Unclassified),
+        ]);
+        // The copy of the first point shares its entry.
+        assert_eq!(labels_in_order(&points, &classes), [1, 0, -1, 1, -2]);
+        // Points without an entry are noise.
+        let partial = HashMap::from([(&points[1], Class::Classified(2))]);
+        assert_eq!(labels_in_order(&points, &partial), [-1, 2, -1, -1, -1]);
+        assert!(labels_in_order(&[], &classes).is_empty());
+    }
+
+    #[test]
+    fn labels_convert_back_to_classes() {
+        let points = vec![pt(&[0.0]), pt(&[5.0]), pt(&[1.0]), pt(&[9.0])];
+        let labels = [1, 0, -1, -2];
+        let classes = labels_to_classes(&points, &labels);
+        assert_eq!(classes.len(), 4);
+        assert_eq!(classes[&points[0]], Class::Classified(1));
+        assert_eq!(classes[&points[3]], Class::Unclassified);
+        assert_eq!(labels_in_order(&points, &classes), labels);
+
+        // Ids below -2 are noise, and the last of equal points wins.
+        let copies = vec![pt(&[0.0]), pt(&[0.0]), pt(&[3.0])];
+        let classes = labels_to_classes(&copies, &[4, 2, -7]);
+        assert_eq!(classes.len(), 2);
+        assert_eq!(classes[&pt(&[0.0])], Class::Classified(2));
+        assert_eq!(classes[&pt(&[3.0])], Class::Noise);
+    }
+
+    // Cluster 0 with a duplicate point, a single-point cluster 1, a line
+    // (cluster 2), a pair (cluster 3) and one noise point.
+    fn summarized() -> (Vec<Point>, Vec<Class>) {
+        let points = [
+            (pt(&[0.0, 0.0]), Class::Classified(0)),
+            (pt(&[4.0, 0.0]), Class::Classified(0)),
+            (pt(&[4.0, 0.0]), Class::Classified(0)),
+            (pt(&[1.0, 3.0]), Class::Classified(0)),
+            (pt(&[10.0, 10.0]), Class::Classified(1)),
+            (pt(&[0.0, 20.0]), Class::Classified(2)),
+            (pt(&[3.0, 20.0]), Class::Classified(2)),
+            (pt(&[1.0, 20.0]), Class::Classified(2)),
+            (pt(&[0.0, 30.0]), Class::Classified(3)),
+            (pt(&[2.0, 30.0]), Class::Classified(3)),
+            (pt(&[50.0, 50.0]), Class::Noise),
+        ];
+        points.into_iter().unzip()
+    }
+
+    #[test]
+    fn centroids_are_cluster_means() {
+        let (points, ids) = summarized();
+        let classes = points.iter().zip(ids).collect::<HashMap<_, _>>();
+        let centroids = centroids(&points, &classes);
+        assert_eq!(centroids.len(), 4);
+        // The duplicate counts twice: (0 + 4 + 4 + 1) / 4, (0 + 0 + 0 + 3) / 4.
+        assert_eq!(centroids[&0], pt(&[2.25, 0.75]));
+        assert_eq!(centroids[&1], pt(&[10.0, 10.0]));
+        assert_eq!(centroids[&2], pt(&[4.0 / 3.0, 20.0]));
+        assert_eq!(centroids[&3], pt(&[1.0, 30.0]));
+    }
+
+    #[test]
+    fn medoids_minimize_the_total_distance() {
+        let (points, ids) = summarized();
+        let classes = points.iter().zip(ids).collect::<HashMap<_, _>>();
+        let medoids = medoids(&points, &classes, &Euclidean);
+        assert_eq!(medoids.len(), 4);
+        // Totals 4 + 4 + sqrt(10) for (0, 0), 4 + 0 + sqrt(18) for both copies
+        // of (4, 0) and sqrt(10) + 2 sqrt(18) for (1, 3); the first copy wins.
+        assert!(std::ptr::eq(medoids[&0], &points[1]));
+        assert!(std::ptr::eq(medoids[&1], &points[4]));
+        assert_eq!(medoids[&2], &pt(&[1.0, 20.0]));
+        // Both ends of a pair tie.
+        assert!(std::ptr::eq(medoids[&3], &points[8]));
+
+        // Without the duplicate, the medoid depends on the metric: totals 4 +
+        // sqrt(10), 4 + sqrt(18) and sqrt(10) + sqrt(18) under Euclidean, but
+        // 4 + 3, 4 + 3 and 3 + 3 under Chebyshev.
+        let triangle = [pt(&[0.0, 0.0]), pt(&[4.0, 0.0]), pt(&[1.0, 3.0])];
+        let classes = triangle
+            .iter()
+            .map(|p| (p, Class::Classified(0)))
+            .collect::<HashMap<_, _>>();
+        let medoid = |metric: MetricKind| super::medoids(&triangle, &classes, &metric)[&0];
+        assert_eq!(medoid(MetricKind::Euclidean), &triangle[0]);
+        assert_eq!(medoid(MetricKind::Chebyshev), &triangle[2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "got 1 labels for 2 points")]
+    fn labels_must_match_the_points() {
+        labels_to_classes(&[pt(&[0.0]), pt(&[1.0])], &[0]);
+    }
+
+    #[test]
+    fn validation_reports_the_first_non_finite_coordinate() {
+        let points = [
+            pt(&[0.0, 1.0]),
+            pt(&[2.0, f64::NEG_INFINITY]),
+            pt(&[f64::NAN, f64::INFINITY]),
+        ];
+        let err = validate_points(&points).unwrap_err();
+        assert_eq!(
+            (err.row, err.column, err.kind),
+            (Some(1), 1, NonFinite::NegInfinity)
+        );
+        assert_eq!(
+            err.to_string(),
+            "row 1, column 1: coordinate is -inf in point (2, -inf)"
+        );
+        let err = validate_points(&points[2..]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "row 0, column 0: coordinate is NaN in point (NaN, inf)"
+        );
+        let err = validate_point(&pt(&[1.5, f64::INFINITY])).unwrap_err();
+        assert_eq!(err.row, None);
+        assert_eq!(
+            err.to_string(),
+            "column 1: coordinate is inf in point (1.5, inf)"
+        );
+
+        assert_eq!(validate_points(&points[..1]), Ok(()));
+        assert_eq!(validate_points::<f64>(&[]), Ok(()));
+        let f32s: Vec<Point<f32>> = vec![vec![OrderedFloat(1.0), OrderedFloat(f32::NAN)]];
+        assert_eq!(validate_points(&f32s).unwrap_err().kind, NonFinite::NaN);
+    }
+
+    #[test]
+    fn fallible_algo_rejects_non_finite_points() {
+        use crate::algo::Algo;
+        use crate::brute_query::BruteForceQueryEngine;
+        use std::collections::HashSet;
+
+        let points = [pt(&[0.0, 0.0]), pt(&[0.5, f64::NAN])];
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        let err = Algo::try_new(&mut engine, &refs, 1.0, 2).err().unwrap();
+        assert_eq!((err.column, err.kind), (1, NonFinite::NaN));
+        assert_eq!(err.point, "(0.5, NaN)");
+
+        let finite = points[..1].iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        assert!(Algo::try_new(&mut engine, &finite, 1.0, 2).is_ok());
+    }
+
+    #[test]
+    fn schemas_name_and_find_columns() {
+        let names = ["temperature", "pressure", "flow"]
+            .map(String::from)
+            .to_vec();
+        let schema = Schema::new(names.clone()).unwrap();
+        assert_eq!(schema.len(), 3);
+        assert_eq!(schema.names(), names);
+        assert_eq!(schema.name(1), "pressure");
+        assert_eq!(schema.index_of("flow"), Some(2));
+        assert_eq!(schema.index_of("Flow"), None);
+        assert_eq!(Schema::numbered(2).names(), ["x1", "x2"]);
+        assert!(Schema::numbered(0).is_empty());
+
+        let err = Schema::new(vec!["a".into(), "".into()]).unwrap_err();
+        assert_eq!(err.to_string(), "column 1 has an empty name");
+        let err = Schema::new(vec!["a".into(), "b".into(), "a".into()]).unwrap_err();
+        assert_eq!(err.to_string(), "duplicate column name 'a'");
+    }
+
+    #[test]
+    fn datasets_select_and_validate_columns_by_name() {
+        let schema = Schema::new(vec!["temperature".into(), "pressure".into()]).unwrap();
+        let points = vec![pt(&[20.0, 1.0]), pt(&[21.5, f64::NAN])];
+        let dataset = Dataset::new(schema.clone(), points.clone()).unwrap();
+        assert_eq!(dataset.schema(), &schema);
+        assert_eq!(dataset.column("temperature").unwrap(), [20.0, 21.5]);
+        let err = dataset.column("flow").unwrap_err();
+        assert_eq!(err.to_string(), "no column named 'flow'");
+        assert_eq!(
+            dataset.validate().unwrap_err().to_string(),
+            "NaN in column 'pressure', row 1 (point (21.5, NaN))"
+        );
+
+        let err = Dataset::new(schema, vec![pt(&[1.0])]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dimension mismatch at point 0: expected 2 columns, got 1"
+        );
+    }
+
+    #[test]
+    fn datasets_convert_to_and_from_points_losslessly() {
+        let points = vec![pt(&[0.1, -0.0]), pt(&[1e300, 5e-324])];
+        let dataset = Dataset::from(points.clone());
+        assert_eq!(dataset.schema().names(), ["x1", "x2"]);
+        assert_eq!(dataset.points(), points);
+        let back: Vec<Point> = dataset.into();
+        assert_eq!(back, points);
+        assert!(back[0][1].0.is_sign_negative());
+        assert!(Dataset::from(Vec::new()).schema().is_empty());
+    }
+
+    // A point of dimension `dim` with about `nonzeros` small integer
+    // coordinates, most of them on the first 50 axes so that points overlap.
+    fn sparse_dense(rng: &mut crate::sampling::SplitMix64, dim: u64, nonzeros: u64) -> Point {
+        let mut coords = vec![0.0; dim as usize];
+        for _ in 0..nonzeros {
+            let i = if rng.below(2) == 0 {
+                rng.below(50)
+            } else {
+                rng.below(dim)
+            };
+            coords[i as usize] = rng.below(7) as f64 - 3.0;
+        }
+        pt(&coords)
+    }
+
+    #[test]
+    fn sparse_distances_equal_dense_ones() {
+        let mut rng = crate::sampling::SplitMix64::new(356);
+        let dense = (0..40)
+            .map(|_| sparse_dense(&mut rng, 10_000, 20))
+            .collect::<Vec<_>>();
+        let sparse = dense
+            .iter()
+            .map(SparsePoint::from_dense)
+            .collect::<Vec<_>>();
+        for (d, s) in dense.iter().zip(&sparse) {
+            assert_eq!(&s.to_dense(), d);
+            assert_eq!(s.dim(), 10_000);
+            assert!(s.indices().len() <= 20);
+            assert!(s.values().iter().all(|&x| x != 0.0));
+        }
+        for i in 0..dense.len() {
+            for j in 0..dense.len() {
+                assert_eq!(sparse[i].dist_sq(&sparse[j]), dist_sq(&dense[i], &dense[j]));
+                assert_eq!(sparse[i].dist(&sparse[j]), dist(&dense[i], &dense[j]));
+            }
+        }
+        // An all-zero point stores nothing.
+        let zero = SparsePoint::from_dense(&pt(&[0.0; 5]));
+        assert!(zero.indices().is_empty());
+        let other = SparsePoint::new(5, vec![1, 4], vec![3.0, -4.0]).unwrap();
+        assert_eq!(zero.dist(&other), 5.0);
+    }
+
+    #[test]
+    fn sparse_points_need_sorted_indices_in_range() {
+        let message = |indices: Vec<u32>, values: Vec<f64>| {
+            SparsePoint::new(10, indices, values)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(message(vec![1, 2], vec![1.0]), "2 indices but 1 values");
+        assert_eq!(message(vec![3, 3], vec![1.0, 2.0]), "duplicate index 3");
+        assert_eq!(
+            message(vec![4, 2], vec![1.0, 2.0]),
+            "indices must be strictly increasing; got 4 before 2"
+        );
+        assert_eq!(
+            message(vec![2, 10], vec![1.0, 2.0]),
+            "index 10 is out of range for dimension 10"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "sparse point dimension mismatch: 3 vs 4")]
+    fn sparse_distances_need_one_dimension() {
+        let a = SparsePoint::from_dense(&pt(&[1.0, 0.0, 0.0]));
+        a.dist_sq(&SparsePoint::from_dense(&pt(&[1.0, 0.0, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn hamming_distances_count_differing_bits() {
+        let bits = |s: &str| BitPoint::from_bits(&s.chars().map(|c| c == '1').collect_vec());
+        assert_eq!(bits("1011").hamming(&bits("1011")), 0);
+        assert_eq!(bits("1011").hamming(&bits("0011")), 1);
+        assert_eq!(bits("1011").hamming(&bits("0100")), 4);
+        assert_eq!(bits("").hamming(&bits("")), 0);
+        // Bits 63 and 64 lie in different words.
+        let mut a = vec![false; 130];
+        let mut b = a.clone();
+        a[63] = true;
+        b[64] = true;
+        b[129] = true;
+        let (a, b) = (BitPoint::from_bits(&a), BitPoint::from_bits(&b));
+        assert_eq!(a.words().len(), 3);
+        assert_eq!(a.hamming(&b), 3);
+        assert_eq!(b.hamming(&a), 3);
+
+        // On 0/1 coordinates the squared Euclidean distance counts the same bits.
+        let mut rng = crate::sampling::SplitMix64::new(360);
+        for _ in 0..20 {
+            let mut point = || pt(&[0; 100].map(|_| rng.below(2) as f64));
+            let (a, b) = (point(), point());
+            let hamming = BitPoint::from_dense(&a)
+                .unwrap()
+                .hamming(&BitPoint::from_dense(&b).unwrap());
+            assert_eq!(hamming as f64, dist_sq(&a, &b));
+        }
+    }
+
+    #[test]
+    fn bit_points_pack_and_unpack() {
+        let mut rng = crate::sampling::SplitMix64::new(360);
+        for len in [0, 1, 63, 64, 65, 2048] {
+            let bits = (0..len).map(|_| rng.below(2) == 1).collect_vec();
+            let point = BitPoint::from_bits(&bits);
+            assert_eq!(point.len() as usize, len);
+            assert_eq!(point.is_empty(), len == 0);
+            assert_eq!(point.words().len(), len.div_ceil(64));
+            assert_eq!(point.to_bits(), bits);
+            assert_eq!(BitPoint::from_dense(&point.to_dense()).unwrap(), point);
+        }
+        let point = BitPoint::from_dense(&pt(&[1.0, 0.0, 1.0])).unwrap();
+        assert_eq!(point.words(), [0b101]);
+        assert!(point.get(2) && !point.get(1));
+
+        let err = BitPoint::from_dense(&pt(&[1.0, 0.5])).unwrap_err();
+        assert_eq!(err.to_string(), "coordinate 1 is 0.5, expected 0 or 1");
+    }
+
+    #[test]
+    #[should_panic(expected = "bit point length mismatch: 3 vs 4")]
+    fn hamming_needs_one_length() {
+        let a = BitPoint::from_bits(&[true; 3]);
+        a.hamming(&BitPoint::from_bits(&[true; 4]));
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn class_has_a_stable_json_representation() {
+        let cases = [
+            (Class::Classified(3), json!({"type": "classified", "id": 3})),
+            (Class::Noise, json!("noise")),
+            (Class::Unclassified, json!("unclassified")),
+        ];
+        for (class, expected) in cases {
+            assert_eq!(serde_json::to_value(class).unwrap(), expected);
+            let text = serde_json::to_string(&class).unwrap();
+            assert_eq!(serde_json::from_str::<Class>(&text).unwrap(), class);
+        }
+        assert_eq!(
+            serde_json::to_string(&Class::Classified(3)).unwrap(),
+            r#"{"type":"classified","id":3}"#
+        );
+    }
+
+    #[test]
+    fn unknown_classes_are_rejected() {
+        for text in [
+            r#""border""#,
+            r#"{"type":"noise","id":1}"#,
+            r#"{"type":"classified"}"#,
+            r#"{"type":"classified","id":-1}"#,
+            "3",
+        ] {
+            assert!(serde_json::from_str::<Class>(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn clustering_result_round_trips() {
+        let points = [
+            vec![OrderedFloat(0.5), OrderedFloat(-1.0)],
+            vec![OrderedFloat(2.0), OrderedFloat(1e-9)],
+            vec![OrderedFloat(0.1), OrderedFloat(0.2)],
+        ];
+        let classes = HashMap::from([
+            (&points[0], Class::Classified(1)),
+            (&points[1], Class::Unclassified),
+        ]);
+        let result = ClusteringResult::from_classes(&points, &classes);
+        assert_eq!(
+            result.labels,
+            [Class::Classified(1), Class::Unclassified, Class::Noise]
+        );
+
+        let text = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            json!({
+                "labels": [{"type": "classified", "id": 1}, "unclassified", "noise"],
+                "points": [[0.5, -1.0], [2.0, 1e-9], [0.1, 0.2]],
+            })
+        );
+        assert_eq!(
+            serde_json::from_str::<ClusteringResult>(&text).unwrap(),
+            result
+        );
+    }
+}
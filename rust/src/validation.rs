@@ -0,0 +1,304 @@
+use crate::types::CondensedMatrix;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// What `compare` does with the points a clustering calls noise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum NoiseHandling {
+    /// All noise points form one more cluster.
+    #[default]
+    Cluster,
+    /// Noise points are left out of the comparison, on both sides.
+    Exclude,
+}
+
+/// Agreement of a clustering with ground-truth labels, as found by `compare`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scores {
+    /// Adjusted Rand index; see `adjusted_rand_index`.
+    pub ari: f64,
+    /// Normalized mutual information; see `normalized_mutual_info`.
+    pub nmi: f64,
+    /// Number of points compared.
+    pub points: usize,
+}
+
+/// Scores the cluster ids `cids` (as by `types::labels_in_order`, noise
+/// negative) against the `truth` label of every point, treating noise as
+/// `noise` says. `None` if no point is left to compare. Panics if the two
+/// slices differ in length.
+pub fn compare<L: Hash + Eq>(cids: &[isize], truth: &[L], noise: NoiseHandling) -> Option<Scores> {
+    assert_eq!(cids.len(), truth.len(), "one label per point is needed");
+    let (cids, truth): (Vec<_>, Vec<_>) = cids
+        .iter()
+        .map(|&cid| cid.max(-1))
+        .zip(truth.iter())
+        .filter(|&(cid, _)| cid >= 0 || noise == NoiseHandling::Cluster)
+        .unzip();
+    if cids.is_empty() {
+        return None;
+    }
+    Some(Scores {
+        ari: adjusted_rand_index(&cids, &truth),
+        nmi: normalized_mutual_info(&cids, &truth),
+        points: cids.len(),
+    })
+}
+
+/// Adjusted Rand index of two groupings of the same items, given as the group
+/// of every item: 1 for the same partition, about 0 for unrelated ones and
+/// below 0 for worse than chance. Two partitions that cannot differ by chance
+/// (fewer than 2 items, or both with a single group) score 1.
+pub fn adjusted_rand_index<A: Hash + Eq, B: Hash + Eq>(a: &[A], b: &[B]) -> f64 {
+    let table = Contingency::new(a, b);
+    let pairs = |n: usize| (n * n.saturating_sub(1) / 2) as f64;
+    let index = table.cells.values().map(|&n| pairs(n)).sum::<f64>();
+    let rows = table.rows.iter().map(|&n| pairs(n)).sum::<f64>();
+    let cols = table.cols.iter().map(|&n| pairs(n)).sum::<f64>();
+    let expected = rows * cols / pairs(a.len()).max(1.0);
+    let max = (rows + cols) / 2.0;
+    if max == expected {
+        return 1.0;
+    }
+    (index - expected) / (max - expected)
+}
+
+/// Mutual information of two groupings of the same items, divided by the
+/// mean of their entropies: 1 for the same partition, 0 for independent
+/// ones. Two groupings with a single group each score 1.
+pub fn normalized_mutual_info<A: Hash + Eq, B: Hash + Eq>(a: &[A], b: &[B]) -> f64 {
+    let table = Contingency::new(a, b);
+    let n = a.len() as f64;
+    let entropy = |sizes: &[usize]| {
+        sizes
+            .iter()
+            .map(|&k| k as f64 / n)
+            .map(|p| -p * p.ln())
+            .sum::<f64>()
+    };
+    let (ha, hb) = (entropy(&table.rows), entropy(&table.cols));
+    if ha == 0.0 && hb == 0.0 {
+        return 1.0;
+    }
+    let mi = table
+        .cells
+        .iter()
+        .map(|(&(i, j), &k)| {
+            let k = k as f64;
+            k / n * (n * k / (table.rows[i] as f64 * table.cols[j] as f64)).ln()
+        })
+        .sum::<f64>();
+    (mi / ((ha + hb) / 2.0)).clamp(0.0, 1.0)
+}
+
+/// Mean silhouette coefficient of the clusters `cids` (as by
+/// `types::labels_in_order`, noise negative), given the pairwise distances of
+/// the points (see `types::pairwise_distances`). A point scores
+/// `(b - a) / max(a, b)`, with `a` its mean distance to the rest of its
+/// cluster and `b` the smallest mean distance to another cluster; points alone
+/// in their cluster score 0. Noise points are left out. In [-1, 1], higher
+/// meaning tighter and better separated clusters. `None` with fewer than two
+/// clusters. Panics unless there is one cid per point.
+pub fn silhouette(distances: &CondensedMatrix, cids: &[isize]) -> Option<f64> {
+    let clusters = clusters(distances, cids);
+    if clusters.len() < 2 {
+        return None;
+    }
+    let mean_distance = |i: usize, members: &[usize], others: usize| {
+        members.iter().map(|&j| distances.get(i, j)).sum::<f64>() / others as f64
+    };
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for (c, members) in clusters.iter().enumerate() {
+        count += members.len();
+        if members.len() == 1 {
+            continue;
+        }
+        for &i in members {
+            let a = mean_distance(i, members, members.len() - 1);
+            let b = clusters
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != c)
+                .map(|(_, other)| mean_distance(i, other, other.len()))
+                .fold(f64::INFINITY, f64::min);
+            if a.max(b) > 0.0 {
+                total += (b - a) / a.max(b);
+            }
+        }
+    }
+    Some(total / count as f64)
+}
+
+/// Dunn index of the clusters `cids` (as in `silhouette`, noise left out):
+/// the smallest distance between points of different clusters divided by the
+/// largest distance within one cluster. Higher is better; infinite when every
+/// cluster is a single location. `None` with fewer than two clusters. Panics
+/// unless there is one cid per point.
+pub fn dunn_index(distances: &CondensedMatrix, cids: &[isize]) -> Option<f64> {
+    if clusters(distances, cids).len() < 2 {
+        return None;
+    }
+    let mut separation = f64::INFINITY;
+    let mut diameter: f64 = 0.0;
+    for i in (0..cids.len()).filter(|&i| cids[i] >= 0) {
+        for j in (i + 1..cids.len()).filter(|&j| cids[j] >= 0) {
+            let d = distances.get(i, j);
+            if cids[i] == cids[j] {
+                diameter = diameter.max(d);
+            } else {
+                separation = separation.min(d);
+            }
+        }
+    }
+    Some(if diameter == 0.0 {
+        f64::INFINITY
+    } else {
+        separation / diameter
+    })
+}
+
+// The points of every cluster (cid >= 0), by increasing cid.
+fn clusters(distances: &CondensedMatrix, cids: &[isize]) -> Vec<Vec<usize>> {
+    assert_eq!(
+        cids.len(),
+        distances.len(),
+        "one cid per point of the distance matrix is needed"
+    );
+    let mut clusters = BTreeMap::<isize, Vec<usize>>::new();
+    for (i, &cid) in cids.iter().enumerate().filter(|&(_, &cid)| cid >= 0) {
+        clusters.entry(cid).or_default().push(i);
+    }
+    clusters.into_values().collect()
+}
+
+// How many items fall into each pair of groups, and into each group.
+struct Contingency {
+    cells: HashMap<(usize, usize), usize>,
+    rows: Vec<usize>,
+    cols: Vec<usize>,
+}
+
+impl Contingency {
+    fn new<A: Hash + Eq, B: Hash + Eq>(a: &[A], b: &[B]) -> Self {
+        assert_eq!(a.len(), b.len(), "groupings of different lengths");
+        let mut row_of = HashMap::new();
+        let mut col_of = HashMap::new();
+        let mut table = Self {
+            cells: HashMap::new(),
+            rows: Vec::new(),
+            cols: Vec::new(),
+        };
+        for (x, y) in a.iter().zip(b.iter()) {
+            let i = *row_of.entry(x).or_insert_with(|| {
+                table.rows.push(0);
+                table.rows.len() - 1
+            });
+            let j = *col_of.entry(y).or_insert_with(|| {
+                table.cols.push(0);
+                table.cols.len() - 1
+            });
+            table.rows[i] += 1;
+            table.cols[j] += 1;
+            *table.cells.entry((i, j)).or_insert(0) += 1;
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{pairwise_distances, Euclidean, Point};
+    use ordered_float::OrderedFloat;
+
+    fn matrix(xs: &[f64]) -> CondensedMatrix {
+        let points = xs
+            .iter()
+            .map(|&x| vec![OrderedFloat(x)])
+            .collect::<Vec<Point>>();
+        pairwise_distances(&points, &Euclidean).unwrap()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn silhouette_of_two_pairs() {
+        let distances = matrix(&[0.0, 1.0, 10.0, 11.0]);
+        // a = 1 for every point, b = 10.5 for the outer and 9.5 for the inner
+        // points.
+        let expected = (9.5 / 10.5 + 8.5 / 9.5) / 2.0;
+        assert_close(silhouette(&distances, &[0, 0, 1, 1]).unwrap(), expected);
+        // Swapping the clusters of two points makes them score negatively.
+        assert!(silhouette(&distances, &[0, 1, 0, 1]).unwrap() < 0.0);
+    }
+
+    #[test]
+    fn singletons_score_zero_and_noise_is_left_out() {
+        let distances = matrix(&[0.0, 1.0, 5.0, 100.0]);
+        let expected = (0.8 + 0.75 + 0.0) / 3.0;
+        assert_close(silhouette(&distances, &[0, 0, 1, -1]).unwrap(), expected);
+        assert_eq!(
+            dunn_index(&distances, &[0, 0, 1, -1]),
+            dunn_index(&matrix(&[0.0, 1.0, 5.0]), &[0, 0, 1])
+        );
+    }
+
+    #[test]
+    fn dunn_index_of_two_pairs() {
+        let distances = matrix(&[0.0, 1.0, 10.0, 12.0]);
+        assert_eq!(dunn_index(&distances, &[0, 0, 1, 1]), Some(9.0 / 2.0));
+        assert_eq!(dunn_index(&distances, &[0, 1, 0, 1]), Some(1.0 / 11.0));
+        let points = matrix(&[3.0, 3.0, 7.0]);
+        assert_eq!(dunn_index(&points, &[0, 0, 1]), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn one_cluster_has_no_score() {
+        let distances = matrix(&[0.0, 1.0, 2.0]);
+        for cids in [[0, 0, 0], [-1, -1, -1], [2, -1, 2]] {
+            assert_eq!(silhouette(&distances, &cids), None);
+            assert_eq!(dunn_index(&distances, &cids), None);
+        }
+    }
+
+    #[test]
+    fn relabeled_clusters_score_one_and_unrelated_ones_zero() {
+        let truth = ["a", "a", "a", "b", "b", "b"];
+        let scores = compare(&[1, 1, 1, 0, 0, 0], &truth, NoiseHandling::Cluster).unwrap();
+        assert_eq!((scores.ari, scores.nmi, scores.points), (1.0, 1.0, 6));
+        // Every cluster holds one point of each label.
+        let scores = compare(&[0, 1, 2, 0, 1, 2], &truth, NoiseHandling::Cluster).unwrap();
+        assert!(scores.ari < 0.0, "{:?}", scores);
+        assert_close(scores.nmi, 0.0);
+        // So does a single cluster, which cannot tell the labels apart.
+        let scores = compare(&[0; 6], &truth, NoiseHandling::Cluster).unwrap();
+        assert_close(scores.ari, 0.0);
+        assert_close(scores.nmi, 0.0);
+    }
+
+    #[test]
+    fn noise_is_one_more_cluster_or_left_out() {
+        let truth = [1, 1, 1, 2, 2, 2];
+        let cids = [0, 0, -1, 1, 1, -3];
+        let scores = compare(&cids, &truth, NoiseHandling::Exclude).unwrap();
+        assert_eq!((scores.ari, scores.nmi, scores.points), (1.0, 1.0, 4));
+        let scores = compare(&cids, &truth, NoiseHandling::Cluster).unwrap();
+        assert_eq!(scores.points, 6);
+        assert!(scores.ari < 1.0 && scores.nmi < 1.0, "{:?}", scores);
+        // Noise with other noise ids is the same single cluster.
+        let same = compare(&[0, 0, -1, 1, 1, -1], &truth, NoiseHandling::Cluster);
+        assert_eq!(same, Some(scores));
+        assert_eq!(compare(&[-1, -1], &[1, 2], NoiseHandling::Exclude), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "one cid per point")]
+    fn cids_must_match_the_matrix() {
+        silhouette(&matrix(&[0.0, 1.0, 2.0]), &[0, 1]);
+    }
+}
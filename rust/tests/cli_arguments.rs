@@ -0,0 +1,148 @@
+//! dbscan checking MIN_POINTS and EPS, given as positionals or as --min-pts
+//! and --eps.
+
+mod common;
+
+// A blob of three points and a stray one.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n5,5\n";
+const CLUSTERED: &str = "0,0,0\n0,0,0.1\n0,0.1,0\n-1,5,5\n";
+const HINT: &str = "; the order is INPUT OUTPUT MIN_POINTS EPS, were they swapped?";
+
+// Runs dbscan on POINTS with `args` after the two paths.
+fn run(args: &[&str]) -> assert_cmd::assert::Assert {
+    common::dbscan(&[&["-", "-"], args].concat(), POINTS)
+}
+
+// The stdout and stderr of a successful run.
+fn succeeds(args: &[&str]) -> (String, String) {
+    common::output(&run(args).success())
+}
+
+// The stderr of a run rejecting its arguments.
+fn fails(args: &[&str]) -> String {
+    let assert = run(args).code(2);
+    assert!(assert.get_output().stdout.is_empty());
+    common::stderr(&assert)
+}
+
+#[test]
+fn bad_parameters_are_rejected_with_their_name() {
+    for (args, message) in [
+        (
+            &["0.3", "5"][..],
+            format!("MIN_POINTS must be at least 1, got 0.3{}", HINT),
+        ),
+        (
+            &["0", "0.3"],
+            "MIN_POINTS must be at least 1, got 0".to_string(),
+        ),
+        (
+            &["5", "0"],
+            "EPS must be a positive number, got 0".to_string(),
+        ),
+        (
+            &["5", "-1"],
+            "EPS must be a positive number, got -1".to_string(),
+        ),
+        (
+            &["5", "inf"],
+            "EPS must be a positive number, got inf".to_string(),
+        ),
+        (
+            &["5", "NaN"],
+            "EPS must be a positive number, got NaN".to_string(),
+        ),
+        (
+            &["x", "0.3"],
+            "expected a whole number of points, got 'x'".to_string(),
+        ),
+        (
+            &["--min-pts", "0", "--eps", "1"],
+            "--min-pts must be at least 1, got 0".to_string(),
+        ),
+        (
+            &["--min-pts", "0.5", "--eps", "1"],
+            "--min-pts must be at least 1, got 0.5".to_string(),
+        ),
+        (
+            &["--min-pts", "2", "--eps", "-1"],
+            "--eps must be a positive number, got -1".to_string(),
+        ),
+        (
+            &["2", "0.3", "--eps", "0.3"],
+            "cannot be used with".to_string(),
+        ),
+        (
+            &["--min-pts", "2", "0.3"],
+            "cannot be used with".to_string(),
+        ),
+    ] {
+        let stderr = fails(args);
+        assert!(stderr.contains(&message), "{:?}: {}", args, stderr);
+        // The hint is only for the positionals that are easily swapped.
+        if !message.ends_with(HINT) {
+            assert!(!stderr.contains(HINT), "{:?}: {}", args, stderr);
+        }
+    }
+}
+
+#[test]
+fn suspicious_parameters_get_a_warning() {
+    let (stdout, stderr) = succeeds(&["2.0", "0.3"]);
+    assert_eq!(stdout, CLUSTERED);
+    let warning = format!(
+        "warning: MIN_POINTS 2.0 has a decimal point, using 2{}",
+        HINT
+    );
+    assert!(stderr.contains(&warning), "{}", stderr);
+
+    for eps in ["4", "4.0", "10"] {
+        let (_, stderr) = succeeds(&["2", eps]);
+        let warning = format!(
+            "warning: EPS {} is a whole number no smaller than the number of points (4){}",
+            eps.trim_end_matches(".0"),
+            HINT
+        );
+        assert!(stderr.contains(&warning), "{}: {}", eps, stderr);
+    }
+    // A whole EPS below the number of points is not suspicious.
+    let (_, stderr) = succeeds(&["2", "3"]);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+
+    let (_, stderr) = succeeds(&["--min-pts", "2.5", "--eps", "4"]);
+    assert!(
+        stderr.contains("warning: --min-pts 2.5 has a decimal point, using 2\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "warning: --eps 4 is a whole number no smaller than the number of points (4)\n"
+        ),
+        "{}",
+        stderr
+    );
+    let (_, stderr) = succeeds(&["2.0", "4", "-q"]);
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn named_parameters_cluster_like_positionals() {
+    for args in [
+        &["2", "0.3"][..],
+        &["--min-pts", "2", "--eps", "0.3"],
+        &["--eps", "0.3", "2"],
+        &["2", "--eps", "0.3"],
+    ] {
+        let (stdout, stderr) = succeeds(args);
+        assert_eq!(stdout, CLUSTERED, "{:?}", args);
+        assert!(!stderr.contains("warning"), "{:?}: {}", args, stderr);
+    }
+    // --min-pts needs EPS named too.
+    let stderr = fails(&["--min-pts", "2"]);
+    assert!(
+        stderr.contains("--min-pts needs EPS as a flag too: --eps, --auto-eps or --eps-list"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,48 @@
+//! dbscan --cache-bin writing, reusing and refreshing the binary copy of its
+//! CSV input.
+
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+mod common;
+
+// One cluster at eps 0.5 and min_points 2, and a noise point.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n5,5\n";
+
+// Runs dbscan on `input` with --cache-bin and returns what it printed to
+// stderr.
+fn run(input: &str, output: &str) -> String {
+    let args = [input, output, "2", "0.5", "--cache-bin", "-v"];
+    common::stderr(&common::dbscan(&args, "").success())
+}
+
+// Sets the modification time of `path` to `ago` before now.
+fn touch(path: &str, ago: Duration) {
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - ago).unwrap();
+}
+
+#[test]
+fn cached_points_are_reused_until_the_csv_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    let (input, output, cache) = (file("in.csv"), file("out.csv"), file("in.csv.bin"));
+    fs::write(&input, POINTS).unwrap();
+    touch(&input, Duration::from_secs(60));
+
+    let stderr = run(&input, &output);
+    assert!(stderr.contains("wrote cached points to"), "{}", stderr);
+    let clustered = fs::read_to_string(&output).unwrap();
+    assert_eq!(clustered, "0,0,0\n0,0,0.1\n0,0.1,0\n-1,5,5\n");
+
+    let stderr = run(&input, &output);
+    assert!(stderr.contains("reading cached points from"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&output).unwrap(), clustered);
+
+    // A CSV newer than its cache is read again, and the cache replaced.
+    fs::write(&input, "1,1\n1,1.1\n").unwrap();
+    touch(&cache, Duration::from_secs(120));
+    let stderr = run(&input, &output);
+    assert!(stderr.contains("wrote cached points to"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&output).unwrap(), "0,1,1\n0,1,1.1\n");
+}
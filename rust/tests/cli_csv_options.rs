@@ -0,0 +1,91 @@
+//! CSV reader options of dbscan given together.
+
+mod common;
+
+// Two clusters at eps 0.3 and min_points 2, and a noise point, with a class
+// column; line 5 has text for a coordinate, line 7 no class.
+const POINTS: &str = "x,y,class\n0,0,a\n0,0.1,a\n0.1,0,a\nbad,0,a\n5,5,b\n5,5.1\n5.1,5,b\n9,9,c\n";
+
+fn run(extra: &[&str]) -> (String, String) {
+    let args = [&["-", "-", "2", "0.3", "--header"], extra].concat();
+    common::output(&common::dbscan(&args, POINTS).success())
+}
+
+#[test]
+fn skip_bad_lines_with_a_label_column() {
+    let (stdout, stderr) = run(&["--label-column", "class", "--skip-bad-lines"]);
+    assert_eq!(
+        stdout,
+        "cid,class,x,y\n0,a,0,0\n0,a,0,0.1\n0,a,0.1,0\n1,b,5,5\n1,b,5.1,5\n-1,c,9,9\n"
+    );
+    assert!(stderr.contains("skipped 2 malformed lines"), "{}", stderr);
+    assert!(stderr.contains("ground truth: ARI 1.000"), "{}", stderr);
+}
+
+#[test]
+fn sample_with_a_label_column() {
+    let args = [
+        "--label-column",
+        "2",
+        "--skip-bad-lines",
+        "--sample",
+        "4",
+        "--seed",
+        "3",
+        "-q",
+    ];
+    let (stdout, _) = run(&args);
+    assert_eq!(
+        stdout,
+        "cid,class,x,y\n0,a,0,0.1\n0,a,0.1,0\n1,b,5,5\n1,b,5.1,5\n"
+    );
+    assert_eq!(run(&args).0, stdout);
+}
+
+#[test]
+fn passthrough_with_a_label_column() {
+    let (stdout, _) = run(&[
+        "--label-column",
+        "class",
+        "--skip-bad-lines",
+        "--passthrough",
+        "-q",
+    ]);
+    assert_eq!(
+        stdout,
+        "cid,x,y,class\n0,0,0,a\n0,0,0.1,a\n0,0.1,0,a\n1,5,5,b\n1,5.1,5,b\n-1,9,9,c\n"
+    );
+}
+
+#[test]
+fn weights_column_with_skip_bad_lines() {
+    // Every class is text, so no row has a valid weight.
+    let args = [
+        "-",
+        "-",
+        "2",
+        "0.3",
+        "--header",
+        "--weights-column",
+        "class",
+        "--skip-bad-lines",
+    ];
+    let output = common::dbscan(&args, POINTS).failure().code(3);
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("no points found in input"), "{}", stderr);
+
+    // The weights of the other rows reach MIN_POINTS together.
+    let args = [
+        "-",
+        "-",
+        "3",
+        "0.3",
+        "--weights-column",
+        "1",
+        "--skip-bad-lines",
+        "-q",
+    ];
+    let output = common::dbscan(&args, "0,1,0\n0,x,0.1\n0,2,0.1\n5,1,5\n").success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, "0,0,0\n0,0,0.1\n-1,5,5\n");
+}
@@ -0,0 +1,107 @@
+//! dbscan clustering the same points with every --engine.
+
+mod common;
+
+// Runs dbscan on `input` with `extra` flags and returns its stdout and
+// stderr.
+fn run(input: &str, extra: &[&str]) -> (String, String) {
+    let args = [&["-", "-", "4", "1.5"], extra].concat();
+    common::output(&common::dbscan(&args, input).success())
+}
+
+// Three blobs, the corners of small cubes in `dim` dimensions, and three
+// stray points.
+fn points(dim: usize) -> String {
+    let mut rows = Vec::new();
+    for center in [0.0, 10.0, 20.0] {
+        for j in 0..8 {
+            let row = (0..dim)
+                .map(|d| center + ((j >> (d % 3)) & 1) as f64 * 0.25)
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            rows.push(row.join(","));
+        }
+    }
+    for stray in [5.0, 15.0, 30.0] {
+        rows.push(vec![stray.to_string(); dim].join(","));
+    }
+    rows.join("\n") + "\n"
+}
+
+#[test]
+fn engines_write_the_same_clusters() {
+    let input = points(3);
+    let (fake, _) = run(&input, &["--engine", "fake"]);
+    assert_eq!(fake.lines().count(), 27);
+    assert!(fake.lines().any(|row| row.starts_with("2,")), "{}", fake);
+    assert!(fake.ends_with("-1,30,30,30\n"), "{}", fake);
+    for engine in ["rtree", "brute", "auto"] {
+        let (stdout, stderr) = run(&input, &["--engine", engine, "-v"]);
+        assert_eq!(stdout, fake, "{}", engine);
+        if engine != "auto" {
+            assert!(
+                stderr.contains(&format!("engine: {}", engine)),
+                "{}: {}",
+                engine,
+                stderr
+            );
+        }
+    }
+}
+
+#[test]
+fn rtree_falls_back_to_brute_beyond_16_dimensions() {
+    let input = points(17);
+    let (rtree, stderr) = run(&input, &["--engine", "rtree", "-v"]);
+    let warning = "warning: the rtree engine supports up to 16 dimensions, not 17; using brute";
+    assert!(stderr.contains(warning), "{}", stderr);
+    let (brute, _) = run(&input, &["--engine", "brute"]);
+    assert_eq!(rtree, brute);
+    assert_eq!(
+        brute.lines().filter(|row| row.starts_with("-1,")).count(),
+        3
+    );
+
+    // Within the limit, rtree is used as asked.
+    let (_, stderr) = run(&points(16), &["--engine", "rtree", "-v"]);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+}
+
+#[test]
+fn large_inputs_default_to_the_rtree() {
+    // 20,000 points on a jittered grid: far too many for the quadratic fake
+    // engine to finish quickly.
+    let mut rng = 1u64;
+    let mut jitter = || {
+        rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (rng >> 40) as f64 / (1u64 << 24) as f64 * 0.1
+    };
+    let input = (0..20_000)
+        .map(|i| {
+            format!(
+                "{},{}\n",
+                (i % 200) as f64 + jitter(),
+                (i / 200) as f64 + jitter()
+            )
+        })
+        .collect::<String>();
+    let start = std::time::Instant::now();
+    let (stdout, stderr) = run(&input, &["-v"]);
+    assert!(start.elapsed().as_secs() < 60, "{:?}", start.elapsed());
+    assert!(
+        stderr.contains("engine: rtree (auto, for 20000 points in 2 dimensions)"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stdout.lines().count(), 20_000);
+
+    // A few hundred of the rows are clustered alike by the fake engine.
+    let subset = input
+        .lines()
+        .take(600)
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    let (auto, _) = run(&subset, &[]);
+    let (fake, _) = run(&subset, &["--engine", "fake"]);
+    assert_eq!(auto, fake);
+}
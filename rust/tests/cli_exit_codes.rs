@@ -0,0 +1,93 @@
+//! The exit status of dbscan for each kind of failure; see the README.
+
+mod common;
+
+fn run(args: &[&str], stdin: &str) -> (i32, String) {
+    let assert = common::dbscan(&[args, &["-q"]].concat(), stdin);
+    (
+        assert.get_output().status.code().unwrap(),
+        common::stderr(&assert),
+    )
+}
+
+fn assert_exit(args: &[&str], stdin: &str, code: i32, message: &str) {
+    let (status, stderr) = run(args, stdin);
+    assert_eq!(status, code, "{}", stderr);
+    assert!(stderr.contains(message), "{}", stderr);
+}
+
+#[test]
+fn success_is_zero() {
+    assert_exit(&["-", "-", "2", "0.5"], "0,0\n0,0.1\n", 0, "");
+}
+
+#[test]
+fn bad_parameters_are_two() {
+    assert_exit(
+        &["-", "-", "2", "0"],
+        "0,0\n1,1\n",
+        2,
+        "EPS must be a positive number, got 0",
+    );
+}
+
+#[test]
+fn missing_and_malformed_input_are_three() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.csv");
+    assert_exit(
+        &[missing.to_str().unwrap(), "-", "2", "0.5"],
+        "",
+        3,
+        "not found or unreadable",
+    );
+    assert_exit(
+        &["-", "-", "2", "0.5"],
+        "0,0\nx,1\n",
+        3,
+        "parse error at line 2",
+    );
+}
+
+#[test]
+fn clustering_errors_are_four() {
+    assert_exit(
+        &["-", "-", "2", "--auto-eps"],
+        "0,0\n",
+        4,
+        "--auto-eps needs at least 2 distinct points",
+    );
+}
+
+// Clustering a later eps of a sweep fails after the first one was written.
+#[cfg(debug_assertions)]
+#[test]
+fn clustering_a_later_eps_of_a_sweep_is_four() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("points.csv");
+    std::fs::write(&input, "0,0\n0,0.1\n5,5\n").unwrap();
+    let output = dir.path().join("out.csv");
+    let assert = common::command()
+        .arg(&input)
+        .arg(&output)
+        .args(["2", "--eps-list", "0.5,1", "-q"])
+        .env("DBSCAN_TEST_PANIC_AT_EPS", "1")
+        .assert()
+        .code(4);
+    let stderr = common::stderr(&assert);
+    assert!(stderr.contains("test panic at eps 1"), "{}", stderr);
+    assert!(dir.path().join("out.eps0.5.csv").exists());
+    assert!(!dir.path().join("out.eps1.csv").exists());
+}
+
+#[test]
+fn all_noise_with_fail_if_no_clusters_is_five() {
+    let (status, stderr) = run(&["-", "-", "2", "0.5"], "0,0\n5,5\n");
+    assert_eq!(status, 0, "{}", stderr);
+    assert_exit(
+        &["-", "-", "2", "0.5", "--fail-if-no-clusters"],
+        "0,0\n5,5\n",
+        5,
+        "no clusters found; every point is noise",
+    );
+}
@@ -0,0 +1,110 @@
+//! dbscan scoring its clusters against a --ground-truth labels file.
+
+use std::path::Path;
+
+mod common;
+
+// Three blobs of 30 points, 20 apart, each in a grid 0.1 apart, and the blob
+// of every point as its label. The labels file has a BOM and CRLF endings.
+fn write_blobs(dir: &Path) {
+    let mut points = String::new();
+    let mut labels = String::from("\u{feff}");
+    for (blob, name) in ["north", "east", "west"].into_iter().enumerate() {
+        for i in 0..30 {
+            let (x, y) = (
+                blob as f64 * 20.0 + (i % 6) as f64 * 0.1,
+                (i / 6) as f64 * 0.1,
+            );
+            points.push_str(&format!("{},{}\n", x, y));
+            labels.push_str(&format!("{}\r\n", name));
+        }
+    }
+    // A stray point, labeled like the blob nearest to it.
+    points.push_str("5,5\n");
+    labels.push_str("north\r\n");
+    std::fs::write(dir.join("points.csv"), points).unwrap();
+    std::fs::write(dir.join("labels.txt"), labels).unwrap();
+}
+
+fn run(dir: &Path, args: &[&str]) -> assert_cmd::assert::Assert {
+    common::command()
+        .arg(dir.join("points.csv"))
+        .arg(dir.join("out.csv"))
+        .arg("--ground-truth")
+        .arg(dir.join("labels.txt"))
+        .args(args)
+        .assert()
+}
+
+// ARI and NMI as reported on stderr.
+fn scores(dir: &Path, args: &[&str]) -> (f64, f64, usize) {
+    let assert = run(dir, args).success();
+    let stderr = common::stderr(&assert);
+    let line = stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("ground truth: ARI "))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    let words = line.split([' ', ',']).collect::<Vec<_>>();
+    // "1.000, NMI 1.000 over 91 points"
+    (
+        words[0].parse().unwrap(),
+        words[3].parse().unwrap(),
+        words[5].parse().unwrap(),
+    )
+}
+
+#[test]
+fn a_good_eps_scores_one_and_a_terrible_eps_zero() {
+    let dir = tempfile::tempdir().unwrap();
+    write_blobs(dir.path());
+    // The stray point is noise, its own cluster by default.
+    let (ari, nmi, points) = scores(dir.path(), &["4", "0.15"]);
+    assert!(ari > 0.95 && nmi > 0.9, "{} {}", ari, nmi);
+    assert_eq!(points, 91);
+    // Left out, the blobs match their labels exactly.
+    let (ari, nmi, points) = scores(dir.path(), &["4", "0.15", "--truth-noise", "exclude"]);
+    assert_eq!((ari, nmi, points), (1.0, 1.0, 90));
+
+    // An eps wider than the data puts everything in one cluster.
+    let (ari, nmi, _) = scores(dir.path(), &["4", "100"]);
+    assert_eq!((ari, nmi), (0.0, 0.0));
+    // An eps below the grid makes everything noise.
+    let (ari, _, _) = scores(dir.path(), &["4", "0.05"]);
+    assert_eq!(ari, 0.0);
+    let assert = run(dir.path(), &["4", "0.05", "--truth-noise", "exclude"]).success();
+    let stderr = common::stderr(&assert);
+    assert!(!stderr.contains("ground truth: ARI"), "{}", stderr);
+}
+
+#[test]
+fn labels_must_match_the_points() {
+    let dir = tempfile::tempdir().unwrap();
+    write_blobs(dir.path());
+    std::fs::write(dir.path().join("labels.txt"), "a\nb\n").unwrap();
+    let assert = run(dir.path(), &["4", "0.15"]).failure();
+    let stderr = common::stderr(&assert);
+    assert!(
+        stderr.contains("2 labels in ") && stderr.contains("for 91 points"),
+        "{}",
+        stderr
+    );
+    let assert = run(dir.path(), &["4", "0.15", "--sample", "10"]).code(2);
+    let stderr = common::stderr(&assert);
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn summaries_have_the_scores() {
+    let dir = tempfile::tempdir().unwrap();
+    write_blobs(dir.path());
+    let summary = dir.path().join("run.json");
+    let args = ["4", "0.15", "--truth-noise", "exclude", "-q", "--summary"];
+    let args = [&args[..], &[summary.to_str().unwrap()]].concat();
+    run(dir.path(), &args).success();
+    let text = std::fs::read_to_string(&summary).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(json["ground_truth"]["ari"], 1.0);
+    assert_eq!(json["ground_truth"]["nmi"], 1.0);
+    assert_eq!(json["ground_truth"]["points"], 90);
+}
@@ -0,0 +1,54 @@
+//! dbscan reading shards of one data set with --inputs.
+
+mod common;
+
+// Runs dbscan in `dir` on a.csv and the --inputs `more`, writing to stdout.
+fn run(dir: &std::path::Path, more: &[&str], extra: &[&str]) -> assert_cmd::assert::Assert {
+    common::command()
+        .current_dir(dir)
+        .args(["a.csv", "-", "2", "0.3", "--inputs"])
+        .args(more)
+        .args(extra)
+        .assert()
+}
+
+#[test]
+fn shards_are_clustered_as_one_file() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, text) in [
+        ("a.csv", "0,0\n0,0.1\n"),
+        ("empty.csv", ""),
+        ("c.csv", "0.1,0\n5,5\n"),
+        ("wide.csv", "1,2,3\n"),
+    ] {
+        std::fs::write(dir.path().join(name), text).unwrap();
+    }
+
+    // The cluster spans both shards; the empty one in between adds nothing.
+    let output = run(dir.path(), &["empty.csv", "c.csv"], &["-v"]).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, "0,0,0\n0,0,0.1\n0,0.1,0\n-1,5,5\n");
+    let stderr = common::stderr(&output);
+    for line in [
+        "read 2 points from a.csv",
+        "read 0 points from empty.csv",
+        "read 2 points from c.csv",
+    ] {
+        assert!(stderr.contains(line), "{}", stderr);
+    }
+
+    let output = run(dir.path(), &["empty.csv,c.csv"], &["--source-column"]).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(
+        stdout,
+        "0,a.csv,0,0\n0,a.csv,0,0.1\n0,c.csv,0.1,0\n-1,c.csv,5,5\n"
+    );
+
+    let output = run(dir.path(), &["c.csv", "wide.csv"], &[]).code(3);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("dimension mismatch: 'wide.csv' has 3 coordinates per row, 'a.csv' has 2"),
+        "{}",
+        stderr
+    );
+}
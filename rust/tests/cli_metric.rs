@@ -0,0 +1,71 @@
+//! dbscan measuring eps in the distance selected with --metric.
+
+mod common;
+
+// A diagonal chain with steps of 0.8 in each coordinate, about 1.13 apart in
+// euclidean and 1.6 in manhattan distance, and a far point.
+const CHAIN: &str = "0,0\n0.8,0.8\n1.6,1.6\n2.4,2.4\n9,0\n";
+
+fn run(args: &[&str], stdin: &str) -> assert_cmd::assert::Assert {
+    common::dbscan(&[&["-", "-", "2", "1.2"], args].concat(), stdin)
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = run(args, CHAIN).success();
+    common::stdout(&output)
+}
+
+fn stderr(args: &[&str], stdin: &str) -> String {
+    let output = run(args, stdin).failure();
+    common::stderr(&output)
+}
+
+#[test]
+fn the_metric_decides_what_is_within_eps() {
+    let chained = "0,0,0\n0,0.8,0.8\n0,1.6,1.6\n0,2.4,2.4\n-1,9,0\n";
+    assert_eq!(stdout(&[]), chained);
+    assert_eq!(stdout(&["--metric", "euclidean"]), chained);
+    assert_eq!(stdout(&["--metric", "chebyshev"]), chained);
+    let p3 = ["--metric", "minkowski", "--minkowski-p", "3"];
+    assert_eq!(stdout(&p3), chained);
+    let manhattan = stdout(&["--metric", "manhattan"]);
+    assert_eq!(
+        manhattan,
+        "-1,0,0\n-1,0.8,0.8\n-1,1.6,1.6\n-1,2.4,2.4\n-1,9,0\n"
+    );
+    // The rtree measures in the metric too.
+    let rtree = stdout(&["--metric", "manhattan", "--engine", "rtree"]);
+    assert_eq!(rtree, manhattan);
+}
+
+#[test]
+fn metrics_reject_what_they_cannot_measure() {
+    let err = stderr(&["--metric", "cosine", "--engine", "rtree"], CHAIN);
+    assert!(
+        err.contains(
+            "--engine rtree only supports the euclidean, manhattan, chebyshev and minkowski metrics"
+        ),
+        "{}",
+        err
+    );
+    let err = stderr(&["--metric", "haversine"], "1,2,3\n");
+    assert!(
+        err.contains("point 0: haversine needs (latitude, longitude) points, got dimension 3"),
+        "{}",
+        err
+    );
+    let err = stderr(&["--metric", "haversine"], "100,2\n");
+    assert!(
+        err.contains("invalid (latitude, longitude) (100, 2)"),
+        "{}",
+        err
+    );
+    let err = stderr(&["--metric", "minkowski"], CHAIN);
+    assert!(err.contains("--minkowski-p <P>"), "{}", err);
+    let err = stderr(&["--minkowski-p", "3"], CHAIN);
+    assert!(
+        err.contains("--minkowski-p needs --metric minkowski"),
+        "{}",
+        err
+    );
+}
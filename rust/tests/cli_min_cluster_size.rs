@@ -0,0 +1,134 @@
+//! --min-cluster-size on its own, per eps of --eps-list and in --summary.
+
+mod common;
+
+// A blob of six points and three clumps of two, far from everything else:
+// four clusters at eps 0.3 or 0.5 and min_points 2.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n0.1,0.1\n0.2,0\n0,0.2\n\
+                      5,5\n5,5.1\n10,0\n10,0.1\n0,10\n0.1,10\n";
+
+// Only the blob is left with --min-cluster-size 5.
+const BLOB_ONLY: &str = "0,0,0\n0,0,0.1\n0,0.1,0\n0,0.1,0.1\n0,0.2,0\n0,0,0.2\n\
+                         -1,5,5\n-1,5,5.1\n-1,10,0\n-1,10,0.1\n-1,0,10\n-1,0.1,10\n";
+
+#[test]
+fn small_clusters_become_noise() {
+    let args = ["-", "-", "2", "0.3", "--min-cluster-size", "5"];
+    let output = common::dbscan(&args, POINTS).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, BLOB_ONLY);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("dropped 3 clusters of fewer than 5 points"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn every_eps_of_a_sweep_is_filtered() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("points.csv");
+    std::fs::write(&input, POINTS).unwrap();
+    let output = dir.path().join("out.csv");
+    let sweep = dir.path().join("sweep.csv");
+    common::command()
+        .arg(&input)
+        .arg(&output)
+        .args([
+            "2",
+            "--eps-list",
+            "0.3,0.5",
+            "--min-cluster-size",
+            "5",
+            "-q",
+        ])
+        .arg("--sweep-summary")
+        .arg(&sweep)
+        .assert()
+        .success();
+    for eps in ["0.3", "0.5"] {
+        let written = dir.path().join(format!("out.eps{}.csv", eps));
+        assert_eq!(
+            std::fs::read_to_string(written).unwrap(),
+            BLOB_ONLY,
+            "{}",
+            eps
+        );
+    }
+    let table = std::fs::read_to_string(sweep).unwrap();
+    let mut lines = table.lines();
+    assert_eq!(lines.next(), Some("eps,clusters,noise,noise_pct,dropped"));
+    for (line, eps) in lines.zip(["0.3,", "0.5,"]) {
+        assert!(line.starts_with(eps), "{}", line);
+        assert!(line.ends_with(",3"), "{}", line);
+        assert!(line.contains(",1,6,"), "{}", line);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn summaries_count_the_dropped_clusters() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("points.csv");
+    std::fs::write(&input, POINTS).unwrap();
+    let summary = |name: &str| -> serde_json::Value {
+        let text = std::fs::read_to_string(dir.path().join(name)).unwrap();
+        serde_json::from_str(&text).unwrap()
+    };
+    let check = |json: serde_json::Value, eps: f64| {
+        assert_eq!(json["eps"], eps);
+        assert_eq!(json["n_clusters"], 1);
+        assert_eq!(json["n_noise"], 6);
+        assert_eq!(json["min_cluster_size"], 5);
+        assert_eq!(json["dropped_clusters"], 3);
+    };
+
+    common::command()
+        .arg(&input)
+        .arg(dir.path().join("out.csv"))
+        .args(["2", "0.3", "--min-cluster-size", "5", "-q"])
+        .arg("--summary")
+        .arg(dir.path().join("run.json"))
+        .assert()
+        .success();
+    check(summary("run.json"), 0.3);
+
+    common::command()
+        .arg(&input)
+        .arg(dir.path().join("out.csv"))
+        .args([
+            "2",
+            "--eps-list",
+            "0.3,0.5",
+            "--min-cluster-size",
+            "5",
+            "-q",
+        ])
+        .arg("--summary")
+        .arg(dir.path().join("run.json"))
+        .assert()
+        .success();
+    check(summary("run.eps0.3.json"), 0.3);
+    check(summary("run.eps0.5.json"), 0.5);
+}
+
+#[test]
+fn sweep_summaries_need_a_file() {
+    let args = [
+        "-",
+        "out.csv",
+        "2",
+        "--eps-list",
+        "0.3,0.5",
+        "--summary",
+        "-",
+    ];
+    let output = common::dbscan(&args, POINTS).code(2);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("--summary can only write to stdout"),
+        "{}",
+        stderr
+    );
+}
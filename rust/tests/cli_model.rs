@@ -0,0 +1,70 @@
+//! Saves a model with `dbscan --save-model` and labels new points with
+//! `dbscan --predict`.
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::dbscan;
+
+// Two blobs of five points and one outlier.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n0.1,0.1\n0.05,0.05\n\
+                      3,3\n3,3.1\n3.1,3\n3.1,3.1\n3.05,3.05\n\
+                      9,-9\n";
+
+fn cids(output: &assert_cmd::assert::Assert) -> Vec<String> {
+    let stdout = common::stdout(output);
+    stdout
+        .lines()
+        .map(|row| row.split(',').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn predict_labels_points_with_the_saved_clusters() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model.bin");
+    let model = model.to_str().unwrap();
+    let fitted = cids(&dbscan(&["-", "-", "5", "0.3", "--save-model", model], POINTS).success());
+
+    // The training points get their clusters back.
+    let predicted = cids(&dbscan(&["-", "-", "--predict", model], POINTS).success());
+    assert_eq!(predicted, fitted);
+
+    // New points inside either blob get its cluster, the others are noise.
+    let new = "0.2,0.2\n2.9,3.2\n1.5,1.5\n-5,5\n";
+    let predicted = cids(&dbscan(&["-", "-", "--predict", model], new).success());
+    assert_eq!(predicted, [&fitted[0], &fitted[5], "-1", "-1"]);
+}
+
+#[test]
+fn predict_rejects_points_of_another_dimension() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model.bin");
+    let model = model.to_str().unwrap();
+    dbscan(&["-", "-", "5", "0.3", "--save-model", model], POINTS).success();
+
+    let output = dbscan(&["-", "-", "--predict", model], "0,0,0\n1,1,1\n")
+        .failure()
+        .code(4);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("point 0 has 3 coordinates, but the model was fitted on 2"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn predict_takes_its_parameters_from_the_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model.bin");
+    let model = model.to_str().unwrap();
+    dbscan(&["-", "-", "5", "0.3", "--save-model", model], POINTS).success();
+
+    dbscan(&["-", "-", "5", "--predict", model], POINTS)
+        .failure()
+        .code(2);
+    dbscan(&["-", "-", "--predict", model, "--eps", "0.3"], POINTS)
+        .failure()
+        .code(2);
+}
@@ -0,0 +1,65 @@
+//! dbscan --normalize scaling columns of very different ranges.
+
+mod common;
+
+// Two rows of 20 points: the first column runs from 1000 to 4800 in steps of
+// 200, the second is about 0 in one row and about 0.9 in the other, and the
+// third is constant.
+fn points() -> String {
+    (0..20)
+        .flat_map(|i| {
+            let x = 1000 + i * 200;
+            [
+                format!("{},0.0{},7\n", x, i % 5),
+                format!("{},0.9{},7\n", x, i % 5),
+            ]
+        })
+        .collect()
+}
+
+fn run(args: &[&str]) -> (String, String) {
+    let args = [&["-", "-", "3"], args].concat();
+    common::output(&common::dbscan(&args, &points()).success())
+}
+
+// The distinct cluster ids of the rows of `stdout`, by row of points.
+fn ids(stdout: &str) -> [Vec<&str>; 2] {
+    let cids = stdout
+        .lines()
+        .map(|row| row.split(',').next().unwrap())
+        .collect::<Vec<_>>();
+    let mut rows = [Vec::new(), Vec::new()];
+    for (i, cid) in cids.into_iter().enumerate() {
+        if !rows[i % 2].contains(&cid) {
+            rows[i % 2].push(cid);
+        }
+    }
+    rows
+}
+
+#[test]
+fn only_scaled_columns_separate_the_rows() {
+    // Unscaled, the first column decides: a small eps leaves only noise, a
+    // large one merges both rows.
+    let (stdout, _) = run(&["0.2"]);
+    assert_eq!(ids(&stdout), [vec!["-1"], vec!["-1"]]);
+    let (stdout, _) = run(&["250.5"]);
+    assert_eq!(ids(&stdout), [vec!["0"], vec!["0"]]);
+
+    for (scaling, eps) in [("minmax", "0.2"), ("zscore", "0.5")] {
+        let (stdout, stderr) = run(&[eps, "--normalize", scaling]);
+        assert_eq!(ids(&stdout), [vec!["0"], vec!["1"]], "{}", scaling);
+        // The output keeps the original coordinates.
+        let coords = stdout
+            .lines()
+            .map(|row| row.split_once(',').unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(coords[..3], ["1000,0,7", "1000,0.9,7", "1200,0.01,7"]);
+        assert!(!stdout.contains("NaN"), "{}", stdout);
+        let note = format!(
+            "eps {} is a distance between {}-scaled points",
+            eps, scaling
+        );
+        assert!(stderr.contains(&note), "{}", stderr);
+    }
+}
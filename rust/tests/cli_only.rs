@@ -0,0 +1,138 @@
+//! dbscan writing only the noise or only some clusters with --only-noise and
+//! --only-cluster.
+
+use std::path::Path;
+
+mod common;
+
+// Clusters of 2, 5, 3 and 4 points along lines 10 apart, and three noise
+// points.
+fn points() -> String {
+    let mut rows = Vec::new();
+    for (i, size) in [2, 5, 3, 4].into_iter().enumerate() {
+        for j in 0..size {
+            rows.push(format!("{},{}\n", i * 10, j as f64 * 0.2));
+        }
+    }
+    rows.extend(["5,5\n".into(), "15,-5\n".into(), "25,5\n".into()]);
+    rows.concat()
+}
+
+fn run(dir: &Path, args: &[&str]) -> (String, String) {
+    let input = dir.join("points.csv");
+    std::fs::write(&input, points()).unwrap();
+    let assert = common::command()
+        .arg(input)
+        .args(["-", "2", "0.3"])
+        .args(args)
+        .assert()
+        .success();
+    common::output(&assert)
+}
+
+// How many written rows have each of `cids`.
+fn count(stdout: &str, cids: &[&str]) -> Vec<usize> {
+    cids.iter()
+        .map(|cid| {
+            let prefix = format!("{},", cid);
+            stdout
+                .lines()
+                .filter(|row| row.starts_with(&prefix))
+                .count()
+        })
+        .collect()
+}
+
+#[test]
+fn filtered_rows_match_the_cluster_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let (all, _) = run(dir.path(), &[]);
+    assert_eq!(count(&all, &["0", "1", "2", "3", "-1"]), [2, 5, 3, 4, 3]);
+
+    let (noise, stderr) = run(dir.path(), &["--only-noise"]);
+    assert_eq!(noise, "-1,5,5\n-1,15,-5\n-1,25,5\n");
+    // The report still covers every point.
+    assert!(
+        stderr.contains("17 points: 4 clusters, 3 noise points"),
+        "{}",
+        stderr
+    );
+
+    let (some, stderr) = run(dir.path(), &["--only-cluster", "3,1"]);
+    assert_eq!(some.lines().count(), 9);
+    assert_eq!(count(&some, &["1", "3"]), [5, 4]);
+    // In input order, as without the filter.
+    let kept = all
+        .lines()
+        .filter(|row| row.starts_with("1,") || row.starts_with("3,"))
+        .map(|row| format!("{}\n", row))
+        .collect::<String>();
+    assert_eq!(some, kept);
+    assert!(stderr.contains("17 points: 4 clusters"), "{}", stderr);
+
+    let (none, stderr) = run(dir.path(), &["--only-cluster", "7"]);
+    assert_eq!(none, "");
+    assert!(
+        stderr.contains("warning: --only-cluster 7 names no cluster; there are 4"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn split_output_keeps_only_the_selected_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let split = dir.path().join("split");
+    let split = split.to_str().unwrap();
+    run(
+        dir.path(),
+        &["--only-cluster", "0,2", "--split-output", split, "-q"],
+    );
+    let mut files = std::fs::read_dir(split)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    // The noise file is always written, here empty.
+    assert_eq!(files, ["cluster_000.csv", "cluster_002.csv", "noise.csv"]);
+    let lines = |name: &str| {
+        let text = std::fs::read_to_string(Path::new(split).join(name)).unwrap();
+        text.lines().count()
+    };
+    let counts = ["cluster_000.csv", "cluster_002.csv", "noise.csv"].map(lines);
+    assert_eq!(counts, [2, 3, 0]);
+
+    let split = dir.path().join("noise");
+    let split = split.to_str().unwrap();
+    run(dir.path(), &["--only-noise", "--split-output", split, "-q"]);
+    let noise = std::fs::read_to_string(Path::new(split).join("noise.csv")).unwrap();
+    assert_eq!(noise, "5,5\n15,-5\n25,5\n");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn summaries_count_every_point() {
+    let dir = tempfile::tempdir().unwrap();
+    let summary = dir.path().join("run.json");
+    let (some, _) = run(
+        dir.path(),
+        &[
+            "--only-cluster",
+            "1",
+            "--summary",
+            summary.to_str().unwrap(),
+        ],
+    );
+    let text = std::fs::read_to_string(&summary).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(json["n_points"], 17);
+    assert_eq!(json["n_noise"], 3);
+    let sizes = json["clusters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["size"].as_u64().unwrap() as usize)
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [2, 5, 3, 4]);
+    assert_eq!(some.lines().count(), sizes[1]);
+}
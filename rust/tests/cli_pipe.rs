@@ -0,0 +1,608 @@
+//! Drives the binaries with `-` paths, piping data through stdin and stdout.
+
+use assert_cmd::Command;
+
+mod common;
+
+use common::dbscan;
+
+// Two blobs of five points and one outlier.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n0.1,0.1\n0.05,0.05\n\
+                      3,3\n3,3.1\n3.1,3\n3.1,3.1\n3.05,3.05\n\
+                      9,-9\n";
+
+#[test]
+fn dbscan_pipes_points_to_clustered_rows() {
+    let output = dbscan(&["-", "-", "5", "0.3"], POINTS).success();
+    let stdout = common::stdout(&output);
+    let rows = stdout.lines().collect::<Vec<_>>();
+
+    // One row per input row, in input order, and nothing else on stdout.
+    assert_eq!(rows.len(), POINTS.lines().count());
+    for (row, input) in rows.iter().zip(POINTS.lines()) {
+        let (cid, coords) = row.split_once(',').unwrap();
+        assert_eq!(coords, input);
+        assert!(cid.parse::<isize>().is_ok(), "{}", row);
+    }
+    let cids = rows
+        .iter()
+        .map(|row| row.split(',').next().unwrap())
+        .collect::<Vec<_>>();
+    assert!(cids[..5].iter().all(|&c| c == cids[0] && c != "-1"));
+    assert!(cids[5..10].iter().all(|&c| c == cids[5] && c != "-1"));
+    assert_ne!(cids[0], cids[5]);
+    assert_eq!(cids[10], "-1");
+
+    // The summary goes to stderr.
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("11 points: 2 clusters"), "{}", stderr);
+}
+
+#[test]
+fn clustered_output_pipes_into_plot() {
+    let clustered = dbscan(&["-", "-", "5", "0.3"], POINTS).success();
+    let stdout = clustered.get_output().stdout.clone();
+
+    let png = Command::cargo_bin("plot")
+        .unwrap()
+        .args(["-", "-"])
+        .write_stdin(stdout)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn dbscan_plots_like_the_plot_binary() {
+    let dir = tempfile::tempdir().unwrap();
+    let plot = dir.path().join("clusters.png");
+    let size = ["--plot-width", "300", "--plot-height", "200"];
+    let args = [
+        &["-", "-", "5", "0.3", "--plot", plot.to_str().unwrap()][..],
+        &size,
+    ]
+    .concat();
+    let clustered = dbscan(&args, POINTS).success();
+    let stdout = clustered.get_output().stdout.clone();
+
+    let png = Command::cargo_bin("plot")
+        .unwrap()
+        .args(["-", "-", "--width", "300", "--height", "200"])
+        .args(["--title", "DBSCAN, eps = 0.3, min_points = 5"])
+        .write_stdin(stdout)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&plot).unwrap(), png.get_output().stdout);
+
+    let output = dbscan(&["-", "-", "5", "0.3", "--plot", "-"], POINTS).code(2);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr
+            .contains("--plot can only write to stdout (`-`) for a single run with an OUTPUT file"),
+        "{}",
+        stderr
+    );
+    let output = dbscan(
+        &["-", "-", "5", "0.3", "--plot", "x.png", "--plot-y-col", "2"],
+        POINTS,
+    );
+    let stderr = common::stderr(&output.code(2));
+    assert!(
+        stderr.contains("--plot-x-col/--plot-y-col out of bounds for 2 data columns"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn k_dist_reads_stdin() {
+    let png = Command::cargo_bin("k_dist")
+        .unwrap()
+        .args(["-", "-", "-k", "3"])
+        .write_stdin(POINTS)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn empty_stdin_is_reported() {
+    let output = dbscan(&["-", "-", "5", "0.3"], "").failure();
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("no points found in input"), "{}", stderr);
+    assert!(output.get_output().stdout.is_empty());
+}
+
+#[test]
+fn malformed_stdin_is_reported() {
+    let output = dbscan(&["-", "-", "5", "0.3"], "1,2\n3,x\n").failure();
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("parse error at line 2"), "{}", stderr);
+    assert!(stderr.contains("invalid float literal"), "{}", stderr);
+}
+
+#[test]
+fn headers_are_copied_to_the_output() {
+    let named = format!("x,y\n{}", POINTS);
+    for flag in ["--header", "--auto-header"] {
+        let output = dbscan(&["-", "-", "5", "0.3", flag], &named).success();
+        let stdout = common::stdout(&output);
+        assert!(stdout.starts_with("cid,x,y\n"), "{}: {}", flag, stdout);
+        assert_eq!(stdout.lines().count(), POINTS.lines().count() + 1);
+    }
+
+    // An all-numeric first row is data, and nothing is written for it.
+    let output = dbscan(&["-", "-", "5", "0.3", "--auto-header"], POINTS).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout.lines().count(), POINTS.lines().count());
+    assert!(stdout.lines().next().unwrap().ends_with(",0,0"));
+
+    let output = dbscan(&["-", "-", "5", "0.3"], &named).failure();
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("parse error at line 1"), "{}", stderr);
+}
+
+#[test]
+fn output_keeps_the_input_delimiter() {
+    let comma = dbscan(&["-", "-", "5", "0.3"], POINTS).success();
+    let comma = common::stdout(&comma);
+    for (name, c) in [("tab", "\t"), (";", ";")] {
+        let input = POINTS.replace(',', c);
+        let output = dbscan(&["-", "-", "5", "0.3", "--delimiter", name], &input).success();
+        let stdout = common::stdout(&output);
+        assert_eq!(stdout, comma.replace(',', c), "{}", name);
+    }
+}
+
+#[test]
+fn selected_columns_are_clustered_and_written() {
+    let input = POINTS
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{},{}\n", i, line))
+        .collect::<String>();
+    let plain = dbscan(&["-", "-", "5", "0.3"], POINTS).success();
+    let plain = common::stdout(&plain);
+    for columns in ["1,2", "1..3"] {
+        let output = dbscan(&["-", "-", "5", "0.3", "--columns", columns], &input).success();
+        let stdout = common::stdout(&output);
+        assert_eq!(stdout, plain, "{}", columns);
+    }
+
+    let output = dbscan(&["-", "-", "5", "0.3", "--columns", "1,3"], &input).failure();
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("column 3 is out of range; the row has 3 columns"),
+        "{}",
+        stderr
+    );
+    let png = Command::cargo_bin("k_dist")
+        .unwrap()
+        .args(["-", "-", "-k", "3", "--columns", "1..3"])
+        .write_stdin(input)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn excluded_columns_are_left_out_of_the_clustering() {
+    // An id first and a leaking column last, with values far apart.
+    let rows = POINTS
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{},{},{}\n", i, line, i * 100))
+        .collect::<String>();
+    let plain = dbscan(&["-", "-", "5", "0.3"], POINTS).success();
+    let plain = common::stdout(&plain);
+
+    let output = dbscan(&["-", "-", "5", "0.3", "--exclude-columns", "0,3"], &rows).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, plain);
+
+    let named = format!("id,x,y,leak\n{}", rows);
+    let args = [
+        "-",
+        "-",
+        "5",
+        "0.3",
+        "--header",
+        "--exclude-columns",
+        "leak,id",
+    ];
+    let output = dbscan(&args, &named).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, format!("cid,x,y\n{}", plain));
+    // --passthrough writes the whole rows.
+    let args = [&args[..], &["--passthrough"]].concat();
+    let output = dbscan(&args, &named).success();
+    let stdout = common::stdout(&output);
+    let cids = plain.lines().map(|row| row.split(',').next().unwrap());
+    let whole = named
+        .lines()
+        .skip(1)
+        .zip(cids)
+        .map(|(row, cid)| format!("{},{}\n", cid, row));
+    assert_eq!(
+        stdout,
+        format!("cid,id,x,y,leak\n{}", whole.collect::<String>())
+    );
+
+    for (args, message) in [
+        (
+            &["--header", "--exclude-columns", "z"][..],
+            "no column 'z' in header",
+        ),
+        (
+            &["--header", "--columns", "x,y", "--exclude-columns", "y"],
+            "column 'y' is both selected and excluded",
+        ),
+    ] {
+        let args = [&["-", "-", "5", "0.3"][..], args].concat();
+        let output = dbscan(&args, &named).failure();
+        let stderr = common::stderr(&output);
+        assert!(stderr.contains(message), "{:?}: {}", args, stderr);
+    }
+    let png = Command::cargo_bin("k_dist")
+        .unwrap()
+        .args(["-", "-", "-k", "3", "--exclude-columns", "0,3"])
+        .write_stdin(rows)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn passthrough_copies_the_input_rows() {
+    let input = POINTS
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:03},{}\n", i, line))
+        .collect::<String>();
+    let args = ["-", "-", "5", "0.3", "--columns", "1..3", "--passthrough"];
+    let output = dbscan(&args, &input).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout.lines().count(), input.lines().count());
+    for (row, line) in stdout.lines().zip(input.lines()) {
+        assert_eq!(row.split_once(',').unwrap().1, line);
+    }
+    assert!(stdout.ends_with(",010,9,-9\n"), "{}", stdout);
+}
+
+#[test]
+fn preserved_input_keeps_its_number_text() {
+    let input = "x , y\n0.10,0.0\n1e-1, 0\n 0.1000 ,0.00\n0.05,\t5E-2\n\
+                 3.0,3.000\n3e0 ,3\n30E-1,  3.1\n9,-9.0\n";
+    let args = ["-", "-", "2", "0.3", "--header", "--preserve-input"];
+    let output = dbscan(&args, input).success();
+    let stdout = common::stdout(&output);
+    let cids = ["cid", "0", "0", "0", "0", "1", "1", "1", "-1"];
+    let expected = input
+        .lines()
+        .zip(cids)
+        .map(|(line, cid)| format!("{},{}\n", cid, line))
+        .collect::<String>();
+    assert_eq!(stdout, expected);
+
+    let output = dbscan(
+        &["-", "-", "2", "0.3", "--preserve-input", "--format", "json"],
+        "1,2\n",
+    )
+    .failure();
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("--preserve-input needs CSV input and output"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn precision_sets_the_printed_digits() {
+    let input = "0.30000000000000004,1e-7\n0.3,1e-7\n";
+    for (precision, rows) in [
+        (
+            "shortest",
+            "0,0.30000000000000004,0.0000001\n0,0.3,0.0000001\n",
+        ),
+        ("roundtrip", "0,0.30000000000000004,1e-7\n0,0.3,1e-7\n"),
+        ("2", "0,0.30,0.00\n0,0.30,0.00\n"),
+    ] {
+        let args = ["-", "-", "2", "0.1", "--precision", precision];
+        let output = dbscan(&args, input).success();
+        let stdout = common::stdout(&output);
+        assert_eq!(stdout, rows, "{}", precision);
+    }
+    let output = dbscan(&["-", "-", "2", "0.1", "--precision", "many"], input).failure();
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("invalid float format 'many'"), "{}", stderr);
+}
+
+#[test]
+fn samples_follow_the_seed() {
+    let sample = |seed: &str| {
+        let args = ["-", "-", "2", "0.3", "--sample", "4", "--seed", seed];
+        let output = dbscan(&args, POINTS).success();
+        common::stdout(&output)
+    };
+    let rows = sample("1");
+    assert_eq!(rows.lines().count(), 4);
+    // Sampled rows keep their input order.
+    let positions = rows
+        .lines()
+        .map(|row| {
+            let coords = row.split_once(',').unwrap().1;
+            POINTS.lines().position(|line| line == coords).unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", rows);
+    assert_eq!(sample("1"), rows);
+    assert_ne!(sample("2"), rows);
+
+    let png = Command::cargo_bin("k_dist")
+        .unwrap()
+        .args(["-", "-", "-k", "2", "--sample", "100", "--seed", "1"])
+        .write_stdin(POINTS)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn shuffled_output_can_be_restored() {
+    let run = |extra: &[&str]| {
+        let args = [&["-", "-", "5", "0.3", "-q"][..], extra].concat();
+        let output = dbscan(&args, POINTS).success();
+        common::stdout(&output)
+    };
+    // The cluster ids and coordinates of each row.
+    let split = |rows: &str| {
+        rows.lines()
+            .map(|row| {
+                let (cid, coords) = row.split_once(',').unwrap();
+                (cid.to_string(), coords.to_string())
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>()
+    };
+    let shuffled = run(&["--shuffle-seed", "3"]);
+    assert_eq!(run(&["--shuffle-seed", "3"]), shuffled);
+    assert_ne!(run(&["--shuffle-seed", "4"]), shuffled);
+    let (_, mut coords) = split(&shuffled);
+    assert_ne!(coords, POINTS.lines().collect::<Vec<_>>());
+    coords.sort();
+    let mut input = POINTS.lines().collect::<Vec<_>>();
+    input.sort();
+    assert_eq!(coords, input);
+
+    // Back in input order, with the same clusters, though their ids may
+    // differ.
+    let (ids, coords) = split(&run(&["--shuffle-seed", "3", "--restore-order"]));
+    assert_eq!(coords, POINTS.lines().collect::<Vec<_>>());
+    let (plain, _) = split(&run(&[]));
+    for i in 0..ids.len() {
+        for j in 0..ids.len() {
+            assert_eq!(ids[i] == ids[j], plain[i] == plain[j], "rows {} {}", i, j);
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn summary_describes_the_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run.json");
+    let summary = path.to_str().unwrap();
+    dbscan(&["-", "-", "5", "0.3", "-q", "--summary", summary], POINTS).success();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["input"], "-");
+    assert_eq!(json["eps"], 0.3);
+    assert_eq!(json["min_points"], 5);
+    assert_eq!(json["n_points"], 11);
+    assert_eq!(json["n_clusters"], 2);
+    assert_eq!(json["n_noise"], 1);
+    let sizes = json["clusters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["size"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [5, 5]);
+    assert!(json["elapsed_secs"].as_f64().unwrap() >= 0.0);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_paths_get_clusters_grouped_by_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.json");
+    let output = path.to_str().unwrap();
+    let read = || -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+    };
+    dbscan(&["-", output, "5", "0.3", "-q"], POINTS).success();
+    let json = read();
+    let clusters = json["clusters"].as_array().unwrap();
+    assert_eq!(clusters.len(), 2);
+    assert_eq!(clusters[0]["id"], 0);
+    assert_eq!(clusters[0]["points"][0], serde_json::json!([0.0, 0.0]));
+    assert_eq!(clusters[1]["id"], 1);
+    assert_eq!(clusters[1]["points"].as_array().unwrap().len(), 5);
+    assert_eq!(json["noise"], serde_json::json!([[9.0, -9.0]]));
+
+    let args = [
+        "-",
+        output,
+        "5",
+        "0.3",
+        "-q",
+        "--json-style",
+        "flat",
+        "--pretty",
+    ];
+    dbscan(&args, POINTS).success();
+    let json = read();
+    assert_eq!(json.as_array().unwrap().len(), 11);
+    assert_eq!(
+        json[10],
+        serde_json::json!({"cluster": -1, "coords": [9.0, -9.0]})
+    );
+}
+
+#[test]
+fn max_rows_reads_only_the_first_rows() {
+    let output = dbscan(&["-", "-", "3", "0.3", "--max-rows", "6", "-q"], POINTS).success();
+    let stdout = common::stdout(&output);
+    let first = POINTS
+        .lines()
+        .take(6)
+        .map(|line| format!("{},{}\n", if line == "3,3" { -1 } else { 0 }, line))
+        .collect::<String>();
+    assert_eq!(stdout, first);
+
+    let args = ["-", "-", "3", "0.3", "--max-rows", "6", "--sample", "2"];
+    let output = dbscan(&args, POINTS).code(2);
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+    // k_dist sees only four points.
+    let k_dist = |k: &str| {
+        Command::cargo_bin("k_dist")
+            .unwrap()
+            .args(["-", "-", "--max-rows", "4", "-k", k])
+            .write_stdin(POINTS)
+            .assert()
+    };
+    let output = k_dist("4").failure();
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("got k=4, N=4"), "{}", stderr);
+    k_dist("3").success();
+    let png = Command::cargo_bin("plot")
+        .unwrap()
+        .args(["-", "-", "--max-rows", "4"])
+        .write_stdin(stdout)
+        .assert()
+        .success();
+    assert!(png.get_output().stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn auto_eps_clusters_without_an_eps() {
+    let output = dbscan(&["-", "-", "3", "--auto-eps"], POINTS).success();
+    let stdout = common::stdout(&output);
+    let explicit = dbscan(&["-", "-", "3", "0.3"], POINTS).success();
+    assert_eq!(stdout.as_bytes(), explicit.get_output().stdout);
+    let stderr = common::stderr(&output);
+    let eps = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("auto eps: "))
+        .and_then(|rest| rest.split(' ').next())
+        .unwrap_or_else(|| panic!("{}", stderr));
+    // Between the spacing within a blob and the gap between blobs.
+    let eps = eps.parse::<f64>().unwrap();
+    assert!((0.05..3.0).contains(&eps), "{}", eps);
+}
+
+#[test]
+fn report_counts_clusters_noise_and_phases() {
+    // Clusters of 2, 7, 3, 6, 4 and 5 points along lines 10 apart, and two
+    // noise points.
+    let mut input = String::new();
+    for (i, size) in [2, 7, 3, 6, 4, 5].into_iter().enumerate() {
+        for j in 0..size {
+            input += &format!("{},{}\n", i * 10, j as f64 * 0.1);
+        }
+    }
+    input += "100,100\n-100,100\n";
+
+    let output = dbscan(&["-", "-", "2", "0.15"], &input).success();
+    let stderr = common::stderr(&output);
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "29 points: 6 clusters, 2 noise points (6.9%)");
+    assert_eq!(
+        lines[1],
+        "largest clusters: 7 (#1), 6 (#3), 5 (#5), 4 (#4), 3 (#2), ..."
+    );
+    let phases = lines[2].strip_prefix("time: ").unwrap();
+    let names = phases
+        .split(", ")
+        .map(|phase| phase.split(' ').next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["read", "index", "cluster", "write", "total"]);
+    assert_eq!(lines.len(), 3, "{}", stderr);
+
+    let output = dbscan(&["-", "-", "2", "0.15", "-v"], &input).success();
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("  cluster 1: 7 points, centroid (10.0000, 0.3000)\n"),
+        "{}",
+        stderr
+    );
+    let output = dbscan(&["-", "-", "2", "0.15", "-q"], &input).success();
+    assert!(output.get_output().stderr.is_empty());
+}
+
+#[test]
+fn stderr_that_is_not_a_terminal_gets_no_progress_bar() {
+    // Enough points for the bar to update many times, written to a file so
+    // that only the terminal check stands between them and a bar.
+    let input = (0..3000)
+        .map(|i| format!("{},{}\n", i % 60, i / 60))
+        .collect::<String>();
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.csv");
+    let args = ["-", output.to_str().unwrap(), "3", "1.5"];
+    let assert = dbscan(&args, &input).success();
+    let stderr = common::stderr(&assert);
+    assert!(stderr.contains("3000 points"), "{}", stderr);
+    assert!(
+        !stderr.contains('\x1b') && !stderr.contains('\r'),
+        "{:?}",
+        stderr
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap().lines().count(),
+        3000
+    );
+}
+
+#[test]
+fn diagnostics_never_reach_stdout() {
+    // A warning, verbose logging and the report all go to stderr.
+    let args = ["-", "-", "5.0", "0.3", "-v", "--engine", "brute"];
+    let output = dbscan(&args, POINTS).success();
+    let stdout = common::stdout(&output);
+    for row in stdout.lines() {
+        let fields = row.split(',').collect::<Vec<_>>();
+        assert_eq!(fields.len(), 3, "{}", stdout);
+        assert!(fields[0].parse::<isize>().is_ok(), "{}", stdout);
+        assert!(
+            fields[1..].iter().all(|x| x.parse::<f64>().is_ok()),
+            "{}",
+            stdout
+        );
+    }
+    assert_eq!(stdout.lines().count(), 11);
+    let stderr = common::stderr(&output);
+    assert!(stderr.contains("warning: MIN_POINTS 5.0"), "{}", stderr);
+    assert!(stderr.contains("engine: brute"), "{}", stderr);
+    assert!(stderr.contains("11 points: 2 clusters"), "{}", stderr);
+
+    // Stdout is refused to a second stream, and stdin read only once.
+    let output = dbscan(&["-", "-", "5", "0.3", "--summary", "-"], POINTS).failure();
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("OUTPUT and --summary cannot both be stdout (`-`)"),
+        "{}",
+        stderr
+    );
+    assert!(output.get_output().stdout.is_empty());
+    let output = dbscan(&["-", "-", "5", "0.3", "--inputs", "-"], POINTS).failure();
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("stdin (`-`) can only be read once"),
+        "{}",
+        stderr
+    );
+    assert!(output.get_output().stdout.is_empty());
+}
@@ -0,0 +1,36 @@
+//! Exact dbscan output for a small fixture under both --sort-output modes.
+
+mod common;
+
+// Two clusters at eps 1.5 and min_points 2, the first of them holding a
+// duplicate point, and two noise points.
+const POINTS: &str = "5,6\n0,1\n9,9\n5,5\n0,0\n-9,0\n1,0\n5,5\n";
+
+fn run(extra: &[&str]) -> String {
+    let args = [&["-", "-", "2", "1.5", "-q"], extra].concat();
+    common::stdout(&common::dbscan(&args, POINTS).success())
+}
+
+#[test]
+fn input_order_keeps_the_rows_as_read() {
+    // Clusters are numbered by their first row.
+    let expected = "0,5,6\n1,0,1\n-1,9,9\n0,5,5\n1,0,0\n-1,-9,0\n1,1,0\n0,5,5\n";
+    assert_eq!(run(&[]), expected);
+    assert_eq!(run(&["--sort-output", "input"]), expected);
+}
+
+#[test]
+fn cluster_order_sorts_by_id_then_coordinates_with_noise_last() {
+    let expected = "0,5,5\n0,5,5\n0,5,6\n1,0,0\n1,0,1\n1,1,0\n-1,-9,0\n-1,9,9\n";
+    assert_eq!(run(&["--sort-output", "cluster"]), expected);
+}
+
+#[test]
+fn runs_give_identical_bytes() {
+    for mode in ["input", "cluster"] {
+        let first = run(&["--sort-output", mode]);
+        for _ in 0..4 {
+            assert_eq!(run(&["--sort-output", mode]), first, "{}", mode);
+        }
+    }
+}
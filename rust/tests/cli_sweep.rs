@@ -0,0 +1,113 @@
+//! dbscan --eps-list clustering the same points once per eps.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+mod common;
+
+// A tight blob 0.1 apart, a loose one 0.4 apart, 3 from each other, and two
+// stray points.
+const POINTS: &str = "0,0\n0,0.1\n0.1,0\n0.1,0.1\n\
+                      3,0\n3,0.4\n3.4,0\n3.4,0.4\n3.2,0.2\n\
+                      1.5,5\n-6,-6\n";
+
+const EPS: [&str; 4] = ["0.15", "0.5", "3", "10"];
+
+fn run(dir: &Path, output: &str, args: &[&str]) -> String {
+    let assert = common::command()
+        .arg(dir.join("points.csv"))
+        .arg(dir.join(output))
+        .args(args)
+        .assert()
+        .success();
+    common::stderr(&assert)
+}
+
+// Clusters and noise points of a clustered CSV.
+fn count(clustered: &str) -> (usize, usize) {
+    let cids = clustered
+        .lines()
+        .map(|row| row.split(',').next().unwrap())
+        .collect::<Vec<_>>();
+    let noise = cids.iter().filter(|&&cid| cid == "-1").count();
+    let clusters = cids
+        .into_iter()
+        .filter(|&cid| cid != "-1")
+        .collect::<HashSet<_>>();
+    (clusters.len(), noise)
+}
+
+#[test]
+fn a_sweep_writes_one_output_and_one_summary_row_per_eps() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("points.csv"), POINTS).unwrap();
+    let sweep = dir.path().join("sweep.csv");
+    let stderr = run(
+        dir.path(),
+        "out.csv",
+        &[
+            "3",
+            "--eps-list",
+            &EPS.join(","),
+            "--sweep-summary",
+            sweep.to_str().unwrap(),
+        ],
+    );
+
+    let table = std::fs::read_to_string(&sweep).unwrap();
+    let mut rows = table.lines();
+    assert_eq!(rows.next(), Some("eps,clusters,noise,noise_pct"));
+    let mut counts = Vec::new();
+    for (eps, row) in EPS.iter().zip(rows.by_ref()) {
+        let written = std::fs::read_to_string(dir.path().join(format!("out.eps{}.csv", eps)))
+            .unwrap_or_else(|err| panic!("eps {}: {}", eps, err));
+        let (clusters, noise) = count(&written);
+        let pct = 100.0 * noise as f64 / 11.0;
+        assert_eq!(
+            row,
+            format!("{},{},{},{:.3}", eps, clusters, noise, pct),
+            "{}",
+            table
+        );
+        // The table on stderr shows the same counts.
+        let line = format!("{:>12} {:>9} {:>9} {:>7.1}", eps, clusters, noise, pct);
+        assert!(stderr.contains(&line), "{}", stderr);
+
+        // Clustering with just this eps writes the same rows.
+        run(dir.path(), "single.csv", &["3", eps, "-q"]);
+        let single = std::fs::read_to_string(dir.path().join("single.csv")).unwrap();
+        assert_eq!(written, single, "{}", eps);
+        counts.push((clusters, noise));
+    }
+    assert_eq!(rows.next(), None);
+    assert_eq!(counts, [(1, 7), (2, 2), (1, 2), (1, 0)]);
+    // No output is written under the plain name.
+    assert!(!dir.path().join("out.csv").exists());
+}
+
+#[test]
+fn a_sweep_needs_output_files_and_positive_eps() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("points.csv"), POINTS).unwrap();
+    let fails = |output: &str, args: &[&str]| {
+        let assert = common::command()
+            .arg(dir.path().join("points.csv"))
+            .arg(output)
+            .args(args)
+            .assert()
+            .failure();
+        common::stderr(&assert)
+    };
+    let err = fails("-", &["3", "--eps-list", "0.5"]);
+    assert!(err.contains("give an OUTPUT path, not `-`"), "{}", err);
+    let out = dir.path().join("out.csv");
+    let out = out.to_str().unwrap();
+    let err = fails(out, &["3", "--eps-list", "0.5,0"]);
+    assert!(
+        err.contains("--eps-list values must be positive; got 0"),
+        "{}",
+        err
+    );
+    let err = fails(out, &["3", "0.5", "--eps-list", "0.5"]);
+    assert!(err.contains("cannot be used with"), "{}", err);
+}
@@ -0,0 +1,74 @@
+//! dbscan running its indexing and clustering on a pool of --threads.
+
+mod common;
+
+// A few hundred points in three blobs, enough for the fake engine to split its
+// work between threads.
+fn points() -> String {
+    (0..300)
+        .map(|i| {
+            let center = (i % 3) as f64 * 10.0;
+            format!(
+                "{},{}\n",
+                center + (i / 3 % 10) as f64 * 0.1,
+                (i / 30) as f64 * 0.1
+            )
+        })
+        .collect()
+}
+
+fn run(args: &[&str]) -> assert_cmd::assert::Assert {
+    common::dbscan(&[&["-", "-", "4", "0.15"], args].concat(), &points())
+}
+
+fn output(args: &[&str]) -> (String, String) {
+    common::output(&run(args).success())
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn any_number_of_threads_writes_the_same_clusters() {
+    let (one, stderr) = output(&["--engine", "fake", "--threads", "1", "-v"]);
+    assert!(stderr.contains("threads: 1\n"), "{}", stderr);
+    assert_eq!(one.lines().count(), 300);
+    assert!(one.lines().any(|row| row.starts_with("2,")), "{}", one);
+    let (four, stderr) = output(&["--engine", "fake", "--threads", "4", "-v"]);
+    assert!(stderr.contains("threads: 4\n"), "{}", stderr);
+    assert!(!stderr.contains("warning"), "{}", stderr);
+    assert_eq!(one, four);
+    // The pool is logged only with -v.
+    let (_, stderr) = output(&["--engine", "fake", "--threads", "4"]);
+    assert!(!stderr.contains("threads:"), "{}", stderr);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn threads_without_parallel_work_are_accepted_with_a_warning() {
+    let (fake, _) = output(&["--engine", "fake"]);
+    let (brute, stderr) = output(&["--engine", "brute", "--threads", "2"]);
+    let warning =
+        "warning: the brute engine does no parallel work in this run; --threads has no effect";
+    assert!(stderr.contains(warning), "{}", stderr);
+    assert_eq!(brute, fake);
+    let (_, stderr) = output(&["--engine", "brute", "--threads", "2", "-q"]);
+    assert_eq!(stderr, "");
+}
+
+#[cfg(not(feature = "parallel"))]
+#[test]
+fn threads_without_parallel_work_are_accepted_with_a_warning() {
+    let (_, stderr) = output(&["--threads", "2"]);
+    let warning = "warning: built without the `parallel` feature; --threads has no effect";
+    assert!(stderr.contains(warning), "{}", stderr);
+}
+
+#[test]
+fn threads_must_be_at_least_one() {
+    let assert = run(&["--threads", "0"]).failure();
+    let stderr = common::stderr(&assert);
+    assert!(
+        stderr.contains("--threads must be at least 1"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,179 @@
+//! dbscan clustering weighted rows with --weights-column.
+
+use std::collections::HashMap;
+
+mod common;
+
+fn run(args: &[&str], stdin: &str) -> assert_cmd::assert::Assert {
+    common::dbscan(&[&["-", "-"], args].concat(), stdin)
+}
+
+// Cluster ids renumbered by first appearance, so that runs numbering their
+// clusters in another order compare equal.
+fn canonical<'a>(cids: impl Iterator<Item = &'a str>) -> Vec<isize> {
+    let mut ids = HashMap::new();
+    cids.map(|cid| match cid {
+        "-1" => -1,
+        cid => {
+            let next = ids.len() as isize;
+            *ids.entry(cid).or_insert(next)
+        }
+    })
+    .collect()
+}
+
+// The sizes reported by -v for every cluster, after `weight ` if `weighted`.
+fn sizes(stderr: &str, weighted: bool) -> Vec<f64> {
+    let mut sizes = stderr
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("cluster "))
+        .map(|line| {
+            let size = match weighted {
+                true => line.split(", weight ").nth(1).unwrap(),
+                false => line.split(": ").nth(1).unwrap(),
+            };
+            size.split([' ', ',']).next().unwrap().parse().unwrap()
+        })
+        .collect::<Vec<f64>>();
+    sizes.sort_by(f64::total_cmp);
+    sizes
+}
+
+#[test]
+fn weighted_rows_cluster_like_duplicated_rows() {
+    // Rows on a unit grid with weights 1 to 3. The copies of a row are moved
+    // by millionths, far less than the gaps between eps and the distances of
+    // the grid, so that they stay separate points.
+    let mut rng = 7u64;
+    let mut next = |n: u64| {
+        rng = rng
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (rng >> 33) % n
+    };
+    let mut weighted = String::new();
+    let mut duplicated = String::new();
+    let mut copies = Vec::new();
+    for _ in 0..60 {
+        let (x, y, weight) = (next(10), next(10), 1 + next(3));
+        weighted.push_str(&format!("{},{},{}\n", x, y, weight));
+        copies.push(weight as usize);
+        for _ in 0..weight {
+            // Rows drawn twice must not get copies in the same place either.
+            let shift = duplicated.lines().count() as f64 * 1e-6;
+            duplicated.push_str(&format!("{},{}\n", x as f64 + shift, y));
+        }
+    }
+
+    for min_pts in ["3", "5", "8"] {
+        let args = [min_pts, "1.2", "-v"];
+        let output = run(&[&args[..], &["--weights-column", "2"]].concat(), &weighted);
+        let output = output.success();
+        let stderr = common::stderr(&output);
+        let stdout = common::stdout(&output);
+        // The weight column is not a coordinate.
+        assert!(stdout.lines().all(|row| row.split(',').count() == 3));
+        let weighted_cids = canonical(stdout.lines().map(|row| row.split(',').next().unwrap()));
+        let weighted_sizes = sizes(&stderr, true);
+
+        let output = run(&args, &duplicated).success();
+        let stderr = common::stderr(&output);
+        let stdout = common::stdout(&output);
+        // The first copy of every row.
+        let mut rows = stdout.lines();
+        let firsts = copies.iter().map(|&n| {
+            let first = rows.next().unwrap();
+            rows.by_ref().take(n - 1).for_each(drop);
+            first.split(',').next().unwrap()
+        });
+        let duplicated_cids = canonical(firsts);
+
+        assert_eq!(weighted_cids, duplicated_cids, "min_pts {}", min_pts);
+        assert!(weighted_cids.contains(&-1), "min_pts {}", min_pts);
+        assert!(weighted_cids.contains(&1), "min_pts {}", min_pts);
+        // The total weight of a cluster is the number of its copies.
+        assert_eq!(weighted_sizes, sizes(&stderr, false), "min_pts {}", min_pts);
+    }
+}
+
+#[test]
+fn weights_are_read_by_name_and_reported() {
+    let input = "x,w,y\n0,3,0\n0,1,0.2\n5,1,5\n5,1,5.2\n9,4,9\n";
+    let args = ["4", "0.3", "--header", "--weights-column", "w", "-v"];
+    let output = run(&args, input).success();
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout, "cid,x,y\n0,0,0\n0,0,0.2\n-1,5,5\n-1,5,5.2\n1,9,9\n");
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("largest clusters: 2 (#0, weight 4), 1 (#1, weight 4)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("cluster 1: 1 points, weight 4, centroid (9.0000, 9.0000)"),
+        "{}",
+        stderr
+    );
+    // Unweighted, the same rows are all noise.
+    let output = run(&["4", "0.3", "--header", "--columns", "x,y"], input);
+    let plain = common::stdout(&output.success());
+    assert!(
+        plain.lines().skip(1).all(|row| row.starts_with("-1,")),
+        "{}",
+        plain
+    );
+}
+
+#[test]
+fn bad_weights_are_reported_with_their_line() {
+    for (input, message) in [
+        ("0,0,1\n1,1,x\n", "line 2: invalid weight"),
+        (
+            "0,0,1\n1,1,\n",
+            "line 2: weight column 2 is empty or missing",
+        ),
+        (
+            "0,0,-1\n",
+            "line 1: weight must be finite and positive, got -1",
+        ),
+        (
+            "0,0,1\n1,1,inf\n",
+            "line 2: weight must be finite and positive, got inf",
+        ),
+        (
+            "0,0,1\n1,1,0\n",
+            "line 2: weight must be finite and positive, got 0",
+        ),
+    ] {
+        let output = run(&["4", "0.3", "--weights-column", "2"], input).failure();
+        let stderr = common::stderr(&output);
+        assert!(stderr.contains(message), "{:?}: {}", input, stderr);
+    }
+    let args = ["4", "0.3", "--weights-column", "2", "--metric", "manhattan"];
+    let output = run(&args, "0,0,1\n").code(2);
+    let stderr = common::stderr(&output);
+    assert!(
+        stderr.contains("--weights-column only works with --metric euclidean"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn summaries_have_the_weight_of_every_cluster() {
+    let dir = tempfile::tempdir().unwrap();
+    let summary = dir.path().join("run.json");
+    let args = ["4", "0.3", "--weights-column", "2", "-q", "--summary"];
+    let args = [&args[..], &[summary.to_str().unwrap()]].concat();
+    run(&args, "0,0,3\n0,0.2,1.5\n5,5,1\n9,9,4\n").success();
+    let text = std::fs::read_to_string(&summary).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(json["n_points"], 4);
+    let clusters = json["clusters"].as_array().unwrap();
+    let sizes = clusters
+        .iter()
+        .map(|c| (c["size"].as_u64().unwrap(), c["weight"].as_f64().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [(2, 4.5), (1, 4.0)]);
+}
@@ -0,0 +1,32 @@
+//! Helpers shared by the tests of the dbscan binary.
+
+// Every test file compiles its own copy and uses only some of them.
+#![allow(dead_code)]
+
+use assert_cmd::Command;
+use assert_cmd::assert::Assert;
+
+/// The dbscan binary, for runs that need more than plain arguments.
+pub fn command() -> Command {
+    Command::cargo_bin("dbscan").unwrap()
+}
+
+/// Runs dbscan with `args`, feeding it `stdin`.
+pub fn dbscan(args: &[&str], stdin: &str) -> Assert {
+    command().args(args).write_stdin(stdin).assert()
+}
+
+/// The stdout of a run, as text.
+pub fn stdout(assert: &Assert) -> String {
+    String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+}
+
+/// The stderr of a run, as text.
+pub fn stderr(assert: &Assert) -> String {
+    String::from_utf8(assert.get_output().stderr.clone()).unwrap()
+}
+
+/// The stdout and stderr of a run.
+pub fn output(assert: &Assert) -> (String, String) {
+    (stdout(assert), stderr(assert))
+}
@@ -0,0 +1,123 @@
+//! A metric defined outside the crate, threaded through the engines, `Algo`
+//! and `k_distances` by type.
+
+use ordered_float::OrderedFloat;
+use rust::algo::{k_distances, Algo, RegionQuery};
+use rust::brute_query::BruteForceQueryEngine;
+use rust::fake_query::FakeQueryEngine;
+use rust::types::{Class, Metric, Point};
+use std::collections::{HashMap, HashSet};
+
+// Euclidean distance with one weight per dimension.
+struct WeightedEuclidean {
+    weights: Vec<f64>,
+}
+
+impl Metric for WeightedEuclidean {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.key(a, b).sqrt()
+    }
+
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        a.iter()
+            .zip(b.iter())
+            .zip(self.weights.iter())
+            .map(|((x, y), w)| w * (x.0 - y.0).powi(2))
+            .sum()
+    }
+
+    fn key_bound(&self, eps: f64) -> f64 {
+        eps * eps
+    }
+
+    fn key_to_dist(&self, key: f64) -> f64 {
+        key.sqrt()
+    }
+}
+
+fn pt(x: f64, y: f64) -> Point {
+    vec![OrderedFloat(x), OrderedFloat(y)]
+}
+
+// Two rows of three points, 1 apart in x and y. Weighting y by 4 puts the
+// rows 2 apart, so at eps 1.5 they only form one cluster under plain
+// Euclidean distance.
+fn two_rows() -> Vec<Point> {
+    (0..3)
+        .flat_map(|x| [pt(x as f64, 0.0), pt(x as f64, 1.0)])
+        .collect()
+}
+
+fn stretched() -> WeightedEuclidean {
+    WeightedEuclidean {
+        weights: vec![1.0, 4.0],
+    }
+}
+
+fn cluster_count(classes: &HashMap<&Point, Class>) -> usize {
+    classes
+        .values()
+        .filter_map(|c| match c {
+            Class::Classified(id) => Some(id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+#[test]
+fn weighted_metric_separates_the_rows() {
+    let points = two_rows();
+    let refs = points.iter().collect::<HashSet<_>>();
+
+    let mut fake = FakeQueryEngine::with_metric(stretched());
+    let classes = Algo::<_, f64, WeightedEuclidean>::new(&mut fake, &refs, 1.5, 3).dbscan();
+    assert_eq!(cluster_count(&classes), 2);
+    for p in points.iter() {
+        let same_row = points.iter().filter(|q| q[1] == p[1]);
+        assert!(same_row.into_iter().all(|q| classes[q] == classes[p]));
+    }
+
+    let mut brute = BruteForceQueryEngine::with_metric(stretched());
+    let classes = Algo::new(&mut brute, &refs, 1.5, 3).dbscan();
+    assert_eq!(cluster_count(&classes), 2);
+
+    let mut euclidean = FakeQueryEngine::new();
+    let classes = Algo::new(&mut euclidean, &refs, 1.5, 3).dbscan();
+    assert_eq!(cluster_count(&classes), 1);
+}
+
+#[test]
+fn k_distances_use_the_weighted_metric() {
+    let points = two_rows();
+    let refs = points.iter().collect::<HashSet<_>>();
+    let queries = points.iter().collect::<Vec<_>>();
+
+    let mut fake = FakeQueryEngine::with_metric(stretched());
+    let mut brute = BruteForceQueryEngine::with_metric(stretched());
+    let fake_distances = {
+        let algo = Algo::new(&mut fake, &refs, 1.5, 3);
+        k_distances(algo.region_query(), &queries, 2)
+    };
+    let brute_distances = {
+        let algo = Algo::new(&mut brute, &refs, 1.5, 3);
+        k_distances(algo.region_query(), &queries, 2)
+    };
+
+    // The second neighbor of a middle point is on its row, 1 away. At the
+    // ends of a row it is two steps along the row or across the rows, both 2
+    // away under the weights but only 1 under plain Euclidean distance.
+    let expected = points
+        .iter()
+        .map(|p| if p[0].0 == 1.0 { 1.0 } else { 2.0 })
+        .collect::<Vec<_>>();
+    assert_eq!(fake_distances, expected);
+    assert_eq!(brute_distances, expected);
+
+    let mut euclidean = FakeQueryEngine::new();
+    euclidean.init(&refs);
+    assert_eq!(
+        k_distances(&euclidean, &queries, 2),
+        vec![1.0; points.len()]
+    );
+}
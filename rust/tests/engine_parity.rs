@@ -0,0 +1,271 @@
+//! Differential tests of the query engines. Every engine in `ENGINES` is
+//! checked against a reference computed from the metric itself, on random
+//! point sets with many tied distances. The eps values include every kind of
+//! boundary: 0, exact pairwise distances (which must be included, `<=`), and
+//! one past the largest distance.
+//!
+//! The semantics pinned down here are those of `RegionQuery`: `run` includes
+//! every indexed point within eps, the query point itself too, and `k_dist`
+//! skips only the query point's own entry. To cover a new engine, add a
+//! variant to `Engine` and to `ENGINES`.
+
+use ordered_float::OrderedFloat;
+use rust::algo::{k_distances, Algo, RegionQuery};
+use rust::brute_query::BruteForceQueryEngine;
+use rust::fake_query::FakeQueryEngine;
+use rust::query::{RTreeQueryEngine, RTreeQueryEngineN};
+use rust::sampling::SplitMix64;
+use rust::types::{Class, MetricKind, Point};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Copy, Clone)]
+enum Engine {
+    Fake,
+    FakeOnDemand,
+    FakeLazy,
+    RTree,
+    RTree2,
+    RTree4,
+    Brute,
+}
+
+const ENGINES: [Engine; 7] = [
+    Engine::Fake,
+    Engine::FakeOnDemand,
+    Engine::FakeLazy,
+    Engine::RTree,
+    Engine::RTree2,
+    Engine::RTree4,
+    Engine::Brute,
+];
+
+const METRICS: [MetricKind; 4] = [
+    MetricKind::Euclidean,
+    MetricKind::Manhattan,
+    MetricKind::Chebyshev,
+    MetricKind::Minkowski(3.0),
+];
+
+impl Engine {
+    // `None` if the engine cannot index points of `dim` dimensions under
+    // `metric`.
+    fn build<'a>(self, dim: usize, metric: MetricKind) -> Option<Box<dyn RegionQuery<'a> + 'a>> {
+        let euclidean = metric == MetricKind::Euclidean;
+        Some(match self {
+            Engine::Fake => Box::new(FakeQueryEngine::with_metric(metric)),
+            Engine::FakeOnDemand if euclidean => Box::new(FakeQueryEngine::on_demand()),
+            Engine::FakeLazy if euclidean => Box::new(FakeQueryEngine::lazy(4)),
+            Engine::RTree => Box::new(RTreeQueryEngine::with_metric(metric)),
+            Engine::RTree2 if dim <= 2 => Box::new(RTreeQueryEngineN::<2>::with_metric(metric)),
+            Engine::RTree4 if dim <= 4 => Box::new(RTreeQueryEngineN::<4>::with_metric(metric)),
+            Engine::Brute => Box::new(BruteForceQueryEngine::with_metric(metric)),
+            _ => return None,
+        })
+    }
+}
+
+// Points on a coarse grid, so that many pairwise distances tie, with a few
+// off-grid ones. Duplicates are dropped, as the engines index sets.
+fn random_points(rng: &mut SplitMix64, n: usize, dim: usize) -> Vec<Point> {
+    let mut seen = HashSet::new();
+    (0..n)
+        .map(|_| {
+            (0..dim)
+                .map(|_| match rng.below(8) {
+                    0 => rng.below(1000) as f64 / 137.0,
+                    _ => rng.below(6) as f64 * 0.5,
+                })
+                .map(OrderedFloat)
+                .collect::<Point>()
+        })
+        .filter(|p| seen.insert(p.clone()))
+        .collect()
+}
+
+// Distances from `query` to every indexed point except its own entry,
+// ascending.
+fn reference_distances(points: &[Point], query: &Point, metric: MetricKind) -> Vec<f64> {
+    let mut d = points
+        .iter()
+        .filter(|&p| !std::ptr::eq(p, query))
+        .map(|p| metric.distance(query, p))
+        .collect::<Vec<_>>();
+    d.sort_by(f64::total_cmp);
+    d
+}
+
+fn reference_run<'p>(
+    points: &'p [Point],
+    query: &Point,
+    eps: f64,
+    metric: MetricKind,
+) -> HashSet<&'p Point> {
+    points
+        .iter()
+        .filter(|p| metric.distance(query, p) <= eps)
+        .collect()
+}
+
+// The eps values to try around `query`: 0, every exact distance to another
+// point (deduplicated), and past the largest one.
+fn boundary_eps(points: &[Point], query: &Point, metric: MetricKind) -> Vec<f64> {
+    let mut eps = vec![0.0];
+    eps.extend(points.iter().map(|p| metric.distance(query, p)));
+    eps.sort_by(f64::total_cmp);
+    eps.dedup();
+    let max = *eps.last().unwrap();
+    eps.push(max * 1.5 + 1.0);
+    eps
+}
+
+// Checks `run`, `weight_within`, `k_dist`, `k_dist_with_count` and `knn` of
+// `engine` against the reference for every indexed point and one point that
+// is not indexed.
+fn check_queries(engine: Engine, dim: usize, metric: MetricKind, points: &[Point], case: &str) {
+    let refs = points.iter().collect::<HashSet<_>>();
+    let Some(mut region_query) = engine.build(dim, metric) else {
+        return;
+    };
+    region_query.init(&refs);
+
+    let outside = points[0].iter().map(|x| *x + 0.25).collect::<Point>();
+    // A copy of an indexed point: equal coordinates, but not its entry.
+    let copy = points[0].clone();
+    for query in points.iter().chain([&outside, &copy]) {
+        for eps in boundary_eps(points, query, metric) {
+            let expected = reference_run(points, query, eps, metric);
+            let got = region_query.run(query, eps);
+            assert_eq!(
+                got, expected,
+                "{:?} run({:?}, {}) under {:?}, {}",
+                engine, query, eps, metric, case
+            );
+            assert_eq!(
+                region_query.weight_within(query, eps),
+                expected.len() as f64,
+                "{:?} weight_within under {:?}, {}",
+                engine,
+                metric,
+                case
+            );
+        }
+
+        let distances = reference_distances(points, query, metric);
+        for k in 1..=distances.len() {
+            let kth = distances[k - 1];
+            let context = format!("{:?} k={} under {:?}, {}", engine, k, metric, case);
+            assert_eq!(region_query.k_dist(query, k), kth, "k_dist: {}", context);
+            let ties = distances.iter().filter(|&&d| d <= kth).count();
+            assert_eq!(
+                region_query.k_dist_with_count(query, k),
+                (kth, ties),
+                "k_dist_with_count: {}",
+                context
+            );
+            let knn = region_query
+                .knn(query, k)
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<_>>();
+            assert_eq!(knn, distances[..k], "knn: {}", context);
+        }
+    }
+
+    let queries = points.iter().collect::<Vec<_>>();
+    if points.len() > 1 {
+        let expected = queries
+            .iter()
+            .map(|q| reference_distances(points, q, metric)[0])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            k_distances(region_query.as_ref(), &queries, 1),
+            expected,
+            "{:?} k_distances under {:?}, {}",
+            engine,
+            metric,
+            case
+        );
+    }
+}
+
+// Core points and noise do not depend on the order of expansion, so every
+// engine must agree on them; cluster ids and border points may differ.
+fn check_clustering(engine: Engine, dim: usize, metric: MetricKind, points: &[Point], case: &str) {
+    let refs = points.iter().collect::<HashSet<_>>();
+    let Some(mut region_query) = engine.build(dim, metric) else {
+        return;
+    };
+    for eps in [0.5, 1.0, 1.5] {
+        let (classes, cores) = Algo::new(region_query.as_mut(), &refs, eps, 3).dbscan_with_cores();
+
+        let expected_cores = points
+            .iter()
+            .filter(|&q| reference_run(points, q, eps, metric).len() >= 3)
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            cores, expected_cores,
+            "{:?} cores at eps {} under {:?}, {}",
+            engine, eps, metric, case
+        );
+        let expected_noise = points
+            .iter()
+            .filter(|&q| {
+                !expected_cores.contains(q)
+                    && reference_run(points, q, eps, metric)
+                        .iter()
+                        .all(|n| !expected_cores.contains(n))
+            })
+            .collect::<HashSet<_>>();
+        let noise = classes
+            .iter()
+            .filter(|&(_, &c)| c == Class::Noise)
+            .map(|(&p, _)| p)
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            noise, expected_noise,
+            "{:?} noise at eps {} under {:?}, {}",
+            engine, eps, metric, case
+        );
+
+        // Core points within eps of each other share a cluster.
+        let cluster = cores
+            .iter()
+            .map(|&p| (p, classes[p]))
+            .collect::<HashMap<_, _>>();
+        for &p in cores.iter() {
+            for q in reference_run(points, p, eps, metric) {
+                if let Some(&c) = cluster.get(q) {
+                    assert_eq!(c, cluster[p], "{:?} split a cluster, {}", engine, case);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn engines_agree_with_the_reference() {
+    for dim in 1..=4 {
+        for seed in 0..8 {
+            let mut rng = SplitMix64::new(seed * 31 + dim as u64);
+            let n = 1 + rng.below(32) as usize;
+            let points = random_points(&mut rng, n, dim);
+            let case = format!("dim {}, seed {}, {} points", dim, seed, points.len());
+            for metric in METRICS {
+                for engine in ENGINES {
+                    check_queries(engine, dim, metric, &points, &case);
+                    check_clustering(engine, dim, metric, &points, &case);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn single_point() {
+    let points = vec![vec![OrderedFloat(1.0), OrderedFloat(2.0)]];
+    for metric in METRICS {
+        for engine in ENGINES {
+            check_queries(engine, 2, metric, &points, "single point");
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Points that keep their input row through clustering and output.
+
+use ordered_float::OrderedFloat;
+use rust::algo::dbscan_labeled;
+use rust::engine::EngineKind;
+use rust::io::write_labeled_clustered_csv;
+use rust::types::{Class, LabeledPoint, Point, label_points};
+use std::collections::HashMap;
+
+fn pt(x: f64, y: f64) -> Point {
+    vec![OrderedFloat(x), OrderedFloat(y)]
+}
+
+// Three copies of the origin, a lone point and a copy of it.
+fn rows() -> Vec<LabeledPoint> {
+    label_points(vec![
+        pt(0.0, 0.0),
+        pt(5.0, 5.0),
+        pt(0.0, 0.0),
+        pt(5.0, 5.0),
+        pt(0.0, 0.0),
+    ])
+}
+
+fn write(points: &[LabeledPoint], classes: &HashMap<usize, Class>) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.csv");
+    let path = path.to_str().unwrap();
+    write_labeled_clustered_csv(path, points, classes).unwrap();
+    std::fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn duplicate_coordinates_get_independent_labels() {
+    let points = rows();
+    for engine in [EngineKind::Fake, EngineKind::RTree, EngineKind::Brute] {
+        let classes = dbscan_labeled(&points, 0.5, 3, engine);
+        // One entry per row: the three copies of the origin are dense enough
+        // for a cluster on their own, the two copies of (5, 5) are not.
+        let expected = [0, 2, 4]
+            .map(|id| (id, Class::Classified(0)))
+            .into_iter()
+            .chain([1, 3].map(|id| (id, Class::Noise)))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(classes, expected, "{}", engine);
+    }
+}
+
+#[test]
+fn output_keeps_input_row_order() {
+    let points = rows();
+    let classes = dbscan_labeled(&points, 0.5, 3, EngineKind::Fake);
+    let expected = "0,0,0\n-1,5,5\n0,0,0\n-1,5,5\n0,0,0\n";
+    assert_eq!(write(&points, &classes), expected);
+
+    // The order of the slice does not matter, only the ids.
+    let mut shuffled = points.clone();
+    shuffled.reverse();
+    shuffled.swap(1, 3);
+    assert_eq!(write(&shuffled, &classes), expected);
+}
+
+#[test]
+fn ids_missing_from_classes_are_noise() {
+    let points = rows();
+    let classes = HashMap::from([(0, Class::Classified(2)), (4, Class::Unclassified)]);
+    assert_eq!(
+        write(&points, &classes),
+        "2,0,0\n-1,5,5\n-1,0,0\n-1,5,5\n-2,0,0\n"
+    );
+}
@@ -0,0 +1,98 @@
+//! The `PointCloud` path (reader, engine, clustering) against the
+//! Vec-of-`Point`s one.
+
+use ordered_float::OrderedFloat;
+use rust::algo::{dbscan_cloud, Algo, RegionQuery};
+use rust::brute_query::BruteForceQueryEngine;
+use rust::io::{read_points_csv, read_points_csv_cloud};
+use rust::sampling::SplitMix64;
+use rust::types::{Class, Point, PointCloud};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+// Blobs of 20 points around (10 * c, 10 * c) plus scattered points. Distinct
+// coordinates, so that both paths index the same points.
+fn blobs(seed: u64) -> Vec<Point> {
+    let mut rng = SplitMix64::new(seed);
+    let mut seen = HashSet::new();
+    let mut coord = |center: f64, spread: u64| center + rng.below(spread) as f64 / 100.0;
+    (0..3)
+        .flat_map(|c| (0..20).map(move |_| c))
+        .map(|c| (10.0 * c as f64, 50))
+        .chain((0..10).map(|_| (0.0, 3000)))
+        .map(|(center, spread)| {
+            vec![
+                OrderedFloat(coord(center, spread)),
+                OrderedFloat(coord(center, spread)),
+            ]
+        })
+        .filter(|p| seen.insert(p.clone()))
+        .collect()
+}
+
+// Labels renamed in order of first appearance, so that clusterings that
+// only differ in their ids compare equal.
+fn canonical(labels: impl IntoIterator<Item = Class>) -> Vec<Class> {
+    let mut ids = HashMap::new();
+    labels
+        .into_iter()
+        .map(|c| match c {
+            Class::Classified(id) => {
+                let next = ids.len();
+                Class::Classified(*ids.entry(id).or_insert(next))
+            }
+            other => other,
+        })
+        .collect()
+}
+
+#[test]
+fn clustering_matches_the_point_path() {
+    for seed in 0..5 {
+        let points = blobs(seed);
+        let cloud = PointCloud::from(points.as_slice());
+        let from_cloud = dbscan_cloud(cloud.view(), 0.3, 4);
+
+        let refs = points.iter().collect::<HashSet<_>>();
+        let mut engine = BruteForceQueryEngine::new();
+        let classes = Algo::new(&mut engine, &refs, 0.3, 4).dbscan();
+        let from_points = points.iter().map(|p| classes[p]);
+
+        assert_eq!(
+            canonical(from_cloud),
+            canonical(from_points),
+            "seed {}",
+            seed
+        );
+    }
+}
+
+#[test]
+fn cloud_engine_finds_the_point_neighbors() {
+    let points = blobs(7);
+    let cloud = PointCloud::from(points.as_slice());
+    let cloud_engine = BruteForceQueryEngine::from_cloud(&cloud);
+    let refs = points.iter().collect::<HashSet<_>>();
+    let mut engine = BruteForceQueryEngine::new();
+    let algo = Algo::new(&mut engine, &refs, 0.3, 4);
+
+    for (i, p) in points.iter().enumerate() {
+        let rows = cloud_engine.run_rows(cloud.row(i), 0.3);
+        let found = rows.iter().map(|&j| &points[j]).collect::<HashSet<_>>();
+        assert_eq!(found, algo.region_query().run(p, 0.3));
+    }
+}
+
+#[test]
+fn csv_reads_into_the_same_points() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, "1,2,3\n-0.5,1e3,4\n\n7,8,9\n").unwrap();
+    let path = file.path().to_str().unwrap();
+
+    let points = read_points_csv(path).unwrap();
+    let cloud = read_points_csv_cloud(path).unwrap();
+    assert_eq!(cloud.len(), 3);
+    assert_eq!(cloud.dim(), 3);
+    assert_eq!(cloud.to_points(), points);
+    assert_eq!(PointCloud::from(points.as_slice()), cloud);
+}
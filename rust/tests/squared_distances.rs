@@ -0,0 +1,122 @@
+//! The engines compare squared distances (`Metric::key`) against `eps²` and
+//! only take square roots for the distances they return, without changing
+//! any result.
+
+use ordered_float::OrderedFloat;
+use rust::algo::{Algo, RegionQuery};
+use rust::brute_query::BruteForceQueryEngine;
+use rust::fake_query::FakeQueryEngine;
+use rust::query::RTreeQueryEngine;
+use rust::sampling::SplitMix64;
+use rust::types::{dist, Class, Euclidean, Metric, Point};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Euclidean distance that counts how often it is measured with and without
+// the square root.
+#[derive(Clone, Default)]
+struct Counting {
+    roots: Arc<AtomicUsize>,
+    squares: Arc<AtomicUsize>,
+}
+
+impl Counting {
+    fn counts(&self) -> (usize, usize) {
+        let roots = self.roots.swap(0, Ordering::Relaxed);
+        (roots, self.squares.swap(0, Ordering::Relaxed))
+    }
+}
+
+impl Metric for Counting {
+    fn dist(&self, a: &Point, b: &Point) -> f64 {
+        self.roots.fetch_add(1, Ordering::Relaxed);
+        Euclidean.dist(a, b)
+    }
+
+    fn key(&self, a: &Point, b: &Point) -> f64 {
+        self.squares.fetch_add(1, Ordering::Relaxed);
+        Euclidean.key(a, b)
+    }
+
+    fn key_coords(&self, a: &[f64], b: &[f64]) -> f64 {
+        self.squares.fetch_add(1, Ordering::Relaxed);
+        Euclidean.key_coords(a, b)
+    }
+
+    fn key_bound(&self, eps: f64) -> f64 {
+        Euclidean.key_bound(eps)
+    }
+
+    fn key_to_dist(&self, key: f64) -> f64 {
+        Euclidean.key_to_dist(key)
+    }
+}
+
+// Points on a grid of quarters, so that many distances are exactly eps.
+fn grid_points(seed: u64, n: usize) -> Vec<Point> {
+    let mut rng = SplitMix64::new(seed);
+    (0..n)
+        .map(|_| {
+            let mut c = || OrderedFloat(rng.below(24) as f64 / 4.0);
+            vec![c(), c()]
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[test]
+fn range_queries_take_no_square_roots() {
+    let points = grid_points(343, 150);
+    let refs = points.iter().collect::<HashSet<_>>();
+    let metric = Counting::default();
+    let mut fake = FakeQueryEngine::with_metric(metric.clone());
+    fake.init(&refs);
+    let mut brute = BruteForceQueryEngine::with_metric(metric.clone());
+    brute.init(&refs);
+    metric.counts();
+
+    let engines: [(&str, &dyn RegionQuery); 2] = [("fake", &fake), ("brute", &brute)];
+    for (name, engine) in engines {
+        for p in points.iter() {
+            engine.run(p, 1.25);
+            engine.weight_within_at_least(p, 1.25, 4.0);
+        }
+        let (roots, squares) = metric.counts();
+        assert_eq!(roots, 0, "{}", name);
+        assert!(squares > 0, "{}", name);
+
+        // A k-distance is returned, so it takes one root per query at most.
+        for p in points.iter() {
+            engine.k_dist(p, 3);
+        }
+        let (roots, _) = metric.counts();
+        assert!(roots <= points.len(), "{}: {} roots", name, roots);
+    }
+}
+
+#[test]
+fn clustering_matches_plain_distances() {
+    // The reference measures the distance itself, square root included, and
+    // compares it against eps.
+    let plain = |a: &Point, b: &Point| dist(a, b);
+    for seed in 0..5 {
+        let points = grid_points(seed, 200);
+        let refs = points.iter().collect::<HashSet<_>>();
+        for eps in [0.5, 0.75, 1.25] {
+            let mut reference = FakeQueryEngine::with_metric(plain);
+            let expected = Algo::new(&mut reference, &refs, eps, 4).dbscan();
+            let same = |name: &str, classes: HashMap<&Point, Class>| {
+                assert_eq!(classes, expected, "{} seed {} eps {}", name, seed, eps);
+            };
+
+            let mut fake = FakeQueryEngine::with_metric(Counting::default());
+            same("fake", Algo::new(&mut fake, &refs, eps, 4).dbscan());
+            let mut brute = BruteForceQueryEngine::with_metric(Counting::default());
+            same("brute", Algo::new(&mut brute, &refs, eps, 4).dbscan());
+            let mut rtree = RTreeQueryEngine::new();
+            same("rtree", Algo::new(&mut rtree, &refs, eps, 4).dbscan());
+        }
+    }
+}